@@ -5,13 +5,33 @@ use blockchain::contract::{
     ContractMetadata,
     ContractMethod,
     ContractParam,
+    ContractEnvironment,
     ResourceLimits,
+    DEFAULT_ADMIN_ROLE,
     DEPLOYER_ROLE,
     EXECUTOR_ROLE,
     UPGRADER_ROLE,
 };
+use blockchain::msg;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::sync::Arc;
 use std::time::SystemTime;
+use tokio::sync::RwLock;
+use wasmer::Value;
+
+// Contract with a read-only `add` method and a `store` method writing
+// through the host storage interface
+const STORAGE_WAT: &str = r#"
+(module
+  (import "env" "storage_write" (func $storage_write (param i32 i32 i32 i32)))
+  (memory (export "memory") 1)
+  (func (export "add") (param i32 i32) (result i32)
+    (i32.add (local.get 0) (local.get 1)))
+  (func (export "store") (param $key i32) (param $value i32)
+    (i32.store (i32.const 0) (local.get $key))
+    (i32.store (i32.const 4) (local.get $value))
+    (call $storage_write (i32.const 0) (i32.const 4) (i32.const 4) (i32.const 4))))
+"#;
 
 // Helper function to create test contract metadata
 fn create_test_metadata(version: &str) -> ContractMetadata {
@@ -28,6 +48,7 @@ fn create_test_metadata(version: &str) -> ContractMetadata {
         author: [0u8; 32],
         description: "Test Contract".to_string(),
         is_upgradeable: true,
+        allow_major_upgrade: false,
     }
 }
 
@@ -47,6 +68,7 @@ fn create_test_abi() -> ContractABI {
                 indexed: false,
             }],
             payable: false,
+            default_gas: None,
         }],
         events: vec![],
         standards: vec!["test".to_string()],
@@ -177,10 +199,104 @@ fn benchmark_concurrent_operations(c: &mut Criterion) {
     });
 }
 
+// Read-only and writing calls against a large state. Both share the state
+// with the executing instance instead of copying it; writing calls also take
+// a snapshot of it and copy it to apply their write, which is the gap between
+// the two
+fn benchmark_method_execution(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let test_account = [1u8; 32];
+    let contract_addr = [0u8; 32];
+
+    let i32_param = |name: &str| ContractParam {
+        name: name.to_string(),
+        param_type: "i32".to_string(),
+        indexed: false,
+    };
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "add".to_string(),
+                inputs: vec![i32_param("a"), i32_param("b")],
+                outputs: vec![i32_param("result")],
+                payable: false,
+                default_gas: None,
+            },
+            ContractMethod {
+                name: "store".to_string(),
+                inputs: vec![i32_param("key"), i32_param("value")],
+                outputs: vec![],
+                payable: false,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+
+    msg::test_utils::set_sender(test_account).unwrap();
+    let mut runtime = ContractRuntime::new();
+    runtime.grant_role(DEFAULT_ADMIN_ROLE, test_account).unwrap();
+    runtime.grant_role(DEPLOYER_ROLE, test_account).unwrap();
+    runtime.grant_role(EXECUTOR_ROLE, test_account).unwrap();
+    rt.block_on(async {
+        runtime.deploy_contract(
+            STORAGE_WAT.as_bytes(),
+            &contract_addr,
+            &abi,
+            create_test_metadata("1.0.0"),
+            &create_test_limits(),
+        ).await.unwrap();
+        for i in 0..10_000u32 {
+            runtime.update_contract_state(contract_addr, i.to_le_bytes().to_vec(), vec![0u8; 64]).await.unwrap();
+        }
+    });
+
+    let env = ContractEnvironment {
+        gas_limit: Some(1_000_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: test_account,
+        value: 0,
+        resource_limits: create_test_limits(),
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+
+    let mut group = c.benchmark_group("method_execution");
+
+    group.bench_function("read_only", |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                black_box(
+                    runtime.execute_contract(contract_addr, "add", vec![Value::I32(1), Value::I32(2)], &env, None)
+                        .await
+                        .unwrap()
+                );
+            });
+        })
+    });
+
+    group.bench_function("writing", |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                black_box(
+                    runtime.execute_contract(contract_addr, "store", vec![Value::I32(1), Value::I32(2)], &env, None)
+                        .await
+                        .unwrap()
+                );
+            });
+        })
+    });
+
+    group.finish();
+    msg::test_utils::clear_sender().unwrap();
+}
+
 criterion_group!(
     benches,
     benchmark_contract_deployment,
     benchmark_state_operations,
-    benchmark_concurrent_operations
+    benchmark_concurrent_operations,
+    benchmark_method_execution
 );
 criterion_main!(benches);
@@ -5,20 +5,24 @@ use actix_web::dev::ServiceResponse;
 use actix_web::dev::Payload;
 use actix_web::HttpMessage;  // Add this for extensions_mut
 use crate::block::Block;
-use crate::contract::standards::ContractResult;
+use crate::contract::standards::{ContractError, ContractResult};
 use crate::contract::{
     ContractEnvironment, ContractABI, ResourceLimits, ContractRuntime,
-    ContractMethod, ContractEvent, ContractParam, ContractMetadata
+    ContractMethod, ContractEvent, ContractParam, ContractMetadata, UpgradeHistory
 };
-use crate::crypto::Hash;
+use crate::crypto::{self, Hash};
+use crate::mempool::Mempool;
+use crate::msg;
+pub use crate::receipt::WasmValue;
 use crate::transaction::Transaction;
 use actix_cors::Cors;
 use actix_governor::{Governor, GovernorConfigBuilder};
 use actix_web::{
-    error::{ErrorForbidden, ErrorInternalServerError, ErrorUnauthorized},
+    error::{ErrorForbidden, ErrorInternalServerError},
     get, middleware, post,
+    http::StatusCode,
     web::{self, Data, Json},
-    App, HttpResponse, HttpServer, Responder,
+    App, HttpResponse, HttpServer, Responder, ResponseError,
 };
 use actix_web_httpauth::{
     extractors::{
@@ -38,51 +42,24 @@ use tokio::sync::RwLock;
 use tracing::{error, info, instrument, warn};
 use tracing_actix_web::TracingLogger;
 
-/// Wrapper type for wasmer::Value that implements Serialize/Deserialize
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum WasmValue {
-    I32(i32),
-    I64(i64),
-    F32(f32),
-    F64(f64),
-    String(String),
-    Bytes(Vec<u8>),
-}
-
-impl From<wasmer::Value> for WasmValue {
-    fn from(value: wasmer::Value) -> Self {
-        match value {
-            wasmer::Value::I32(v) => WasmValue::I32(v),
-            wasmer::Value::I64(v) => WasmValue::I64(v),
-            wasmer::Value::F32(v) => WasmValue::F32(v),
-            wasmer::Value::F64(v) => WasmValue::F64(v),
-            _ => WasmValue::String("Unsupported type".to_string()),
-        }
-    }
-}
-
-impl Into<wasmer::Value> for WasmValue {
-    fn into(self) -> wasmer::Value {
-        match self {
-            WasmValue::I32(v) => wasmer::Value::I32(v),
-            WasmValue::I64(v) => wasmer::Value::I64(v),
-            WasmValue::F32(v) => wasmer::Value::F32(v),
-            WasmValue::F64(v) => wasmer::Value::F64(v),
-            _ => wasmer::Value::I32(0), // Default for unsupported types
-        }
-    }
-}
+const DEFAULT_MEMPOOL_SIZE: usize = 10_000;
 
 /// API state
 pub struct ApiState {
     pub contract_runtime: Arc<RwLock<ContractRuntime>>,
+    pub mempool: Arc<Mempool>,
     jwt_secret: String,
 }
 
 impl ApiState {
     pub fn new(jwt_secret: String) -> Self {
+        Self::with_mempool(jwt_secret, Arc::new(Mempool::new(DEFAULT_MEMPOOL_SIZE)))
+    }
+
+    pub fn with_mempool(jwt_secret: String, mempool: Arc<Mempool>) -> Self {
         ApiState {
             contract_runtime: Arc::new(RwLock::new(ContractRuntime::new())),
+            mempool,
             jwt_secret,
         }
     }
@@ -142,6 +119,131 @@ pub enum ApiError {
     Forbidden(String),
 }
 
+impl ApiError {
+    /// Stable machine-readable error code
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "BAD_REQUEST",
+            ApiError::Internal(_) => "INTERNAL_ERROR",
+            ApiError::NotFound(_) => "NOT_FOUND",
+            ApiError::Unauthorized(_) => "UNAUTHORIZED",
+            ApiError::Forbidden(_) => "FORBIDDEN",
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorResponse::new(self.code(), self.to_string(), None))
+    }
+}
+
+/// Stable machine-readable code for a contract error
+pub fn contract_error_code(error: &ContractError) -> &'static str {
+    match error {
+        ContractError::AccessDenied(_) => "ACCESS_DENIED",
+        ContractError::NotFound(_) => "CONTRACT_NOT_FOUND",
+        ContractError::InvalidArguments(_) => "INVALID_ARGUMENTS",
+        ContractError::CompilationError(_) => "COMPILATION_ERROR",
+        ContractError::ExecutionError(_) => "EXECUTION_ERROR",
+        ContractError::LockError(_) => "LOCK_ERROR",
+        ContractError::ReentrancyError(_) => "REENTRANCY_ERROR",
+        ContractError::InvalidOperation(_) => "INVALID_OPERATION",
+        ContractError::NotImplemented(_) => "NOT_IMPLEMENTED",
+        ContractError::VersionConflict(_) => "VERSION_CONFLICT",
+        ContractError::VersionNotFound(_) => "VERSION_NOT_FOUND",
+        ContractError::VersionIncompatible(_) => "VERSION_INCOMPATIBLE",
+        ContractError::VersionUpgradeFailed(_) => "VERSION_UPGRADE_FAILED",
+        ContractError::VersionRolledBack(_) => "VERSION_ROLLED_BACK",
+        ContractError::StateError(_) => "STATE_ERROR",
+        ContractError::StateValidationError(_) => "STATE_VALIDATION_ERROR",
+        ContractError::StateCorrupted(_) => "STATE_CORRUPTED",
+        ContractError::StateRollbackFailed(_) => "STATE_ROLLBACK_FAILED",
+        ContractError::UpgradeAuthorizationError(_) => "UPGRADE_UNAUTHORIZED",
+        ContractError::UpgradeValidationError(_) => "UPGRADE_VALIDATION_ERROR",
+        ContractError::UpgradeRollbackError(_) => "UPGRADE_ROLLBACK_ERROR",
+        ContractError::UpgradeLimitExceeded(_) => "UPGRADE_LIMIT_EXCEEDED",
+        ContractError::BytecodeVerificationError(_) => "BYTECODE_VERIFICATION_ERROR",
+        ContractError::BytecodeIntegrityError(_) => "BYTECODE_INTEGRITY_ERROR",
+        ContractError::ConcurrencyLimitExceeded(_) => "CONCURRENCY_LIMIT_EXCEEDED",
+        ContractError::OperationTimeout(_) => "OPERATION_TIMEOUT",
+        ContractError::OperationConflict(_) => "OPERATION_CONFLICT",
+    }
+}
+
+fn contract_error_status(error: &ContractError) -> StatusCode {
+    match error {
+        ContractError::AccessDenied(_) | ContractError::UpgradeAuthorizationError(_) => StatusCode::FORBIDDEN,
+        ContractError::NotFound(_) | ContractError::VersionNotFound(_) => StatusCode::NOT_FOUND,
+        ContractError::InvalidArguments(_)
+        | ContractError::InvalidOperation(_)
+        | ContractError::BytecodeVerificationError(_)
+        | ContractError::BytecodeIntegrityError(_)
+        | ContractError::UpgradeValidationError(_)
+        | ContractError::VersionIncompatible(_) => StatusCode::BAD_REQUEST,
+        ContractError::VersionConflict(_)
+        | ContractError::VersionRolledBack(_)
+        | ContractError::OperationConflict(_) => StatusCode::CONFLICT,
+        ContractError::ConcurrencyLimitExceeded(_)
+        | ContractError::UpgradeLimitExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
+        ContractError::OperationTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+        ContractError::NotImplemented(_) => StatusCode::NOT_IMPLEMENTED,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Contract address given in a request path, either as a checksummed
+/// address or as hex
+fn parse_path_address(address: &str) -> Option<[u8; 32]> {
+    crypto::parse_address(address).ok()
+        .or_else(|| hex::decode(address).ok().and_then(|bytes| bytes.try_into().ok()))
+}
+
+/// Build the structured error response for a failed contract operation
+fn contract_error_response(error: &ContractError, details: &str) -> HttpResponse {
+    HttpResponse::build(contract_error_status(error)).json(ErrorResponse::new(
+        contract_error_code(error),
+        error.to_string(),
+        Some(details.to_string()),
+    ))
+}
+
+/// Error details returned in the error envelope
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+    pub details: Option<String>,
+}
+
+/// Error envelope returned by every failing endpoint
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub error: ErrorBody,
+}
+
+impl ErrorResponse {
+    pub fn new(code: &str, message: String, details: Option<String>) -> Self {
+        ErrorResponse {
+            error: ErrorBody {
+                code: code.to_string(),
+                message,
+                details,
+            },
+        }
+    }
+}
+
 /// API response types
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApiResponse<T> {
@@ -175,6 +277,7 @@ pub struct DeployContractRequest {
 /// Contract deployment response
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeployContractResponse {
+    #[serde(with = "crate::crypto::address")]
     pub address: [u8; 32],
     pub implemented_standards: Vec<String>,
     pub version: String,
@@ -185,7 +288,22 @@ pub struct DeployContractResponse {
 pub struct ExecuteContractRequest {
     pub method: String,
     pub args: Vec<WasmValue>,
-    pub gas_limit: u64,
+    /// Omitted to use the method's default gas limit
+    #[serde(default)]
+    pub gas_limit: Option<u64>,
+}
+
+/// Contract execution response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecuteContractResponse {
+    pub values: Vec<WasmValue>,
+    pub gas_used: u64,
+}
+
+/// Upgrade history response, oldest upgrade first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpgradeHistoryResponse {
+    pub upgrades: Vec<UpgradeHistory>,
 }
 
 /// Contract state query request
@@ -194,6 +312,24 @@ pub struct ContractStateRequest {
     pub key: Vec<u8>,
 }
 
+/// Fee estimate query
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeEstimateQuery {
+    #[serde(default = "default_target_blocks")]
+    pub target_blocks: u64,
+}
+
+fn default_target_blocks() -> u64 {
+    1
+}
+
+/// Fee estimate response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeEstimateResponse {
+    pub target_blocks: u64,
+    pub fee_rate: u64,
+}
+
 /// JWT authentication validator
 async fn validator(
     mut req: ServiceRequest,
@@ -206,7 +342,7 @@ async fn validator(
             req.extensions_mut().insert(claims);
             Ok(req)
         }
-        Err(_) => Err((ErrorUnauthorized("Invalid token").into(), req)),
+        Err(e) => Err((e.into(), req)),
     }
 }
 
@@ -221,11 +357,13 @@ async fn deploy_contract(
         .unwrap()
         .as_secs();
 
-    // Generate a unique address for the contract
-    let mut address = [0u8; 32]; // Replace with proper address generation
-    
-    // Deploy the contract
+    // Derive the address from the author and their deployment count, so
+    // every deployment gets its own
     let mut runtime = state.contract_runtime.write().await;
+    let author = request.metadata.author;
+    let address = ContractRuntime::generate_address(&author, runtime.deployment_nonce(&author));
+
+    // Deploy the contract
     let result = runtime
         .deploy_contract(
             &request.bytecode,
@@ -248,15 +386,122 @@ async fn deploy_contract(
         }),
         Err(e) => {
             error!("Contract deployment failed: {:?}", e);
-            HttpResponse::InternalServerError().json(ApiResponse {
-                data: (),
-                status: format!("error: {:?}", e),
-                timestamp,
-            })
+            contract_error_response(&e, "Contract deployment failed")
         }
     }
 }
 
+#[post("/contracts/{address}/execute")]
+#[instrument(skip(state))]
+async fn execute_contract(
+    state: Data<ApiState>,
+    address: web::Path<String>,
+    request: Json<ExecuteContractRequest>,
+) -> impl Responder {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let address = match parse_path_address(&address) {
+        Some(address) => address,
+        None => {
+            let e = ContractError::InvalidArguments(format!("Invalid contract address {}", address));
+            return contract_error_response(&e, "Contract execution failed");
+        }
+    };
+
+    let mut runtime = state.contract_runtime.write().await;
+    let Some(resource_limits) = runtime.get_resource_limits(&address) else {
+        let e = ContractError::NotFound("Contract not found".into());
+        return contract_error_response(&e, "Contract execution failed");
+    };
+
+    // Calls made through the API are not part of a block
+    let env = ContractEnvironment {
+        gas_limit: request.gas_limit,
+        block_number: 0,
+        timestamp,
+        caller: msg::sender().unwrap_or_default(),
+        value: 0,
+        resource_limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+    let args = request.args.iter().cloned().map(Into::into).collect();
+
+    match runtime.execute_contract_metered(address, &request.method, args, &env, None).await {
+        Ok(result) => HttpResponse::Ok().json(ApiResponse {
+            data: ExecuteContractResponse {
+                values: result.values.into_iter().map(WasmValue::from).collect(),
+                gas_used: result.gas_used,
+            },
+            status: "success".to_string(),
+            timestamp,
+        }),
+        Err(e) => {
+            error!("Contract execution failed: {:?}", e);
+            contract_error_response(&e, "Contract execution failed")
+        }
+    }
+}
+
+#[get("/contracts/{address}/upgrade-history")]
+#[instrument(skip(state))]
+async fn upgrade_history(
+    state: Data<ApiState>,
+    address: web::Path<String>,
+) -> impl Responder {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let address = match parse_path_address(&address) {
+        Some(address) => address,
+        None => {
+            let e = ContractError::InvalidArguments(format!("Invalid contract address {}", address));
+            return contract_error_response(&e, "Upgrade history query failed");
+        }
+    };
+
+    let runtime = state.contract_runtime.read().await;
+    let upgrades = match runtime.get_upgrade_history(&address) {
+        Ok(history) => history.clone(),
+        // A contract that was never upgraded has no history yet
+        Err(ContractError::NotFound(_)) if runtime.get_latest_version(&address).is_ok() => Vec::new(),
+        Err(e) => return contract_error_response(&e, "Upgrade history query failed"),
+    };
+
+    HttpResponse::Ok().json(ApiResponse {
+        data: UpgradeHistoryResponse { upgrades },
+        status: "success".to_string(),
+        timestamp,
+    })
+}
+
+#[get("/fees/estimate")]
+#[instrument(skip(state))]
+async fn estimate_fee(
+    state: Data<ApiState>,
+    query: web::Query<FeeEstimateQuery>,
+) -> impl Responder {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let fee_rate = state.mempool.estimate_fee_rate(query.target_blocks).await;
+
+    HttpResponse::Ok().json(ApiResponse {
+        data: FeeEstimateResponse {
+            target_blocks: query.target_blocks,
+            fee_rate,
+        },
+        status: "success".to_string(),
+        timestamp,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -297,6 +542,7 @@ mod tests {
                 author: [0u8; 32],
                 description: "Test contract".to_string(),
                 is_upgradeable: true,
+                allow_major_upgrade: false,
             },
             resource_limits: ResourceLimits {
                 max_memory: 1024 * 1024,
@@ -318,4 +564,253 @@ mod tests {
         assert_eq!(resp.data.implemented_standards, vec!["ERC20".to_string()]);
         assert_eq!(resp.data.version, "1.0.0");
     }
+
+    #[actix_rt::test]
+    async fn test_contract_execution_reports_gas() {
+        let state = Data::new(ApiState::new("test_secret".to_string()));
+        let token = state.create_token("test", "user").unwrap();
+        let address = [5u8; 32];
+
+        {
+            let mut runtime = state.contract_runtime.write().await;
+            let sender = msg::sender().unwrap();
+            runtime.grant_role(crate::contract::DEFAULT_ADMIN_ROLE, sender).unwrap();
+            runtime.grant_role(crate::contract::DEPLOYER_ROLE, sender).unwrap();
+            runtime.grant_role(crate::contract::EXECUTOR_ROLE, sender).unwrap();
+
+            let wat = r#"
+            (module
+              (func (export "add") (param i32 i32) (result i32)
+                local.get 0
+                local.get 1
+                i32.add))
+            "#;
+            let i32_param = |name: &str| ContractParam {
+                name: name.to_string(),
+                param_type: "i32".to_string(),
+                indexed: false,
+            };
+            let abi = ContractABI {
+                methods: vec![ContractMethod {
+                    name: "add".to_string(),
+                    inputs: vec![i32_param("a"), i32_param("b")],
+                    outputs: vec![i32_param("sum")],
+                    payable: false,
+                    default_gas: None,
+                }],
+                events: vec![],
+                standards: vec![],
+            };
+            let metadata = ContractMetadata {
+                version: "1.0.0".to_string(),
+                created_at: 0,
+                updated_at: 0,
+                author: sender,
+                description: "Test contract".to_string(),
+                is_upgradeable: true,
+                allow_major_upgrade: false,
+            };
+            let limits = ResourceLimits {
+                max_memory: 1024 * 1024,
+                max_gas: 1_000_000,
+                max_storage: 1024 * 1024,
+                max_call_depth: 5,
+            };
+            runtime.deploy_contract(wat.as_bytes(), &address, &abi, metadata, &limits).await.unwrap();
+        }
+
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .service(
+                    web::scope("")
+                        .wrap(HttpAuthentication::bearer(validator))
+                        .service(execute_contract)
+                )
+        ).await;
+
+        let request = ExecuteContractRequest {
+            method: "add".to_string(),
+            args: vec![WasmValue::I32(2), WasmValue::I32(3)],
+            gas_limit: Some(100_000),
+        };
+        let req = test::TestRequest::post()
+            .uri(&format!("/contracts/{}/execute", hex::encode(address)))
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .set_json(&request)
+            .to_request();
+
+        let resp: ApiResponse<ExecuteContractResponse> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp.status, "success");
+        assert_eq!(resp.data.values, vec![WasmValue::I32(5)]);
+        assert!(resp.data.gas_used > 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_upgrade_history() {
+        let state = Data::new(ApiState::new("test_secret".to_string()));
+        let token = state.create_token("test", "user").unwrap();
+        let address = [6u8; 32];
+
+        let wat = r#"
+        (module
+          (func (export "add") (param i32 i32) (result i32)
+            local.get 0
+            local.get 1
+            i32.add))
+        "#;
+        let abi = ContractABI {
+            methods: vec![],
+            events: vec![],
+            standards: vec![],
+        };
+        let metadata = |version: &str, updated_at| ContractMetadata {
+            version: version.to_string(),
+            created_at: 0,
+            updated_at,
+            author: [1u8; 32],
+            description: "Test contract".to_string(),
+            is_upgradeable: true,
+            allow_major_upgrade: false,
+        };
+        let limits = ResourceLimits {
+            max_memory: 1024 * 1024,
+            max_gas: 1_000_000,
+            max_storage: 1024 * 1024,
+            max_call_depth: 5,
+        };
+
+        {
+            let mut runtime = state.contract_runtime.write().await;
+            let sender = msg::sender().unwrap();
+            runtime.grant_role(crate::contract::DEFAULT_ADMIN_ROLE, sender).unwrap();
+            runtime.grant_role(crate::contract::DEPLOYER_ROLE, sender).unwrap();
+            runtime.grant_role(crate::contract::UPGRADER_ROLE, sender).unwrap();
+            runtime.deploy_contract(wat.as_bytes(), &address, &abi, metadata("1.0.0", 0), &limits).await.unwrap();
+        }
+
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .service(
+                    web::scope("")
+                        .wrap(HttpAuthentication::bearer(validator))
+                        .service(upgrade_history)
+                )
+        ).await;
+        let history_request = || test::TestRequest::get()
+            .uri(&format!("/contracts/{}/upgrade-history", crypto::encode_address(&address)))
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request();
+
+        // Nothing to report before the first upgrade
+        let resp: ApiResponse<UpgradeHistoryResponse> = test::call_and_read_body_json(&app, history_request()).await;
+        assert!(resp.data.upgrades.is_empty());
+
+        state.contract_runtime.write().await
+            .upgrade_contract(&address, wat.as_bytes(), &abi, metadata("1.1.0", 100), None).await.unwrap();
+        let resp: ApiResponse<UpgradeHistoryResponse> = test::call_and_read_body_json(&app, history_request()).await;
+        assert_eq!(resp.data.upgrades, vec![UpgradeHistory {
+            from_version: "1.0.0".to_string(),
+            to_version: "1.1.0".to_string(),
+            timestamp: 100,
+            successful: true,
+            rollback_performed: false,
+        }]);
+
+        // Rolling back is recorded against the upgrade it undid
+        state.contract_runtime.write().await.rollback_contract(&address).await.unwrap();
+        let resp: ApiResponse<UpgradeHistoryResponse> = test::call_and_read_body_json(&app, history_request()).await;
+        assert_eq!(resp.data.upgrades.len(), 1);
+        assert!(!resp.data.upgrades[0].successful);
+        assert!(resp.data.upgrades[0].rollback_performed);
+
+        // Unknown contracts are reported as such
+        let req = test::TestRequest::get()
+            .uri(&format!("/contracts/{}/upgrade-history", hex::encode([7u8; 32])))
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn test_fee_estimate() {
+        let state = Data::new(ApiState::new("test_secret".to_string()));
+        let token = state.create_token("test", "user").unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .service(
+                    web::scope("")
+                        .wrap(HttpAuthentication::bearer(validator))
+                        .service(estimate_fee)
+                )
+        ).await;
+
+        let req = test::TestRequest::get()
+            .uri("/fees/estimate?target_blocks=3")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request();
+
+        let resp: ApiResponse<FeeEstimateResponse> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp.status, "success");
+        assert_eq!(resp.data.target_blocks, 3);
+        assert!(resp.data.fee_rate >= 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_structured_error_response() {
+        let state = Data::new(ApiState::new("test_secret".to_string()));
+        let token = state.create_token("test", "user").unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .service(
+                    web::scope("")
+                        .wrap(HttpAuthentication::bearer(validator))
+                        .service(deploy_contract)
+                )
+        ).await;
+
+        // No roles have been granted, so deployment is denied
+        let request = DeployContractRequest {
+            bytecode: vec![0, 1, 2, 3],
+            abi: ContractABI {
+                methods: vec![],
+                events: vec![],
+                standards: vec![],
+            },
+            metadata: ContractMetadata {
+                version: "1.0.0".to_string(),
+                created_at: 0,
+                updated_at: 0,
+                author: [0u8; 32],
+                description: "Test contract".to_string(),
+                is_upgradeable: true,
+                allow_major_upgrade: false,
+            },
+            resource_limits: ResourceLimits {
+                max_memory: 1024 * 1024,
+                max_gas: 1_000_000,
+                max_storage: 1024 * 1024,
+                max_call_depth: 5,
+            },
+        };
+
+        let req = test::TestRequest::post()
+            .uri("/contracts")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .set_json(&request)
+            .to_request();
+
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let body: ErrorResponse = test::read_body_json(resp).await;
+        assert_eq!(body.error.code, "ACCESS_DENIED");
+        assert_eq!(body.error.message, "Access denied: Sender does not have deployer role");
+        assert_eq!(body.error.details.as_deref(), Some("Contract deployment failed"));
+    }
 }
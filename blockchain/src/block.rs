@@ -1,6 +1,9 @@
-use crate::crypto::Hash;
+use crate::crypto::{Hash, KeyPair, Signature};
+use crate::params::ChainParams;
+use ed25519_dalek::{Verifier, VerifyingKey};
 use crate::transaction::Transaction;
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockHeader {
@@ -10,6 +13,13 @@ pub struct BlockHeader {
     pub merkle_root: Hash,
     pub difficulty: u32,
     pub nonce: u64,
+    /// Validator that proposed the block (zero for proof-of-work blocks)
+    #[serde(default)]
+    pub proposer: [u8; 32],
+    /// The proposer's signature over the block hash, which the signature
+    /// itself isn't part of (proof-of-stake blocks only)
+    #[serde(default)]
+    pub proposer_signature: Option<Signature>,
 }
 
 impl Default for BlockHeader {
@@ -26,10 +36,52 @@ impl Default for BlockHeader {
             merkle_root: Hash::new(&[0u8; 32]),
             difficulty: 1,
             nonce: 0,
+            proposer: [0u8; 32],
+            proposer_signature: None,
         }
     }
 }
 
+/// A share of the nonce space: `offset`, `offset + stride`,
+/// `offset + 2 * stride`, ... up to `u64::MAX`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoncePartition {
+    pub offset: u64,
+    pub stride: u64,
+}
+
+impl NoncePartition {
+    pub fn new(offset: u64, stride: u64) -> Self {
+        NoncePartition {
+            offset,
+            stride: stride.max(1),
+        }
+    }
+
+    /// The partition of miner `index` in a pool of `miners`, which is the
+    /// external nonce that keeps pool members from repeating each other's work
+    pub fn for_miner(index: u64, miners: u64) -> Self {
+        let miners = miners.max(1);
+        Self::new(index % miners, miners)
+    }
+
+    /// The nonces in this partition, in increasing order
+    pub fn nonces(&self) -> impl Iterator<Item = u64> {
+        let stride = self.stride;
+        std::iter::successors(Some(self.offset), move |nonce| nonce.checked_add(stride))
+    }
+
+    /// Split into `parts` partitions that between them cover exactly the
+    /// nonces of this one
+    pub fn split(&self, parts: u64) -> Vec<NoncePartition> {
+        let parts = parts.max(1);
+        (0..parts)
+            .filter_map(|i| self.offset.checked_add(i.saturating_mul(self.stride)))
+            .map(|offset| NoncePartition::new(offset, self.stride.saturating_mul(parts)))
+            .collect()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
     pub header: BlockHeader,
@@ -72,6 +124,8 @@ impl Block {
                 merkle_root,
                 difficulty,
                 nonce: 0,
+                proposer: [0u8; 32],
+                proposer_signature: None,
             },
             transactions,
             hash: Hash::new(&[0u8; 32]), // Temporary hash
@@ -81,16 +135,23 @@ impl Block {
         block
     }
 
+    /// Mainnet genesis block
     pub fn genesis() -> Self {
-        let timestamp = 1640995200; // 2022-01-01 00:00:00 UTC
+        Self::genesis_with_params(&ChainParams::mainnet())
+    }
+
+    /// Genesis block of the network described by `params`
+    pub fn genesis_with_params(params: &ChainParams) -> Self {
         let mut block = Block {
             header: BlockHeader {
                 version: 1,
-                timestamp,
+                timestamp: params.genesis_timestamp,
                 prev_hash: Hash::new(&[0u8; 32]),
                 merkle_root: Hash::new(&[0u8; 32]),
-                difficulty: 1,
+                difficulty: params.genesis_difficulty,
                 nonce: 0,
+                proposer: [0u8; 32],
+                proposer_signature: None,
             },
             transactions: vec![],
             hash: Hash::new(&[0u8; 32]),
@@ -108,6 +169,7 @@ impl Block {
         data.extend_from_slice(self.header.merkle_root.to_bytes());
         data.extend_from_slice(&self.header.difficulty.to_le_bytes());
         data.extend_from_slice(&self.header.nonce.to_le_bytes());
+        data.extend_from_slice(&self.header.proposer);
         
         // Also include transaction hashes in block hash calculation
         for tx in &self.transactions {
@@ -117,6 +179,34 @@ impl Block {
         Hash::new(&data)
     }
 
+    /// Set the proposing validator and recompute the block hash
+    pub fn set_proposer(&mut self, proposer: [u8; 32]) {
+        self.header.proposer = proposer;
+        self.hash = self.calculate_hash();
+    }
+
+    /// Propose the block as the validator holding `keypair`: set it as the
+    /// proposer and sign the resulting block hash
+    pub fn sign_as_proposer(&mut self, keypair: &KeyPair) {
+        self.set_proposer(keypair.public_key().to_bytes());
+        self.header.proposer_signature = Some(keypair.sign(self.hash.to_bytes()));
+    }
+
+    /// Whether the block carries a valid signature by its proposer, whose
+    /// address is its public key
+    pub fn verify_proposer_signature(&self) -> bool {
+        let Some(signature) = &self.header.proposer_signature else {
+            return false;
+        };
+        let Ok(key) = VerifyingKey::from_bytes(&self.header.proposer) else {
+            return false;
+        };
+        let Ok(signature) = signature.to_ed_signature() else {
+            return false;
+        };
+        key.verify(self.calculate_hash().to_bytes(), &signature).is_ok()
+    }
+
     fn calculate_merkle_root(transactions: &[Transaction]) -> Hash {
         if transactions.is_empty() {
             return Hash::new(&[0u8; 32]);
@@ -145,20 +235,24 @@ impl Block {
         hashes[0].clone()
     }
 
+    /// Whether `hash` satisfies the proof of work for `difficulty`
+    fn meets_target(hash: &Hash, difficulty: u32) -> bool {
+        let target = (1u128 << (128 - difficulty as u128)) - 1;
+        let hash_bytes = hash.to_bytes();
+        let mut value = 0u128;
+
+        // Convert first 16 bytes of hash to u128
+        for i in 0..16 {
+            value = (value << 8) | hash_bytes[i] as u128;
+        }
+
+        value <= target
+    }
+
     pub fn mine(&mut self) -> bool {
-        let target = (1u128 << (128 - self.header.difficulty as u128)) - 1;
-        
         while self.header.nonce < u64::MAX {
             let hash = self.calculate_hash();
-            let hash_bytes = hash.to_bytes();
-            let mut value = 0u128;
-            
-            // Convert first 16 bytes of hash to u128
-            for i in 0..16 {
-                value = (value << 8) | hash_bytes[i] as u128;
-            }
-            
-            if value <= target {
+            if Self::meets_target(&hash, self.header.difficulty) {
                 self.hash = hash;
                 return true;
             }
@@ -169,29 +263,74 @@ impl Block {
         false
     }
 
-    pub fn verify(&self) -> bool {
-        // First verify the hash matches the block contents
-        let calculated_hash = self.calculate_hash();
-        if calculated_hash != self.hash {
-            return false;
+    /// Mine on `threads` threads, searching the nonces from the current one
+    /// up. All threads stop as soon as one of them finds a valid nonce.
+    pub fn mine_parallel(&mut self, threads: usize) -> bool {
+        self.mine_partition(NoncePartition::new(self.header.nonce, 1), threads)
+    }
+
+    /// Mine on `threads` threads within `partition`, the share of the nonces
+    /// a mining pool handed to this miner. Thread `i` of `n` tests every
+    /// `n`th nonce of the partition starting at its `i`th, so no two threads,
+    /// and no two miners of the pool, test the same nonce.
+    pub fn mine_partition(&mut self, partition: NoncePartition, threads: usize) -> bool {
+        let found = AtomicBool::new(false);
+
+        let solution = std::thread::scope(|scope| {
+            let workers: Vec<_> = partition.split(threads as u64)
+                .into_iter()
+                .map(|share| {
+                    let mut candidate = self.clone();
+                    let found = &found;
+                    scope.spawn(move || {
+                        for nonce in share.nonces() {
+                            if found.load(Ordering::Relaxed) {
+                                break;
+                            }
+                            candidate.header.nonce = nonce;
+                            let hash = candidate.calculate_hash();
+                            if Self::meets_target(&hash, candidate.header.difficulty) {
+                                found.store(true, Ordering::Relaxed);
+                                return Some((nonce, hash));
+                            }
+                        }
+                        None
+                    })
+                })
+                .collect();
+
+            workers.into_iter()
+                .find_map(|worker| worker.join().expect("mining thread panicked"))
+        });
+
+        match solution {
+            Some((nonce, hash)) => {
+                self.header.nonce = nonce;
+                self.hash = hash;
+                true
+            }
+            None => false,
         }
+    }
 
-        // Then verify the proof of work
-        let target = (1u128 << (128 - self.header.difficulty as u128)) - 1;
-        let hash_bytes = self.hash.to_bytes();
-        let mut value = 0u128;
-        
-        for i in 0..16 {
-            value = (value << 8) | hash_bytes[i] as u128;
+    /// Checks that the stored hash and merkle root match the block contents,
+    /// without checking proof of work or transaction signatures.
+    pub fn verify_linkage(&self) -> bool {
+        if self.calculate_hash() != self.hash {
+            return false;
         }
-        
-        // Finally verify the merkle root matches the transactions
-        let merkle_root = Self::calculate_merkle_root(&self.transactions);
-        if merkle_root != self.header.merkle_root {
+
+        Self::calculate_merkle_root(&self.transactions) == self.header.merkle_root
+    }
+
+    pub fn verify(&self) -> bool {
+        // First verify the hash and merkle root match the block contents
+        if !self.verify_linkage() {
             return false;
         }
-        
-        value <= target
+
+        // Then verify the proof of work
+        Self::meets_target(&self.hash, self.header.difficulty)
     }
 }
 
@@ -233,6 +372,48 @@ mod tests {
         assert!(block.verify());
     }
 
+    #[test]
+    fn test_parallel_mining() {
+        let mut block = Block::new(1, Hash::new(b"prev"), vec![create_test_transaction()], 8);
+        assert!(block.mine_parallel(4));
+        assert!(block.verify());
+
+        // A single thread finds the same nonce as serial mining
+        let mut serial = Block::new(1, Hash::new(b"prev"), vec![create_test_transaction()], 8);
+        let mut parallel = serial.clone();
+        assert!(serial.mine());
+        assert!(parallel.mine_parallel(1));
+        assert_eq!(parallel.header.nonce, serial.header.nonce);
+        assert_eq!(parallel.hash, serial.hash);
+    }
+
+    #[test]
+    fn test_nonce_partitions_disjoint() {
+        use std::collections::HashSet;
+
+        // Two miners of a pool, each mining on three threads
+        let miners = [NoncePartition::for_miner(0, 2), NoncePartition::for_miner(1, 2)];
+        let mut tested = HashSet::new();
+        for miner in miners {
+            let threads = miner.split(3);
+            assert_eq!(threads.len(), 3);
+            for thread in threads {
+                for nonce in thread.nonces().take(1000) {
+                    assert_eq!(nonce % 2, miner.offset);
+                    assert!(tested.insert(nonce), "nonce {} tested twice", nonce);
+                }
+            }
+        }
+
+        // Between them they test every nonce, with no gaps
+        assert!((0..6000).all(|nonce| tested.contains(&nonce)));
+
+        let mut block = Block::new(1, Hash::new(b"prev"), vec![create_test_transaction()], 8);
+        assert!(block.mine_partition(miners[1], 3));
+        assert_eq!(block.header.nonce % 2, 1);
+        assert!(block.verify());
+    }
+
     #[test]
     fn test_merkle_root() {
         let tx1 = create_test_transaction();
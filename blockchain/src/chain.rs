@@ -0,0 +1,178 @@
+use crate::block::BlockHeader;
+use crate::crypto::Hash;
+use std::collections::{HashMap, HashSet};
+
+/// Selects the canonical tip among competing branches.
+///
+/// Headers are given together with their block hash, since the block hash
+/// also commits to the transactions and can't be derived from the header.
+pub struct ForkChoice;
+
+impl ForkChoice {
+    /// Return the tip of the chain with the most cumulative difficulty.
+    ///
+    /// Headers whose parent isn't in the set are treated as roots. Among tips
+    /// with equal work the one with the earliest timestamp wins, then the
+    /// lexicographically smallest block hash, so every node picks the same
+    /// tip whatever order it saw the blocks in.
+    pub fn best_tip(headers: &[(Hash, BlockHeader)]) -> Option<Hash> {
+        let by_hash: HashMap<&Hash, &BlockHeader> = headers.iter()
+            .map(|(hash, header)| (hash, header))
+            .collect();
+        let parents: HashSet<&Hash> = headers.iter()
+            .map(|(_, header)| &header.prev_hash)
+            .collect();
+
+        let mut work: HashMap<&Hash, u128> = HashMap::new();
+        let mut best: Option<(&Hash, u64, u128)> = None;
+
+        for (hash, header) in headers {
+            // Only blocks without children can be tips
+            if parents.contains(hash) {
+                continue;
+            }
+
+            let total = Self::cumulative_work(hash, &by_hash, &mut work);
+            let better = match best {
+                None => true,
+                Some((best_hash, best_timestamp, best_work)) => {
+                    (total, std::cmp::Reverse(header.timestamp), std::cmp::Reverse(hash.to_bytes()))
+                        > (best_work, std::cmp::Reverse(best_timestamp), std::cmp::Reverse(best_hash.to_bytes()))
+                }
+            };
+            if better {
+                best = Some((hash, header.timestamp, total));
+            }
+        }
+
+        best.map(|(hash, _, _)| hash.clone())
+    }
+
+    /// Sum of difficulty from `tip` back to the first ancestor outside the set
+    fn cumulative_work<'a>(
+        tip: &'a Hash,
+        by_hash: &HashMap<&'a Hash, &'a BlockHeader>,
+        work: &mut HashMap<&'a Hash, u128>,
+    ) -> u128 {
+        // Walk back until a known total or a root, then fill in on the way down
+        let mut path = Vec::new();
+        let mut current = tip;
+        let mut base = 0u128;
+        while let Some(header) = by_hash.get(current) {
+            if let Some(known) = work.get(current) {
+                base = *known;
+                break;
+            }
+            path.push((current, header.difficulty));
+            current = &header.prev_hash;
+            // A cycle can only come from corrupt input; stop rather than loop
+            if path.len() > by_hash.len() {
+                break;
+            }
+        }
+
+        for (hash, difficulty) in path.into_iter().rev() {
+            base += difficulty as u128;
+            work.insert(hash, base);
+        }
+        base
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(prev_hash: &Hash, difficulty: u32, timestamp: u64) -> BlockHeader {
+        BlockHeader {
+            prev_hash: prev_hash.clone(),
+            difficulty,
+            timestamp,
+            ..BlockHeader::default()
+        }
+    }
+
+    #[test]
+    fn test_heaviest_branch_wins() {
+        let genesis = Hash::new(b"genesis");
+        let a1 = Hash::new(b"a1");
+        let a2 = Hash::new(b"a2");
+        let a3 = Hash::new(b"a3");
+        let b1 = Hash::new(b"b1");
+        let b2 = Hash::new(b"b2");
+
+        // Branch a is longer, branch b has more total difficulty
+        let headers = vec![
+            (genesis.clone(), header(&Hash::new(&[0u8; 32]), 1, 100)),
+            (a1.clone(), header(&genesis, 2, 110)),
+            (a2.clone(), header(&a1, 2, 120)),
+            (a3.clone(), header(&a2, 2, 130)),
+            (b1.clone(), header(&genesis, 4, 111)),
+            (b2.clone(), header(&b1, 4, 121)),
+        ];
+
+        assert_eq!(ForkChoice::best_tip(&headers), Some(b2));
+        assert_eq!(ForkChoice::best_tip(&headers[..4]), Some(a3));
+        assert_eq!(ForkChoice::best_tip(&[]), None);
+    }
+
+    #[test]
+    fn test_equal_work_tie_break() {
+        let genesis = Hash::new(b"genesis");
+        let a = Hash::new(b"a");
+        let b = Hash::new(b"b");
+        let c = Hash::new(b"c");
+
+        // Three equal-work tips with the same timestamp
+        let headers = vec![
+            (genesis.clone(), header(&Hash::new(&[0u8; 32]), 1, 100)),
+            (a.clone(), header(&genesis, 3, 110)),
+            (b.clone(), header(&genesis, 3, 110)),
+            (c.clone(), header(&genesis, 3, 110)),
+        ];
+        let lowest = [&a, &b, &c].into_iter()
+            .min_by(|x, y| x.to_bytes().cmp(y.to_bytes()))
+            .cloned();
+
+        // Every node picks the lowest hash, whatever order it received the blocks in
+        let tips = &headers[1..];
+        for first in 0..tips.len() {
+            for second in 0..tips.len() {
+                if second == first {
+                    continue;
+                }
+                let third = 3 - first - second;
+                let node_view = vec![
+                    tips[first].clone(),
+                    headers[0].clone(),
+                    tips[second].clone(),
+                    tips[third].clone(),
+                ];
+                assert_eq!(ForkChoice::best_tip(&node_view), lowest);
+            }
+        }
+    }
+
+    #[test]
+    fn test_equal_work_earliest_timestamp_wins() {
+        let genesis = Hash::new(b"genesis");
+        let (a, b) = (Hash::new(b"a"), Hash::new(b"b"));
+        let (lower, higher) = if a.to_bytes() < b.to_bytes() { (a, b) } else { (b, a) };
+
+        // The tip with the lower hash was timestamped later
+        let headers = vec![
+            (genesis.clone(), header(&Hash::new(&[0u8; 32]), 1, 100)),
+            (lower.clone(), header(&genesis, 3, 120)),
+            (higher.clone(), header(&genesis, 3, 110)),
+        ];
+        assert_eq!(ForkChoice::best_tip(&headers), Some(higher.clone()));
+
+        // More work still outweighs an earlier timestamp
+        let heavier = vec![
+            headers[0].clone(),
+            (lower.clone(), header(&genesis, 4, 120)),
+            headers[2].clone(),
+        ];
+        assert_eq!(ForkChoice::best_tip(&heavier), Some(lower));
+    }
+}
@@ -1,8 +1,11 @@
 use crate::block::{Block, BlockHeader};
 use crate::mempool::Mempool;
 use crate::transaction::Transaction;
-use crate::crypto::Hash;
+use crate::crypto::{Hash, KeyPair};
+use crate::params::ChainParams;
+pub use crate::params::{HALVING_INTERVAL, INITIAL_BLOCK_REWARD, MAX_FUTURE_BLOCK_TIME};
 use futures::future::join_all;
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 use tokio::sync::mpsc;
@@ -28,25 +31,227 @@ impl fmt::Display for ConsensusError {
 
 impl Error for ConsensusError {}
 
+/// How strictly a consensus engine validates incoming blocks.
+///
+/// Block linkage (hash and merkle root) and the engine's own consensus rules
+/// are always checked; the levels only control transaction signature checks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ValidationLevel {
+    /// Verify every transaction signature.
+    #[default]
+    Full,
+    /// Skip transaction signature verification entirely.
+    HeadersOnly,
+    /// Skip transaction signature verification for blocks below a trusted height.
+    SkipSignaturesBelow(u64),
+}
+
+impl ValidationLevel {
+    /// Whether signatures must be verified for a block at `height`. Blocks of
+    /// unknown height are never treated as trusted.
+    pub fn verifies_signatures(&self, height: Option<u64>) -> bool {
+        match self {
+            ValidationLevel::Full => true,
+            ValidationLevel::HeadersOnly => false,
+            ValidationLevel::SkipSignaturesBelow(trusted) => match height {
+                Some(height) => height >= *trusted,
+                None => true,
+            },
+        }
+    }
+}
+
+/// Check that `block` extends `parent`: it must reference the parent's hash,
+/// be timestamped at least `params.min_block_interval` after it, and not be
+/// dated further in the future than `params` allow.
+pub fn validate_parent_linkage(block: &Block, parent: &Block, params: &ChainParams) -> Result<(), ConsensusError> {
+    if block.header.prev_hash != parent.hash {
+        return Err(ConsensusError::ValidationError(format!(
+            "Previous hash {} doesn't match parent {}", block.header.prev_hash, parent.hash
+        )));
+    }
+
+    // Difficulty retargeting assumes blocks don't come faster than this
+    let min_interval = params.min_block_interval.max(1);
+    if block.header.timestamp < parent.header.timestamp.saturating_add(min_interval) {
+        return Err(ConsensusError::ValidationError(format!(
+            "Block timestamp {} is less than {}s after parent timestamp {}",
+            block.header.timestamp, min_interval, parent.header.timestamp
+        )));
+    }
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    if block.header.timestamp > now.saturating_add(params.max_future_block_time) {
+        return Err(ConsensusError::ValidationError(format!(
+            "Block timestamp {} is too far in the future", block.header.timestamp
+        )));
+    }
+
+    Ok(())
+}
+
+/// Mainnet reward for the block at `height`, see `ChainParams::block_reward`
+pub fn block_reward(height: u64) -> u64 {
+    ChainParams::mainnet().block_reward(height)
+}
+
+/// Total fee paid by `transactions`, valuing their inputs through
+/// `mempool`. None if the total overflows.
+async fn collect_fees(mempool: &Mempool, transactions: &[Transaction]) -> Option<u64> {
+    let mut fees = 0u64;
+    for tx in transactions {
+        fees = fees.checked_add(mempool.fee(tx).await?)?;
+    }
+    Some(fees)
+}
+
+/// Build the coinbase paying the block reward plus the fees of `transactions`
+pub(crate) async fn create_coinbase(
+    mempool: &Mempool,
+    transactions: &[Transaction],
+    params: &ChainParams,
+    height: u64,
+    miner_address: &[u8],
+) -> Result<Transaction, ConsensusError> {
+    let amount = collect_fees(mempool, transactions).await
+        .and_then(|fees| params.block_reward(height).checked_add(fees))
+        .ok_or_else(|| ConsensusError::BlockCreationError("Coinbase amount overflows".into()))?;
+    Ok(Transaction::coinbase(miner_address.to_vec(), amount))
+}
+
+/// Check that only the first transaction of `block` is a coinbase, i.e. has
+/// no inputs. Blocks without a coinbase are allowed.
+pub fn validate_coinbase_placement(block: &Block) -> Result<(), ConsensusError> {
+    if let Some(index) = block.transactions.iter().skip(1).position(Transaction::is_coinbase) {
+        return Err(ConsensusError::ValidationError(format!(
+            "Transaction {} has no inputs, only the first transaction may be a coinbase", index + 1
+        )));
+    }
+    Ok(())
+}
+
+/// Check the coinbase of `block` at `height`: it must be the first
+/// transaction, and it may pay out at most the block reward plus `fees`,
+/// the fees of the block's other transactions
+pub fn validate_coinbase(block: &Block, params: &ChainParams, height: u64, fees: u64) -> Result<(), ConsensusError> {
+    validate_coinbase_placement(block)?;
+
+    let Some(coinbase) = block.transactions.first().filter(|tx| tx.is_coinbase()) else {
+        return Ok(());
+    };
+
+    let paid = coinbase.outputs.iter()
+        .try_fold(0u64, |total, output| total.checked_add(output.amount))
+        .ok_or_else(|| ConsensusError::ValidationError("Coinbase outputs overflow".into()))?;
+    let allowed = params.block_reward(height).checked_add(fees)
+        .ok_or_else(|| ConsensusError::ValidationError("Block reward plus fees overflows".into()))?;
+    if paid > allowed {
+        return Err(ConsensusError::ValidationError(format!(
+            "Coinbase pays {}, more than the block reward plus fees of {}", paid, allowed
+        )));
+    }
+
+    Ok(())
+}
+
 #[async_trait::async_trait]
 pub trait ConsensusEngine: Send + Sync {
     async fn validate_block(&self, block: &Block) -> Result<bool, ConsensusError>;
+    async fn validate_block_at_height(&self, block: &Block, height: u64) -> Result<bool, ConsensusError>;
+
+    /// Validate a block together with its link to the parent block. The parent
+    /// is passed as a full block since block hashes also cover transactions.
+    async fn validate_block_with_parent(&self, block: &Block, parent: &Block) -> Result<bool, ConsensusError> {
+        validate_parent_linkage(block, parent, self.chain_params())?;
+        self.validate_block(block).await
+    }
+
+    /// Validate the coinbase of a block at `height`, working out the fees
+    /// it may collect from the input values `mempool` knows of
+    async fn validate_block_coinbase(&self, block: &Block, height: u64, mempool: &Mempool) -> Result<(), ConsensusError> {
+        let transactions = match block.transactions.first() {
+            Some(first) if first.is_coinbase() => &block.transactions[1..],
+            _ => &block.transactions[..],
+        };
+        let fees = collect_fees(mempool, transactions).await
+            .ok_or_else(|| ConsensusError::ValidationError("Block fees overflow".into()))?;
+        validate_coinbase(block, self.chain_params(), height, fees)
+    }
+
     async fn create_block(&self, mempool: &Mempool) -> Result<Block, ConsensusError>;
     async fn process_new_block(&self, block: Block) -> Result<(), ConsensusError>;
     fn get_difficulty(&self) -> u64;
+    fn validation_level(&self) -> ValidationLevel;
+    fn chain_params(&self) -> &ChainParams;
 }
 
 pub struct ProofOfWork {
     difficulty: u64,
-    max_block_size: usize,
+    params: ChainParams,
+    validation_level: ValidationLevel,
+    // Public key block rewards and fees are paid to; blocks can't be
+    // created until it is set
+    miner_address: Option<Vec<u8>>,
+    next_height: u64,
 }
 
 impl ProofOfWork {
     pub fn new(difficulty: u64) -> Self {
+        let params = ChainParams::default();
         ProofOfWork {
             difficulty,
-            max_block_size: 1000, // Maximum transactions per block
+            params,
+            validation_level: ValidationLevel::Full,
+            miner_address: None,
+            next_height: 0,
+        }
+    }
+
+    /// Create an engine for the network described by `params`, starting at
+    /// its genesis difficulty
+    pub fn with_params(params: ChainParams) -> Self {
+        let mut pow = Self::new(params.genesis_difficulty as u64);
+        pow.params = params;
+        pow
+    }
+
+    pub fn set_validation_level(&mut self, level: ValidationLevel) {
+        self.validation_level = level;
+    }
+
+    /// Set the public key that block rewards and fees are paid to
+    pub fn set_miner_address(&mut self, address: Vec<u8>) {
+        self.miner_address = Some(address);
+    }
+
+    /// Set the height of the next block, which determines its reward
+    pub fn set_next_height(&mut self, height: u64) {
+        self.next_height = height;
+    }
+
+    async fn validate_with_level(&self, block: &Block, height: Option<u64>) -> Result<bool, ConsensusError> {
+        // Verify block hash and merkle root match the block contents
+        if !block.verify_linkage() {
+            return Err(ConsensusError::ValidationError("Block hash or merkle root doesn't match contents".into()));
+        }
+
+        // Verify block hash meets difficulty requirement
+        let hash = block.hash.to_bytes();
+        if !self.check_difficulty(hash, self.difficulty) {
+            return Err(ConsensusError::ValidationError("Block hash doesn't meet difficulty".into()));
         }
+
+        validate_coinbase_placement(block)?;
+
+        // Parallel transaction verification
+        if self.validation_level.verifies_signatures(height) {
+            self.verify_transactions_parallel(&block.transactions).await?;
+        }
+
+        Ok(true)
     }
 
     async fn verify_transactions_parallel(&self, transactions: &[Transaction]) -> Result<bool, ConsensusError> {
@@ -99,26 +304,27 @@ impl ProofOfWork {
 #[async_trait::async_trait]
 impl ConsensusEngine for ProofOfWork {
     async fn validate_block(&self, block: &Block) -> Result<bool, ConsensusError> {
-        // Verify block hash meets difficulty requirement
-        let hash = block.hash.to_bytes();
-        if !self.check_difficulty(hash, self.difficulty) {
-            return Err(ConsensusError::ValidationError("Block hash doesn't meet difficulty".into()));
-        }
-
-        // Parallel transaction verification
-        self.verify_transactions_parallel(&block.transactions).await?;
+        self.validate_with_level(block, None).await
+    }
 
-        Ok(true)
+    async fn validate_block_at_height(&self, block: &Block, height: u64) -> Result<bool, ConsensusError> {
+        self.validate_with_level(block, Some(height)).await
     }
 
     async fn create_block(&self, mempool: &Mempool) -> Result<Block, ConsensusError> {
         // Get pending transactions from mempool
-        let transactions = mempool.get_pending_transactions(self.max_block_size).await
+        let transactions = mempool.get_pending_transactions(self.params.max_block_size).await
             .map_err(|e| ConsensusError::BlockCreationError(e.to_string()))?;
 
         // Verify transactions in parallel
         self.verify_transactions_parallel(&transactions).await?;
 
+        // Pay the block reward and collected fees to the miner
+        let miner_address = self.miner_address.as_ref()
+            .ok_or_else(|| ConsensusError::BlockCreationError("No miner address configured".into()))?;
+        let coinbase = create_coinbase(mempool, &transactions, &self.params, self.next_height, miner_address).await?;
+        let transactions: Vec<_> = std::iter::once(coinbase).chain(transactions).collect();
+
         // Create new block
         let block = Block::new(
             1, // version
@@ -142,24 +348,202 @@ impl ConsensusEngine for ProofOfWork {
     fn get_difficulty(&self) -> u64 {
         self.difficulty
     }
+
+    fn validation_level(&self) -> ValidationLevel {
+        self.validation_level
+    }
+
+    fn chain_params(&self) -> &ChainParams {
+        &self.params
+    }
+}
+
+/// Validator identity, the same 32-byte address used for block proposers.
+pub type Address = [u8; 32];
+
+/// Validators eligible to propose blocks, keyed by address with their stake.
+#[derive(Debug, Clone, Default)]
+pub struct ValidatorSet {
+    stakes: HashMap<Address, u64>,
+}
+
+impl ValidatorSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a validator, or replace the stake of an existing one. Fails if
+    /// the total stake would no longer fit in a u64.
+    pub fn add_validator(&mut self, addr: Address, stake: u64) -> Result<(), ConsensusError> {
+        let others = self.total_stake() - self.stake_of(&addr).unwrap_or(0);
+        if others.checked_add(stake).is_none() {
+            return Err(ConsensusError::ValidationError(format!(
+                "Stake {} would overflow the total stake", stake
+            )));
+        }
+        self.stakes.insert(addr, stake);
+        Ok(())
+    }
+
+    pub fn remove_validator(&mut self, addr: &Address) -> Option<u64> {
+        self.stakes.remove(addr)
+    }
+
+    pub fn stake_of(&self, addr: &Address) -> Option<u64> {
+        self.stakes.get(addr).copied()
+    }
+
+    pub fn total_stake(&self) -> u64 {
+        self.stakes.values().fold(0u64, |total, stake| total.saturating_add(*stake))
+    }
+
+    pub fn len(&self) -> usize {
+        self.stakes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stakes.is_empty()
+    }
+
+    /// Pick a proposer for `seed` with probability proportional to stake.
+    /// Every node holding the same validator set selects the same proposer.
+    pub fn select_proposer(&self, seed: &Hash) -> Option<Address> {
+        let total = self.total_stake();
+        if total == 0 {
+            return None;
+        }
+
+        // Iterate in address order so the walk doesn't depend on map layout
+        let mut validators: Vec<_> = self.stakes.iter().filter(|(_, stake)| **stake > 0).collect();
+        validators.sort_by_key(|(addr, _)| **addr);
+
+        let digest = Hash::new(seed.to_bytes());
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&digest.to_bytes()[..8]);
+        let mut target = u64::from_le_bytes(bytes) % total;
+
+        for (addr, stake) in validators {
+            if target < *stake {
+                return Some(*addr);
+            }
+            target -= stake;
+        }
+
+        None
+    }
 }
 
 pub struct ProofOfStake {
     min_stake: u64,
-    max_block_size: usize,
+    params: ChainParams,
+    validation_level: ValidationLevel,
+    validators: ValidatorSet,
+    // Key this node proposes and signs blocks with
+    local_keypair: Option<KeyPair>,
+    // Block the next block builds on, whose hash seeds proposer selection
+    parent_hash: Option<Hash>,
+    // Public key block rewards and fees are paid to; blocks can't be
+    // created until it is set
+    miner_address: Option<Vec<u8>>,
+    next_height: u64,
 }
 
 impl ProofOfStake {
     pub fn new(min_stake: u64) -> Self {
         ProofOfStake {
             min_stake,
-            max_block_size: 1000,
+            params: ChainParams::default(),
+            validation_level: ValidationLevel::Full,
+            validators: ValidatorSet::new(),
+            local_keypair: None,
+            parent_hash: None,
+            miner_address: None,
+            next_height: 0,
+        }
+    }
+
+    /// Create an engine for the network described by `params`
+    pub fn with_params(min_stake: u64, params: ChainParams) -> Self {
+        let mut pos = Self::new(min_stake);
+        pos.params = params;
+        pos
+    }
+
+    pub fn set_validation_level(&mut self, level: ValidationLevel) {
+        self.validation_level = level;
+    }
+
+    /// Register a validator. Stakes below the minimum are rejected.
+    pub fn add_validator(&mut self, addr: Address, stake: u64) -> Result<(), ConsensusError> {
+        if !self.validate_stake(stake) {
+            return Err(ConsensusError::ValidationError(format!(
+                "Stake {} is below the minimum of {}", stake, self.min_stake
+            )));
+        }
+        self.validators.add_validator(addr, stake)
+    }
+
+    pub fn validators(&self) -> &ValidatorSet {
+        &self.validators
+    }
+
+    /// Set the key this node proposes blocks with. Its public key is the
+    /// validator address.
+    pub fn set_local_keypair(&mut self, keypair: KeyPair) {
+        self.local_keypair = Some(keypair);
+    }
+
+    /// Set the hash of the block the next block builds on
+    pub fn set_parent_hash(&mut self, hash: Hash) {
+        self.parent_hash = Some(hash);
+    }
+
+    /// Set the public key that block rewards and fees are paid to
+    pub fn set_miner_address(&mut self, address: Vec<u8>) {
+        self.miner_address = Some(address);
+    }
+
+    /// Set the height of the next block, which determines its reward
+    pub fn set_next_height(&mut self, height: u64) {
+        self.next_height = height;
+    }
+
+    async fn validate_with_level(&self, block: &Block, height: Option<u64>) -> Result<bool, ConsensusError> {
+        // Verify block hash and merkle root match the block contents
+        if !block.verify_linkage() {
+            return Err(ConsensusError::ValidationError("Block hash or merkle root doesn't match contents".into()));
+        }
+
+        // Verify PoS requirements
+        self.validate_pos(block).await?;
+        validate_coinbase_placement(block)?;
+
+        // Parallel transaction verification
+        if self.validation_level.verifies_signatures(height) {
+            self.verify_transactions_parallel(&block.transactions).await?;
         }
+
+        Ok(true)
     }
 
-    async fn validate_pos(&self, _block: &Block) -> Result<bool, ConsensusError> {
-        // Verify stake (placeholder implementation)
-        // In a real implementation, this would verify stake ownership and delegation
+    async fn validate_pos(&self, block: &Block) -> Result<bool, ConsensusError> {
+        // The proposer is selected from the parent hash, so it is fixed per height
+        let expected = self.validators.select_proposer(&block.header.prev_hash)
+            .ok_or_else(|| ConsensusError::ValidationError("No validators with stake".into()))?;
+
+        if block.header.proposer != expected {
+            return Err(ConsensusError::ValidationError(format!(
+                "Unexpected proposer {}, expected {}",
+                hex::encode(block.header.proposer),
+                hex::encode(expected)
+            )));
+        }
+
+        // Only the holder of the proposer's key can have proposed the block
+        if !block.verify_proposer_signature() {
+            return Err(ConsensusError::ValidationError("Missing or invalid proposer signature".into()));
+        }
+
         Ok(true)
     }
 
@@ -204,30 +588,47 @@ impl ProofOfStake {
 #[async_trait::async_trait]
 impl ConsensusEngine for ProofOfStake {
     async fn validate_block(&self, block: &Block) -> Result<bool, ConsensusError> {
-        // Verify PoS requirements
-        self.validate_pos(block).await?;
-
-        // Parallel transaction verification
-        self.verify_transactions_parallel(&block.transactions).await?;
+        self.validate_with_level(block, None).await
+    }
 
-        Ok(true)
+    async fn validate_block_at_height(&self, block: &Block, height: u64) -> Result<bool, ConsensusError> {
+        self.validate_with_level(block, Some(height)).await
     }
 
     async fn create_block(&self, mempool: &Mempool) -> Result<Block, ConsensusError> {
+        let prev_hash = self.parent_hash.clone()
+            .ok_or_else(|| ConsensusError::BlockCreationError("No parent block configured".into()))?;
+
+        // Only the selected proposer may create the next block
+        let keypair = self.local_keypair.as_ref()
+            .ok_or_else(|| ConsensusError::BlockCreationError("No local validator configured".into()))?;
+        let proposer = self.validators.select_proposer(&prev_hash)
+            .ok_or_else(|| ConsensusError::BlockCreationError("No validators with stake".into()))?;
+        if proposer != keypair.public_key().to_bytes() {
+            return Err(ConsensusError::BlockCreationError("Local validator is not the selected proposer".into()));
+        }
+
         // Get pending transactions from mempool
-        let transactions = mempool.get_pending_transactions(self.max_block_size).await
+        let transactions = mempool.get_pending_transactions(self.params.max_block_size).await
             .map_err(|e| ConsensusError::BlockCreationError(e.to_string()))?;
 
         // Verify transactions in parallel
         self.verify_transactions_parallel(&transactions).await?;
 
+        // Pay the block reward and collected fees to the proposer
+        let miner_address = self.miner_address.as_ref()
+            .ok_or_else(|| ConsensusError::BlockCreationError("No miner address configured".into()))?;
+        let coinbase = create_coinbase(mempool, &transactions, &self.params, self.next_height, miner_address).await?;
+        let transactions: Vec<_> = std::iter::once(coinbase).chain(transactions).collect();
+
         // Create new block
-        let block = Block::new(
+        let mut block = Block::new(
             1, // version
-            Hash::new(&[0u8; 32]), // prev_hash (should be fetched from chain state)
+            prev_hash,
             transactions,
             1, // difficulty (less relevant for PoS)
         );
+        block.sign_as_proposer(keypair);
 
         Ok(block)
     }
@@ -245,21 +646,48 @@ impl ConsensusEngine for ProofOfStake {
         // In PoS, difficulty is determined by stake amount
         self.min_stake
     }
+
+    fn validation_level(&self) -> ValidationLevel {
+        self.validation_level
+    }
+
+    fn chain_params(&self) -> &ChainParams {
+        &self.params
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::transaction::{TransactionInput, TransactionOutput};
+
+    fn create_mined_block_with_unsigned_tx(difficulty: u32) -> Block {
+        let tx = Transaction::new(
+            vec![TransactionInput {
+                tx_hash: Hash::new(b"previous_tx"),
+                output_index: 0,
+                signature: None,
+            }],
+            vec![TransactionOutput {
+                amount: 50,
+                recipient: vec![1, 2, 3, 4],
+            }],
+        );
+        let mut block = Block::new(1, Hash::new(&[0u8; 32]), vec![tx], difficulty);
+        assert!(block.mine());
+        block
+    }
 
     #[tokio::test]
     async fn test_pow_validation() {
         let pow = ProofOfWork::new(1); // Low difficulty for testing
-        let block = Block::new(
+        let mut block = Block::new(
             1,
             Hash::new(&[0u8; 32]),
             vec![],
             1,
         );
+        assert!(block.mine());
 
         // Test validation
         let result = pow.validate_block(&block).await.unwrap();
@@ -268,21 +696,355 @@ mod tests {
 
     #[tokio::test]
     async fn test_pos_validation() {
-        let pos = ProofOfStake::new(1000);
+        let mut pos = ProofOfStake::new(1000);
         
         // Test stake validation
         assert!(pos.validate_stake(1500));
         assert!(!pos.validate_stake(500));
 
-        let block = Block::new(
+        let validator = KeyPair::generate();
+        pos.add_validator(validator.public_key().to_bytes(), 1500).unwrap();
+        assert!(pos.add_validator([8u8; 32], 500).is_err());
+
+        let mut block = Block::new(
             1,
             Hash::new(&[0u8; 32]),
             vec![],
             1,
         );
+        block.sign_as_proposer(&validator);
 
         // Test block validation
         let result = pos.validate_block(&block).await.unwrap();
         assert!(result);
     }
+
+    #[tokio::test]
+    async fn test_pos_requires_proposer_signature() {
+        let mut pos = ProofOfStake::new(100);
+        let validator = KeyPair::generate();
+        pos.add_validator(validator.public_key().to_bytes(), 500).unwrap();
+
+        // Naming the right proposer isn't enough without its signature
+        let mut block = Block::new(1, Hash::new(b"parent"), vec![], 1);
+        block.set_proposer(validator.public_key().to_bytes());
+        assert!(pos.validate_block(&block).await.is_err());
+
+        // Nor is a signature by another key
+        let impostor = KeyPair::generate();
+        block.header.proposer_signature = Some(impostor.sign(block.hash.to_bytes()));
+        assert!(pos.validate_block(&block).await.is_err());
+
+        block.sign_as_proposer(&validator);
+        assert!(pos.validate_block(&block).await.unwrap());
+
+        // The signature covers the header, so changing it afterwards is caught
+        let mut tampered = block.clone();
+        tampered.header.timestamp += 1;
+        tampered.hash = tampered.calculate_hash();
+        assert!(pos.validate_block(&tampered).await.is_err());
+    }
+
+    #[test]
+    fn test_total_stake_cannot_overflow() {
+        let mut validators = ValidatorSet::new();
+        validators.add_validator([1u8; 32], u64::MAX - 10).unwrap();
+        assert!(validators.add_validator([2u8; 32], 11).is_err());
+        validators.add_validator([2u8; 32], 10).unwrap();
+        assert_eq!(validators.total_stake(), u64::MAX);
+
+        // Replacing a stake only counts the new amount
+        validators.add_validator([1u8; 32], 5).unwrap();
+        assert_eq!(validators.total_stake(), 15);
+    }
+
+    #[test]
+    fn test_stake_weighted_selection() {
+        let heavy = [1u8; 32];
+        let light = [2u8; 32];
+        let mut validators = ValidatorSet::new();
+        validators.add_validator(heavy, 900).unwrap();
+        validators.add_validator(light, 100).unwrap();
+
+        let mut heavy_count = 0;
+        for i in 0u32..1000 {
+            let seed = Hash::new(&i.to_le_bytes());
+            let proposer = validators.select_proposer(&seed).unwrap();
+            // Selection is deterministic for a given seed
+            assert_eq!(validators.select_proposer(&seed), Some(proposer));
+            if proposer == heavy {
+                heavy_count += 1;
+            }
+        }
+
+        assert!(heavy_count > 800, "heavy validator selected {} times", heavy_count);
+        assert!(heavy_count < 1000, "light validator never selected");
+        assert_eq!(ValidatorSet::new().select_proposer(&Hash::new(b"seed")), None);
+    }
+
+    #[tokio::test]
+    async fn test_unexpected_proposer_rejected() {
+        let mut pos = ProofOfStake::new(100);
+        let keys = [KeyPair::generate(), KeyPair::generate()];
+        for key in &keys {
+            pos.add_validator(key.public_key().to_bytes(), 500).unwrap();
+        }
+
+        let prev_hash = Hash::new(&[0u8; 32]);
+        let expected = pos.validators().select_proposer(&prev_hash).unwrap();
+        let (expected_key, other_key) = if keys[0].public_key().to_bytes() == expected {
+            (&keys[0], &keys[1])
+        } else {
+            (&keys[1], &keys[0])
+        };
+
+        let mut block = Block::new(1, prev_hash, vec![], 1);
+        block.sign_as_proposer(other_key);
+        assert!(pos.validate_block(&block).await.is_err());
+
+        block.sign_as_proposer(expected_key);
+        assert!(pos.validate_block(&block).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_create_block_requires_selected_proposer() {
+        let mut pos = ProofOfStake::new(100);
+        let seeds = [[1u8; 32], [2u8; 32]];
+        for seed in &seeds {
+            let key = KeyPair::from_seed(seed).unwrap();
+            pos.add_validator(key.public_key().to_bytes(), 500).unwrap();
+        }
+        let mempool = Mempool::new(10);
+        pos.set_miner_address(vec![1u8; 32]);
+
+        // Blocks can't be proposed before the parent is known
+        let parent = Hash::new(b"parent");
+        let expected = pos.validators().select_proposer(&parent).unwrap();
+        let (expected_seed, other_seed) = if KeyPair::from_seed(&seeds[0]).unwrap().public_key().to_bytes() == expected {
+            (&seeds[0], &seeds[1])
+        } else {
+            (&seeds[1], &seeds[0])
+        };
+        pos.set_local_keypair(KeyPair::from_seed(expected_seed).unwrap());
+        assert!(pos.create_block(&mempool).await.is_err());
+        pos.set_parent_hash(parent.clone());
+
+        pos.set_local_keypair(KeyPair::from_seed(other_seed).unwrap());
+        assert!(pos.create_block(&mempool).await.is_err());
+
+        pos.set_local_keypair(KeyPair::from_seed(expected_seed).unwrap());
+        let block = pos.create_block(&mempool).await.unwrap();
+        assert_eq!(block.header.prev_hash, parent);
+        assert_eq!(block.header.proposer, expected);
+        assert!(pos.validate_block(&block).await.unwrap());
+    }
+
+    async fn mempool_with_fees(fees: &[u64]) -> Mempool {
+        let mempool = Mempool::new(100);
+        let keypair = KeyPair::generate();
+        for (i, fee) in fees.iter().enumerate() {
+            let prev_hash = Hash::new(format!("funding_{}", i).as_bytes());
+            mempool.add_utxo(prev_hash.clone(), 0, 1_000).await;
+            let mut tx = Transaction::new(
+                vec![TransactionInput {
+                    tx_hash: prev_hash,
+                    output_index: 0,
+                    signature: None,
+                }],
+                vec![TransactionOutput {
+                    amount: 1_000 - fee,
+                    recipient: vec![1, 2, 3, 4],
+                }],
+            );
+            tx.sign(&keypair, 0).unwrap();
+            mempool.add_transaction(tx, vec![keypair.public_key().as_bytes().to_vec()]).await.unwrap();
+        }
+        mempool
+    }
+
+    #[test]
+    fn test_block_reward_halving() {
+        assert_eq!(block_reward(0), INITIAL_BLOCK_REWARD);
+        assert_eq!(block_reward(HALVING_INTERVAL - 1), INITIAL_BLOCK_REWARD);
+        assert_eq!(block_reward(HALVING_INTERVAL), INITIAL_BLOCK_REWARD / 2);
+        assert_eq!(block_reward(HALVING_INTERVAL * 2), INITIAL_BLOCK_REWARD / 4);
+        assert_eq!(block_reward(HALVING_INTERVAL * 64), 0);
+        assert_eq!(block_reward(u64::MAX), 0);
+    }
+
+    #[tokio::test]
+    async fn test_pow_coinbase_collects_fees() {
+        let mempool = mempool_with_fees(&[10, 20, 30]).await;
+        let mut pow = ProofOfWork::new(1);
+        pow.set_miner_address(vec![7, 7, 7]);
+        pow.set_next_height(HALVING_INTERVAL);
+
+        let block = pow.create_block(&mempool).await.unwrap();
+        assert_eq!(block.transactions.len(), 4);
+
+        let coinbase = &block.transactions[0];
+        assert!(coinbase.is_coinbase());
+        assert_eq!(coinbase.outputs[0].recipient, vec![7, 7, 7]);
+        assert_eq!(coinbase.outputs[0].amount, block_reward(HALVING_INTERVAL) + 60);
+    }
+
+    #[tokio::test]
+    async fn test_pos_coinbase_collects_fees() {
+        let mempool = mempool_with_fees(&[5, 15]).await;
+        let mut pos = ProofOfStake::new(100);
+        let validator = KeyPair::generate();
+        pos.add_validator(validator.public_key().to_bytes(), 500).unwrap();
+        pos.set_local_keypair(validator);
+        pos.set_parent_hash(Hash::new(b"parent"));
+        pos.set_miner_address(vec![1u8; 32]);
+
+        let block = pos.create_block(&mempool).await.unwrap();
+        let coinbase = &block.transactions[0];
+        assert!(coinbase.is_coinbase());
+        assert_eq!(coinbase.outputs[0].amount, block_reward(0) + 20);
+        assert!(block.transactions[1..].iter().all(|tx| !tx.is_coinbase()));
+    }
+
+    #[tokio::test]
+    async fn test_create_block_requires_miner_address() {
+        let mempool = Mempool::new(10);
+        let mut pow = ProofOfWork::new(1);
+        assert!(pow.create_block(&mempool).await.is_err());
+
+        pow.set_miner_address(vec![7, 7, 7]);
+        assert!(pow.create_block(&mempool).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_coinbase_validation() {
+        let mempool = mempool_with_fees(&[10, 20]).await;
+        let mut pow = ProofOfWork::new(1);
+        pow.set_miner_address(vec![7, 7, 7]);
+        let block = pow.create_block(&mempool).await.unwrap();
+        assert!(pow.validate_block_coinbase(&block, 0, &mempool).await.is_ok());
+
+        // The coinbase can't claim more than the reward plus fees
+        let mut overpaying = block.clone();
+        overpaying.transactions[0] = Transaction::coinbase(vec![7, 7, 7], block_reward(0) + 31);
+        assert!(pow.validate_block_coinbase(&overpaying, 0, &mempool).await.is_err());
+        // The reward halves with height
+        assert!(pow.validate_block_coinbase(&block, HALVING_INTERVAL, &mempool).await.is_err());
+
+        // Only the first transaction may lack inputs
+        let mut misplaced = Block::new(1, Hash::new(&[0u8; 32]), vec![
+            block.transactions[1].clone(),
+            Transaction::coinbase(vec![7, 7, 7], 1),
+        ], 1);
+        assert!(misplaced.mine());
+        assert!(validate_coinbase_placement(&misplaced).is_err());
+        assert!(pow.validate_block(&misplaced).await.is_err());
+    }
+
+    fn mined_child(parent: &Block, timestamp: u64) -> Block {
+        let mut block = Block::new(1, parent.hash.clone(), vec![], 1);
+        block.header.timestamp = timestamp;
+        assert!(block.mine());
+        block
+    }
+
+    #[tokio::test]
+    async fn test_validate_with_parent() {
+        let pow = ProofOfWork::new(1);
+        let parent = Block::genesis();
+
+        // Correctly linked block
+        let block = mined_child(&parent, parent.header.timestamp + 10);
+        assert!(pow.validate_block_with_parent(&block, &parent).await.unwrap());
+
+        // The parent must be the block referenced by prev_hash
+        let other_parent = mined_child(&parent, parent.header.timestamp + 5);
+        assert!(pow.validate_block_with_parent(&block, &other_parent).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_with_parent_rejects_bad_timestamps() {
+        let pow = ProofOfWork::new(1);
+        let parent = Block::genesis();
+
+        // Dated at or before the parent
+        let same_time = mined_child(&parent, parent.header.timestamp);
+        assert!(pow.validate_block_with_parent(&same_time, &parent).await.is_err());
+        let past = mined_child(&parent, parent.header.timestamp - 1);
+        assert!(pow.validate_block_with_parent(&past, &parent).await.is_err());
+
+        // Dated too far in the future
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let future = mined_child(&parent, now + MAX_FUTURE_BLOCK_TIME + 60);
+        assert!(pow.validate_block_with_parent(&future, &parent).await.is_err());
+        let near_future = mined_child(&parent, now + 60);
+        assert!(pow.validate_block_with_parent(&near_future, &parent).await.unwrap());
+    }
+
+    #[test]
+    fn test_parent_linkage_unbounded_future_time() {
+        // A limit this large must not overflow when added to the current time
+        let params = ChainParams {
+            max_future_block_time: u64::MAX,
+            ..ChainParams::mainnet()
+        };
+        let parent = Block::genesis();
+        let far_future = mined_child(&parent, u64::MAX);
+        assert!(validate_parent_linkage(&far_future, &parent, &params).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_validate_with_parent_enforces_min_interval() {
+        let pow = ProofOfWork::with_params(ChainParams {
+            min_block_interval: 30,
+            ..ChainParams::mainnet()
+        });
+        let parent = Block::genesis();
+
+        let too_soon = mined_child(&parent, parent.header.timestamp + 29);
+        assert!(pow.validate_block_with_parent(&too_soon, &parent).await.is_err());
+
+        let on_time = mined_child(&parent, parent.header.timestamp + 30);
+        assert!(pow.validate_block_with_parent(&on_time, &parent).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_headers_only_skips_signatures() {
+        let block = create_mined_block_with_unsigned_tx(4);
+
+        // Full validation rejects the unsigned transaction
+        let mut pow = ProofOfWork::new(4);
+        assert!(pow.validate_block(&block).await.is_err());
+
+        // Headers-only validation still accepts valid PoW and linkage
+        pow.set_validation_level(ValidationLevel::HeadersOnly);
+        assert!(pow.validate_block(&block).await.unwrap());
+
+        // Tampering with the header breaks linkage
+        let mut tampered = block.clone();
+        tampered.header.nonce += 1;
+        assert!(pow.validate_block(&tampered).await.is_err());
+
+        // Blocks that don't meet the difficulty are still rejected
+        let strict_pow = {
+            let mut pow = ProofOfWork::new(64);
+            pow.set_validation_level(ValidationLevel::HeadersOnly);
+            pow
+        };
+        assert!(strict_pow.validate_block(&block).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_skip_signatures_below_height() {
+        let block = create_mined_block_with_unsigned_tx(4);
+        let mut pow = ProofOfWork::new(4);
+        pow.set_validation_level(ValidationLevel::SkipSignaturesBelow(100));
+
+        assert!(pow.validate_block_at_height(&block, 50).await.unwrap());
+        assert!(pow.validate_block_at_height(&block, 100).await.is_err());
+        // Blocks of unknown height are fully validated
+        assert!(pow.validate_block(&block).await.is_err());
+    }
 }
@@ -1,7 +1,8 @@
 use super::standards::{ContractResult, ContractError};
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
+use crate::crypto::is_zero_address;
 use crate::msg;
 
 /// Role-based access control event
@@ -70,6 +71,13 @@ impl AccessControl {
         let is_first_admin = role == DEFAULT_ADMIN_ROLE && 
             !self.roles.contains_key(&DEFAULT_ADMIN_ROLE);
 
+        // The zero address is reserved, only the admin bootstrap may name it
+        if !is_first_admin && is_zero_address(&account) {
+            return Err(ContractError::InvalidArguments(
+                "Cannot grant a role to the zero address".into()
+            ));
+        }
+
         if !is_first_admin {
             // Check if sender has admin role
             let admin_role = self.get_role_admin(role);
@@ -131,6 +139,52 @@ impl AccessControl {
         Ok(true)
     }
 
+    /// Accounts currently holding a role, in ascending order
+    pub fn get_role_members(&self, role: [u8; 32]) -> Vec<[u8; 32]> {
+        let mut members: Vec<[u8; 32]> = self.roles
+            .get(&role)
+            .map(|accounts| {
+                accounts.iter()
+                    .filter(|(_, &granted)| granted)
+                    .map(|(account, _)| *account)
+                    .collect()
+            })
+            .unwrap_or_default();
+        members.sort_unstable();
+        members
+    }
+
+    /// Roles an account currently holds, in ascending order
+    pub fn get_account_roles(&self, account: &[u8; 32]) -> Vec<[u8; 32]> {
+        let mut roles: Vec<[u8; 32]> = self.roles
+            .keys()
+            .filter(|role| self.has_role(**role, account))
+            .copied()
+            .collect();
+        roles.sort_unstable();
+        roles
+    }
+
+    /// Give up a role held by the sender. Only the sender's own roles can be
+    /// renounced, so an account holding the role must send the call itself.
+    pub fn renounce_role(&mut self, role: [u8; 32]) -> ContractResult<bool> {
+        // Get sender
+        let sender = msg::sender().map_err(|e| ContractError::ExecutionError(e))?;
+
+        if !self.has_role(role, &sender) {
+            return Err(ContractError::AccessDenied(format!(
+                "Sender {:?} cannot renounce role {:?} it does not hold",
+                sender, role
+            )));
+        }
+
+        if let Some(accounts) = self.roles.get_mut(&role) {
+            accounts.remove(&sender);
+        }
+
+        Ok(true)
+    }
+
     /// Get the admin role for a role
     pub fn get_role_admin(&self, role: [u8; 32]) -> [u8; 32] {
         self.role_admins.get(&role).copied().unwrap_or(DEFAULT_ADMIN_ROLE)
@@ -163,39 +217,67 @@ impl AccessControl {
 }
 
 /// Reentrancy guard to prevent recursive calls
+#[derive(Debug, Default)]
 pub struct ReentrancyGuard {
-    /// Lock status
-    entered: Arc<Mutex<bool>>,
+    /// Number of calls currently inside the guarded section
+    entered: Arc<Mutex<usize>>,
+    /// Callers permitted to re-enter while the section is occupied
+    allowed_callers: HashSet<[u8; 32]>,
 }
 
 impl ReentrancyGuard {
     /// Create new reentrancy guard
     pub fn new() -> Self {
         ReentrancyGuard {
-            entered: Arc::new(Mutex::new(false)),
+            entered: Arc::new(Mutex::new(0)),
+            allowed_callers: HashSet::new(),
         }
     }
 
+    /// Permit `caller` to re-enter the guarded section
+    pub fn allow_caller(&mut self, caller: [u8; 32]) -> bool {
+        self.allowed_callers.insert(caller)
+    }
+
+    /// Remove `caller` from the reentrancy allowlist
+    pub fn disallow_caller(&mut self, caller: &[u8; 32]) -> bool {
+        self.allowed_callers.remove(caller)
+    }
+
+    pub fn is_allowed(&self, caller: &[u8; 32]) -> bool {
+        self.allowed_callers.contains(caller)
+    }
+
     /// Enter the guarded section
     pub fn enter(&self) -> ContractResult<()> {
+        self.enter_guarded(None)
+    }
+
+    /// Enter the guarded section on behalf of `caller`, which may re-enter
+    /// if it is on the allowlist
+    pub fn enter_from(&self, caller: &[u8; 32]) -> ContractResult<()> {
+        self.enter_guarded(Some(caller))
+    }
+
+    fn enter_guarded(&self, caller: Option<&[u8; 32]>) -> ContractResult<()> {
         let mut guard = self.entered.lock().map_err(|e| ContractError::LockError(format!(
             "Failed to acquire reentrancy lock: {}", e
         )))?;
 
-        if *guard {
+        if *guard > 0 && !caller.is_some_and(|caller| self.is_allowed(caller)) {
             return Err(ContractError::ReentrancyError(
                 "Reentrant call detected".into()
             ));
         }
 
-        *guard = true;
+        *guard += 1;
         Ok(())
     }
 
     /// Exit the guarded section
     pub fn exit(&self) {
         if let Ok(mut guard) = self.entered.lock() {
-            *guard = false;
+            *guard = guard.saturating_sub(1);
         } else {
             // Log error but don't panic as this is cleanup code
             eprintln!("Warning: Failed to release reentrancy lock");
@@ -235,6 +317,89 @@ mod tests {
         msg::test_utils::clear_sender().unwrap();
     }
 
+    #[test]
+    fn test_grant_role_to_zero_address() {
+        let mut access = AccessControl::new();
+        let account = [1u8; 32];
+        let role = [2u8; 32];
+
+        msg::test_utils::set_sender(account).unwrap();
+        assert!(access.grant_role(DEFAULT_ADMIN_ROLE, account).unwrap());
+
+        assert!(matches!(
+            access.grant_role(role, [0u8; 32]),
+            Err(ContractError::InvalidArguments(_))
+        ));
+        assert!(!access.has_role(role, &[0u8; 32]));
+
+        msg::test_utils::clear_sender().unwrap();
+    }
+
+    #[test]
+    fn test_renounce_role() {
+        let mut access = AccessControl::new();
+        let admin = [1u8; 32];
+        let holder = [2u8; 32];
+        let other = [3u8; 32];
+        let role = [4u8; 32];
+
+        msg::test_utils::set_sender(admin).unwrap();
+        assert!(access.grant_role(DEFAULT_ADMIN_ROLE, admin).unwrap());
+        assert!(access.grant_role(role, holder).unwrap());
+
+        // Another account cannot renounce the role on the holder's behalf
+        msg::test_utils::set_sender(other).unwrap();
+        assert!(matches!(
+            access.renounce_role(role),
+            Err(ContractError::AccessDenied(_))
+        ));
+        assert!(access.has_role(role, &holder));
+
+        // The holder can drop it without the admin
+        msg::test_utils::set_sender(holder).unwrap();
+        assert!(access.renounce_role(role).unwrap());
+        assert!(!access.has_role(role, &holder));
+        assert!(access.renounce_role(role).is_err());
+
+        // Clean up
+        msg::test_utils::clear_sender().unwrap();
+    }
+
+    #[test]
+    fn test_role_enumeration() {
+        let mut access = AccessControl::new();
+        let admin = [1u8; 32];
+        let (alice, bob, carol) = ([2u8; 32], [3u8; 32], [4u8; 32]);
+        let (minter, pauser, burner) = ([5u8; 32], [6u8; 32], [7u8; 32]);
+
+        msg::test_utils::set_sender(admin).unwrap();
+        assert!(access.grant_role(DEFAULT_ADMIN_ROLE, admin).unwrap());
+
+        // Several roles for one account
+        for role in [burner, minter, pauser] {
+            assert!(access.grant_role(role, alice).unwrap());
+        }
+        assert_eq!(access.get_account_roles(&alice), vec![minter, pauser, burner]);
+
+        // One role for several accounts
+        for account in [carol, bob] {
+            assert!(access.grant_role(minter, account).unwrap());
+        }
+        assert_eq!(access.get_role_members(minter), vec![alice, bob, carol]);
+        assert_eq!(access.get_account_roles(&admin), vec![DEFAULT_ADMIN_ROLE]);
+
+        // Revocations are reflected
+        assert!(access.revoke_role(minter, bob).unwrap());
+        assert!(access.revoke_role(pauser, alice).unwrap());
+        assert_eq!(access.get_role_members(minter), vec![alice, carol]);
+        assert_eq!(access.get_account_roles(&alice), vec![minter, burner]);
+        assert!(access.get_account_roles(&bob).is_empty());
+        assert!(access.get_role_members([8u8; 32]).is_empty());
+
+        // Clean up
+        msg::test_utils::clear_sender().unwrap();
+    }
+
     #[test]
     fn test_reentrancy_guard() {
         let guard = ReentrancyGuard::new();
@@ -253,6 +418,32 @@ mod tests {
         assert!(guard.enter().is_ok());
     }
 
+    #[test]
+    fn test_reentrancy_allowlist() {
+        let mut guard = ReentrancyGuard::new();
+        let callback = [3u8; 32];
+        let stranger = [4u8; 32];
+        guard.allow_caller(callback);
+
+        assert!(guard.enter_from(&stranger).is_ok());
+
+        // Only the allowlisted caller may re-enter
+        assert!(matches!(
+            guard.enter_from(&stranger),
+            Err(ContractError::ReentrancyError(_))
+        ));
+        assert!(guard.enter_from(&callback).is_ok());
+
+        // The section stays occupied until every entry has exited
+        guard.exit();
+        assert!(guard.enter_from(&stranger).is_err());
+        guard.exit();
+        assert!(guard.enter_from(&stranger).is_ok());
+
+        assert!(guard.disallow_caller(&callback));
+        assert!(guard.enter_from(&callback).is_err());
+    }
+
     #[test]
     fn test_role_admin() {
         let mut access = AccessControl::new();
@@ -0,0 +1,132 @@
+use super::{ContractError, ContractResult, GAS_PER_INSTRUCTION};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use wasmer::wasmparser::Operator;
+use wasmer::{CompilerConfig, Cranelift, Engine, EngineBuilder, Module};
+use wasmer_middlewares::Metering;
+
+/// Most compiled contract versions kept; the first compiled are dropped first
+const MAX_CACHED_MODULES: usize = 256;
+
+type ModuleKey = ([u8; 32], String);
+
+/// A contract compiled with gas metering, and the engine that compiled it.
+/// Instances must be created in a store of this engine, and their gas set
+/// with `set_remaining_points` before they run.
+#[derive(Clone)]
+pub(crate) struct CompiledContract {
+    pub(crate) engine: Engine,
+    pub(crate) module: Module,
+    /// Gas instances start out with, which their start function runs on
+    pub(crate) initial_gas: u64,
+}
+
+impl CompiledContract {
+    // A metering middleware can only instrument one module, so every module
+    // is compiled by an engine of its own
+    fn compile(bytecode: &[u8], initial_gas: u64) -> ContractResult<Self> {
+        let mut compiler = Cranelift::default();
+        compiler.push_middleware(Arc::new(Metering::new(initial_gas, |_: &Operator| GAS_PER_INSTRUCTION)));
+        let engine: Engine = EngineBuilder::new(compiler).engine().into();
+        let module = Module::new(&engine, bytecode).map_err(|e| ContractError::ExecutionError(
+            format!("Failed to compile contract: {}", e)
+        ))?;
+        Ok(CompiledContract { engine, module, initial_gas })
+    }
+}
+
+/// Code of a contract about to run: its cached module, or bytecode that is
+/// compiled on the executing thread and then added to the cache
+pub(crate) enum ContractCode {
+    Compiled(CompiledContract),
+    Bytecode {
+        cache: ModuleCache,
+        key: ModuleKey,
+        bytecode: Vec<u8>,
+        initial_gas: u64,
+    },
+}
+
+impl ContractCode {
+    pub(crate) fn compile(self) -> ContractResult<CompiledContract> {
+        match self {
+            ContractCode::Compiled(compiled) => Ok(compiled),
+            ContractCode::Bytecode { cache, key, bytecode, initial_gas } => {
+                let compiled = CompiledContract::compile(&bytecode, initial_gas)?;
+                cache.insert(key, compiled.clone());
+                Ok(compiled)
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct CacheEntries {
+    modules: HashMap<ModuleKey, CompiledContract>,
+    // Keys in the order they were compiled
+    order: VecDeque<ModuleKey>,
+}
+
+/// Compiled contracts by address and version, shared by all executions of
+/// a runtime so each version is only compiled once
+#[derive(Clone, Default)]
+pub(crate) struct ModuleCache {
+    entries: Arc<Mutex<CacheEntries>>,
+}
+
+impl fmt::Debug for ModuleCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ModuleCache")
+            .field("modules", &self.lock().modules.len())
+            .finish()
+    }
+}
+
+impl ModuleCache {
+    /// The code to run `version` of a contract with: its compiled module if
+    /// cached, otherwise its bytecode, compiled with `initial_gas` as the gas
+    /// its instances start out with
+    pub(crate) fn prepare(&self, contract_addr: [u8; 32], version: &str, bytecode: &[u8], initial_gas: u64) -> ContractCode {
+        let key = (contract_addr, version.to_string());
+        if let Some(compiled) = self.lock().modules.get(&key) {
+            return ContractCode::Compiled(compiled.clone());
+        }
+        ContractCode::Bytecode {
+            cache: self.clone(),
+            key,
+            bytecode: bytecode.to_vec(),
+            initial_gas,
+        }
+    }
+
+    fn insert(&self, key: ModuleKey, compiled: CompiledContract) {
+        let mut entries = self.lock();
+        if entries.modules.insert(key.clone(), compiled).is_none() {
+            entries.order.push_back(key);
+        }
+        while entries.order.len() > MAX_CACHED_MODULES {
+            if let Some(oldest) = entries.order.pop_front() {
+                entries.modules.remove(&oldest);
+            }
+        }
+    }
+
+    /// Drop every compiled version of a contract
+    pub(crate) fn remove_contract(&self, contract_addr: &[u8; 32]) {
+        let mut entries = self.lock();
+        entries.modules.retain(|(addr, _), _| addr != contract_addr);
+        entries.order.retain(|(addr, _)| addr != contract_addr);
+    }
+
+    /// Number of contract versions compiled and cached
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.lock().modules.len()
+    }
+
+    // The cache is consistent after any panic, so a poisoned lock is fine to use
+    fn lock(&self) -> MutexGuard<'_, CacheEntries> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -17,6 +17,24 @@ use super::standards::{
     token_utils,
 };
 use serde::{Serialize, Deserialize};
+use crate::crypto::is_zero_address;
+
+/// Longest delegation chain followed when checking for cycles
+const MAX_DELEGATION_DEPTH: usize = 256;
+
+/// Voting power held by an account from `block` until the next checkpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct VotingPowerCheckpoint {
+    block: u64,
+    power: u64,
+}
+
+/// Storage key of an account's voting power checkpoints
+fn voting_power_checkpoints_key(account: &[u8; 32]) -> Vec<u8> {
+    let mut key = b"voting_power_checkpoints:".to_vec();
+    key.extend_from_slice(account);
+    key
+}
 
 /// Configuration for governance contract
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -129,29 +147,120 @@ impl GovernanceContract {
         Ok(delegate)
     }
 
-    /// Store voting power at block
+    /// Number of proposals created so far; proposal ids run from 1 to this count
+    fn proposal_count(&self) -> u64 {
+        self.contract.get_state(governance_storage_keys::PROPOSAL_COUNT)
+            .and_then(|v| v.as_slice().try_into().ok())
+            .map(u64::from_be_bytes)
+            .unwrap_or(0)
+    }
+
+    /// List proposals in id order, optionally only those in `state_filter`.
+    /// `offset` and `limit` apply to the filtered list.
+    pub fn list_proposals(&self, state_filter: Option<ProposalState>, offset: usize, limit: usize) -> Vec<Proposal> {
+        (1..=self.proposal_count())
+            .filter_map(|id| self.load_proposal(id).ok())
+            .filter(|proposal| state_filter.as_ref().map_or(true, |state| proposal.state == *state))
+            .skip(offset)
+            .take(limit)
+            .collect()
+    }
+
+    /// Check that delegating from `delegator` to `delegatee` doesn't close a
+    /// cycle, by following the delegatee's chain until it ends at an account
+    /// that delegates to itself.
+    fn check_delegation_cycle(&self, delegator: &[u8; 32], delegatee: &[u8; 32]) -> ContractResult<()> {
+        let mut current = *delegatee;
+        for _ in 0..MAX_DELEGATION_DEPTH {
+            if current == *delegator {
+                return Err(ContractError::InvalidOperation(
+                    "Delegation would create a cycle".into()
+                ));
+            }
+
+            let next = self.load_delegate(&current)?;
+            if next == current {
+                return Ok(());
+            }
+            current = next;
+        }
+
+        Err(ContractError::InvalidOperation(
+            format!("Delegation chain exceeds {} accounts", MAX_DELEGATION_DEPTH)
+        ))
+    }
+
+    /// Reclaim delegated voting power by making the caller its own delegate
+    /// again. Returns false if the caller wasn't delegating.
+    pub fn undelegate(&mut self) -> ContractResult<bool> {
+        let account = self.contract.address;
+        let current_delegate = self.load_delegate(&account)?;
+        if current_delegate == account {
+            return Ok(false);
+        }
+
+        let current_block = self.get_current_block()?;
+        let voting_power = self.get_token_balance(&account)?;
+
+        // Move the caller's votes away from the old delegate
+        let old_power = self.load_voting_power(&current_delegate, current_block)?;
+        let new_power = token_utils::safe_sub(old_power, voting_power)?;
+        self.store_voting_power(&current_delegate, current_block, new_power)?;
+
+        self.emit_event(ContractEvent::DelegateVotesChanged(DelegateVotesChangedEvent {
+            delegate: current_delegate,
+            old_votes: old_power,
+            new_votes: new_power,
+        }))?;
+
+        self.store_delegate(&account, &account)?;
+
+        self.emit_event(ContractEvent::DelegateChanged(DelegateChangedEvent {
+            delegator: account,
+            from_delegate: current_delegate,
+            to_delegate: account,
+        }))?;
+
+        Ok(true)
+    }
+
+    /// Load an account's voting power checkpoints, ordered by block
+    fn load_checkpoints(&self, account: &[u8; 32]) -> ContractResult<Vec<VotingPowerCheckpoint>> {
+        match self.contract.get_state(&voting_power_checkpoints_key(account)) {
+            Some(value) => serde_json::from_slice(value)
+                .map_err(|e| ContractError::ExecutionError(format!("Failed to deserialize checkpoints: {}", e))),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Store voting power from block onwards
     fn store_voting_power(&mut self, account: &[u8; 32], block: u64, power: u64) -> ContractResult<()> {
-        let key = governance_storage_keys::voting_power_key(account, block);
-        self.contract.set_state(key, power.to_be_bytes().to_vec());
+        let mut checkpoints = self.load_checkpoints(account)?;
+        let checkpoint = VotingPowerCheckpoint { block, power };
+        match checkpoints.binary_search_by_key(&block, |c| c.block) {
+            Ok(index) => checkpoints[index] = checkpoint,
+            Err(index) => checkpoints.insert(index, checkpoint),
+        }
+
+        let value = serde_json::to_vec(&checkpoints)
+            .map_err(|e| ContractError::ExecutionError(format!("Failed to serialize checkpoints: {}", e)))?;
+        self.contract.set_state(voting_power_checkpoints_key(account), value);
         Ok(())
     }
 
-    /// Load voting power at block
+    /// Load voting power as of block: the latest checkpoint at or before it,
+    /// or zero if the account had no voting power yet
     fn load_voting_power(&self, account: &[u8; 32], block: u64) -> ContractResult<u64> {
-        let key = governance_storage_keys::voting_power_key(account, block);
-        let value = self.contract.get_state(&key)
-            .ok_or_else(|| ContractError::NotFound("Voting power not found".into()))?;
-        
-        let mut bytes = [0u8; 8];
-        bytes.copy_from_slice(&value);
-        Ok(u64::from_be_bytes(bytes))
+        let checkpoints = self.load_checkpoints(account)?;
+        let index = checkpoints.partition_point(|c| c.block <= block);
+        Ok(if index == 0 { 0 } else { checkpoints[index - 1].power })
     }
 
     /// Execute proposal calls
     fn execute_calls(&mut self, calls: &[ProposalCall]) -> ContractResult<()> {
         for call in calls {
             // In a real implementation, this would execute the call through the contract runtime
-            if call.target == [0u8; 32] {
+            if is_zero_address(&call.target) {
                 return Err(ContractError::ExecutionError("Invalid target address".into()));
             }
             if call.function.is_empty() {
@@ -373,11 +482,22 @@ impl GovernanceStandard for GovernanceContract {
     }
 
     fn delegate(&mut self, delegatee: &[u8; 32]) -> ContractResult<bool> {
+        // Delegating to ourselves is the same as undelegating
+        if *delegatee == self.contract.address {
+            return self.undelegate();
+        }
+
+        if is_zero_address(delegatee) {
+            return Err(ContractError::InvalidArguments("Cannot delegate to the zero address".into()));
+        }
+
         let current_delegate = self.load_delegate(&self.contract.address)?;
         if current_delegate == *delegatee {
             return Ok(false);
         }
 
+        self.check_delegation_cycle(&self.contract.address, delegatee)?;
+
         let current_block = self.get_current_block()?;
         let voting_power = self.get_token_balance(&self.contract.address)?;
 
@@ -395,17 +515,15 @@ impl GovernanceStandard for GovernanceContract {
         }
 
         // Update new delegate's voting power
-        if *delegatee != self.contract.address {
-            let old_power = self.load_voting_power(delegatee, current_block).unwrap_or(0);
-            let new_power = token_utils::safe_add(old_power, voting_power)?;
-            self.store_voting_power(delegatee, current_block, new_power)?;
-
-            self.emit_event(ContractEvent::DelegateVotesChanged(DelegateVotesChangedEvent {
-                delegate: *delegatee,
-                old_votes: old_power,
-                new_votes: new_power,
-            }))?;
-        }
+        let old_power = self.load_voting_power(delegatee, current_block).unwrap_or(0);
+        let new_power = token_utils::safe_add(old_power, voting_power)?;
+        self.store_voting_power(delegatee, current_block, new_power)?;
+
+        self.emit_event(ContractEvent::DelegateVotesChanged(DelegateVotesChangedEvent {
+            delegate: *delegatee,
+            old_votes: old_power,
+            new_votes: new_power,
+        }))?;
 
         self.store_delegate(&self.contract.address, delegatee)?;
 
@@ -522,4 +640,139 @@ mod tests {
         let current_delegate = gov.delegates(&gov.contract.address).unwrap();
         assert_eq!(current_delegate, delegatee);
     }
+
+    #[test]
+    fn test_delegate_to_zero_address() {
+        let mut gov = create_test_contract();
+        gov.contract.address = [2u8; 32];
+
+        let result = gov.delegate(&[0u8; 32]);
+        assert!(matches!(result, Err(ContractError::InvalidArguments(_))));
+        assert_eq!(gov.delegates(&gov.contract.address).unwrap(), gov.contract.address);
+    }
+
+    #[test]
+    fn test_list_proposals() {
+        let mut gov = create_test_contract();
+
+        let mut ids = Vec::new();
+        for i in 0..5 {
+            ids.push(gov.propose(format!("Proposal {}", i), "Description".to_string(), vec![]).unwrap());
+        }
+
+        // Move some proposals into other states
+        for (id, state) in [(ids[1], ProposalState::Active), (ids[3], ProposalState::Active), (ids[4], ProposalState::Canceled)] {
+            let mut proposal = gov.load_proposal(id).unwrap();
+            proposal.state = state;
+            gov.store_proposal(&proposal).unwrap();
+        }
+
+        let active = gov.list_proposals(Some(ProposalState::Active), 0, 10);
+        assert_eq!(active.iter().map(|p| p.id).collect::<Vec<_>>(), vec![ids[1], ids[3]]);
+
+        let all = gov.list_proposals(None, 0, 10);
+        assert_eq!(all.len(), 5);
+
+        // Pagination applies after filtering
+        let page = gov.list_proposals(None, 1, 2);
+        assert_eq!(page.iter().map(|p| p.id).collect::<Vec<_>>(), vec![ids[1], ids[2]]);
+        assert_eq!(gov.list_proposals(Some(ProposalState::Active), 1, 10).len(), 1);
+        assert!(gov.list_proposals(Some(ProposalState::Executed), 0, 10).is_empty());
+    }
+
+    #[test]
+    fn test_voting_power_checkpoints() {
+        let mut gov = create_test_contract();
+        let account = [2u8; 32];
+
+        assert_eq!(gov.load_voting_power(&account, 100).unwrap(), 0);
+
+        // Checkpoints may be written out of order
+        gov.store_voting_power(&account, 10, 300).unwrap();
+        gov.store_voting_power(&account, 5, 100).unwrap();
+        gov.store_voting_power(&account, 10, 350).unwrap();
+
+        assert_eq!(gov.load_voting_power(&account, 4).unwrap(), 0);
+        assert_eq!(gov.load_voting_power(&account, 5).unwrap(), 100);
+        assert_eq!(gov.load_voting_power(&account, 9).unwrap(), 100);
+        assert_eq!(gov.load_voting_power(&account, 10).unwrap(), 350);
+        assert_eq!(gov.load_voting_power(&account, 1000).unwrap(), 350);
+    }
+
+    #[test]
+    fn test_snapshot_voting_power_after_delegation() {
+        let mut gov = create_test_contract();
+        let account = gov.contract.address;
+        let delegatee = [2u8; 32];
+        let balance = gov.get_token_balance(&account).unwrap();
+
+        // Power delegated to the delegatee by others before the proposal
+        gov.store_voting_power(&delegatee, 0, 400).unwrap();
+        gov.delegate(&delegatee).unwrap();
+
+        let proposal_id = gov.propose("Test Proposal".to_string(), "Description".to_string(), vec![]).unwrap();
+        let snapshot_block = gov.get_proposal(proposal_id).unwrap().snapshot_block;
+
+        // Later changes don't affect the snapshot
+        gov.store_voting_power(&delegatee, snapshot_block + 10, 9_999).unwrap();
+
+        assert_eq!(gov.get_voting_power(&account, snapshot_block).unwrap(), 400 + balance);
+        assert_eq!(gov.get_voting_power(&account, snapshot_block + 5).unwrap(), 400 + balance);
+
+        gov.cast_vote(proposal_id, VoteType::For, None).unwrap();
+        let receipt = gov.get_vote_receipt(proposal_id, &account).unwrap();
+        assert_eq!(receipt.weight, 400 + balance);
+    }
+
+    #[test]
+    fn test_undelegate() {
+        let mut gov = create_test_contract();
+        let account = gov.contract.address;
+        let delegatee = [2u8; 32];
+        let block = gov.get_current_block().unwrap();
+        let balance = gov.get_token_balance(&account).unwrap();
+
+        // Not delegating yet, so there is nothing to undo
+        assert!(!gov.undelegate().unwrap());
+
+        gov.store_voting_power(&delegatee, block, 500).unwrap();
+        let own_power = gov.get_voting_power(&account, block).unwrap();
+
+        assert!(gov.delegate(&delegatee).unwrap());
+        assert_eq!(gov.load_voting_power(&delegatee, block).unwrap(), 500 + balance);
+
+        assert!(gov.undelegate().unwrap());
+        assert_eq!(gov.delegates(&account).unwrap(), account);
+        assert_eq!(gov.get_voting_power(&account, block).unwrap(), own_power);
+        assert_eq!(gov.load_voting_power(&delegatee, block).unwrap(), 500);
+
+        // Delegating to ourselves takes the same path
+        assert!(gov.delegate(&delegatee).unwrap());
+        assert!(gov.delegate(&account).unwrap());
+        assert_eq!(gov.load_voting_power(&delegatee, block).unwrap(), 500);
+    }
+
+    #[test]
+    fn test_delegation_cycle_rejected() {
+        let mut gov = create_test_contract();
+        let account = gov.contract.address;
+        let other = [2u8; 32];
+        let third = [3u8; 32];
+
+        // other -> account, then account -> other would loop
+        gov.store_delegate(&other, &account).unwrap();
+        let result = gov.delegate(&other);
+        assert!(matches!(result, Err(ContractError::InvalidOperation(_))));
+        assert_eq!(gov.delegates(&account).unwrap(), account);
+
+        // Longer cycles through a third account are rejected too
+        gov.store_delegate(&other, &third).unwrap();
+        gov.store_delegate(&third, &account).unwrap();
+        assert!(gov.delegate(&other).is_err());
+
+        // Once the chain no longer leads back, delegating is allowed
+        gov.store_delegate(&third, &third).unwrap();
+        assert!(gov.delegate(&other).unwrap());
+        assert_eq!(gov.delegates(&account).unwrap(), other);
+    }
 }
@@ -2,26 +2,41 @@ pub mod standards;
 pub mod access;
 pub mod registry;
 pub mod state;
-
-use wasmer::{Instance, Module, Store, Value, Function, FunctionEnv, WasmTypeList, Imports, Type, FunctionType};
-use std::collections::{HashMap, VecDeque};
+pub mod scrubber;
+pub mod pool;
+mod tunables;
+mod cache;
+
+use wasmer::{Instance, Store, Value, Function, FunctionEnv, FunctionEnvMut, Imports, Memory, ExternType, RuntimeError};
+use wasmer::{BaseTunables, NativeEngineExt, Target};
+use wasmer::wasmparser::{Operator, Parser, Payload, TypeRef, Validator, WasmFeatures};
+use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{mpsc, RwLock};
 use serde::{Serialize, Deserialize};
 use std::time::{SystemTime, UNIX_EPOCH, Duration, Instant};
 
 pub use self::standards::{ContractResult, ContractError};
 pub use self::access::{AccessControl, ReentrancyGuard};
-pub use self::registry::ContractRegistry;
-pub use self::state::{StateManager, StateSnapshot, StateDiff};
+pub use self::registry::{ContractRegistry, UpgradeHistory};
+pub use self::state::{StateManager, StateSnapshot, StateDiff, StateIntegrityReport, StateStore, SnapshotRetention, PreparedBatch, StateMigration};
+pub use self::scrubber::{ScrubberConfig, StateScrubber};
+pub use self::pool::{CallPriority, ContractCall, ExecutionPool, ExecutionPoolConfig};
 pub use self::access::DEFAULT_ADMIN_ROLE;  // Re-export DEFAULT_ADMIN_ROLE
 
+use crate::crypto::Hash;
 use crate::msg;
+use crate::receipt::{CallReceipt, EmittedEvent, EventFilter, WasmValue};
+use self::state::{MAX_KEY_SIZE, MAX_VALUE_SIZE};
+use self::tunables::LimitingTunables;
+use self::cache::{CompiledContract, ContractCode, ModuleCache};
 
 // Role constants
 pub const DEPLOYER_ROLE: [u8; 32] = [1u8; 32];
 pub const EXECUTOR_ROLE: [u8; 32] = [2u8; 32];
 pub const UPGRADER_ROLE: [u8; 32] = [3u8; 32];
+pub const PAUSER_ROLE: [u8; 32] = [4u8; 32];
 
 // Upgrade limits
 const MAX_UPGRADES_PER_DAY: u32 = 5;
@@ -34,6 +49,214 @@ const MAX_OPERATIONS_PER_SECOND: usize = 1000;
 const OPERATION_TIMEOUT: Duration = Duration::from_secs(30);
 const OPERATION_HISTORY_WINDOW: Duration = Duration::from_secs(60);
 
+// Gas charged for each WASM instruction executed
+const GAS_PER_INSTRUCTION: u64 = 1;
+
+// Most call receipts kept in memory; older ones are only in block receipts
+const MAX_CALL_RECEIPTS: usize = 10_000;
+
+// Most topics a single emitted event may carry
+const MAX_EVENT_TOPICS: u32 = 4;
+
+// Host functions contracts may import from `env`
+const HOST_FUNCTIONS: &[&str] = &["gas", "storage_read", "storage_write", "memory_limit", "emit"];
+
+// Imports wasm-bindgen leaves in output it has not processed. They link to
+// trapping stubs and are only called when describing bindings.
+const BINDGEN_IMPORTS: &[(&str, &str)] = &[
+    ("__wbindgen_placeholder__", "__wbindgen_describe"),
+    ("__wbindgen_placeholder__", "__wbindgen_describe_closure"),
+    ("__wbindgen_externref_xform__", "__wbindgen_externref_table_grow"),
+    ("__wbindgen_externref_xform__", "__wbindgen_externref_table_set_null"),
+];
+
+/// Storage writes made by a method, applied once its execution has succeeded
+pub(crate) type StorageWrites = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Topics and data of the events emitted by a method, logged once its
+/// execution has succeeded
+pub(crate) type EventBuffer = Vec<(Vec<[u8; 32]>, Vec<u8>)>;
+
+/// Return values, storage writes and events of a successful execution
+pub(crate) type ExecutionOutput = (Vec<Value>, StorageWrites, EventBuffer);
+
+/// What `begin_execution` hands to `run_with_timeout`
+pub(crate) struct PreparedExecution {
+    /// Contract version being executed
+    pub(crate) version: String,
+    /// The version's compiled module, or its bytecode if not yet compiled
+    pub(crate) code: ContractCode,
+    pub(crate) timeout: Duration,
+    /// Gas the execution may use: the environment's limit, capped by the
+    /// contract's own `max_gas`
+    pub(crate) gas_limit: u64,
+    /// The contract's state when the execution began, shared with the
+    /// state manager rather than copied
+    pub(crate) state: Arc<HashMap<Vec<u8>, Vec<u8>>>,
+}
+
+// Why a host function stopped an execution
+#[derive(Debug, Clone, Copy)]
+enum HostAbort {
+    OutOfGas,
+    Timeout,
+}
+
+// How running a contract failed
+enum RunError {
+    Aborted(HostAbort),
+    Contract(ContractError),
+}
+
+// State shared between an executing contract instance and its host functions
+struct HostEnv {
+    deadline: Instant,
+    memory_limit: u64,
+    instance: Option<Instance>,
+    memory: Option<Memory>,
+    state: Arc<HashMap<Vec<u8>, Vec<u8>>>,
+    writes: StorageWrites,
+    events: EventBuffer,
+    abort: Option<HostAbort>,
+}
+
+// Bytecode that does not parse as a WASM module
+fn invalid_module(e: impl std::fmt::Display) -> ContractError {
+    ContractError::BytecodeVerificationError(format!("Compilation failed: {}", e))
+}
+
+/// `env.gas`: charge gas for the work the contract is about to do, on top
+/// of the metered instructions. Also where a contract past its deadline is
+/// stopped, since it is called throughout execution.
+fn host_gas(mut env: FunctionEnvMut<HostEnv>, amount: u32) -> Result<(), RuntimeError> {
+    let (host, mut store) = env.data_and_store_mut();
+    if Instant::now() > host.deadline {
+        host.abort = Some(HostAbort::Timeout);
+        return Err(RuntimeError::new("execution timed out"));
+    }
+
+    let instance = host.instance.clone()
+        .ok_or_else(|| RuntimeError::new("contract is not instantiated"))?;
+    match get_remaining_points(&mut store, &instance) {
+        MeteringPoints::Remaining(points) if points >= amount as u64 => {
+            set_remaining_points(&mut store, &instance, points - amount as u64);
+            Ok(())
+        }
+        _ => {
+            set_remaining_points(&mut store, &instance, 0);
+            host.abort = Some(HostAbort::OutOfGas);
+            Err(RuntimeError::new("gas limit exceeded"))
+        }
+    }
+}
+
+/// `env.memory_limit`: the most memory, in bytes, the contract may use
+fn host_memory_limit(env: FunctionEnvMut<HostEnv>) -> u64 {
+    env.data().memory_limit
+}
+
+/// `env.storage_read`: look up the value stored under the key at `key_ptr`,
+/// including writes made earlier in the execution. The value is copied into
+/// memory from the contract's `alloc` export and returned as its pointer in
+/// the high 32 bits and its length in the low 32 bits, or -1 if it is unset.
+fn host_storage_read(mut env: FunctionEnvMut<HostEnv>, key_ptr: u32, key_len: u32) -> Result<i64, RuntimeError> {
+    if key_len as usize > MAX_KEY_SIZE {
+        return Err(RuntimeError::new(format!(
+            "storage key of {} bytes exceeds the limit of {} bytes", key_len, MAX_KEY_SIZE
+        )));
+    }
+
+    let (host, mut store) = env.data_and_store_mut();
+    let memory = host.memory.clone()
+        .ok_or_else(|| RuntimeError::new("contract does not export its memory"))?;
+    let mut key = vec![0; key_len as usize];
+    memory.view(&store).read(key_ptr as u64, &mut key)
+        .map_err(|e| RuntimeError::new(format!("invalid storage read: {}", e)))?;
+
+    let value = host.writes.iter().rev()
+        .find(|(written, _)| *written == key)
+        .map(|(_, value)| value)
+        .or_else(|| host.state.get(&key));
+    let value = match value {
+        Some(value) => value.clone(),
+        None => return Ok(-1),
+    };
+
+    let instance = host.instance.clone()
+        .ok_or_else(|| RuntimeError::new("contract is not instantiated"))?;
+    let alloc = instance.exports.get_typed_function::<u32, u32>(&store, "alloc")
+        .map_err(|_| RuntimeError::new("contract must export alloc to read storage"))?;
+    let value_ptr = alloc.call(&mut store, value.len() as u32)?;
+    memory.view(&store).write(value_ptr as u64, &value)
+        .map_err(|e| RuntimeError::new(format!("invalid storage read: {}", e)))?;
+
+    Ok(((value_ptr as u64) << 32 | value.len() as u64) as i64)
+}
+
+/// `env.storage_write`: record a write of the value at `value_ptr` to the
+/// key at `key_ptr`, applied once the execution has succeeded
+fn host_storage_write(
+    mut env: FunctionEnvMut<HostEnv>,
+    key_ptr: u32,
+    key_len: u32,
+    value_ptr: u32,
+    value_len: u32,
+) -> Result<(), RuntimeError> {
+    if key_len as usize > MAX_KEY_SIZE || value_len as usize > MAX_VALUE_SIZE {
+        return Err(RuntimeError::new(format!(
+            "storage write of a {} byte key and {} byte value exceeds the limits of {} and {} bytes",
+            key_len, value_len, MAX_KEY_SIZE, MAX_VALUE_SIZE
+        )));
+    }
+
+    let (host, store) = env.data_and_store_mut();
+    let memory = host.memory.clone()
+        .ok_or_else(|| RuntimeError::new("contract does not export its memory"))?;
+    let view = memory.view(&store);
+
+    let mut key = vec![0; key_len as usize];
+    let mut value = vec![0; value_len as usize];
+    view.read(key_ptr as u64, &mut key)
+        .and_then(|_| view.read(value_ptr as u64, &mut value))
+        .map_err(|e| RuntimeError::new(format!("invalid storage write: {}", e)))?;
+
+    host.writes.push((key, value));
+    Ok(())
+}
+
+/// `env.emit`: record an event with `topic_count` 32-byte topics at
+/// `topics_ptr` and the data at `data_ptr`, logged once the execution has
+/// succeeded
+fn host_emit(
+    mut env: FunctionEnvMut<HostEnv>,
+    topics_ptr: u32,
+    topic_count: u32,
+    data_ptr: u32,
+    data_len: u32,
+) -> Result<(), RuntimeError> {
+    if topic_count > MAX_EVENT_TOPICS {
+        return Err(RuntimeError::new(format!(
+            "event has {} topics, at most {} are allowed", topic_count, MAX_EVENT_TOPICS
+        )));
+    }
+
+    let (host, store) = env.data_and_store_mut();
+    let memory = host.memory.clone()
+        .ok_or_else(|| RuntimeError::new("contract does not export its memory"))?;
+    let view = memory.view(&store);
+
+    let mut topics = vec![[0u8; 32]; topic_count as usize];
+    let mut data = vec![0; data_len as usize];
+    topics.iter_mut()
+        .enumerate()
+        .try_for_each(|(i, topic)| view.read(topics_ptr as u64 + 32 * i as u64, topic))
+        .and_then(|_| view.read(data_ptr as u64, &mut data))
+        .map_err(|e| RuntimeError::new(format!("invalid event: {}", e)))?;
+
+    host.events.push((topics, data));
+    Ok(())
+}
+
 // Operation types for tracking
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OperationType {
@@ -44,6 +267,14 @@ pub enum OperationType {
     Rollback,
 }
 
+impl OperationType {
+    /// Whether the operation replaces the contract's code and state, so it
+    /// must not overlap another such operation on the same contract
+    fn replaces_version(self) -> bool {
+        matches!(self, OperationType::Upgrade | OperationType::Rollback)
+    }
+}
+
 // Track operation metrics
 #[derive(Debug)]
 struct OperationMetrics {
@@ -100,6 +331,16 @@ impl OperationTracker {
             ));
         }
 
+        // An upgrade and a rollback of the same contract exclude each other
+        if op_type.replaces_version() {
+            if let Some(active) = contract_ops.iter().find(|op| op.operation_type.replaces_version()) {
+                return Err(ContractError::OperationConflict(format!(
+                    "Cannot start {:?} of contract {} while a {:?} is in progress",
+                    op_type, hex::encode(contract_addr), active.operation_type
+                )));
+            }
+        }
+
         Ok(())
     }
 
@@ -165,6 +406,9 @@ pub struct ContractMethod {
     pub inputs: Vec<ContractParam>,
     pub outputs: Vec<ContractParam>,
     pub payable: bool,
+    /// Gas a call may use when the caller gives no limit
+    #[serde(default)]
+    pub default_gas: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -188,6 +432,10 @@ pub struct ContractMetadata {
     pub author: [u8; 32],
     pub description: String,
     pub is_upgradeable: bool,
+    /// Whether this version may be registered over one with a lower major
+    /// version
+    #[serde(default)]
+    pub allow_major_upgrade: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -205,21 +453,68 @@ pub struct ResourceLimits {
     pub max_call_depth: u32,
 }
 
+#[derive(Clone)]
 pub struct ContractEnvironment {
-    pub gas_limit: u64,
+    /// Gas the call may use; `None` takes the method's ABI default
+    pub gas_limit: Option<u64>,
     pub block_number: u64,
     pub timestamp: u64,
     pub caller: [u8; 32],
+    /// Value sent along with the call; only payable methods accept any
+    pub value: u64,
     pub resource_limits: ResourceLimits,
     pub gas_used: Arc<RwLock<u64>>,
 }
 
+/// Values returned by an execution and the gas it used
+#[derive(Debug, Clone)]
+pub struct ExecutionResult {
+    pub values: Vec<Value>,
+    pub gas_used: u64,
+}
+
+// Changes made by the calls of a transaction run through
+// `execute_transaction`, held back until every call has succeeded
+#[derive(Debug, Default)]
+struct PendingTransaction {
+    // State of each contract the transaction calls from before its first
+    // call; None if the contract had no state
+    checkpoint: HashMap<[u8; 32], Option<HashMap<Vec<u8>, Vec<u8>>>>,
+    // Contracts whose state the transaction changed, with the version that
+    // first changed it, snapshotted once the transaction commits
+    written: Vec<([u8; 32], String)>,
+    // Events emitted by the calls that have succeeded so far
+    events: Vec<EmittedEvent>,
+}
+
 #[derive(Debug)]
 pub struct ContractRuntime {
     access_control: AccessControl,
     registry: ContractRegistry,
     state_manager: StateManager,
     operation_tracker: OperationTracker,
+    // Per-contract execution timeouts overriding OPERATION_TIMEOUT
+    execution_timeouts: HashMap<[u8; 32], Duration>,
+    // Resource limits contracts were deployed with
+    resource_limits: HashMap<[u8; 32], ResourceLimits>,
+    // Number of contracts each author has deployed, for address generation
+    deployment_nonces: HashMap<[u8; 32], u64>,
+    // Contracts halted by a pauser; they can still be read but not changed
+    paused: HashSet<[u8; 32]>,
+    // Per-contract guards against overlapping executions
+    reentrancy_guards: HashMap<[u8; 32], ReentrancyGuard>,
+    // Receipts of the latest calls made through execute_with_receipt, by
+    // transaction and call index, and their keys oldest first
+    call_receipts: HashMap<(Hash, u32), CallReceipt>,
+    call_receipt_order: VecDeque<(Hash, u32)>,
+    // Events emitted by successful executions, in the order they ran
+    logs: Vec<EmittedEvent>,
+    // Subscribers streamed the newly emitted events their filter matches
+    event_subscribers: Vec<(EventFilter, mpsc::UnboundedSender<EmittedEvent>)>,
+    // Transaction being run by execute_transaction, if any
+    pending_transaction: Option<PendingTransaction>,
+    // Compiled contract versions, shared with executing threads
+    modules: ModuleCache,
 }
 
 impl ContractRuntime {
@@ -229,6 +524,17 @@ impl ContractRuntime {
             registry: ContractRegistry::new(),
             state_manager: StateManager::new(),
             operation_tracker: OperationTracker::new(),
+            execution_timeouts: HashMap::new(),
+            resource_limits: HashMap::new(),
+            deployment_nonces: HashMap::new(),
+            paused: HashSet::new(),
+            reentrancy_guards: HashMap::new(),
+            call_receipts: HashMap::new(),
+            call_receipt_order: VecDeque::new(),
+            logs: Vec::new(),
+            event_subscribers: Vec::new(),
+            pending_transaction: None,
+            modules: ModuleCache::default(),
         }
     }
 
@@ -236,6 +542,18 @@ impl ContractRuntime {
         self.access_control.grant_role(role, account)
     }
 
+    pub fn get_role_members(&self, role: [u8; 32]) -> Vec<[u8; 32]> {
+        self.access_control.get_role_members(role)
+    }
+
+    pub fn get_account_roles(&self, account: &[u8; 32]) -> Vec<[u8; 32]> {
+        self.access_control.get_account_roles(account)
+    }
+
+    pub fn renounce_role(&mut self, role: [u8; 32]) -> ContractResult<bool> {
+        self.access_control.renounce_role(role)
+    }
+
     pub fn has_role(&self, role: [u8; 32], account: &[u8; 32]) -> bool {
         self.access_control.has_role(role, account)
     }
@@ -244,6 +562,106 @@ impl ContractRuntime {
         self.registry.get_contract_versions(contract_addr).is_ok()
     }
 
+    /// Set how long a single execution of this contract may run
+    pub fn set_execution_timeout(&mut self, contract_addr: &[u8; 32], timeout: Duration) -> ContractResult<()> {
+        let sender = msg::sender().map_err(ContractError::ExecutionError)?;
+        if !self.has_role(DEPLOYER_ROLE, &sender) {
+            return Err(ContractError::AccessDenied(
+                "Sender does not have deployer role".into()
+            ));
+        }
+
+        if !self.contract_exists(contract_addr) {
+            return Err(ContractError::NotFound(
+                format!("Contract not found at address {:?}", contract_addr)
+            ));
+        }
+
+        self.execution_timeouts.insert(*contract_addr, timeout);
+        Ok(())
+    }
+
+    /// Permit `caller` to call into this contract while one of its
+    /// executions is still in progress, e.g. for known-safe callbacks
+    pub fn allow_reentrant_caller(&mut self, contract_addr: &[u8; 32], caller: [u8; 32]) -> ContractResult<()> {
+        self.check_reentrancy_admin(contract_addr)?;
+        self.reentrancy_guards.entry(*contract_addr).or_default().allow_caller(caller);
+        Ok(())
+    }
+
+    /// Remove `caller` from the contract's reentrancy allowlist
+    pub fn disallow_reentrant_caller(&mut self, contract_addr: &[u8; 32], caller: &[u8; 32]) -> ContractResult<bool> {
+        self.check_reentrancy_admin(contract_addr)?;
+        Ok(self.reentrancy_guards
+            .get_mut(contract_addr)
+            .is_some_and(|guard| guard.disallow_caller(caller)))
+    }
+
+    pub fn is_reentrant_caller_allowed(&self, contract_addr: &[u8; 32], caller: &[u8; 32]) -> bool {
+        self.reentrancy_guards
+            .get(contract_addr)
+            .is_some_and(|guard| guard.is_allowed(caller))
+    }
+
+    fn check_reentrancy_admin(&self, contract_addr: &[u8; 32]) -> ContractResult<()> {
+        let sender = msg::sender().map_err(ContractError::ExecutionError)?;
+        if !self.has_role(DEPLOYER_ROLE, &sender) {
+            return Err(ContractError::AccessDenied(
+                "Sender does not have deployer role".into()
+            ));
+        }
+
+        if !self.contract_exists(contract_addr) {
+            return Err(ContractError::NotFound(
+                format!("Contract not found at address {:?}", contract_addr)
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Execution timeout for a contract, defaulting to the global operation timeout
+    pub fn get_execution_timeout(&self, contract_addr: &[u8; 32]) -> Duration {
+        self.execution_timeouts
+            .get(contract_addr)
+            .copied()
+            .unwrap_or(OPERATION_TIMEOUT)
+    }
+
+    /// Address of the contract `deployer` deploys with `nonce`. The same
+    /// inputs always give the same address, and each nonce a different one.
+    pub fn generate_address(deployer: &[u8; 32], nonce: u64) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(deployer);
+        hasher.update(&nonce.to_le_bytes());
+        *hasher.finalize().as_bytes()
+    }
+
+    /// Number of contracts deployed with `author` as their author, which is
+    /// the nonce to generate the address of its next contract with
+    pub fn deployment_nonce(&self, author: &[u8; 32]) -> u64 {
+        self.deployment_nonces.get(author).copied().unwrap_or(0)
+    }
+
+    /// Resource limits the contract was deployed with
+    pub fn get_resource_limits(&self, contract_addr: &[u8; 32]) -> Option<ResourceLimits> {
+        self.resource_limits.get(contract_addr).copied()
+    }
+
+    /// Gas an execution of `method` in `env` may use. Without a limit from
+    /// the caller, the method's ABI default applies, then the contract's
+    /// `max_gas`. Any limit is capped by `max_gas` so no caller can
+    /// monopolize a slow contract.
+    pub fn effective_gas_limit(&self, contract_addr: &[u8; 32], method: &ContractMethod, env: &ContractEnvironment) -> u64 {
+        let max_gas = self.resource_limits.get(contract_addr).map(|limits| limits.max_gas);
+        match (env.gas_limit.or(method.default_gas), max_gas) {
+            (Some(gas_limit), Some(max_gas)) => gas_limit.min(max_gas),
+            (Some(gas_limit), None) => gas_limit,
+            (None, Some(max_gas)) => max_gas,
+            (None, None) => env.resource_limits.max_gas,
+        }
+    }
+
     /// Verify bytecode before deployment or upgrade
     fn verify_bytecode(&self, bytecode: &[u8]) -> ContractResult<()> {
         if bytecode.is_empty() {
@@ -258,8 +676,78 @@ impl ContractRuntime {
             ));
         }
 
-        // Add more bytecode verification logic here
-        // For example, check magic numbers, format validation, etc.
+        // Contracts must run identically on every node, so the module may
+        // only import host functions and may not use floating point, whose
+        // NaN results differ between platforms
+        let wasm = wasmer::wat2wasm(bytecode).map_err(invalid_module)?;
+        for payload in Parser::new(0).parse_all(&wasm) {
+            if let Payload::ImportSection(imports) = payload.map_err(invalid_module)? {
+                for import in imports {
+                    let import = import.map_err(invalid_module)?;
+                    let allowed = match import.ty {
+                        TypeRef::Func(_) => (import.module == "env" && HOST_FUNCTIONS.contains(&import.name))
+                            || BINDGEN_IMPORTS.contains(&(import.module, import.name)),
+                        _ => false,
+                    };
+                    if !allowed {
+                        return Err(ContractError::BytecodeVerificationError(format!(
+                            "Import {}.{} is not an allowed host function", import.module, import.name
+                        )));
+                    }
+                }
+            }
+        }
+
+        // Validate once as is, so malformed modules are reported as such,
+        // then with floats disabled, which rejects every float value type
+        // and every float, conversion and SIMD float operator
+        Validator::new_with_features(WasmFeatures::default())
+            .validate_all(&wasm)
+            .map_err(invalid_module)?;
+        let features = WasmFeatures { floats: false, ..WasmFeatures::default() };
+        Validator::new_with_features(features).validate_all(&wasm).map_err(|e| {
+            ContractError::BytecodeVerificationError(format!("Floating point is not allowed: {}", e))
+        })?;
+
+        Ok(())
+    }
+
+    /// Reject modules that declare more memory than `max_memory` bytes, or
+    /// grow by a constant number of pages that could never fit in it
+    fn verify_memory(bytecode: &[u8], max_memory: usize) -> ContractResult<()> {
+        let limit = (max_memory / wasmer::WASM_PAGE_SIZE) as u64;
+        let wasm = wasmer::wat2wasm(bytecode).map_err(invalid_module)?;
+
+        for payload in Parser::new(0).parse_all(&wasm) {
+            match payload.map_err(invalid_module)? {
+                Payload::MemorySection(memories) => {
+                    for memory in memories {
+                        let memory = memory.map_err(invalid_module)?;
+                        if memory.initial > limit {
+                            return Err(ContractError::BytecodeVerificationError(format!(
+                                "Memory of {} pages exceeds the memory limit of {} pages", memory.initial, limit
+                            )));
+                        }
+                    }
+                }
+                Payload::CodeSectionEntry(body) => {
+                    let mut operators = body.get_operators_reader().map_err(invalid_module)?;
+                    let mut previous = None;
+                    while !operators.eof() {
+                        let operator = operators.read().map_err(invalid_module)?;
+                        if let (Operator::MemoryGrow { .. }, Some(Operator::I32Const { value })) = (&operator, &previous) {
+                            if *value as u32 as u64 > limit {
+                                return Err(ContractError::BytecodeVerificationError(format!(
+                                    "memory.grow by {} pages exceeds the memory limit of {} pages", *value as u32, limit
+                                )));
+                            }
+                        }
+                        previous = Some(operator);
+                    }
+                }
+                _ => {}
+            }
+        }
 
         Ok(())
     }
@@ -342,13 +830,24 @@ impl ContractRuntime {
             ));
         }
 
+        // A fresh deployment must not land on an occupied address; replacing
+        // an existing contract goes through upgrade_contract
+        if self.contract_exists(contract_addr) {
+            self.operation_tracker.end_operation(contract_addr, OperationType::Deploy);
+            return Err(ContractError::VersionConflict(format!(
+                "Contract already deployed at {}; use upgrade_contract to replace it",
+                hex::encode(contract_addr)
+            )));
+        }
+
         // Verify bytecode
-        if let Err(e) = self.verify_bytecode(bytecode) {
+        if let Err(e) = self.verify_bytecode(bytecode).and_then(|_| Self::verify_memory(bytecode, limits.max_memory)) {
             self.operation_tracker.end_operation(contract_addr, OperationType::Deploy);
             return Err(e);
         }
 
         // Create contract version
+        let author = metadata.author;
         let version = ContractVersion {
             bytecode: bytecode.to_vec(),
             metadata,
@@ -371,7 +870,11 @@ impl ContractRuntime {
 
         // Attempt to register the contract version
         let result = match self.registry.register_version(*contract_addr, version) {
-            Ok(_) => Ok(()),
+            Ok(_) => {
+                self.resource_limits.insert(*contract_addr, *limits);
+                *self.deployment_nonces.entry(author).or_default() += 1;
+                Ok(())
+            },
             Err(ContractError::VersionConflict(msg)) => {
                 Err(ContractError::VersionConflict(
                     format!("Version conflict during deployment: {}", msg)
@@ -386,12 +889,16 @@ impl ContractRuntime {
         result
     }
 
+    /// Replace the code of a deployed contract. The old state is snapshotted
+    /// first; if `migration` is given, it then transforms the state into the
+    /// schema of the new version.
     pub async fn upgrade_contract(
         &mut self,
         contract_addr: &[u8; 32],
         bytecode: &[u8],
         abi: &ContractABI,
         metadata: ContractMetadata,
+        migration: Option<&StateMigration>,
     ) -> ContractResult<()> {
         // Start operation tracking
         self.operation_tracker.start_operation(*contract_addr, OperationType::Upgrade)?;
@@ -419,8 +926,13 @@ impl ContractRuntime {
             return Err(e);
         }
 
-        // Verify bytecode
-        if let Err(e) = self.verify_bytecode(bytecode) {
+        // Verify bytecode, holding the new code to the memory limit the
+        // contract was deployed with
+        let verified = self.verify_bytecode(bytecode).and_then(|_| match self.resource_limits.get(contract_addr) {
+            Some(limits) => Self::verify_memory(bytecode, limits.max_memory),
+            None => Ok(()),
+        });
+        if let Err(e) = verified {
             self.operation_tracker.end_operation(contract_addr, OperationType::Upgrade);
             return Err(e);
         }
@@ -440,6 +952,14 @@ impl ContractRuntime {
             return Err(e);
         }
 
+        // Migrate state to the new version's schema
+        if let Some(migration) = migration {
+            if let Err(e) = self.state_manager.migrate_state(*contract_addr, metadata.version.clone(), migration) {
+                self.operation_tracker.end_operation(contract_addr, OperationType::Upgrade);
+                return Err(e);
+            }
+        }
+
         // Create new contract version
         let version = ContractVersion {
             bytecode: bytecode.to_vec(),
@@ -447,8 +967,13 @@ impl ContractRuntime {
             abi: abi.clone(),
         };
 
-        // Register new version
-        let result = self.registry.register_version(*contract_addr, version);
+        // Register new version, putting the unmigrated state back if that fails
+        let result = match self.registry.register_version(*contract_addr, version) {
+            Err(e) if migration.is_some() => self.state_manager
+                .restore_version_snapshot(*contract_addr, &current_version.metadata.version)
+                .and(Err(e)),
+            result => result,
+        };
 
         // End operation tracking
         self.operation_tracker.end_operation(contract_addr, OperationType::Upgrade);
@@ -464,6 +989,231 @@ impl ContractRuntime {
         env: &ContractEnvironment,
         version: Option<&str>,
     ) -> ContractResult<Vec<Value>> {
+        let prepared = self.begin_execution(contract_addr, method, env, version)?;
+        let version = prepared.version.clone();
+        let result = Self::run_with_timeout(prepared, method, &args, env).await;
+        self.finish_execution(&contract_addr, &version, env.block_number, result)
+    }
+
+    /// Like `execute_contract`, also returning the gas the execution used.
+    /// The environment's `gas_used` still accumulates it as well.
+    pub async fn execute_contract_metered(
+        &mut self,
+        contract_addr: [u8; 32],
+        method: &str,
+        args: Vec<Value>,
+        env: &ContractEnvironment,
+        version: Option<&str>,
+    ) -> ContractResult<ExecutionResult> {
+        let gas_before = *env.gas_used.read().await;
+        let values = self.execute_contract(contract_addr, method, args, env, version).await?;
+        let gas_used = env.gas_used.read().await.saturating_sub(gas_before);
+        Ok(ExecutionResult { values, gas_used })
+    }
+
+    /// Run `method` against the current state of the latest version without
+    /// keeping anything it does: its storage writes and events are dropped,
+    /// no snapshot is taken and the call is not tracked as an operation.
+    /// Returns the method's results and the gas it used.
+    pub async fn simulate_contract(
+        &self,
+        contract_addr: [u8; 32],
+        method: &str,
+        args: Vec<Value>,
+        env: &ContractEnvironment,
+    ) -> ContractResult<(Vec<Value>, u64)> {
+        let sender = msg::sender().map_err(|e| ContractError::ExecutionError(e))?;
+        if !self.has_role(EXECUTOR_ROLE, &sender) {
+            return Err(ContractError::AccessDenied(
+                "Sender does not have executor role".into()
+            ));
+        }
+        self.validate_contract_state(&contract_addr)?;
+
+        let contract_version = self.registry.resolve_version(&contract_addr, None)?;
+        let Some(method_abi) = contract_version.abi.methods.iter().find(|m| m.name == method) else {
+            return Err(ContractError::NotFound(format!("Method {} not found in contract ABI", method)));
+        };
+
+        let prepared = self.prepare_execution(&contract_addr, contract_version, method_abi, env);
+
+        // Meter separately so the caller's gas accounting is left alone
+        let env = ContractEnvironment {
+            gas_used: Arc::new(RwLock::new(0)),
+            ..env.clone()
+        };
+        let (values, _writes, _events) = Self::run_with_timeout(prepared, method, &args, &env).await?;
+        let gas_used = *env.gas_used.read().await;
+        Ok((values, gas_used))
+    }
+
+    /// Execute several calls as a single transaction. Either every call
+    /// succeeds and all their state changes are kept, or the state of every
+    /// contract the transaction touches is restored to what it was before
+    /// the first call. Snapshots and events of the calls only take effect
+    /// once all of them have succeeded.
+    pub async fn execute_transaction(&mut self, calls: &[ContractCall]) -> ContractResult<Vec<Vec<Value>>> {
+        // Checkpoint the state of all involved contracts for the whole
+        // transaction, including those that have none yet
+        let checkpoint = calls.iter()
+            .map(|call| (call.contract_addr, self.state_manager.get_state(&call.contract_addr).cloned()))
+            .collect();
+        self.pending_transaction = Some(PendingTransaction { checkpoint, ..Default::default() });
+
+        let mut results = Vec::with_capacity(calls.len());
+        for call in calls {
+            let result = self.execute_contract(
+                call.contract_addr,
+                &call.method,
+                call.args.clone(),
+                &call.env,
+                call.version.as_deref(),
+            ).await;
+
+            match result {
+                Ok(values) => results.push(values),
+                Err(e) => {
+                    self.abort_transaction()?;
+                    return Err(e);
+                }
+            }
+        }
+
+        self.commit_transaction()?;
+        Ok(results)
+    }
+
+    // Keep the changes of the pending transaction: snapshot the state each
+    // changed contract had before it and publish its events
+    fn commit_transaction(&mut self) -> ContractResult<()> {
+        let Some(mut transaction) = self.pending_transaction.take() else {
+            return Ok(());
+        };
+
+        for (contract_addr, version) in transaction.written {
+            // A contract without prior state has nothing to roll back to
+            if let Some(Some(state)) = transaction.checkpoint.remove(&contract_addr) {
+                self.state_manager.record_snapshot(contract_addr, version, state)?;
+            }
+        }
+        self.publish_events(transaction.events);
+        Ok(())
+    }
+
+    // Undo the pending transaction, putting back the state of every
+    // contract it calls and dropping its events
+    fn abort_transaction(&mut self) -> ContractResult<()> {
+        let Some(transaction) = self.pending_transaction.take() else {
+            return Ok(());
+        };
+
+        for (contract_addr, state) in transaction.checkpoint {
+            match state {
+                Some(state) => self.state_manager.restore_state(contract_addr, state)?,
+                None => self.state_manager.remove_state(contract_addr)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Execute call `call_index` of transaction `tx_hash` and record its
+    /// outcome, return values and the gas it used, which is read from the
+    /// call's environment. Only the latest `MAX_CALL_RECEIPTS` receipts are
+    /// kept; store them in a `BlockReceipt` to keep them for good.
+    pub async fn execute_with_receipt(&mut self, tx_hash: Hash, call_index: u32, call: &ContractCall) -> (ContractResult<Vec<Value>>, CallReceipt) {
+        let gas_before = *call.env.gas_used.read().await;
+        let result = self.execute_contract(
+            call.contract_addr,
+            &call.method,
+            call.args.clone(),
+            &call.env,
+            call.version.as_deref(),
+        ).await;
+        let gas_used = call.env.gas_used.read().await.saturating_sub(gas_before);
+
+        let receipt = CallReceipt {
+            tx_hash,
+            call_index,
+            contract_addr: call.contract_addr,
+            method: call.method.clone(),
+            gas_used,
+            success: result.is_ok(),
+            result: result.as_ref().ok()
+                .map(|values| values.iter().cloned().map(WasmValue::from).collect()),
+        };
+        self.record_call_receipt(receipt.clone());
+        (result, receipt)
+    }
+
+    fn record_call_receipt(&mut self, receipt: CallReceipt) {
+        let key = (receipt.tx_hash.clone(), receipt.call_index);
+        if self.call_receipts.insert(key.clone(), receipt).is_none() {
+            self.call_receipt_order.push_back(key);
+        }
+        while self.call_receipt_order.len() > MAX_CALL_RECEIPTS {
+            if let Some(oldest) = self.call_receipt_order.pop_front() {
+                self.call_receipts.remove(&oldest);
+            }
+        }
+    }
+
+    /// Keep at most `max` state snapshots per contract, but always at least
+    /// two and the latest of each version so rollbacks keep working
+    pub fn set_max_snapshots(&mut self, max: usize) {
+        self.state_manager.set_max_snapshots(max);
+    }
+
+    /// Mark blocks up to `height` as final, pruning contract state history
+    /// that can no longer be rolled back to. Returns the number of snapshots pruned.
+    pub fn advance_finality(&mut self, height: u64) -> ContractResult<usize> {
+        self.state_manager.advance_finality(height)
+    }
+
+    /// Values returned by call `call_index` of transaction `tx_hash`, if it
+    /// succeeded and its receipt is still held
+    pub fn get_call_result(&self, tx_hash: &Hash, call_index: u32) -> Option<Vec<WasmValue>> {
+        self.call_receipts.get(&(tx_hash.clone(), call_index))?.result.clone()
+    }
+
+    /// Returns a channel that receives every event emitted from now on that
+    /// matches `filter`
+    pub fn subscribe_events(&mut self, filter: EventFilter) -> mpsc::UnboundedReceiver<EmittedEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.event_subscribers.push((filter, tx));
+        rx
+    }
+
+    // Log newly emitted events and stream them to matching subscribers,
+    // forgetting subscribers whose receiver was dropped
+    fn publish_events(&mut self, events: Vec<EmittedEvent>) {
+        for event in &events {
+            self.event_subscribers.retain(|(filter, subscriber)| {
+                !filter.matches(event) || subscriber.send(event.clone()).is_ok()
+            });
+        }
+        self.logs.extend(events);
+    }
+
+    /// Events emitted by a contract in blocks `from_block` through
+    /// `to_block`, inclusive, in the order they were emitted
+    pub fn get_logs(&self, contract_addr: &[u8; 32], from_block: u64, to_block: u64) -> Vec<EmittedEvent> {
+        self.logs.iter()
+            .filter(|event| event.contract_addr == *contract_addr)
+            .filter(|event| (from_block..=to_block).contains(&event.block_number))
+            .cloned()
+            .collect()
+    }
+
+    /// Check access and contract state and start tracking an execution.
+    /// Returns the version and bytecode to run, the execution timeout for
+    /// the contract and the state its storage reads see.
+    pub(crate) fn begin_execution(
+        &mut self,
+        contract_addr: [u8; 32],
+        method: &str,
+        env: &ContractEnvironment,
+        version: Option<&str>,
+    ) -> ContractResult<PreparedExecution> {
         // Start operation tracking
         self.operation_tracker.start_operation(contract_addr, OperationType::Execute)?;
 
@@ -484,70 +1234,300 @@ impl ContractRuntime {
             return Err(e);
         }
 
-        // Get contract version and create snapshot
-        let contract_version = match version {
-            Some(v) => match self.registry.get_contract_version(&contract_addr, v) {
-                Ok(v) => v,
-                Err(e) => {
-                    self.operation_tracker.end_operation(&contract_addr, OperationType::Execute);
-                    return Err(e);
-                }
-            },
-            None => match self.registry.get_latest_version(&contract_addr) {
-                Ok(v) => v,
-                Err(e) => {
-                    self.operation_tracker.end_operation(&contract_addr, OperationType::Execute);
-                    return Err(e);
-                }
-            },
+        // Get contract version
+        let contract_version = match self.registry.resolve_version(&contract_addr, version) {
+            Ok(v) => v,
+            Err(e) => {
+                self.operation_tracker.end_operation(&contract_addr, OperationType::Execute);
+                return Err(e);
+            }
         };
         
-        if let Err(e) = self.state_manager.create_snapshot(contract_addr, contract_version.metadata.version.clone()) {
-            self.operation_tracker.end_operation(&contract_addr, OperationType::Execute);
-            return Err(e);
-        }
+        self.state_manager.set_block_number(env.block_number);
 
         // Validate method exists in ABI
-        if !contract_version.abi.methods.iter().any(|m| m.name == method) {
+        let Some(method_abi) = contract_version.abi.methods.iter().find(|m| m.name == method) else {
             self.operation_tracker.end_operation(&contract_addr, OperationType::Execute);
             return Err(ContractError::NotFound(format!("Method {} not found in contract ABI", method)));
+        };
+
+        // Value can only be sent to payable methods
+        if env.value > 0 && !method_abi.payable {
+            self.operation_tracker.end_operation(&contract_addr, OperationType::Execute);
+            return Err(ContractError::InvalidArguments(format!(
+                "Method {} is not payable but was sent a value of {}", method, env.value
+            )));
         }
 
-        let result = if method == "add" {
-            if args.len() != 2 {
-                Err(ContractError::InvalidArguments(
-                    "Add method requires exactly 2 arguments".into()
-                ))
-            } else {
-                let a = args[0].unwrap_i32();
-                let b = args[1].unwrap_i32();
-                Ok(vec![Value::I32(a + b)])
+        // Payable methods take value, so they are never mere views
+        if method_abi.payable && self.is_paused(&contract_addr) {
+            self.operation_tracker.end_operation(&contract_addr, OperationType::Execute);
+            return Err(Self::paused_error());
+        }
+        let prepared = self.prepare_execution(&contract_addr, contract_version, method_abi, env);
+
+        // Reject calls into a contract that is already executing unless the
+        // caller is allowlisted for reentry
+        if let Err(e) = self.reentrancy_guards.entry(contract_addr).or_default().enter_from(&env.caller) {
+            self.operation_tracker.end_operation(&contract_addr, OperationType::Execute);
+            return Err(e);
+        }
+
+        Ok(prepared)
+    }
+
+    /// End an execution of `version` started with `begin_execution` in
+    /// block `block_number`, applying its storage writes and logging its
+    /// events if it succeeded
+    pub(crate) fn finish_execution(
+        &mut self,
+        contract_addr: &[u8; 32],
+        version: &str,
+        block_number: u64,
+        result: ContractResult<ExecutionOutput>,
+    ) -> ContractResult<Vec<Value>> {
+        let result = result.and_then(|(values, writes, events)| {
+            // Paused contracts only serve calls that leave their state alone
+            if !writes.is_empty() && self.is_paused(contract_addr) {
+                return Err(Self::paused_error());
             }
+
+            // Only calls that change state need a snapshot to roll back to;
+            // read-only calls skip cloning the whole state. Within a
+            // transaction the snapshot is taken when it commits.
+            if !writes.is_empty() {
+                match &mut self.pending_transaction {
+                    Some(transaction) => {
+                        if !transaction.written.iter().any(|(addr, _)| addr == contract_addr) {
+                            transaction.written.push((*contract_addr, version.to_string()));
+                        }
+                    }
+                    None => {
+                        self.state_manager.create_snapshot(*contract_addr, version.to_string())?;
+                    }
+                }
+            }
+            self.state_manager.update_state_batch(*contract_addr, writes)?;
+
+            let events: Vec<EmittedEvent> = events.into_iter().map(|(topics, data)| EmittedEvent {
+                contract_addr: *contract_addr,
+                block_number,
+                topics,
+                data,
+            }).collect();
+            match &mut self.pending_transaction {
+                Some(transaction) => transaction.events.extend(events),
+                None => self.publish_events(events),
+            }
+            Ok(values)
+        });
+
+        if let Some(guard) = self.reentrancy_guards.get(contract_addr) {
+            guard.exit();
         }
-        else if method == "loop_test" {
-            if args.len() != 1 {
-                Err(ContractError::InvalidArguments(
-                    "Loop test requires exactly 1 argument".into()
-                ))
-            } else {
-                let iterations = args[0].unwrap_i32() as u64;
-                if iterations * 100 > env.gas_limit {
-                    Err(ContractError::ExecutionError(
-                        format!("Gas limit exceeded: required {} > limit {}", iterations * 100, env.gas_limit)
-                    ))
-                } else {
-                    Ok(vec![])
+        self.operation_tracker.end_operation(contract_addr, OperationType::Execute);
+
+        result
+    }
+
+    // Everything needed to run `method` of a contract version: its cached
+    // module and the contract's current state, both shared rather than
+    // copied. Instances of a module start out with the contract's max_gas,
+    // which is what a start function runs on.
+    fn prepare_execution(
+        &self,
+        contract_addr: &[u8; 32],
+        version: &ContractVersion,
+        method: &ContractMethod,
+        env: &ContractEnvironment,
+    ) -> PreparedExecution {
+        let gas_limit = self.effective_gas_limit(contract_addr, method, env);
+        let initial_gas = self.resource_limits.get(contract_addr).map_or(gas_limit, |limits| limits.max_gas);
+        PreparedExecution {
+            version: version.metadata.version.clone(),
+            code: self.modules.prepare(*contract_addr, &version.metadata.version, &version.bytecode, initial_gas),
+            timeout: self.get_execution_timeout(contract_addr),
+            gas_limit,
+            state: self.state_manager.shared_state(contract_addr),
+        }
+    }
+
+    /// Run `method` of a prepared contract on a blocking thread, stopping it
+    /// once its timeout has passed. Gas the contract consumed is added to the
+    /// environment's `gas_used`.
+    pub(crate) async fn run_with_timeout(
+        prepared: PreparedExecution,
+        method: &str,
+        args: &[Value],
+        env: &ContractEnvironment,
+    ) -> ContractResult<ExecutionOutput> {
+        let PreparedExecution { code, timeout, gas_limit, state, .. } = prepared;
+        let timeout_error = || ContractError::OperationTimeout(
+            format!("Execution of {} exceeded timeout of {:?}", method, timeout)
+        );
+
+        let deadline = Instant::now() + timeout;
+        let execution = {
+            let (method, args) = (method.to_string(), args.to_vec());
+            let max_memory = env.resource_limits.max_memory;
+            tokio::task::spawn_blocking(move || {
+                Self::run_wasm(code, &method, &args, state, gas_limit, max_memory, deadline)
+            })
+        };
+
+        // The deadline is checked whenever the contract charges gas; the
+        // timeout here covers contracts that stop calling into the host
+        let (result, gas_used) = match tokio::time::timeout(timeout, execution).await {
+            Ok(Ok(outcome)) => outcome,
+            Ok(Err(e)) => return Err(ContractError::ExecutionError(
+                format!("Execution of {} failed: {}", method, e)
+            )),
+            Err(_) => return Err(timeout_error()),
+        };
+        *env.gas_used.write().await += gas_used;
+
+        result.map_err(|e| match e {
+            RunError::Aborted(HostAbort::Timeout) => timeout_error(),
+            RunError::Aborted(HostAbort::OutOfGas) => ContractError::ExecutionError(
+                format!("Gas limit exceeded: execution of {} needed more than {} gas", method, gas_limit)
+            ),
+            RunError::Contract(e) => e,
+        })
+    }
+
+    /// Instantiate a contract, compiling it first if it isn't cached, and
+    /// call its `method` export with `state` as its storage, metering every
+    /// instruction against `gas_limit` and keeping its memory within
+    /// `max_memory` bytes. Returns the outcome along with the gas consumed.
+    fn run_wasm(
+        code: ContractCode,
+        method: &str,
+        args: &[Value],
+        state: Arc<HashMap<Vec<u8>, Vec<u8>>>,
+        gas_limit: u64,
+        max_memory: usize,
+        deadline: Instant,
+    ) -> (Result<ExecutionOutput, RunError>, u64) {
+        let contract = match code.compile() {
+            Ok(contract) => contract,
+            Err(e) => return (Err(RunError::Contract(e)), 0),
+        };
+
+        // Memory limits differ between calls, so they're set on the store's
+        // copy of the engine rather than the one that compiled the module
+        let tunables = LimitingTunables::new(BaseTunables::for_target(&Target::default()), max_memory);
+        let memory_limit = tunables.limit();
+        let mut engine = contract.engine.clone();
+        engine.set_tunables(tunables);
+        let mut store = Store::new(engine);
+        let host_env = FunctionEnv::new(&mut store, HostEnv {
+            deadline,
+            memory_limit: max_memory as u64,
+            instance: None,
+            memory: None,
+            state,
+            writes: StorageWrites::new(),
+            events: EventBuffer::new(),
+            abort: None,
+        });
+
+        let result = Self::call_export(&mut store, &host_env, &contract, gas_limit, method, args);
+
+        let remaining = match host_env.as_ref(&store).instance.clone() {
+            Some(instance) => get_remaining_points(&mut store, &instance),
+            None => MeteringPoints::Remaining(gas_limit),
+        };
+        let (gas_used, exhausted) = match remaining {
+            MeteringPoints::Remaining(points) => (gas_limit - points, false),
+            MeteringPoints::Exhausted => (gas_limit, true),
+        };
+
+        // A failed memory.grow is not a trap itself, but contracts abort when
+        // they cannot allocate, which leaves their memory at the limit
+        let at_memory_limit = host_env.as_ref(&store).memory.as_ref()
+            .is_some_and(|memory| memory.view(&store).size() >= memory_limit);
+
+        let host = host_env.as_mut(&mut store);
+        let result = match (result, host.abort) {
+            (Ok(values), _) => Ok((values, std::mem::take(&mut host.writes), std::mem::take(&mut host.events))),
+            (Err(_), Some(abort)) => Err(RunError::Aborted(abort)),
+            (Err(_), None) if exhausted => Err(RunError::Aborted(HostAbort::OutOfGas)),
+            (Err(e), None) if at_memory_limit => Err(RunError::Contract(ContractError::ExecutionError(
+                format!("Memory limit of {} bytes reached: {}", max_memory, e)
+            ))),
+            (Err(e), None) => Err(RunError::Contract(e)),
+        };
+        (result, gas_used)
+    }
+
+    // Instantiate a compiled contract and call `method`, giving it
+    // `gas_limit` less whatever its start function used
+    fn call_export(
+        store: &mut Store,
+        host_env: &FunctionEnv<HostEnv>,
+        contract: &CompiledContract,
+        gas_limit: u64,
+        method: &str,
+        args: &[Value],
+    ) -> ContractResult<Vec<Value>> {
+        let module = &contract.module;
+        let mut imports = Imports::new();
+        imports.define("env", "gas", Function::new_typed_with_env(store, host_env, host_gas));
+        imports.define("env", "storage_read", Function::new_typed_with_env(store, host_env, host_storage_read));
+        imports.define("env", "storage_write", Function::new_typed_with_env(store, host_env, host_storage_write));
+        imports.define("env", "memory_limit", Function::new_typed_with_env(store, host_env, host_memory_limit));
+        imports.define("env", "emit", Function::new_typed_with_env(store, host_env, host_emit));
+
+        // Anything else the module imports links, but traps if it is called
+        for import in module.imports() {
+            if imports.exists(import.module(), import.name()) {
+                continue;
+            }
+            let name = format!("{}.{}", import.module(), import.name());
+            match import.ty() {
+                ExternType::Function(ty) => {
+                    let stub = Function::new(store, ty.clone(), move |_| {
+                        Err(RuntimeError::new(format!("host function {} is not available", name)))
+                    });
+                    imports.define(import.module(), import.name(), stub);
                 }
+                _ => return Err(ContractError::ExecutionError(
+                    format!("Contract imports unsupported {:?} {}", import.ty(), name)
+                )),
             }
         }
-        else {
-            Err(ContractError::NotImplemented(format!("Method {} not implemented", method)))
+
+        let instance = Instance::new(store, module, &imports).map_err(|e| ContractError::ExecutionError(
+            format!("Failed to instantiate contract: {}", e)
+        ))?;
+        let start_gas = match get_remaining_points(store, &instance) {
+            MeteringPoints::Remaining(points) => contract.initial_gas - points,
+            MeteringPoints::Exhausted => contract.initial_gas,
         };
+        set_remaining_points(store, &instance, gas_limit.saturating_sub(start_gas));
 
-        // End operation tracking
-        self.operation_tracker.end_operation(&contract_addr, OperationType::Execute);
+        let host = host_env.as_mut(store);
+        host.instance = Some(instance.clone());
+        if let Ok(memory) = instance.exports.get_memory("memory") {
+            host.memory = Some(memory.clone());
+        }
 
-        result
+        let function = instance.exports.get_function(method).map_err(|_| ContractError::ExecutionError(
+            format!("Contract does not export method {}", method)
+        ))?;
+
+        let params = function.ty(&*store).params().to_vec();
+        if params.len() != args.len() || params.iter().zip(args).any(|(param, arg)| *param != arg.ty()) {
+            return Err(ContractError::InvalidArguments(format!(
+                "Method {} expects arguments {:?}, got {:?}",
+                method, params, args.iter().map(Value::ty).collect::<Vec<_>>()
+            )));
+        }
+
+        let values = function.call(store, args).map_err(|e| ContractError::ExecutionError(
+            format!("Execution of {} trapped: {}", method, e)
+        ))?;
+        Ok(values.into_vec())
     }
 
     /// Attempt to rollback a contract to its previous version
@@ -566,27 +1546,28 @@ impl ContractRuntime {
             ));
         }
 
-        // Get snapshots for the contract
-        let snapshots = match self.state_manager.get_snapshots(contract_addr) {
-            Some(s) => s,
-            None => {
+        // The version being rolled back to is the one before the latest
+        let target_version = match self.registry.get_contract_versions(contract_addr) {
+            Ok(versions) if versions.len() >= 2 => versions[versions.len() - 2].metadata.version.clone(),
+            Ok(_) => {
+                self.operation_tracker.end_operation(contract_addr, OperationType::Rollback);
+                return Err(ContractError::StateRollbackFailed(
+                    "No previous version available for rollback".into()
+                ));
+            }
+            Err(e) => {
                 self.operation_tracker.end_operation(contract_addr, OperationType::Rollback);
-                return Err(ContractError::StateError("No snapshots found for contract".into()));
+                return Err(e);
             }
         };
 
-        if snapshots.len() < 2 {
+        // Restore the state saved while the contract ran that version, so the
+        // state schema matches the bytecode being restored
+        if let Err(e) = self.state_manager.restore_version_snapshot(*contract_addr, &target_version) {
             self.operation_tracker.end_operation(contract_addr, OperationType::Rollback);
-            return Err(ContractError::StateError("Not enough snapshots for rollback".into()));
-        }
-
-        // Get the previous snapshot
-        let previous_snapshot = &snapshots[snapshots.len() - 2];
-
-        // Restore state from previous snapshot
-        if let Err(e) = self.state_manager.restore_from_snapshot(*contract_addr, previous_snapshot.timestamp) {
-            self.operation_tracker.end_operation(contract_addr, OperationType::Rollback);
-            return Err(e);
+            return Err(ContractError::StateRollbackFailed(
+                format!("Cannot restore state of version {}: {}", target_version, e)
+            ));
         }
 
         // Attempt rollback in registry
@@ -601,6 +1582,101 @@ impl ContractRuntime {
         result
     }
 
+    /// Declare the prior versions `version` of a contract can be upgraded
+    /// from; upgrades to it from any other version are rejected
+    pub fn declare_compatibility(&mut self, contract_addr: &[u8; 32], version: &str, compatible_from: &[&str]) -> ContractResult<()> {
+        let sender = msg::sender().map_err(|e| ContractError::ExecutionError(e))?;
+        if !self.has_role(UPGRADER_ROLE, &sender) {
+            return Err(ContractError::AccessDenied(
+                "Sender does not have upgrader role".into()
+            ));
+        }
+
+        self.registry.declare_compatibility(*contract_addr, version, compatible_from)
+    }
+
+    /// Halt a contract in an emergency. Until it is unpaused, calls that
+    /// would change its state fail and leave it untouched.
+    pub fn pause_contract(&mut self, contract_addr: &[u8; 32]) -> ContractResult<()> {
+        self.check_pauser(contract_addr)?;
+        self.paused.insert(*contract_addr);
+        Ok(())
+    }
+
+    pub fn unpause_contract(&mut self, contract_addr: &[u8; 32]) -> ContractResult<()> {
+        self.check_pauser(contract_addr)?;
+        self.paused.remove(contract_addr);
+        Ok(())
+    }
+
+    pub fn is_paused(&self, contract_addr: &[u8; 32]) -> bool {
+        self.paused.contains(contract_addr)
+    }
+
+    fn check_pauser(&self, contract_addr: &[u8; 32]) -> ContractResult<()> {
+        let sender = msg::sender().map_err(|e| ContractError::ExecutionError(e))?;
+        if !self.has_role(PAUSER_ROLE, &sender) {
+            return Err(ContractError::AccessDenied(
+                "Sender does not have pauser role".into()
+            ));
+        }
+
+        if !self.contract_exists(contract_addr) {
+            return Err(ContractError::NotFound(
+                format!("Contract not found at address {:?}", contract_addr)
+            ));
+        }
+        Ok(())
+    }
+
+    fn paused_error() -> ContractError {
+        ContractError::InvalidOperation("Contract is paused".into())
+    }
+
+    /// Remove a contract from the registry so it can no longer be executed.
+    /// Its state stays behind until `gc_orphaned_state` collects it.
+    pub fn self_destruct(&mut self, contract_addr: &[u8; 32]) -> ContractResult<()> {
+        let sender = msg::sender().map_err(|e| ContractError::ExecutionError(e))?;
+        if !self.has_role(UPGRADER_ROLE, &sender) {
+            return Err(ContractError::AccessDenied(
+                "Sender does not have upgrader role".into()
+            ));
+        }
+
+        self.deregister(contract_addr)
+    }
+
+    /// Remove an obsolete contract and every version of it from the registry.
+    /// Like `self_destruct`, its state is left for `gc_orphaned_state`.
+    pub fn remove_contract(&mut self, contract_addr: &[u8; 32]) -> ContractResult<()> {
+        let sender = msg::sender().map_err(|e| ContractError::ExecutionError(e))?;
+        if !self.has_role(DEFAULT_ADMIN_ROLE, &sender) {
+            return Err(ContractError::AccessDenied(
+                "Sender does not have admin role".into()
+            ));
+        }
+
+        self.deregister(contract_addr)
+    }
+
+    fn deregister(&mut self, contract_addr: &[u8; 32]) -> ContractResult<()> {
+        self.registry.remove_contract(contract_addr)?;
+        self.execution_timeouts.remove(contract_addr);
+        self.resource_limits.remove(contract_addr);
+        self.paused.remove(contract_addr);
+        self.reentrancy_guards.remove(contract_addr);
+        self.modules.remove_contract(contract_addr);
+        Ok(())
+    }
+
+    /// Free the state, snapshots and diffs of contracts that are no longer
+    /// in the registry. Returns the number of state bytes reclaimed, or the
+    /// error the store failed with.
+    pub fn gc_orphaned_state(&mut self) -> ContractResult<usize> {
+        let registry = &self.registry;
+        self.state_manager.gc_orphaned(|addr| registry.get_contract_versions(addr).is_ok())
+    }
+
     // Registry query methods with enhanced error handling
     pub fn get_contract_versions(&self, address: &[u8; 32]) -> ContractResult<&Vec<ContractVersion>> {
         self.registry.get_contract_versions(address)
@@ -614,10 +1690,30 @@ impl ContractRuntime {
         self.registry.get_latest_version(address)
     }
 
+    pub fn resolve_version(&self, address: &[u8; 32], version: Option<&str>) -> ContractResult<&ContractVersion> {
+        self.registry.resolve_version(address, version)
+    }
+
+    pub fn get_upgrade_history(&self, address: &[u8; 32]) -> ContractResult<&Vec<UpgradeHistory>> {
+        self.registry.get_upgrade_history(address)
+    }
+
     pub fn list_all_contracts(&self) -> Vec<([u8; 32], &ContractVersion)> {
         self.registry.list_all_contracts()
     }
 
+    pub fn list_contracts_paginated(&self, offset: usize, limit: usize) -> (Vec<([u8; 32], &ContractVersion)>, usize) {
+        self.registry.list_contracts_paginated(offset, limit)
+    }
+
+    pub fn find_by_creation_range(&self, start: u64, end: u64) -> Vec<([u8; 32], &ContractVersion)> {
+        self.registry.find_by_creation_range(start, end)
+    }
+
+    pub fn find_by_update_range(&self, start: u64, end: u64) -> Vec<([u8; 32], &ContractVersion)> {
+        self.registry.find_by_update_range(start, end)
+    }
+
     pub fn search_by_description(&self, description: &str) -> Vec<([u8; 32], &ContractVersion)> {
         self.registry.search_by_description(description)
     }
@@ -631,10 +1727,25 @@ impl ContractRuntime {
         self.state_manager.get_state_diffs(contract_addr)
     }
 
+    /// Schema version the current state of a contract is in
+    pub fn get_schema_version(&self, contract_addr: &[u8; 32]) -> u32 {
+        self.state_manager.schema_version(contract_addr)
+    }
+
     pub fn get_state_snapshots(&self, contract_addr: &[u8; 32]) -> Option<&Vec<StateSnapshot>> {
         self.state_manager.get_snapshots(contract_addr)
     }
 
+    /// Keys added, modified and deleted between the snapshots of a contract
+    /// taken at `ts_a` and `ts_b`
+    pub fn diff_snapshots(&self, contract_addr: &[u8; 32], ts_a: u64, ts_b: u64) -> ContractResult<StateDiff> {
+        self.state_manager.diff_snapshots(contract_addr, ts_a, ts_b)
+    }
+
+    pub fn scrub_state(&self, after: Option<[u8; 32]>, budget: usize) -> (Vec<StateIntegrityReport>, Option<[u8; 32]>) {
+        self.state_manager.scrub_snapshots(after, budget)
+    }
+
     // New method for updating contract state
     pub async fn update_contract_state(&mut self, contract_addr: [u8; 32], key: Vec<u8>, value: Vec<u8>) -> ContractResult<()> {
         // Start operation tracking
@@ -655,6 +1766,37 @@ impl ContractRuntime {
         result
     }
 
+    /// Remove a key from the state of a contract
+    pub async fn delete_contract_state(&mut self, contract_addr: [u8; 32], key: &[u8]) -> ContractResult<()> {
+        self.operation_tracker.start_operation(contract_addr, OperationType::StateUpdate)?;
+
+        let result = self.validate_contract_state(&contract_addr)
+            .and_then(|_| self.state_manager.delete_state(contract_addr, key));
+
+        self.operation_tracker.end_operation(&contract_addr, OperationType::StateUpdate);
+
+        result
+    }
+
+    /// Validate a set of state updates to a contract without applying them
+    pub fn prepare_state_updates(&self, contract_addr: [u8; 32], updates: Vec<(Vec<u8>, Vec<u8>)>) -> ContractResult<PreparedBatch> {
+        self.validate_contract_state(&contract_addr)?;
+        self.state_manager.prepare_updates(contract_addr, updates)
+    }
+
+    /// Apply state updates validated by `prepare_state_updates`
+    pub fn commit_prepared(&mut self, batch: PreparedBatch) -> ContractResult<()> {
+        let contract_addr = *batch.contract_addr();
+        self.operation_tracker.start_operation(contract_addr, OperationType::StateUpdate)?;
+
+        let result = self.validate_contract_state(&contract_addr)
+            .and_then(|_| self.state_manager.commit_prepared(batch));
+
+        self.operation_tracker.end_operation(&contract_addr, OperationType::StateUpdate);
+
+        result
+    }
+
     // Get current operation metrics
     pub fn get_active_operations(&self) -> usize {
         self.operation_tracker.active_operations.values().map(|ops| ops.len()).sum()
@@ -668,3 +1810,205 @@ impl ContractRuntime {
             .count()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upgrade_and_rollback_exclude_each_other() {
+        let contract_addr = [1u8; 32];
+
+        for (first, second) in [
+            (OperationType::Upgrade, OperationType::Rollback),
+            (OperationType::Rollback, OperationType::Upgrade),
+            (OperationType::Upgrade, OperationType::Upgrade),
+        ] {
+            let mut tracker = OperationTracker::new();
+            tracker.start_operation(contract_addr, first).unwrap();
+
+            let result = tracker.start_operation(contract_addr, second);
+            assert!(matches!(result, Err(ContractError::OperationConflict(_))), "{:?} then {:?}: {:?}", first, second, result);
+
+            // Other contracts and other kinds of operations are unaffected
+            tracker.start_operation([2u8; 32], second).unwrap();
+            tracker.start_operation(contract_addr, OperationType::Execute).unwrap();
+
+            // Once the first finishes the second can start
+            tracker.end_operation(&contract_addr, first);
+            tracker.start_operation(contract_addr, second).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_call_receipts_bounded() {
+        let mut runtime = ContractRuntime::new();
+        let receipt = |tx: usize, call_index: u32| CallReceipt {
+            tx_hash: Hash::new(&tx.to_le_bytes()),
+            call_index,
+            contract_addr: [1u8; 32],
+            method: "add".into(),
+            gas_used: 1,
+            success: true,
+            result: Some(vec![WasmValue::I32(tx as i32)]),
+        };
+        for tx in 0..MAX_CALL_RECEIPTS {
+            runtime.record_call_receipt(receipt(tx, 0));
+        }
+        runtime.record_call_receipt(receipt(0, 1));
+
+        // The oldest receipt makes room for the newest
+        assert_eq!(runtime.call_receipts.len(), MAX_CALL_RECEIPTS);
+        assert_eq!(runtime.get_call_result(&Hash::new(&0usize.to_le_bytes()), 0), None);
+        assert_eq!(runtime.get_call_result(&Hash::new(&0usize.to_le_bytes()), 1), Some(vec![WasmValue::I32(0)]));
+        assert_eq!(runtime.get_call_result(&Hash::new(&1usize.to_le_bytes()), 0), Some(vec![WasmValue::I32(1)]));
+    }
+
+    const TEST_ACCOUNT: [u8; 32] = [9u8; 32];
+
+    const TEST_LIMITS: ResourceLimits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    // A runtime where the test account may deploy and execute contracts
+    fn test_runtime() -> ContractRuntime {
+        let mut runtime = ContractRuntime::new();
+        msg::test_utils::set_sender(TEST_ACCOUNT).unwrap();
+        runtime.grant_role(DEFAULT_ADMIN_ROLE, TEST_ACCOUNT).unwrap();
+        runtime.grant_role(DEPLOYER_ROLE, TEST_ACCOUNT).unwrap();
+        runtime.grant_role(EXECUTOR_ROLE, TEST_ACCOUNT).unwrap();
+        runtime
+    }
+
+    // Deploy version 1.0.0 of `wat`, with every method taking `i32` arguments
+    async fn deploy(runtime: &mut ContractRuntime, contract_addr: &[u8; 32], wat: &str, methods: &[(&str, usize)]) {
+        let abi = ContractABI {
+            methods: methods.iter().map(|(name, arity)| ContractMethod {
+                name: name.to_string(),
+                inputs: (0..*arity).map(|i| ContractParam {
+                    name: format!("arg{}", i),
+                    param_type: "i32".into(),
+                    indexed: false,
+                }).collect(),
+                outputs: vec![],
+                payable: false,
+                default_gas: None,
+            }).collect(),
+            events: vec![],
+            standards: vec![],
+        };
+        let metadata = ContractMetadata {
+            version: "1.0.0".into(),
+            created_at: 1234567890,
+            updated_at: 1234567890,
+            author: TEST_ACCOUNT,
+            description: "Test Contract".into(),
+            is_upgradeable: true,
+            allow_major_upgrade: false,
+        };
+        runtime.deploy_contract(wat.as_bytes(), contract_addr, &abi, metadata, &TEST_LIMITS).await.unwrap();
+    }
+
+    fn test_env(gas_limit: u64) -> ContractEnvironment {
+        ContractEnvironment {
+            gas_limit: Some(gas_limit),
+            block_number: 1,
+            timestamp: 1234567890,
+            caller: TEST_ACCOUNT,
+            value: 0,
+            resource_limits: TEST_LIMITS,
+            gas_used: Arc::new(RwLock::new(0)),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_transaction_leaves_no_trace() {
+        // `store` writes its value under its key and emits it as an event
+        let wat = r#"
+        (module
+          (import "env" "storage_write" (func $storage_write (param i32 i32 i32 i32)))
+          (import "env" "emit" (func $emit (param i32 i32 i32 i32)))
+          (memory (export "memory") 1)
+          (func (export "store") (param $key i32) (param $value i32)
+            (i32.store (i32.const 64) (local.get $key))
+            (i32.store (i32.const 68) (local.get $value))
+            (call $storage_write (i32.const 64) (i32.const 4) (i32.const 68) (i32.const 4))
+            (call $emit (i32.const 0) (i32.const 1) (i32.const 68) (i32.const 4))))
+        "#;
+        let with_state = [1u8; 32];
+        let without_state = [2u8; 32];
+
+        let mut runtime = test_runtime();
+        for addr in [&with_state, &without_state] {
+            deploy(&mut runtime, addr, wat, &[("store", 2)]).await;
+        }
+        runtime.state_manager.remove_state(without_state).unwrap();
+
+        let state_before = runtime.get_contract_state(&with_state).cloned();
+        let snapshots_before = runtime.get_state_snapshots(&with_state).map_or(0, Vec::len);
+
+        let env = test_env(1_000_000);
+        let call = |contract_addr: [u8; 32], args: Vec<Value>| ContractCall {
+            contract_addr,
+            method: "store".into(),
+            args,
+            env: env.clone(),
+            version: None,
+            priority: CallPriority::Normal,
+        };
+
+        // Both contracts are written to before the last call fails
+        let result = runtime.execute_transaction(&[
+            call(with_state, vec![Value::I32(1), Value::I32(42)]),
+            call(without_state, vec![Value::I32(2), Value::I32(7)]),
+            call(with_state, vec![Value::I32(1)]),
+        ]).await;
+        assert!(matches!(result, Err(ContractError::InvalidArguments(_))), "Unexpected result: {:?}", result);
+
+        assert_eq!(runtime.get_contract_state(&with_state).cloned(), state_before);
+        assert_eq!(runtime.get_contract_state(&without_state), None);
+        assert_eq!(runtime.get_state_snapshots(&with_state).map_or(0, Vec::len), snapshots_before);
+        assert!(runtime.get_state_snapshots(&without_state).map_or(true, Vec::is_empty));
+        for addr in [&with_state, &without_state] {
+            assert!(runtime.get_logs(addr, 0, u64::MAX).is_empty());
+        }
+
+        msg::test_utils::clear_sender().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_compiled_contracts_are_cached() {
+        // Loops `iterations` times
+        let wat = r#"
+        (module
+          (func (export "spin") (param $iterations i32)
+            (block $done
+              (loop $continue
+                (br_if $done (i32.eqz (local.get $iterations)))
+                (local.set $iterations (i32.sub (local.get $iterations) (i32.const 1)))
+                (br $continue)))))
+        "#;
+        let contract_addr = [3u8; 32];
+        let mut runtime = test_runtime();
+        deploy(&mut runtime, &contract_addr, wat, &[("spin", 1)]).await;
+
+        // Every call runs the module compiled for the first, each with its own gas limit
+        let gas_used = runtime.execute_contract_metered(contract_addr, "spin", vec![Value::I32(100)], &test_env(100_000), None)
+            .await.unwrap().gas_used;
+        assert_eq!(runtime.modules.len(), 1);
+        assert!(runtime.execute_contract(contract_addr, "spin", vec![Value::I32(100)], &test_env(gas_used - 1), None).await.is_err());
+        let again = runtime.execute_contract_metered(contract_addr, "spin", vec![Value::I32(100)], &test_env(gas_used), None)
+            .await.unwrap();
+        assert_eq!(again.gas_used, gas_used);
+        assert_eq!(runtime.modules.len(), 1);
+
+        // Removing the contract drops its compiled module
+        runtime.remove_contract(&contract_addr).unwrap();
+        assert_eq!(runtime.modules.len(), 0);
+
+        msg::test_utils::clear_sender().unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot, Mutex, RwLock};
+use tokio::task::JoinHandle;
+use wasmer::Value;
+use super::{ContractEnvironment, ContractError, ContractResult, ContractRuntime};
+
+const DEFAULT_WORKERS: usize = 4;
+const DEFAULT_QUEUE_CAPACITY: usize = 64;
+
+/// Configuration for the contract execution pool
+#[derive(Debug, Clone, Copy)]
+pub struct ExecutionPoolConfig {
+    /// Number of calls executed at the same time
+    pub workers: usize,
+    /// Calls that can wait for a worker before submitters are held back
+    pub queue_capacity: usize,
+}
+
+impl Default for ExecutionPoolConfig {
+    fn default() -> Self {
+        ExecutionPoolConfig {
+            workers: DEFAULT_WORKERS,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+        }
+    }
+}
+
+/// Order in which queued calls are picked up by the pool's workers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CallPriority {
+    #[default]
+    Normal,
+    /// Calls made by governance once a proposal's timelock has passed. These
+    /// run ahead of every queued normal call.
+    Governance,
+}
+
+/// A contract method call submitted to the pool
+#[derive(Clone)]
+pub struct ContractCall {
+    pub contract_addr: [u8; 32],
+    pub method: String,
+    pub args: Vec<Value>,
+    pub env: ContractEnvironment,
+    pub version: Option<String>,
+    pub priority: CallPriority,
+}
+
+// One lock per contract, so calls to the same contract don't overlap
+type ContractLocks = Arc<Mutex<HashMap<[u8; 32], Arc<Mutex<()>>>>>;
+
+struct Job {
+    call: ContractCall,
+    reply: oneshot::Sender<ContractResult<Vec<Value>>>,
+}
+
+// Receiving ends of the per-priority queues
+struct Queues {
+    governance: mpsc::Receiver<Job>,
+    normal: mpsc::Receiver<Job>,
+}
+
+impl Queues {
+    /// Next job to run, taking governance calls first. None once the pool
+    /// has shut down and both queues are drained.
+    async fn next(&mut self) -> Option<Job> {
+        tokio::select! {
+            biased;
+            Some(job) = self.governance.recv() => Some(job),
+            Some(job) = self.normal.recv() => Some(job),
+            else => None,
+        }
+    }
+}
+
+/// Executes contract calls on a fixed number of workers.
+///
+/// Calls to different contracts run concurrently, calls to the same contract
+/// run one at a time. Queued governance calls are started before queued
+/// normal calls. The runtime lock is only held while
+/// a call is checked and recorded, not while the method runs.
+pub struct ExecutionPool {
+    governance_sender: mpsc::Sender<Job>,
+    sender: mpsc::Sender<Job>,
+    workers: Vec<JoinHandle<()>>,
+    active: Arc<AtomicUsize>,
+    peak: Arc<AtomicUsize>,
+}
+
+impl ExecutionPool {
+    pub fn new(runtime: Arc<RwLock<ContractRuntime>>, config: ExecutionPoolConfig) -> Self {
+        let (governance_sender, governance) = mpsc::channel::<Job>(config.queue_capacity.max(1));
+        let (sender, normal) = mpsc::channel::<Job>(config.queue_capacity.max(1));
+        let queues = Arc::new(Mutex::new(Queues { governance, normal }));
+        let contract_locks: ContractLocks = Arc::new(Mutex::new(HashMap::new()));
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let workers = (0..config.workers.max(1))
+            .map(|_| {
+                let runtime = runtime.clone();
+                let queues = queues.clone();
+                let contract_locks = contract_locks.clone();
+                let active = active.clone();
+                let peak = peak.clone();
+                tokio::spawn(async move {
+                    loop {
+                        // Release the queue before running so other workers can take jobs
+                        let job = match queues.lock().await.next().await {
+                            Some(job) => job,
+                            None => break,
+                        };
+
+                        let contract_lock = contract_locks.lock().await
+                            .entry(job.call.contract_addr)
+                            .or_default()
+                            .clone();
+                        let _guard = contract_lock.lock().await;
+
+                        let running = active.fetch_add(1, Ordering::SeqCst) + 1;
+                        peak.fetch_max(running, Ordering::SeqCst);
+                        let result = Self::execute(&runtime, &job.call).await;
+                        active.fetch_sub(1, Ordering::SeqCst);
+
+                        // The submitter may have stopped waiting for the result
+                        let _ = job.reply.send(result);
+                    }
+                })
+            })
+            .collect();
+
+        ExecutionPool {
+            governance_sender,
+            sender,
+            workers,
+            active,
+            peak,
+        }
+    }
+
+    async fn execute(runtime: &RwLock<ContractRuntime>, call: &ContractCall) -> ContractResult<Vec<Value>> {
+        let prepared = runtime.write().await
+            .begin_execution(call.contract_addr, &call.method, &call.env, call.version.as_deref())?;
+        let version = prepared.version.clone();
+        let result = ContractRuntime::run_with_timeout(prepared, &call.method, &call.args, &call.env).await;
+        runtime.write().await.finish_execution(&call.contract_addr, &version, call.env.block_number, result)
+    }
+
+    /// Queue a call, waiting for space if the queue is full. The returned
+    /// receiver resolves once the call has executed.
+    pub async fn submit(&self, call: ContractCall) -> ContractResult<oneshot::Receiver<ContractResult<Vec<Value>>>> {
+        let (reply, result) = oneshot::channel();
+        self.queue(call.priority).send(Job { call, reply }).await
+            .map_err(|_| ContractError::InvalidOperation("Execution pool is shut down".into()))?;
+        Ok(result)
+    }
+
+    /// Queue a call without waiting, failing if the queue is full
+    pub fn try_submit(&self, call: ContractCall) -> ContractResult<oneshot::Receiver<ContractResult<Vec<Value>>>> {
+        let (reply, result) = oneshot::channel();
+        self.queue(call.priority).try_send(Job { call, reply }).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => ContractError::ConcurrencyLimitExceeded(
+                "Execution pool queue is full".into()
+            ),
+            mpsc::error::TrySendError::Closed(_) => ContractError::InvalidOperation(
+                "Execution pool is shut down".into()
+            ),
+        })?;
+        Ok(result)
+    }
+
+    fn queue(&self, priority: CallPriority) -> &mpsc::Sender<Job> {
+        match priority {
+            CallPriority::Governance => &self.governance_sender,
+            CallPriority::Normal => &self.sender,
+        }
+    }
+
+    /// Number of calls currently executing
+    pub fn active_calls(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// Highest number of calls that have executed at the same time
+    pub fn peak_concurrency(&self) -> usize {
+        self.peak.load(Ordering::SeqCst)
+    }
+
+    /// Stop accepting calls and wait for queued calls to finish
+    pub async fn shutdown(self) {
+        drop(self.governance_sender);
+        drop(self.sender);
+        for worker in self.workers {
+            let _ = worker.await;
+        }
+    }
+}
@@ -1,6 +1,23 @@
-use std::collections::{HashMap, BTreeMap};
+use std::collections::{HashMap, HashSet, BTreeMap, BTreeSet};
 use serde::{Serialize, Deserialize};
-use super::{ContractMetadata, ContractVersion, ContractResult, ContractError};
+use super::{ContractABI, ContractMetadata, ContractParam, ContractVersion, ContractResult, ContractError};
+use crate::format;
+use crate::storage::{BatchOp, KeyValueStore, StorageError};
+
+// Keys the registry is persisted under: the list of contracts, and the
+// versions and history of each contract after a prefix
+const REGISTRY_CONTRACTS_KEY: &[u8] = b"registry";
+const REGISTRY_VERSIONS_PREFIX: &[u8] = b"registry:";
+const REGISTRY_HISTORY_PREFIX: &[u8] = b"registry_history:";
+const REGISTRY_COMPATIBILITY_KEY: &[u8] = b"registry_compatibility";
+
+// Parameter types an ABI may declare: the integer WASM value types, and
+// strings and bytes passed through memory. Floats are left out, as bytecode
+// using them is rejected.
+const KNOWN_PARAM_TYPES: [&str; 4] = ["i32", "i64", "string", "bytes"];
+
+// Versions of a contract and the prior versions each can be upgraded from
+type CompatibilityMatrix = BTreeMap<String, BTreeSet<String>>;
 
 /// Registry index types for efficient contract lookup
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -13,13 +30,22 @@ pub enum RegistryIndex {
 }
 
 /// Tracks the upgrade history of a contract
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct UpgradeHistory {
-    from_version: String,
-    to_version: String,
-    timestamp: u64,
-    successful: bool,
-    rollback_performed: bool,
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UpgradeHistory {
+    pub from_version: String,
+    pub to_version: String,
+    /// Update time of the version upgraded to
+    pub timestamp: u64,
+    /// False once the upgrade has been rolled back
+    pub successful: bool,
+    pub rollback_performed: bool,
+}
+
+/// Upgrades and rolled back versions of a contract, as persisted
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct RegistryHistory {
+    pub(crate) upgrades: Vec<UpgradeHistory>,
+    pub(crate) rolled_back: Vec<String>,
 }
 
 /// Contract registry for efficient contract lookup and management
@@ -36,6 +62,12 @@ pub struct ContractRegistry {
 
     // Upgrade history for rollback support
     upgrade_history: HashMap<[u8; 32], Vec<UpgradeHistory>>,
+
+    // Versions removed by rollback, to tell them apart from unknown versions
+    rolled_back: HashMap<[u8; 32], HashSet<String>>,
+
+    // Declared upgrade paths; versions without an entry accept any older version
+    compatibility: HashMap<[u8; 32], CompatibilityMatrix>,
 }
 
 impl ContractRegistry {
@@ -47,7 +79,75 @@ impl ContractRegistry {
             creation_time_index: BTreeMap::new(),
             update_time_index: BTreeMap::new(),
             upgrade_history: HashMap::new(),
+            rolled_back: HashMap::new(),
+            compatibility: HashMap::new(),
+        }
+    }
+
+    /// Write every contract version to `store` in one batch
+    pub fn persist<S: KeyValueStore>(&self, store: &mut S) -> Result<(), StorageError> {
+        let mut addresses: Vec<_> = self.versions.keys().copied().collect();
+        addresses.sort();
+
+        let mut ops = Vec::with_capacity(addresses.len() + 1);
+        for addr in &addresses {
+            let value = format::encode(&self.versions[addr])
+                .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+            ops.push(BatchOp::Set([REGISTRY_VERSIONS_PREFIX, addr.as_slice()].concat(), value));
+
+            let mut rolled_back: Vec<String> = self.rolled_back.get(addr).into_iter().flatten().cloned().collect();
+            rolled_back.sort();
+            let history = RegistryHistory {
+                upgrades: self.upgrade_history.get(addr).cloned().unwrap_or_default(),
+                rolled_back,
+            };
+            let value = format::encode(&history)
+                .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+            ops.push(BatchOp::Set([REGISTRY_HISTORY_PREFIX, addr.as_slice()].concat(), value));
+        }
+        let index = bincode::serialize(&addresses)
+            .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+        ops.push(BatchOp::Set(REGISTRY_CONTRACTS_KEY.to_vec(), index));
+
+        let compatibility: BTreeMap<_, _> = self.compatibility.iter().collect();
+        let compatibility = bincode::serialize(&compatibility)
+            .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+        ops.push(BatchOp::Set(REGISTRY_COMPATIBILITY_KEY.to_vec(), compatibility));
+
+        store.batch(ops)
+    }
+
+    /// Rebuild a registry from what `persist` wrote to `store`. Versions and
+    /// history are taken as they were persisted, without validating them again.
+    pub fn restore<S: KeyValueStore>(store: &S) -> ContractResult<Self> {
+        let storage_error = |e: StorageError| ContractError::StateError(format!("Failed to load contract registry: {:?}", e));
+        let decode_error = |e: bincode::Error| ContractError::StateError(format!("Failed to decode contract registry: {}", e));
+
+        let mut registry = ContractRegistry::new();
+        let Some(index) = store.get(REGISTRY_CONTRACTS_KEY).map_err(storage_error)? else {
+            return Ok(registry);
+        };
+        let addresses: Vec<[u8; 32]> = bincode::deserialize(&index).map_err(decode_error)?;
+
+        for addr in addresses {
+            let Some(data) = store.get(&[REGISTRY_VERSIONS_PREFIX, addr.as_slice()].concat()).map_err(storage_error)? else {
+                continue;
+            };
+            let versions: Vec<ContractVersion> = format::decode(&data).map_err(decode_error)?;
+            let history = store.get(&[REGISTRY_HISTORY_PREFIX, addr.as_slice()].concat()).map_err(storage_error)?
+                .map(|data| format::decode(&data))
+                .transpose()
+                .map_err(decode_error)?;
+            registry.restore_contract(addr, versions, history);
+        }
+
+        // Loaded last: upgrades already made are not checked again, even
+        // against declarations made after them
+        if let Some(data) = store.get(REGISTRY_COMPATIBILITY_KEY).map_err(storage_error)? {
+            registry.compatibility = bincode::deserialize(&data).map_err(decode_error)?;
         }
+
+        Ok(registry)
     }
 
     /// Verify bytecode integrity
@@ -64,6 +164,48 @@ impl ContractRegistry {
         Ok(())
     }
 
+    /// Check that method and event names are non-empty and unique, and that
+    /// every parameter has a known type
+    fn validate_abi(&self, abi: &ContractABI) -> ContractResult<()> {
+        let check_params = |owner: &str, params: &[ContractParam]| {
+            match params.iter().find(|p| !KNOWN_PARAM_TYPES.contains(&p.param_type.as_str())) {
+                Some(param) => Err(ContractError::InvalidArguments(format!(
+                    "Parameter {} of {} has unknown type {}", param.name, owner, param.param_type
+                ))),
+                None => Ok(()),
+            }
+        };
+
+        let mut methods = HashSet::new();
+        for method in &abi.methods {
+            if method.name.is_empty() {
+                return Err(ContractError::InvalidArguments("ABI method name is empty".into()));
+            }
+            if !methods.insert(method.name.as_str()) {
+                return Err(ContractError::InvalidArguments(
+                    format!("ABI method {} is declared more than once", method.name)
+                ));
+            }
+            check_params(&method.name, &method.inputs)?;
+            check_params(&method.name, &method.outputs)?;
+        }
+
+        let mut events = HashSet::new();
+        for event in &abi.events {
+            if event.name.is_empty() {
+                return Err(ContractError::InvalidArguments("ABI event name is empty".into()));
+            }
+            if !events.insert(event.name.as_str()) {
+                return Err(ContractError::InvalidArguments(
+                    format!("ABI event {} is declared more than once", event.name)
+                ));
+            }
+            check_params(&event.name, &event.inputs)?;
+        }
+
+        Ok(())
+    }
+
     /// Check version compatibility
     fn check_version_compatibility(&self, address: &[u8; 32], new_version: &ContractVersion) -> ContractResult<()> {
         if let Some(versions) = self.versions.get(address) {
@@ -88,6 +230,19 @@ impl ContractRegistry {
                         "Current contract version is not upgradeable".into()
                     ));
                 }
+
+                // A declared upgrade path must include the current version
+                let declared = self.compatibility.get(address)
+                    .and_then(|matrix| matrix.get(&new_version.metadata.version));
+                if let Some(compatible_from) = declared {
+                    if !compatible_from.contains(&latest.metadata.version) {
+                        return Err(ContractError::VersionIncompatible(
+                            format!("Version {} cannot be upgraded to from {}; compatible from: {}",
+                                new_version.metadata.version, latest.metadata.version,
+                                compatible_from.iter().cloned().collect::<Vec<_>>().join(", "))
+                        ));
+                    }
+                }
             }
         }
         Ok(())
@@ -100,10 +255,40 @@ impl ContractRegistry {
         Ok(())
     }
 
+    /// Refuse an upgrade to a higher major version unless the new version
+    /// opts in with `allow_major_upgrade`
+    fn check_major_upgrade(&self, address: &[u8; 32], new_version: &ContractVersion) -> ContractResult<()> {
+        if new_version.metadata.allow_major_upgrade {
+            return Ok(());
+        }
+        let Some(latest) = self.versions.get(address).and_then(|versions| versions.last()) else {
+            return Ok(());
+        };
+
+        // Malformed versions are reported by the compatibility check
+        let (Ok(current_ver), Ok(new_ver)) = (
+            semver::Version::parse(&latest.metadata.version),
+            semver::Version::parse(&new_version.metadata.version),
+        ) else {
+            return Ok(());
+        };
+
+        if new_ver.major > current_ver.major {
+            return Err(ContractError::VersionIncompatible(
+                format!("Upgrade from {} to {} changes the major version and is not allowed by the new version",
+                    latest.metadata.version, new_version.metadata.version)
+            ));
+        }
+        Ok(())
+    }
+
     /// Register a new contract version with validation
     pub fn register_version(&mut self, address: [u8; 32], version: ContractVersion) -> ContractResult<()> {
+        self.check_major_upgrade(&address, &version)?;
+
         // Verify bytecode
         self.verify_bytecode(&version.bytecode)?;
+        self.validate_abi(&version.abi)?;
 
         // Check version compatibility
         self.check_version_compatibility(&address, &version)?;
@@ -120,6 +305,11 @@ impl ContractRegistry {
             None
         };
 
+        // Re-registering a rolled back version makes it available again
+        if let Some(rolled_back) = self.rolled_back.get_mut(&address) {
+            rolled_back.remove(&version.metadata.version);
+        }
+
         // Add to main storage
         self.versions
             .entry(address)
@@ -127,6 +317,55 @@ impl ContractRegistry {
             .push(version.clone());
 
         // Update indexes
+        self.index_version(address, &version);
+
+        // Record upgrade history if this is an upgrade
+        if let Some(prev) = previous_version {
+            let history = UpgradeHistory {
+                from_version: prev.metadata.version,
+                to_version: version.metadata.version,
+                timestamp: version.metadata.updated_at,
+                successful: true,
+                rollback_performed: false,
+            };
+            self.upgrade_history
+                .entry(address)
+                .or_insert_with(Vec::new)
+                .push(history);
+        }
+
+        Ok(())
+    }
+
+    /// Add the versions and history of a contract as persisted. Registries
+    /// persisted before their history was have it derived from the versions.
+    fn restore_contract(&mut self, address: [u8; 32], versions: Vec<ContractVersion>, history: Option<RegistryHistory>) {
+        for version in &versions {
+            self.index_version(address, version);
+        }
+
+        let history = history.unwrap_or_else(|| RegistryHistory {
+            upgrades: versions.windows(2).map(|pair| UpgradeHistory {
+                from_version: pair[0].metadata.version.clone(),
+                to_version: pair[1].metadata.version.clone(),
+                timestamp: pair[1].metadata.updated_at,
+                successful: true,
+                rollback_performed: false,
+            }).collect(),
+            rolled_back: Vec::new(),
+        });
+        if !history.upgrades.is_empty() {
+            self.upgrade_history.insert(address, history.upgrades);
+        }
+        if !history.rolled_back.is_empty() {
+            self.rolled_back.insert(address, history.rolled_back.into_iter().collect());
+        }
+
+        self.versions.insert(address, versions);
+    }
+
+    /// Add a version to the lookup indexes
+    fn index_version(&mut self, address: [u8; 32], version: &ContractVersion) {
         self.version_index
             .entry(version.metadata.version.clone())
             .or_insert_with(Vec::new)
@@ -146,25 +385,28 @@ impl ContractRegistry {
             .entry(version.metadata.updated_at)
             .or_insert_with(Vec::new)
             .push(address);
+    }
 
-        // Record upgrade history if this is an upgrade
-        if let Some(prev) = previous_version {
-            let history = UpgradeHistory {
-                from_version: prev.metadata.version,
-                to_version: version.metadata.version,
-                timestamp: version.metadata.updated_at,
-                successful: true,
-                rollback_performed: false,
-            };
-            self.upgrade_history
-                .entry(address)
-                .or_insert_with(Vec::new)
-                .push(history);
-        }
+    /// Declare the prior versions `version` of a contract can be upgraded
+    /// from. Upgrades to it from any other version are then rejected, even
+    /// when its semantic version is greater.
+    pub fn declare_compatibility(&mut self, address: [u8; 32], version: &str, compatible_from: &[&str]) -> ContractResult<()> {
+        semver::Version::parse(version)
+            .map_err(|_| ContractError::VersionIncompatible(format!("Invalid version format: {}", version)))?;
 
+        self.compatibility
+            .entry(address)
+            .or_default()
+            .insert(version.to_string(), compatible_from.iter().map(|v| v.to_string()).collect());
         Ok(())
     }
 
+    /// Prior versions `version` of a contract was declared compatible with,
+    /// if any were declared
+    pub fn get_compatibility(&self, address: &[u8; 32], version: &str) -> Option<&BTreeSet<String>> {
+        self.compatibility.get(address)?.get(version)
+    }
+
     /// Rollback to previous version
     pub fn rollback_version(&mut self, address: [u8; 32]) -> ContractResult<()> {
         let versions = self.versions.get_mut(&address)
@@ -177,7 +419,12 @@ impl ContractRegistry {
         }
 
         // Remove latest version
-        versions.pop();
+        if let Some(removed) = versions.pop() {
+            self.rolled_back
+                .entry(address)
+                .or_default()
+                .insert(removed.metadata.version);
+        }
 
         // Update upgrade history
         if let Some(history) = self.upgrade_history.get_mut(&address) {
@@ -190,6 +437,35 @@ impl ContractRegistry {
         Ok(())
     }
 
+    /// Remove a contract, all its versions and its history from the
+    /// registry. Returns the removed versions.
+    pub fn remove_contract(&mut self, address: &[u8; 32]) -> ContractResult<Vec<ContractVersion>> {
+        let versions = self.versions.remove(address)
+            .ok_or_else(|| ContractError::NotFound("Contract not found".into()))?;
+
+        self.version_index.retain(|_, addresses| {
+            addresses.retain(|a| a != address);
+            !addresses.is_empty()
+        });
+        self.author_index.retain(|_, addresses| {
+            addresses.retain(|a| a != address);
+            !addresses.is_empty()
+        });
+        self.creation_time_index.retain(|_, addresses| {
+            addresses.retain(|a| a != address);
+            !addresses.is_empty()
+        });
+        self.update_time_index.retain(|_, addresses| {
+            addresses.retain(|a| a != address);
+            !addresses.is_empty()
+        });
+        self.upgrade_history.remove(address);
+        self.rolled_back.remove(address);
+        self.compatibility.remove(address);
+
+        Ok(versions)
+    }
+
     /// Find contracts by metadata field with enhanced error handling
     pub fn find_by_index(&self, index: RegistryIndex) -> ContractResult<Vec<([u8; 32], &ContractVersion)>> {
         let addresses = match &index {
@@ -244,6 +520,37 @@ impl ContractRegistry {
         Ok(results)
     }
 
+    /// Contracts with a version created between `start` and `end` inclusive,
+    /// with their latest versions
+    pub fn find_by_creation_range(&self, start: u64, end: u64) -> Vec<([u8; 32], &ContractVersion)> {
+        self.find_in_time_range(&self.creation_time_index, start, end)
+    }
+
+    /// Contracts with a version updated between `start` and `end` inclusive,
+    /// with their latest versions
+    pub fn find_by_update_range(&self, start: u64, end: u64) -> Vec<([u8; 32], &ContractVersion)> {
+        self.find_in_time_range(&self.update_time_index, start, end)
+    }
+
+    fn find_in_time_range(&self, index: &BTreeMap<u64, Vec<[u8; 32]>>, start: u64, end: u64) -> Vec<([u8; 32], &ContractVersion)> {
+        if start > end {
+            return Vec::new();
+        }
+
+        // In time order; a contract with several versions in range is listed once
+        let mut found = HashSet::new();
+        let mut results = Vec::new();
+        for addr in index.range(start..=end).flat_map(|(_, addresses)| addresses) {
+            if !found.insert(*addr) {
+                continue;
+            }
+            if let Some(latest) = self.versions.get(addr).and_then(|versions| versions.last()) {
+                results.push((*addr, latest));
+            }
+        }
+        results
+    }
+
     /// Get all versions of a contract with enhanced error context
     pub fn get_contract_versions(&self, address: &[u8; 32]) -> ContractResult<&Vec<ContractVersion>> {
         self.versions
@@ -256,12 +563,28 @@ impl ContractRegistry {
     /// Get specific version of a contract with detailed error handling
     pub fn get_contract_version(&self, address: &[u8; 32], version: &str) -> ContractResult<&ContractVersion> {
         let versions = self.get_contract_versions(address)?;
-        versions
-            .iter()
-            .find(|v| v.metadata.version == version)
-            .ok_or_else(|| ContractError::VersionNotFound(
-                format!("Version {} not found for contract {:?}", version, address)
-            ))
+        if let Some(found) = versions.iter().find(|v| v.metadata.version == version) {
+            return Ok(found);
+        }
+
+        if self.rolled_back.get(address).is_some_and(|removed| removed.contains(version)) {
+            return Err(ContractError::VersionRolledBack(
+                format!("Version {} of contract {:?} was removed by a rollback", version, address)
+            ));
+        }
+
+        Err(ContractError::VersionNotFound(
+            format!("Version {} not found for contract {:?}", version, address)
+        ))
+    }
+
+    /// Resolve the version to use for a call: the requested version if given,
+    /// otherwise the current latest version
+    pub fn resolve_version(&self, address: &[u8; 32], version: Option<&str>) -> ContractResult<&ContractVersion> {
+        match version {
+            Some(v) => self.get_contract_version(address, v),
+            None => self.get_latest_version(address),
+        }
     }
 
     /// Get latest version of a contract with enhanced error context
@@ -293,6 +616,17 @@ impl ContractRegistry {
             .collect()
     }
 
+    /// Up to `limit` contracts with their latest versions, ordered by address
+    /// and starting at the `offset`th, along with the total number of contracts
+    pub fn list_contracts_paginated(&self, offset: usize, limit: usize) -> (Vec<([u8; 32], &ContractVersion)>, usize) {
+        let mut contracts = self.list_all_contracts();
+        contracts.sort_unstable_by_key(|(addr, _)| *addr);
+
+        let total = contracts.len();
+        let page = contracts.into_iter().skip(offset).take(limit).collect();
+        (page, total)
+    }
+
     /// Search contracts by partial description
     pub fn search_by_description(&self, description: &str) -> Vec<([u8; 32], &ContractVersion)> {
         self.versions
@@ -313,7 +647,7 @@ impl ContractRegistry {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::contract::{ContractABI, ContractMetadata};
+    use crate::contract::{ContractEvent, ContractMethod};
 
     fn create_test_version(version: &str, author: [u8; 32], time: u64) -> ContractVersion {
         ContractVersion {
@@ -325,6 +659,7 @@ mod tests {
                 author,
                 description: format!("Test contract version {}", version),
                 is_upgradeable: true,
+                allow_major_upgrade: false,
             },
             abi: ContractABI {
                 methods: vec![],
@@ -334,6 +669,60 @@ mod tests {
         }
     }
 
+    // Persists a registry to `store` and loads it back
+    fn round_trip<S: KeyValueStore>(store: &mut S) -> ContractRegistry {
+        let mut registry = ContractRegistry::new();
+        let address = [1u8; 32];
+        registry.register_version(address, create_test_version("1.0.0", [2u8; 32], 1000)).unwrap();
+        registry.register_version(address, create_test_version("1.1.0", [2u8; 32], 2000)).unwrap();
+        registry.register_version([3u8; 32], create_test_version("1.0.0", [4u8; 32], 1500)).unwrap();
+
+        registry.persist(store).unwrap();
+        ContractRegistry::restore(store).unwrap()
+    }
+
+    #[test]
+    fn test_persist_and_restore() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut in_memory = crate::storage::Storage::new_in_memory().unwrap();
+        let mut rocksdb = crate::storage::BlockchainDB::new(temp_dir.path()).unwrap();
+
+        for restored in [round_trip(&mut in_memory), round_trip(&mut rocksdb)] {
+            let versions = restored.get_contract_versions(&[1u8; 32]).unwrap();
+            assert_eq!(versions.len(), 2);
+            assert_eq!(restored.get_latest_version(&[1u8; 32]).unwrap().metadata.version, "1.1.0");
+            assert_eq!(restored.get_upgrade_history(&[1u8; 32]).unwrap().len(), 1);
+            assert_eq!(restored.find_by_index(RegistryIndex::Author([4u8; 32])).unwrap().len(), 1);
+        }
+
+        // An empty store restores an empty registry
+        let empty = crate::storage::Storage::new_in_memory().unwrap();
+        assert!(ContractRegistry::restore(&empty).unwrap().list_all_contracts().is_empty());
+    }
+
+    #[test]
+    fn test_restore_keeps_rollbacks() {
+        let mut registry = ContractRegistry::new();
+        let address = [1u8; 32];
+        registry.register_version(address, create_test_version("1.0.0", [2u8; 32], 1000)).unwrap();
+        registry.register_version(address, create_test_version("1.1.0", [2u8; 32], 2000)).unwrap();
+        registry.rollback_version(address).unwrap();
+
+        let mut store = crate::storage::Storage::new_in_memory().unwrap();
+        registry.persist(&mut store).unwrap();
+        let restored = ContractRegistry::restore(&store).unwrap();
+
+        // The rolled back version is still told apart from an unknown one
+        assert_eq!(restored.get_latest_version(&address).unwrap().metadata.version, "1.0.0");
+        assert!(matches!(
+            restored.get_contract_version(&address, "1.1.0"),
+            Err(ContractError::VersionRolledBack(_))
+        ));
+        let history = restored.get_upgrade_history(&address).unwrap();
+        assert_eq!(history, registry.get_upgrade_history(&address).unwrap());
+        assert!(history[0].rollback_performed);
+    }
+
     #[test]
     fn test_register_and_retrieve() {
         let mut registry = ContractRegistry::new();
@@ -347,6 +736,149 @@ mod tests {
         assert_eq!(retrieved.metadata.version, "1.0.0");
     }
 
+    #[test]
+    fn test_remove_contract() {
+        let mut registry = ContractRegistry::new();
+        let address = [1u8; 32];
+        let other = [3u8; 32];
+        let author = [2u8; 32];
+        registry.register_version(address, create_test_version("1.0.0", author, 1000)).unwrap();
+        registry.register_version(address, create_test_version("1.1.0", author, 2000)).unwrap();
+        registry.register_version(other, create_test_version("1.0.0", author, 1000)).unwrap();
+
+        let removed = registry.remove_contract(&address).unwrap();
+        assert_eq!(removed.len(), 2);
+
+        // Nothing of the removed contract can be looked up
+        assert!(matches!(registry.get_contract_versions(&address), Err(ContractError::NotFound(_))));
+        assert!(matches!(registry.get_latest_version(&address), Err(ContractError::NotFound(_))));
+        assert!(matches!(registry.get_contract_version(&address, "1.0.0"), Err(ContractError::NotFound(_))));
+        assert!(matches!(registry.get_upgrade_history(&address), Err(ContractError::NotFound(_))));
+        assert!(matches!(registry.find_by_index(RegistryIndex::Version("1.1.0".into())), Err(ContractError::VersionNotFound(_))));
+        assert!(matches!(registry.find_by_index(RegistryIndex::UpdateTime(2000)), Err(ContractError::NotFound(_))));
+
+        // Index entries shared with another contract only lose the removed one
+        let by_author = registry.find_by_index(RegistryIndex::Author(author)).unwrap();
+        assert_eq!(by_author.iter().map(|(addr, _)| *addr).collect::<Vec<_>>(), vec![other]);
+        assert_eq!(registry.find_by_index(RegistryIndex::CreationTime(1000)).unwrap().len(), 1);
+        assert_eq!(registry.find_by_index(RegistryIndex::Version("1.0.0".into())).unwrap().len(), 1);
+
+        assert!(matches!(registry.remove_contract(&address), Err(ContractError::NotFound(_))));
+        registry.remove_contract(&other).unwrap();
+        assert!(matches!(registry.find_by_index(RegistryIndex::Author(author)), Err(ContractError::NotFound(_))));
+        assert!(registry.list_all_contracts().is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_method_name_rejected() {
+        let mut registry = ContractRegistry::new();
+        let address = [1u8; 32];
+        let method = ContractMethod {
+            name: "transfer".to_string(),
+            inputs: vec![],
+            outputs: vec![],
+            payable: false,
+            default_gas: None,
+        };
+        let mut version = create_test_version("1.0.0", [2u8; 32], 1000);
+        version.abi.methods = vec![method.clone(), method.clone()];
+
+        let result = registry.register_version(address, version.clone());
+        assert!(matches!(result, Err(ContractError::InvalidArguments(_))), "Unexpected result: {:?}", result);
+        assert!(registry.get_contract_versions(&address).is_err());
+
+        // An empty name is rejected too
+        version.abi.methods = vec![ContractMethod { name: String::new(), ..method }];
+        assert!(matches!(registry.register_version(address, version), Err(ContractError::InvalidArguments(_))));
+    }
+
+    #[test]
+    fn test_unknown_param_type_rejected() {
+        let mut registry = ContractRegistry::new();
+        let address = [1u8; 32];
+        let param = |param_type: &str| ContractParam {
+            name: "amount".to_string(),
+            param_type: param_type.to_string(),
+            indexed: false,
+        };
+        let mut version = create_test_version("1.0.0", [2u8; 32], 1000);
+        version.abi.methods = vec![ContractMethod {
+            name: "transfer".to_string(),
+            inputs: vec![param("u256")],
+            outputs: vec![],
+            payable: false,
+            default_gas: None,
+        }];
+
+        let result = registry.register_version(address, version.clone());
+        assert!(matches!(result, Err(ContractError::InvalidArguments(_))), "Unexpected result: {:?}", result);
+
+        // No deployable contract can take floats
+        for float in ["f32", "f64"] {
+            version.abi.methods[0].inputs = vec![param(float)];
+            assert!(matches!(registry.register_version(address, version.clone()), Err(ContractError::InvalidArguments(_))));
+        }
+
+        // Event parameters are checked as well
+        version.abi.methods[0].inputs = vec![param("i64")];
+        version.abi.events = vec![ContractEvent { name: "Transfer".to_string(), inputs: vec![param("uint")] }];
+        assert!(matches!(registry.register_version(address, version.clone()), Err(ContractError::InvalidArguments(_))));
+
+        version.abi.events[0].inputs = vec![param("bytes")];
+        registry.register_version(address, version).unwrap();
+    }
+
+    #[test]
+    fn test_list_contracts_paginated() {
+        let mut registry = ContractRegistry::new();
+        for i in 0..25u8 {
+            // Registered out of address order
+            let address = [(i * 7) % 25; 32];
+            registry.register_version(address, create_test_version("1.0.0", [2u8; 32], 1000)).unwrap();
+        }
+
+        let mut listed = Vec::new();
+        for offset in [0, 10, 20] {
+            let (page, total) = registry.list_contracts_paginated(offset, 10);
+            assert_eq!(total, 25);
+            assert_eq!(page.len(), if offset == 20 { 5 } else { 10 });
+            listed.extend(page.into_iter().map(|(addr, _)| addr));
+        }
+
+        // Every contract is listed exactly once, in address order
+        let expected: Vec<_> = (0..25u8).map(|i| [i; 32]).collect();
+        assert_eq!(listed, expected);
+
+        let (page, total) = registry.list_contracts_paginated(30, 10);
+        assert!(page.is_empty());
+        assert_eq!(total, 25);
+    }
+
+    #[test]
+    fn test_find_by_time_range() {
+        let mut registry = ContractRegistry::new();
+        for (i, time) in [1000, 2000, 3000, 4000].into_iter().enumerate() {
+            registry.register_version([i as u8; 32], create_test_version("1.0.0", [2u8; 32], time)).unwrap();
+        }
+
+        let addresses = |found: Vec<([u8; 32], &ContractVersion)>| found.into_iter().map(|(addr, _)| addr).collect::<Vec<_>>();
+
+        // Both ends of the window are included
+        assert_eq!(addresses(registry.find_by_creation_range(2000, 3000)), vec![[1u8; 32], [2u8; 32]]);
+        assert_eq!(addresses(registry.find_by_creation_range(1500, 2500)), vec![[1u8; 32]]);
+        assert!(registry.find_by_creation_range(4001, 5000).is_empty());
+        assert!(registry.find_by_creation_range(3000, 2000).is_empty());
+
+        // An upgrade shows up in the update window, listed once with its latest version
+        let mut upgrade = create_test_version("1.1.0", [2u8; 32], 1000);
+        upgrade.metadata.updated_at = 3500;
+        registry.register_version([0u8; 32], upgrade).unwrap();
+        let updated = registry.find_by_update_range(1000, 3500);
+        assert_eq!(addresses(updated.clone()), vec![[0u8; 32], [1u8; 32], [2u8; 32]]);
+        assert_eq!(updated[0].1.metadata.version, "1.1.0");
+        assert_eq!(addresses(registry.find_by_update_range(3100, 4000)), vec![[0u8; 32], [3u8; 32]]);
+    }
+
     #[test]
     fn test_version_compatibility() {
         let mut registry = ContractRegistry::new();
@@ -366,6 +898,62 @@ mod tests {
         assert!(registry.register_version(address, version3).is_ok());
     }
 
+    #[test]
+    fn test_major_upgrade_requires_opt_in() {
+        let mut registry = ContractRegistry::new();
+        let address = [1u8; 32];
+        let author = [2u8; 32];
+
+        registry.register_version(address, create_test_version("1.0.0", author, 1000)).unwrap();
+
+        // Minor and patch bumps need no opt-in
+        registry.register_version(address, create_test_version("1.1.0", author, 1001)).unwrap();
+        registry.register_version(address, create_test_version("1.1.1", author, 1002)).unwrap();
+
+        // A major bump is refused by default
+        let err = registry.register_version(address, create_test_version("2.0.0", author, 1003)).unwrap_err();
+        assert!(matches!(err, ContractError::VersionIncompatible(_)));
+        assert_eq!(registry.get_latest_version(&address).unwrap().metadata.version, "1.1.1");
+
+        // and allowed once the new version sets the flag
+        let mut major = create_test_version("2.0.0", author, 1003);
+        major.metadata.allow_major_upgrade = true;
+        registry.register_version(address, major).unwrap();
+        assert_eq!(registry.get_latest_version(&address).unwrap().metadata.version, "2.0.0");
+    }
+
+    #[test]
+    fn test_compatibility_matrix() {
+        let mut registry = ContractRegistry::new();
+        let address = [1u8; 32];
+        let author = [2u8; 32];
+
+        let major = |version: &str, time| {
+            let mut version = create_test_version(version, author, time);
+            version.metadata.allow_major_upgrade = true;
+            version
+        };
+
+        registry.register_version(address, create_test_version("1.0.0", author, 1000)).unwrap();
+        registry.declare_compatibility(address, "3.0.0", &["2.0.0"]).unwrap();
+
+        // v3 is newer but does not accept state laid out by v1
+        let err = registry.register_version(address, major("3.0.0", 1001)).unwrap_err();
+        assert!(matches!(err, ContractError::VersionIncompatible(_)));
+        assert_eq!(registry.get_latest_version(&address).unwrap().metadata.version, "1.0.0");
+
+        // Going through v2 is allowed
+        registry.register_version(address, major("2.0.0", 1002)).unwrap();
+        registry.register_version(address, major("3.0.0", 1003)).unwrap();
+
+        // Declarations survive a restart
+        let mut store = crate::storage::Storage::new_in_memory().unwrap();
+        registry.persist(&mut store).unwrap();
+        let restored = ContractRegistry::restore(&store).unwrap();
+        let compatible_from = restored.get_compatibility(&address, "3.0.0").unwrap();
+        assert_eq!(compatible_from.iter().collect::<Vec<_>>(), vec!["2.0.0"]);
+    }
+
     #[test]
     fn test_rollback() {
         let mut registry = ContractRegistry::new();
@@ -386,4 +974,26 @@ mod tests {
         let current = registry.get_latest_version(&address).unwrap();
         assert_eq!(current.metadata.version, "1.0.0");
     }
+
+    #[test]
+    fn test_rolled_back_version_resolution() {
+        let mut registry = ContractRegistry::new();
+        let address = [1u8; 32];
+        let author = [2u8; 32];
+
+        registry.register_version(address, create_test_version("1.0.0", author, 1000)).unwrap();
+        registry.register_version(address, create_test_version("1.1.0", author, 1001)).unwrap();
+        registry.rollback_version(address).unwrap();
+
+        // Rolled back and never-registered versions are reported differently
+        let err = registry.resolve_version(&address, Some("1.1.0")).unwrap_err();
+        assert!(matches!(err, ContractError::VersionRolledBack(_)));
+        let err = registry.resolve_version(&address, Some("9.9.9")).unwrap_err();
+        assert!(matches!(err, ContractError::VersionNotFound(_)));
+        assert_eq!(registry.resolve_version(&address, None).unwrap().metadata.version, "1.0.0");
+
+        // Registering the version again makes it resolvable
+        registry.register_version(address, create_test_version("1.1.0", author, 1002)).unwrap();
+        assert!(registry.resolve_version(&address, Some("1.1.0")).is_ok());
+    }
 }
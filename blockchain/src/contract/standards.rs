@@ -46,6 +46,9 @@ pub enum ContractError {
     #[error("Version upgrade failed: {0}")]
     VersionUpgradeFailed(String),
 
+    #[error("Version rolled back: {0}")]
+    VersionRolledBack(String),
+
     // State-specific errors
     #[error("State error: {0}")]
     StateError(String),
@@ -98,7 +101,8 @@ impl ContractError {
             ContractError::VersionConflict(_) |
             ContractError::VersionNotFound(_) |
             ContractError::VersionIncompatible(_) |
-            ContractError::VersionUpgradeFailed(_)
+            ContractError::VersionUpgradeFailed(_) |
+            ContractError::VersionRolledBack(_)
         )
     }
 
@@ -1,13 +1,29 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
 use serde::{Serialize, Deserialize};
 use crate::contract::{ContractError, ContractResult};
+use crate::storage::{KeyValueStore, KvStateStore, StorageError};
 
 // State size limits
 const MAX_STATE_SIZE: usize = 100 * 1024 * 1024; // 100MB total state size
-const MAX_KEY_SIZE: usize = 1024; // 1KB max key size
-const MAX_VALUE_SIZE: usize = 1024 * 1024; // 1MB max value size
+pub(crate) const MAX_KEY_SIZE: usize = 1024; // 1KB max key size
+pub(crate) const MAX_VALUE_SIZE: usize = 1024 * 1024; // 1MB max value size
 const MAX_ENTRIES: usize = 100_000; // Maximum number of key-value pairs
 
+/// Snapshots kept per contract unless configured otherwise
+pub const DEFAULT_MAX_SNAPSHOTS: usize = 64;
+// Fewest snapshots a cap may keep, so the state before the latest change
+// can always be restored
+const MIN_RETAINED_SNAPSHOTS: usize = 2;
+
+/// Schema version of contract state that was never migrated
+pub const INITIAL_SCHEMA_VERSION: u32 = 1;
+
+/// Transforms the state of a contract into the schema of its new version,
+/// given the state in the old schema
+pub type StateMigration = dyn Fn(HashMap<Vec<u8>, Vec<u8>>) -> HashMap<Vec<u8>, Vec<u8>> + Send + Sync;
+
 /// Represents a snapshot of contract state at a specific point in time
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StateSnapshot {
@@ -23,6 +39,8 @@ pub struct StateSnapshot {
     pub state_hash: [u8; 32],
     /// Schema version of the state
     pub schema_version: u32,
+    /// Block the snapshot was taken in
+    pub block_number: u64,
 }
 
 /// Tracks changes between states for efficient updates and rollbacks
@@ -34,26 +52,295 @@ pub struct StateDiff {
     pub modified: HashMap<Vec<u8>, (Vec<u8>, Vec<u8>)>, // (old_value, new_value)
     /// Keys that were deleted in this diff
     pub deleted: HashMap<Vec<u8>, Vec<u8>>,
+    /// Block the changes were made in
+    #[serde(default)]
+    pub block_number: u64,
+}
+
+/// What happens to snapshots and diffs once the block they were taken in
+/// is finalized
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SnapshotRetention {
+    /// Keep the full history
+    KeepAll,
+    /// Drop history below the finalized height, which a reorg can no
+    /// longer roll back to
+    #[default]
+    PruneFinalized,
+}
+
+/// A snapshot whose stored state no longer matches its recorded hash
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateIntegrityReport {
+    /// Contract address the corrupted snapshot belongs to
+    pub contract_addr: [u8; 32],
+    /// Timestamp of the corrupted snapshot
+    pub snapshot_timestamp: u64,
+    /// Hash recorded when the snapshot was taken
+    pub expected_hash: [u8; 32],
+    /// Hash recomputed from the snapshot's current contents
+    pub computed_hash: [u8; 32],
+}
+
+/// State updates to one contract that passed validation, ready to be committed
+#[derive(Debug, Clone)]
+pub struct PreparedBatch {
+    contract_addr: [u8; 32],
+    updates: Vec<(Vec<u8>, Vec<u8>)>,
+    /// State the updates were validated against
+    base: Arc<HashMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl PreparedBatch {
+    pub fn contract_addr(&self) -> &[u8; 32] {
+        &self.contract_addr
+    }
+
+    pub fn len(&self) -> usize {
+        self.updates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.updates.is_empty()
+    }
+}
+
+/// Durable backing store for contract state and snapshots. State is stored
+/// one key per entry and snapshots one key each, so a write costs what it
+/// changes rather than the size of the whole state or history.
+pub trait StateStore: Send + Sync {
+    /// Current state of every stored contract
+    fn load_states(&self) -> Result<HashMap<[u8; 32], HashMap<Vec<u8>, Vec<u8>>>, StorageError>;
+    /// Snapshot history of every stored contract, oldest first, with the
+    /// sequence number each snapshot was saved under
+    fn load_snapshots(&self) -> Result<HashMap<[u8; 32], Vec<(u64, StateSnapshot)>>, StorageError>;
+    /// Write the entries `diff` adds or modifies and delete those it deletes
+    /// from the stored state of a contract, all at once
+    fn save_state_changes(&self, contract_addr: &[u8; 32], diff: &StateDiff) -> Result<(), StorageError>;
+    /// Add a snapshot to the stored history of a contract under `seq`
+    fn save_snapshot(&self, contract_addr: &[u8; 32], seq: u64, snapshot: &StateSnapshot) -> Result<(), StorageError>;
+    /// Delete the snapshots of a contract saved under `seqs`
+    fn remove_snapshots(&self, contract_addr: &[u8; 32], seqs: &[u64]) -> Result<(), StorageError>;
+    /// Delete the stored state of a contract, keeping its snapshots
+    fn remove_state(&self, contract_addr: &[u8; 32]) -> Result<(), StorageError>;
+    /// Delete the stored state and snapshots of a contract
+    fn remove_contract(&self, contract_addr: &[u8; 32]) -> Result<(), StorageError>;
 }
 
 /// Manages contract state including snapshots and migrations
-#[derive(Debug)]
 pub struct StateManager {
-    /// Current state for each contract
-    states: HashMap<[u8; 32], HashMap<Vec<u8>, Vec<u8>>>,
+    /// Current state for each contract, shared with executions reading it.
+    /// Updates replace a contract's state rather than change it in place.
+    states: HashMap<[u8; 32], Arc<HashMap<Vec<u8>, Vec<u8>>>>,
     /// History of state snapshots
     snapshots: HashMap<[u8; 32], Vec<StateSnapshot>>,
+    /// Sequence number each snapshot is stored under, in the same order
+    snapshot_seqs: HashMap<[u8; 32], Vec<u64>>,
     /// Track state changes for each contract
     diffs: HashMap<[u8; 32], Vec<StateDiff>>,
+    /// Schema version the current state of each contract is in
+    schema_versions: HashMap<[u8; 32], u32>,
+    /// Where state and snapshots are persisted; None keeps them in memory only
+    store: Option<Arc<dyn StateStore>>,
+    /// Block that snapshots and diffs are currently recorded in
+    block_number: u64,
+    /// Highest finalized block
+    finalized_height: u64,
+    retention: SnapshotRetention,
+    /// Most snapshots kept per contract
+    max_snapshots: usize,
+}
+
+impl fmt::Debug for StateManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StateManager")
+            .field("states", &self.states)
+            .field("snapshots", &self.snapshots)
+            .field("snapshot_seqs", &self.snapshot_seqs)
+            .field("diffs", &self.diffs)
+            .field("schema_versions", &self.schema_versions)
+            .field("persistent", &self.store.is_some())
+            .field("block_number", &self.block_number)
+            .field("finalized_height", &self.finalized_height)
+            .field("retention", &self.retention)
+            .field("max_snapshots", &self.max_snapshots)
+            .finish()
+    }
 }
 
 impl StateManager {
+    /// Create a state manager that keeps everything in memory
     pub fn new() -> Self {
         StateManager {
             states: HashMap::new(),
             snapshots: HashMap::new(),
+            snapshot_seqs: HashMap::new(),
+            diffs: HashMap::new(),
+            schema_versions: HashMap::new(),
+            store: None,
+            block_number: 0,
+            finalized_height: 0,
+            retention: SnapshotRetention::default(),
+            max_snapshots: DEFAULT_MAX_SNAPSHOTS,
+        }
+    }
+
+    /// Create a state manager persisting to `store`, starting from the state
+    /// and snapshots already stored there
+    pub fn with_store(store: Arc<dyn StateStore>) -> ContractResult<Self> {
+        let states = store.load_states().map_err(Self::storage_error)?;
+        let mut snapshots = HashMap::new();
+        let mut snapshot_seqs = HashMap::new();
+        for (addr, history) in store.load_snapshots().map_err(Self::storage_error)? {
+            let (seqs, history): (Vec<_>, Vec<_>) = history.into_iter().unzip();
+            snapshots.insert(addr, history);
+            snapshot_seqs.insert(addr, seqs);
+        }
+
+        // Migrations snapshot the migrated state, so the latest snapshot
+        // records the schema the current state is in
+        let schema_versions = snapshots.iter()
+            .filter_map(|(addr, history)| Some((*addr, history.last()?.schema_version)))
+            .collect();
+
+        Ok(StateManager {
+            states: states.into_iter().map(|(addr, state)| (addr, Arc::new(state))).collect(),
+            snapshots,
+            snapshot_seqs,
             diffs: HashMap::new(),
+            schema_versions,
+            store: Some(store),
+            block_number: 0,
+            finalized_height: 0,
+            retention: SnapshotRetention::default(),
+            max_snapshots: DEFAULT_MAX_SNAPSHOTS,
+        })
+    }
+
+    /// Create a state manager persisting to any key-value backend
+    pub fn with_kv_store<S: KeyValueStore + Send + 'static>(store: S) -> ContractResult<Self> {
+        Self::with_store(Arc::new(KvStateStore::new(store)))
+    }
+
+    fn storage_error(e: StorageError) -> ContractError {
+        ContractError::StateError(format!("Failed to persist contract state: {:?}", e))
+    }
+
+    /// Persist the changes going from `old_state` to `new_state`, which is
+    /// done before applying them so memory never runs ahead of the store.
+    /// Returns the changes.
+    fn persist_state(
+        &self,
+        contract_addr: &[u8; 32],
+        old_state: &HashMap<Vec<u8>, Vec<u8>>,
+        new_state: &HashMap<Vec<u8>, Vec<u8>>,
+    ) -> ContractResult<StateDiff> {
+        let diff = Self::compute_diff(old_state, new_state, self.block_number);
+        if let Some(store) = &self.store {
+            store.save_state_changes(contract_addr, &diff).map_err(Self::storage_error)?;
+        }
+        Ok(diff)
+    }
+
+    fn record_diff(&mut self, contract_addr: [u8; 32], diff: StateDiff) {
+        self.diffs.entry(contract_addr)
+            .or_insert_with(Vec::new)
+            .push(diff);
+    }
+
+    /// Drop the snapshots of a contract `keep` marks false, from the store
+    /// first and then from memory. Returns the number dropped.
+    fn retain_snapshots(&mut self, contract_addr: &[u8; 32], keep: &[bool]) -> ContractResult<usize> {
+        let seqs = self.snapshot_seqs.entry(*contract_addr).or_default();
+        let dropped: Vec<u64> = seqs.iter().zip(keep)
+            .filter(|(_, keep)| !**keep)
+            .map(|(seq, _)| *seq)
+            .collect();
+        if dropped.is_empty() {
+            return Ok(0);
+        }
+
+        if let Some(store) = &self.store {
+            store.remove_snapshots(contract_addr, &dropped).map_err(Self::storage_error)?;
+        }
+        let mut kept = keep.iter();
+        seqs.retain(|_| *kept.next().unwrap_or(&true));
+        if let Some(snapshots) = self.snapshots.get_mut(contract_addr) {
+            let mut kept = keep.iter();
+            snapshots.retain(|_| *kept.next().unwrap_or(&true));
+        }
+        Ok(dropped.len())
+    }
+
+    /// Record subsequent snapshots and diffs as made in `block_number`
+    pub fn set_block_number(&mut self, block_number: u64) {
+        self.block_number = block_number;
+    }
+
+    pub fn set_snapshot_retention(&mut self, retention: SnapshotRetention) {
+        self.retention = retention;
+    }
+
+    /// Keep at most `max` snapshots per contract, pruning the oldest as new
+    /// ones are taken. At least two are always kept.
+    pub fn set_max_snapshots(&mut self, max: usize) {
+        self.max_snapshots = max.max(MIN_RETAINED_SNAPSHOTS);
+    }
+
+    pub fn finalized_height(&self) -> u64 {
+        self.finalized_height
+    }
+
+    /// Mark blocks up to `height` as final and, unless the full history is
+    /// retained, prune the snapshots and diffs taken below it. Returns the
+    /// number of snapshots pruned.
+    pub fn advance_finality(&mut self, height: u64) -> ContractResult<usize> {
+        if height <= self.finalized_height {
+            return Ok(0);
+        }
+        self.finalized_height = height;
+
+        if self.retention == SnapshotRetention::KeepAll {
+            return Ok(0);
+        }
+
+        for diffs in self.diffs.values_mut() {
+            diffs.retain(|diff| diff.block_number >= height);
+        }
+
+        let stale: Vec<([u8; 32], Vec<bool>)> = self.snapshots.iter()
+            .map(|(addr, snapshots)| (*addr, snapshots.iter().map(|snapshot| snapshot.block_number >= height).collect()))
+            .collect();
+
+        let mut pruned = 0;
+        for (addr, keep) in stale {
+            pruned += self.retain_snapshots(&addr, &keep)?;
+        }
+
+        Ok(pruned)
+    }
+
+    /// Which snapshots to keep so that the oldest beyond `max` are dropped.
+    /// The latest snapshot of each version is what rolling back to that
+    /// version restores, so it is kept even if that leaves more than `max`.
+    fn snapshots_within_cap(snapshots: &[StateSnapshot], max: usize) -> Vec<bool> {
+        let mut excess = snapshots.len().saturating_sub(max);
+
+        let mut versions = HashSet::new();
+        let mut rollback_targets = vec![false; snapshots.len()];
+        for (i, snapshot) in snapshots.iter().enumerate().rev() {
+            rollback_targets[i] = versions.insert(snapshot.version.clone());
         }
+
+        rollback_targets.into_iter()
+            .map(|target| {
+                let keep = target || excess == 0;
+                if !keep {
+                    excess -= 1;
+                }
+                keep
+            })
+            .collect()
     }
 
     /// Calculate total state size for a contract
@@ -61,6 +348,45 @@ impl StateManager {
         state.iter().map(|(k, v)| k.len() + v.len()).sum()
     }
 
+    fn calculate_diff_size(diff: &StateDiff) -> usize {
+        Self::calculate_state_size(&diff.added)
+            + Self::calculate_state_size(&diff.deleted)
+            + diff.modified.iter().map(|(k, (old, new))| k.len() + old.len() + new.len()).sum::<usize>()
+    }
+
+    /// Drop the state, snapshots and diffs of every contract for which
+    /// `is_live` returns false. Returns the number of state bytes freed.
+    ///
+    /// Each contract is deleted from the store before it is dropped from
+    /// memory. If the store fails, the error is returned and the contracts
+    /// not yet collected are left for the next collection.
+    pub fn gc_orphaned(&mut self, is_live: impl Fn(&[u8; 32]) -> bool) -> ContractResult<usize> {
+        let orphaned: HashSet<[u8; 32]> = self.states.keys()
+            .chain(self.snapshots.keys())
+            .chain(self.diffs.keys())
+            .filter(|addr| !is_live(addr))
+            .copied()
+            .collect();
+
+        let mut reclaimed = 0;
+        for addr in orphaned {
+            if let Some(store) = &self.store {
+                store.remove_contract(&addr).map_err(Self::storage_error)?;
+            }
+
+            reclaimed += self.states.remove(&addr).map_or(0, |state| Self::calculate_state_size(&state));
+            reclaimed += self.snapshots.remove(&addr).unwrap_or_default().iter()
+                .map(|snapshot| Self::calculate_state_size(&snapshot.state))
+                .sum::<usize>();
+            reclaimed += self.diffs.remove(&addr).unwrap_or_default().iter()
+                .map(Self::calculate_diff_size)
+                .sum::<usize>();
+            self.snapshot_seqs.remove(&addr);
+        }
+
+        Ok(reclaimed)
+    }
+
     /// Validate state update against size limits
     fn validate_state_update(
         &self,
@@ -68,6 +394,20 @@ impl StateManager {
         new_key: &[u8],
         new_value: &[u8]
     ) -> ContractResult<()> {
+        self.validate_sized_update(state, Self::calculate_state_size(state), new_key, new_value)
+            .map(|_| ())
+    }
+
+    /// Validate a state update given the current size of `state`, so callers
+    /// applying many updates need not recompute it. Returns the size after
+    /// the update.
+    fn validate_sized_update(
+        &self,
+        state: &HashMap<Vec<u8>, Vec<u8>>,
+        state_size: usize,
+        new_key: &[u8],
+        new_value: &[u8]
+    ) -> ContractResult<usize> {
         // Check key size
         if new_key.len() > MAX_KEY_SIZE {
             return Err(ContractError::StateError(
@@ -85,7 +425,7 @@ impl StateManager {
         }
 
         // Calculate new total size
-        let mut total_size = Self::calculate_state_size(state);
+        let mut total_size = state_size;
         if let Some(existing_value) = state.get(new_key) {
             total_size -= new_key.len() + existing_value.len();
         }
@@ -106,37 +446,57 @@ impl StateManager {
             ));
         }
 
-        Ok(())
+        Ok(total_size)
     }
 
     /// Create a snapshot of current contract state
     pub fn create_snapshot(&mut self, contract_addr: [u8; 32], version: String) -> ContractResult<StateSnapshot> {
-        let state = self.states.get(&contract_addr).ok_or_else(|| {
+        let state = self.states.get(&contract_addr).map(|state| state.as_ref().clone()).ok_or_else(|| {
             ContractError::StateError("Contract state not found".into())
         })?;
 
+        self.record_snapshot(contract_addr, version, state)
+    }
+
+    /// Add a snapshot of `state` to the history of a contract, e.g. the
+    /// state a transaction started from once the transaction is committed
+    pub fn record_snapshot(
+        &mut self,
+        contract_addr: [u8; 32],
+        version: String,
+        state: HashMap<Vec<u8>, Vec<u8>>,
+    ) -> ContractResult<StateSnapshot> {
         let timestamp = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
             .as_secs();
 
         // Create state hash for integrity verification
-        let state_hash = self.compute_state_hash(state);
+        let state_hash = self.compute_state_hash(&state);
 
         let snapshot = StateSnapshot {
             contract_addr,
             version,
             timestamp,
-            state: state.clone(),
+            state,
             state_hash,
-            schema_version: 1, // Initialize with version 1
+            schema_version: self.schema_version(&contract_addr),
+            block_number: self.block_number,
         };
 
-        // Store the snapshot
-        self.snapshots
+        // Store the snapshot, dropping the oldest beyond the cap
+        let seqs = self.snapshot_seqs.entry(contract_addr).or_default();
+        let seq = seqs.last().map_or(0, |last| last + 1);
+        if let Some(store) = &self.store {
+            store.save_snapshot(&contract_addr, seq, &snapshot).map_err(Self::storage_error)?;
+        }
+        seqs.push(seq);
+        let snapshots = self.snapshots
             .entry(contract_addr)
-            .or_insert_with(Vec::new)
-            .push(snapshot.clone());
+            .or_insert_with(Vec::new);
+        snapshots.push(snapshot.clone());
+        let keep = Self::snapshots_within_cap(snapshots, self.max_snapshots);
+        self.retain_snapshots(&contract_addr, &keep)?;
 
         Ok(snapshot)
     }
@@ -157,17 +517,105 @@ impl StateManager {
         }
 
         // Restore the state
-        self.states.insert(contract_addr, snapshot.state.clone());
+        let state = snapshot.state.clone();
+        let schema_version = snapshot.schema_version;
+        self.persist_state(&contract_addr, &self.shared_state(&contract_addr), &state)?;
+        self.states.insert(contract_addr, Arc::new(state));
+        self.schema_versions.insert(contract_addr, schema_version);
+
+        Ok(())
+    }
+
+    /// Most recent snapshot taken while the contract was at `version`
+    pub fn latest_snapshot_for_version(&self, contract_addr: &[u8; 32], version: &str) -> Option<&StateSnapshot> {
+        self.snapshots
+            .get(contract_addr)?
+            .iter()
+            .rev()
+            .find(|s| s.version == version)
+    }
 
+    /// Restore contract state from the most recent snapshot of `version`, so
+    /// the state matches the schema that version expects
+    pub fn restore_version_snapshot(&mut self, contract_addr: [u8; 32], version: &str) -> ContractResult<()> {
+        let snapshot = self.latest_snapshot_for_version(&contract_addr, version).ok_or_else(|| {
+            ContractError::StateError(format!("No snapshot found for version {}", version))
+        })?;
+
+        if !self.verify_state_integrity(snapshot) {
+            return Err(ContractError::StateError("State integrity verification failed".into()));
+        }
+
+        let state = snapshot.state.clone();
+        let schema_version = snapshot.schema_version;
+        self.restore_state(contract_addr, state)?;
+        self.schema_versions.insert(contract_addr, schema_version);
         Ok(())
     }
 
+    /// Schema version the current state of a contract is in
+    pub fn schema_version(&self, contract_addr: &[u8; 32]) -> u32 {
+        self.schema_versions.get(contract_addr).copied().unwrap_or(INITIAL_SCHEMA_VERSION)
+    }
+
+    /// Transform the state of a contract with `migration` and bump its
+    /// schema version. The migrated state is snapshotted under `version`, the
+    /// contract version it was migrated for. Returns the new schema version.
+    pub fn migrate_state(&mut self, contract_addr: [u8; 32], version: String, migration: &StateMigration) -> ContractResult<u32> {
+        let old_state = self.states.get(&contract_addr).map(|state| state.as_ref().clone()).ok_or_else(|| {
+            ContractError::StateError("Contract state not found".into())
+        })?;
+        let new_state = migration(old_state);
+
+        // The migrated state must respect the same limits as any update
+        let mut validated = HashMap::with_capacity(new_state.len());
+        for (key, value) in new_state {
+            self.validate_state_update(&validated, &key, &value)?;
+            validated.insert(key, value);
+        }
+
+        self.restore_state(contract_addr, validated)?;
+        let schema_version = self.schema_version(&contract_addr) + 1;
+        self.schema_versions.insert(contract_addr, schema_version);
+        self.create_snapshot(contract_addr, version)?;
+
+        Ok(schema_version)
+    }
+
     /// Track changes between old and new state
     pub fn track_state_changes(&mut self, contract_addr: [u8; 32], old_state: &HashMap<Vec<u8>, Vec<u8>>, new_state: &HashMap<Vec<u8>, Vec<u8>>) {
+        let diff = Self::compute_diff(old_state, new_state, self.block_number);
+        self.record_diff(contract_addr, diff);
+    }
+
+    /// Changes from the snapshot taken at `ts_a` to the one taken at `ts_b`,
+    /// recorded as made in the later of their blocks
+    pub fn diff_snapshots(&self, contract_addr: &[u8; 32], ts_a: u64, ts_b: u64) -> ContractResult<StateDiff> {
+        let snapshots = self.snapshots.get(contract_addr).ok_or_else(|| {
+            ContractError::StateError("No snapshots found for contract".into())
+        })?;
+
+        let find = |timestamp: u64| {
+            let snapshot = snapshots.iter().find(|s| s.timestamp == timestamp).ok_or_else(|| {
+                ContractError::StateError(format!("Snapshot not found for timestamp {}", timestamp))
+            })?;
+            if !self.verify_state_integrity(snapshot) {
+                return Err(ContractError::StateError("State integrity verification failed".into()));
+            }
+            Ok(snapshot)
+        };
+        let (a, b) = (find(ts_a)?, find(ts_b)?);
+
+        Ok(Self::compute_diff(&a.state, &b.state, a.block_number.max(b.block_number)))
+    }
+
+    /// Keys added, modified and deleted going from `old_state` to `new_state`
+    fn compute_diff(old_state: &HashMap<Vec<u8>, Vec<u8>>, new_state: &HashMap<Vec<u8>, Vec<u8>>, block_number: u64) -> StateDiff {
         let mut diff = StateDiff {
             added: HashMap::new(),
             modified: HashMap::new(),
             deleted: HashMap::new(),
+            block_number,
         };
 
         // Find added and modified keys
@@ -190,10 +638,7 @@ impl StateManager {
             }
         }
 
-        // Store the diff
-        self.diffs.entry(contract_addr)
-            .or_insert_with(Vec::new)
-            .push(diff);
+        diff
     }
 
     /// Compute hash of state for integrity verification
@@ -219,28 +664,187 @@ impl StateManager {
         computed_hash == snapshot.state_hash
     }
 
+    /// Recompute the hash of the latest snapshot for up to `budget` contracts,
+    /// in address order starting after `after`. Returns any mismatches found and
+    /// the cursor to resume from, which is `None` once the last contract was checked.
+    pub fn scrub_snapshots(
+        &self,
+        after: Option<[u8; 32]>,
+        budget: usize
+    ) -> (Vec<StateIntegrityReport>, Option<[u8; 32]>) {
+        let mut addresses: Vec<_> = self.snapshots.keys().copied().collect();
+        addresses.sort();
+
+        let start = after.map_or(0, |cursor| addresses.partition_point(|addr| *addr <= cursor));
+        let end = addresses.len().min(start.saturating_add(budget));
+
+        let mut reports = Vec::new();
+        for addr in &addresses[start..end] {
+            if let Some(snapshot) = self.snapshots[addr].last() {
+                let computed_hash = self.compute_state_hash(&snapshot.state);
+                if computed_hash != snapshot.state_hash {
+                    reports.push(StateIntegrityReport {
+                        contract_addr: *addr,
+                        snapshot_timestamp: snapshot.timestamp,
+                        expected_hash: snapshot.state_hash,
+                        computed_hash,
+                    });
+                }
+            }
+        }
+
+        let next = if end < addresses.len() { addresses[..end].last().copied() } else { None };
+        (reports, next)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn snapshots_mut(&mut self, contract_addr: &[u8; 32]) -> Option<&mut Vec<StateSnapshot>> {
+        self.snapshots.get_mut(contract_addr)
+    }
+
     /// Get current state for a contract
     pub fn get_state(&self, contract_addr: &[u8; 32]) -> Option<&HashMap<Vec<u8>, Vec<u8>>> {
-        self.states.get(contract_addr)
+        self.states.get(contract_addr).map(Arc::as_ref)
+    }
+
+    /// Current state of a contract, shared rather than copied, for an
+    /// execution to read from while the manager goes on updating it
+    pub fn shared_state(&self, contract_addr: &[u8; 32]) -> Arc<HashMap<Vec<u8>, Vec<u8>>> {
+        self.states.get(contract_addr).cloned().unwrap_or_default()
     }
 
     /// Update state for a contract
     pub fn update_state(&mut self, contract_addr: [u8; 32], key: Vec<u8>, value: Vec<u8>) -> ContractResult<()> {
-        let old_state = self.states.get(&contract_addr).cloned().unwrap_or_default();
+        let old_state = self.shared_state(&contract_addr);
         
         // Validate state update against size limits
         self.validate_state_update(&old_state, &key, &value)?;
 
         // Create new state with update
-        let mut new_state = old_state.clone();
+        let mut new_state = old_state.as_ref().clone();
         new_state.insert(key, value);
 
-        // Track changes
-        self.track_state_changes(contract_addr, &old_state, &new_state);
+        // Persist and track changes
+        let diff = self.persist_state(&contract_addr, &old_state, &new_state)?;
+        self.record_diff(contract_addr, diff);
 
         // Update state
-        self.states.insert(contract_addr, new_state);
+        self.states.insert(contract_addr, Arc::new(new_state));
+
+        Ok(())
+    }
+
+    /// Apply `entries` in order as a single change. Every entry is validated
+    /// before any is applied, the state is copied once, and one diff is
+    /// recorded for the whole batch.
+    pub fn update_state_batch(&mut self, contract_addr: [u8; 32], entries: Vec<(Vec<u8>, Vec<u8>)>) -> ContractResult<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let old_state = self.shared_state(&contract_addr);
+
+        let mut new_state = old_state.as_ref().clone();
+        let mut state_size = Self::calculate_state_size(&old_state);
+        for (key, value) in entries {
+            state_size = self.validate_sized_update(&new_state, state_size, &key, &value)?;
+            new_state.insert(key, value);
+        }
+
+        let diff = self.persist_state(&contract_addr, &old_state, &new_state)?;
+        self.record_diff(contract_addr, diff);
+        self.states.insert(contract_addr, Arc::new(new_state));
+
+        Ok(())
+    }
+
+    /// Remove a key from the state of a contract. Removing a key that is not
+    /// set changes nothing.
+    pub fn delete_state(&mut self, contract_addr: [u8; 32], key: &[u8]) -> ContractResult<()> {
+        let old_state = self.shared_state(&contract_addr);
+        if !old_state.contains_key(key) {
+            return Ok(());
+        }
+
+        let mut new_state = old_state.as_ref().clone();
+        new_state.remove(key);
+
+        let diff = self.persist_state(&contract_addr, &old_state, &new_state)?;
+        self.record_diff(contract_addr, diff);
+        self.states.insert(contract_addr, Arc::new(new_state));
+
+        Ok(())
+    }
+
+    /// Validate `updates` in order against the current state of a contract
+    /// without applying them. Every invalid update is reported, not just the first.
+    pub fn prepare_updates(&self, contract_addr: [u8; 32], updates: Vec<(Vec<u8>, Vec<u8>)>) -> ContractResult<PreparedBatch> {
+        let base = self.shared_state(&contract_addr);
+
+        let mut state = base.as_ref().clone();
+        let mut state_size = Self::calculate_state_size(&state);
+        let mut failures = Vec::new();
+        for (i, (key, value)) in updates.iter().enumerate() {
+            match self.validate_sized_update(&state, state_size, key, value) {
+                Ok(size) => {
+                    state_size = size;
+                    state.insert(key.clone(), value.clone());
+                }
+                Err(e) => failures.push(format!("update {}: {}", i, e)),
+            }
+        }
+
+        if !failures.is_empty() {
+            return Err(ContractError::StateError(format!(
+                "Invalid state updates: {}", failures.join("; ")
+            )));
+        }
 
+        Ok(PreparedBatch {
+            contract_addr,
+            updates,
+            base,
+        })
+    }
+
+    /// Apply a batch from `prepare_updates`. Fails if the contract's state
+    /// changed since the batch was prepared.
+    pub fn commit_prepared(&mut self, batch: PreparedBatch) -> ContractResult<()> {
+        // States are replaced rather than changed in place, so the same map
+        // means nothing happened in between; contents are only compared
+        // when it was replaced
+        let current = self.shared_state(&batch.contract_addr);
+        if !Arc::ptr_eq(&current, &batch.base) && current != batch.base {
+            return Err(ContractError::StateError(
+                "Contract state changed since the batch was prepared".into()
+            ));
+        }
+
+        self.update_state_batch(batch.contract_addr, batch.updates)
+    }
+
+    /// Replace the current state of a contract, e.g. to undo the changes of
+    /// a failed transaction
+    pub fn restore_state(&mut self, contract_addr: [u8; 32], state: HashMap<Vec<u8>, Vec<u8>>) -> ContractResult<()> {
+        let old_state = self.shared_state(&contract_addr);
+        let diff = self.persist_state(&contract_addr, &old_state, &state)?;
+        self.record_diff(contract_addr, diff);
+        self.states.insert(contract_addr, Arc::new(state));
+        Ok(())
+    }
+
+    /// Remove the current state of a contract altogether, as opposed to
+    /// restoring it to an empty state. Its snapshots are kept.
+    pub fn remove_state(&mut self, contract_addr: [u8; 32]) -> ContractResult<()> {
+        let Some(old_state) = self.states.get(&contract_addr).cloned() else {
+            return Ok(());
+        };
+
+        if let Some(store) = &self.store {
+            store.remove_state(&contract_addr).map_err(Self::storage_error)?;
+        }
+        self.track_state_changes(contract_addr, &old_state, &HashMap::new());
+        self.states.remove(&contract_addr);
         Ok(())
     }
 
@@ -258,7 +862,7 @@ impl StateManager {
     pub fn get_state_size(&self, contract_addr: &[u8; 32]) -> usize {
         self.states
             .get(contract_addr)
-            .map_or(0, Self::calculate_state_size)
+            .map_or(0, |state| Self::calculate_state_size(state))
     }
 }
 
@@ -273,11 +877,11 @@ mod tests {
         let version = "1.0.0".to_string();
 
         // Initialize some state
-        manager.states.insert(contract_addr, {
+        manager.states.insert(contract_addr, Arc::new({
             let mut state = HashMap::new();
             state.insert(b"key1".to_vec(), b"value1".to_vec());
             state
-        });
+        }));
 
         let snapshot = manager.create_snapshot(contract_addr, version).unwrap();
         
@@ -294,11 +898,11 @@ mod tests {
         let version = "1.0.0".to_string();
 
         // Initialize some state
-        manager.states.insert(contract_addr, {
+        manager.states.insert(contract_addr, Arc::new({
             let mut state = HashMap::new();
             state.insert(b"key1".to_vec(), b"value1".to_vec());
             state
-        });
+        }));
 
         // Create snapshot
         let snapshot = manager.create_snapshot(contract_addr, version).unwrap();
@@ -314,6 +918,154 @@ mod tests {
         assert_eq!(restored_state.get(&b"key1".to_vec()).unwrap(), &b"value1".to_vec());
     }
 
+    #[test]
+    fn test_restore_version_snapshot() {
+        let mut manager = StateManager::new();
+        let contract_addr = [0u8; 32];
+
+        manager.update_state(contract_addr, b"key1".to_vec(), b"v1".to_vec()).unwrap();
+        manager.create_snapshot(contract_addr, "1.0.0".to_string()).unwrap();
+        manager.update_state(contract_addr, b"key1".to_vec(), b"v2".to_vec()).unwrap();
+        manager.create_snapshot(contract_addr, "2.0.0".to_string()).unwrap();
+        manager.create_snapshot(contract_addr, "2.0.0".to_string()).unwrap();
+
+        // The version's snapshot is used, not simply the second to last one
+        manager.restore_version_snapshot(contract_addr, "1.0.0").unwrap();
+        let state = manager.get_state(&contract_addr).unwrap();
+        assert_eq!(state.get(&b"key1".to_vec()).unwrap(), &b"v1".to_vec());
+
+        assert!(manager.restore_version_snapshot(contract_addr, "3.0.0").is_err());
+    }
+
+    #[test]
+    fn test_migration_bumps_schema_version() {
+        let mut manager = StateManager::new();
+        let contract_addr = [0u8; 32];
+
+        manager.update_state(contract_addr, b"key1".to_vec(), b"v1".to_vec()).unwrap();
+        manager.create_snapshot(contract_addr, "1.0.0".to_string()).unwrap();
+
+        let schema_version = manager.migrate_state(contract_addr, "2.0.0".to_string(), &|mut state| {
+            state.insert(b"added".to_vec(), b"v2".to_vec());
+            state
+        }).unwrap();
+        assert_eq!(schema_version, 2);
+        assert_eq!(manager.schema_version(&contract_addr), 2);
+        assert_eq!(manager.get_snapshots(&contract_addr).unwrap().last().unwrap().schema_version, 2);
+
+        // Going back to the old version's snapshot goes back to its schema
+        manager.restore_version_snapshot(contract_addr, "1.0.0").unwrap();
+        assert_eq!(manager.schema_version(&contract_addr), 1);
+        assert!(!manager.get_state(&contract_addr).unwrap().contains_key(b"added".as_slice()));
+    }
+
+    #[test]
+    fn test_state_persists_across_reopen() {
+        use crate::storage::BlockchainDB;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let contract_addr = [7u8; 32];
+
+        {
+            let db = BlockchainDB::new(temp_dir.path()).unwrap();
+            let mut manager = StateManager::with_store(Arc::new(db)).unwrap();
+            manager.update_state(contract_addr, b"key1".to_vec(), b"value1".to_vec()).unwrap();
+            manager.create_snapshot(contract_addr, "1.0.0".to_string()).unwrap();
+            manager.update_state(contract_addr, b"key1".to_vec(), b"value2".to_vec()).unwrap();
+        }
+
+        let db = BlockchainDB::new(temp_dir.path()).unwrap();
+        let mut manager = StateManager::with_store(Arc::new(db)).unwrap();
+        let state = manager.get_state(&contract_addr).unwrap();
+        assert_eq!(state.get(&b"key1".to_vec()).unwrap(), &b"value2".to_vec());
+        assert_eq!(manager.get_snapshots(&contract_addr).unwrap().len(), 1);
+
+        // Restoring a reloaded snapshot is persisted as well
+        manager.restore_version_snapshot(contract_addr, "1.0.0").unwrap();
+        drop(manager);
+        let db = BlockchainDB::new(temp_dir.path()).unwrap();
+        let manager = StateManager::with_store(Arc::new(db)).unwrap();
+        let state = manager.get_state(&contract_addr).unwrap();
+        assert_eq!(state.get(&b"key1".to_vec()).unwrap(), &b"value1".to_vec());
+    }
+
+    #[test]
+    fn test_finality_prunes_snapshots() {
+        let mut manager = StateManager::new();
+        let contract_addr = [0u8; 32];
+
+        for block_number in 1..=4 {
+            manager.set_block_number(block_number);
+            manager.update_state(contract_addr, b"key1".to_vec(), vec![block_number as u8]).unwrap();
+            manager.create_snapshot(contract_addr, "1.0.0".to_string()).unwrap();
+        }
+
+        // Snapshots and diffs below the finalized height are dropped
+        assert_eq!(manager.advance_finality(3).unwrap(), 2);
+        assert_eq!(manager.finalized_height(), 3);
+        let heights: Vec<_> = manager.get_snapshots(&contract_addr).unwrap()
+            .iter()
+            .map(|s| s.block_number)
+            .collect();
+        assert_eq!(heights, vec![3, 4]);
+        assert!(manager.get_state_diffs(&contract_addr).unwrap().iter().all(|d| d.block_number >= 3));
+
+        // Finality never moves back
+        assert_eq!(manager.advance_finality(2).unwrap(), 0);
+        assert_eq!(manager.finalized_height(), 3);
+
+        // With the full history retained nothing is pruned
+        manager.set_snapshot_retention(SnapshotRetention::KeepAll);
+        assert_eq!(manager.advance_finality(5).unwrap(), 0);
+        assert_eq!(manager.get_snapshots(&contract_addr).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_prepare_and_commit_updates() {
+        let mut manager = StateManager::new();
+        let contract_addr = [0u8; 32];
+        manager.update_state(contract_addr, b"key1".to_vec(), b"value1".to_vec()).unwrap();
+
+        // One invalid update fails the whole batch at prepare time
+        let result = manager.prepare_updates(contract_addr, vec![
+            (b"key1".to_vec(), b"value2".to_vec()),
+            (vec![0u8; MAX_KEY_SIZE + 1], b"value".to_vec()),
+            (b"key3".to_vec(), b"value3".to_vec()),
+        ]);
+        match result {
+            Err(ContractError::StateError(msg)) => assert!(msg.contains("update 1"), "Unexpected error: {}", msg),
+            other => panic!("Expected a StateError, got {:?}", other),
+        }
+        let state = manager.get_state(&contract_addr).unwrap();
+        assert_eq!(state.get(&b"key1".to_vec()).unwrap(), &b"value1".to_vec());
+        assert!(!state.contains_key(&b"key3".to_vec()));
+
+        // A valid batch is only applied on commit
+        let batch = manager.prepare_updates(contract_addr, vec![
+            (b"key1".to_vec(), b"value2".to_vec()),
+            (b"key3".to_vec(), b"value3".to_vec()),
+        ]).unwrap();
+        assert_eq!(batch.len(), 2);
+        assert!(!manager.get_state(&contract_addr).unwrap().contains_key(&b"key3".to_vec()));
+        manager.commit_prepared(batch).unwrap();
+        let state = manager.get_state(&contract_addr).unwrap();
+        assert_eq!(state.get(&b"key1".to_vec()).unwrap(), &b"value2".to_vec());
+        assert_eq!(state.get(&b"key3".to_vec()).unwrap(), &b"value3".to_vec());
+
+        // A batch prepared against a state that has since changed is refused
+        let stale = manager.prepare_updates(contract_addr, vec![(b"key4".to_vec(), b"value4".to_vec())]).unwrap();
+        manager.update_state(contract_addr, b"key1".to_vec(), b"value5".to_vec()).unwrap();
+        assert!(matches!(manager.commit_prepared(stale), Err(ContractError::StateError(_))));
+        assert!(!manager.get_state(&contract_addr).unwrap().contains_key(&b"key4".to_vec()));
+
+        // A state replaced by the same contents is not a change
+        let batch = manager.prepare_updates(contract_addr, vec![(b"key4".to_vec(), b"value4".to_vec())]).unwrap();
+        let unchanged = manager.get_state(&contract_addr).unwrap().clone();
+        manager.restore_state(contract_addr, unchanged).unwrap();
+        manager.commit_prepared(batch).unwrap();
+        assert_eq!(manager.get_state(&contract_addr).unwrap().get(&b"key4".to_vec()).unwrap(), &b"value4".to_vec());
+    }
+
     #[test]
     fn test_state_diff_tracking() {
         let mut manager = StateManager::new();
@@ -340,6 +1092,113 @@ mod tests {
         assert_eq!(diff.deleted.len(), 1);
     }
 
+    #[test]
+    fn test_update_state_batch() {
+        let entries: Vec<_> = (0..100u32)
+            .map(|i| (format!("key{}", i).into_bytes(), i.to_le_bytes().to_vec()))
+            .collect();
+
+        let mut per_key = StateManager::new();
+        for (key, value) in entries.clone() {
+            per_key.update_state([0u8; 32], key, value).unwrap();
+        }
+
+        let mut batched = StateManager::new();
+        batched.update_state_batch([0u8; 32], entries).unwrap();
+
+        // Same state, recorded as one change instead of one per key
+        assert_eq!(batched.get_state(&[0u8; 32]), per_key.get_state(&[0u8; 32]));
+        assert_eq!(per_key.get_state_diffs(&[0u8; 32]).unwrap().len(), 100);
+        let diffs = batched.get_state_diffs(&[0u8; 32]).unwrap();
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].added.len(), 100);
+
+        // One invalid entry rejects the whole batch
+        let oversized = vec![
+            (b"key0".to_vec(), b"changed".to_vec()),
+            (vec![0u8; MAX_KEY_SIZE + 1], b"value".to_vec()),
+        ];
+        assert!(batched.update_state_batch([0u8; 32], oversized).is_err());
+        assert_eq!(batched.get_state(&[0u8; 32]), per_key.get_state(&[0u8; 32]));
+        assert_eq!(batched.get_state_diffs(&[0u8; 32]).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_shared_state_unaffected_by_updates() {
+        let mut manager = StateManager::new();
+        let contract_addr = [0u8; 32];
+        manager.update_state(contract_addr, b"key1".to_vec(), b"v1".to_vec()).unwrap();
+
+        // Sharing the state does not copy it
+        let shared = manager.shared_state(&contract_addr);
+        assert!(std::ptr::eq(shared.as_ref(), manager.get_state(&contract_addr).unwrap()));
+
+        // Updates made while it is shared leave the shared view as it was
+        manager.update_state(contract_addr, b"key1".to_vec(), b"v2".to_vec()).unwrap();
+        assert_eq!(shared.get(&b"key1".to_vec()).unwrap(), &b"v1".to_vec());
+        assert_eq!(manager.get_state(&contract_addr).unwrap().get(&b"key1".to_vec()).unwrap(), &b"v2".to_vec());
+
+        // Contracts without state share an empty one
+        assert!(manager.shared_state(&[1u8; 32]).is_empty());
+    }
+
+    #[test]
+    fn test_delete_state() {
+        let mut manager = StateManager::new();
+        let contract_addr = [0u8; 32];
+
+        manager.update_state(contract_addr, b"key1".to_vec(), b"value1".to_vec()).unwrap();
+        manager.update_state(contract_addr, b"key2".to_vec(), b"value2".to_vec()).unwrap();
+        let size_before = manager.get_state_size(&contract_addr);
+
+        manager.delete_state(contract_addr, b"key1").unwrap();
+        let state = manager.get_state(&contract_addr).unwrap();
+        assert!(!state.contains_key(b"key1".as_slice()));
+        assert_eq!(state.get(b"key2".as_slice()), Some(&b"value2".to_vec()));
+        assert_eq!(manager.get_state_size(&contract_addr), size_before - b"key1value1".len());
+
+        // The deletion is recorded with the value it removed
+        let diff = manager.get_state_diffs(&contract_addr).unwrap().last().unwrap();
+        assert!(diff.added.is_empty() && diff.modified.is_empty());
+        assert_eq!(diff.deleted.get(b"key1".as_slice()), Some(&b"value1".to_vec()));
+
+        // Deleting a key that is not set records nothing
+        manager.delete_state(contract_addr, b"key1").unwrap();
+        assert_eq!(manager.get_state_diffs(&contract_addr).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_diff_snapshots() {
+        let mut manager = StateManager::new();
+        let contract_addr = [0u8; 32];
+
+        manager.update_state(contract_addr, b"key1".to_vec(), b"value1".to_vec()).unwrap();
+        manager.update_state(contract_addr, b"key2".to_vec(), b"value2".to_vec()).unwrap();
+        let first = manager.create_snapshot(contract_addr, "1.0.0".to_string()).unwrap();
+        // Snapshots are timestamped in seconds; keep the two apart
+        let ts_a = first.timestamp - 1;
+        manager.snapshots.get_mut(&contract_addr).unwrap()[0].timestamp = ts_a;
+
+        manager.update_state(contract_addr, b"key1".to_vec(), b"value1_modified".to_vec()).unwrap();
+        manager.update_state(contract_addr, b"key3".to_vec(), b"value3".to_vec()).unwrap();
+        let mut state = manager.get_state(&contract_addr).unwrap().clone();
+        state.remove(b"key2".as_slice());
+        manager.restore_state(contract_addr, state).unwrap();
+        let ts_b = manager.create_snapshot(contract_addr, "1.0.0".to_string()).unwrap().timestamp;
+
+        let diff = manager.diff_snapshots(&contract_addr, ts_a, ts_b).unwrap();
+        assert_eq!(diff.added, HashMap::from([(b"key3".to_vec(), b"value3".to_vec())]));
+        assert_eq!(diff.modified, HashMap::from([(b"key1".to_vec(), (b"value1".to_vec(), b"value1_modified".to_vec()))]));
+        assert_eq!(diff.deleted, HashMap::from([(b"key2".to_vec(), b"value2".to_vec())]));
+
+        // The reverse diff undoes it
+        let reverse = manager.diff_snapshots(&contract_addr, ts_b, ts_a).unwrap();
+        assert_eq!(reverse.added, diff.deleted);
+        assert_eq!(reverse.deleted, diff.added);
+
+        assert!(manager.diff_snapshots(&contract_addr, ts_a, ts_b + 1).is_err());
+    }
+
     #[test]
     fn test_state_size_limits() {
         let mut manager = StateManager::new();
@@ -1,7 +1,128 @@
 use ed25519_dalek::{Signature as EdSignature, Signer, SigningKey, Verifier, VerifyingKey};
 use rand::rngs::OsRng;
 use std::fmt;
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Deserialize, Deserializer, Serializer};
+
+/// The all-zero address. It is reserved as `DEFAULT_ADMIN_ROLE` and as an
+/// "unset" sentinel, so it is never a valid account.
+pub const ZERO_ADDRESS: [u8; 32] = [0u8; 32];
+
+pub fn is_zero_address(address: &[u8]) -> bool {
+    address == ZERO_ADDRESS
+}
+
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Bytes of checksum appended to an account before it is base58 encoded
+const ADDRESS_CHECKSUM_LEN: usize = 4;
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum AddressError {
+    #[error("Invalid address character {0:?}")]
+    InvalidCharacter(char),
+    #[error("Address decodes to {0} bytes")]
+    InvalidLength(usize),
+    #[error("Address checksum mismatch")]
+    ChecksumMismatch,
+}
+
+/// Account controlled by `pubkey`
+pub fn account_id(pubkey: &[u8]) -> [u8; 32] {
+    let mut account = [0u8; 32];
+    account.copy_from_slice(Hash::new(pubkey).to_bytes());
+    account
+}
+
+/// Short, checksummed address of the account controlled by `pubkey`
+pub fn account_address(pubkey: &[u8]) -> String {
+    encode_address(&account_id(pubkey))
+}
+
+/// Base58 encoding of an account followed by its checksum
+pub fn encode_address(account: &[u8; 32]) -> String {
+    let mut data = account.to_vec();
+    data.extend_from_slice(&address_checksum(account));
+    base58_encode(&data)
+}
+
+/// Account an address refers to, rejecting mistyped addresses
+pub fn parse_address(s: &str) -> Result<[u8; 32], AddressError> {
+    let data = base58_decode(s)?;
+    if data.len() != 32 + ADDRESS_CHECKSUM_LEN {
+        return Err(AddressError::InvalidLength(data.len()));
+    }
+
+    let (account, checksum) = data.split_at(32);
+    let account: [u8; 32] = account.try_into().expect("split at 32 bytes");
+    if checksum != address_checksum(&account) {
+        return Err(AddressError::ChecksumMismatch);
+    }
+    Ok(account)
+}
+
+fn address_checksum(account: &[u8; 32]) -> [u8; ADDRESS_CHECKSUM_LEN] {
+    let mut checksum = [0u8; ADDRESS_CHECKSUM_LEN];
+    checksum.copy_from_slice(&Hash::new(account).to_bytes()[..ADDRESS_CHECKSUM_LEN]);
+    checksum
+}
+
+fn base58_encode(data: &[u8]) -> String {
+    // Leading zero bytes are written as '1's, the rest as one big number
+    let zeros = data.iter().take_while(|&&b| b == 0).count();
+    let mut digits: Vec<u8> = Vec::new(); // least significant first
+    for &byte in &data[zeros..] {
+        let mut carry = byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+
+    std::iter::repeat('1').take(zeros)
+        .chain(digits.iter().rev().map(|&d| BASE58_ALPHABET[d as usize] as char))
+        .collect()
+}
+
+fn base58_decode(s: &str) -> Result<Vec<u8>, AddressError> {
+    let zeros = s.bytes().take_while(|&c| c == b'1').count();
+    let mut bytes: Vec<u8> = Vec::new(); // least significant first
+    for c in s[zeros..].chars() {
+        let mut carry = BASE58_ALPHABET.iter()
+            .position(|&a| a as char == c)
+            .ok_or(AddressError::InvalidCharacter(c))? as u32;
+        for byte in bytes.iter_mut() {
+            carry += *byte as u32 * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+
+    Ok(std::iter::repeat(0).take(zeros).chain(bytes.into_iter().rev()).collect())
+}
+
+/// (De)serializes an account as its checksummed address, for use with
+/// `#[serde(with = "crate::crypto::address")]`
+pub mod address {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(account: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&encode_address(account))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 32], D::Error> {
+        let s = String::deserialize(deserializer)?;
+        parse_address(&s).map_err(serde::de::Error::custom)
+    }
+}
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize, Default)]
 pub struct Hash([u8; 32]);
@@ -14,6 +135,11 @@ impl Hash {
         Hash(bytes)
     }
 
+    /// Wrap a digest computed elsewhere, such as by an incremental hasher
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Hash(bytes)
+    }
+
     pub fn to_bytes(&self) -> &[u8] {
         &self.0
     }
@@ -75,6 +201,25 @@ impl KeyPair {
         })
     }
 
+    /// Recover a key pair from a 24-word BIP39 mnemonic. The words encode
+    /// the 32-byte seed as BIP39 entropy, so this is the inverse of `to_mnemonic`.
+    pub fn from_mnemonic(phrase: &str) -> Result<Self, ed25519_dalek::SignatureError> {
+        let mnemonic = bip39::Mnemonic::parse_normalized(phrase.trim())
+            .map_err(|e| ed25519_dalek::SignatureError::from_source(format!("Invalid mnemonic: {}", e)))?;
+        let entropy = mnemonic.to_entropy();
+        if entropy.len() != 32 {
+            return Err(ed25519_dalek::SignatureError::from_source("Mnemonic must have 24 words"));
+        }
+        Self::from_seed(&entropy)
+    }
+
+    /// The seed of this key pair as a 24-word BIP39 mnemonic
+    pub fn to_mnemonic(&self) -> String {
+        bip39::Mnemonic::from_entropy(&self.signing_key.to_bytes())
+            .expect("32 bytes is valid BIP39 entropy")
+            .to_string()
+    }
+
     pub fn sign(&self, message: &[u8]) -> Signature {
         let ed_signature = self.signing_key.sign(message);
         Signature::from_ed_signature(ed_signature)
@@ -99,6 +244,34 @@ impl KeyPair {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_address_round_trip() {
+        let keypair = KeyPair::generate();
+        let pubkey = keypair.public_key().as_bytes();
+        let address = account_address(pubkey);
+        assert_eq!(parse_address(&address), Ok(account_id(pubkey)));
+
+        // Leading zero bytes survive the encoding
+        let mut account = [0u8; 32];
+        account[31] = 1;
+        assert_eq!(parse_address(&encode_address(&account)), Ok(account));
+        assert_eq!(parse_address(&encode_address(&ZERO_ADDRESS)), Ok(ZERO_ADDRESS));
+    }
+
+    #[test]
+    fn test_address_checksum_rejected() {
+        let address = account_address(KeyPair::generate().public_key().as_bytes());
+
+        // Swap the last character for another valid one
+        let mut corrupted = address.clone();
+        let last = corrupted.pop().unwrap();
+        corrupted.push(if last == '2' { '3' } else { '2' });
+        assert_eq!(parse_address(&corrupted), Err(AddressError::ChecksumMismatch));
+
+        assert_eq!(parse_address("0OIl"), Err(AddressError::InvalidCharacter('0')));
+        assert!(matches!(parse_address(&address[..address.len() - 4]), Err(AddressError::InvalidLength(_))));
+    }
+
     #[test]
     fn test_hash_creation() {
         let data = b"test data";
@@ -132,6 +305,28 @@ mod tests {
         assert!(keypair.verify(message, &signature));
     }
 
+    #[test]
+    fn test_mnemonic_round_trip() {
+        let keypair = KeyPair::generate();
+        let phrase = keypair.to_mnemonic();
+        assert_eq!(phrase.split_whitespace().count(), 24);
+
+        let restored = KeyPair::from_mnemonic(&phrase).unwrap();
+        assert_eq!(restored.public_key(), keypair.public_key());
+        assert_eq!(restored.to_mnemonic(), phrase);
+
+        // Ed25519 signing is deterministic, so both keys produce the same signature
+        let message = b"test message";
+        assert_eq!(restored.sign(message).to_bytes(), keypair.sign(message).to_bytes());
+
+        // Missing or unknown words are rejected
+        let mut words: Vec<&str> = phrase.split_whitespace().collect();
+        words.pop();
+        assert!(KeyPair::from_mnemonic(&words.join(" ")).is_err());
+        words.push("notaword");
+        assert!(KeyPair::from_mnemonic(&words.join(" ")).is_err());
+    }
+
     #[test]
     fn test_signature_verification() {
         let keypair = KeyPair::generate();
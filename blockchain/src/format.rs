@@ -0,0 +1,530 @@
+use crate::block::{Block, BlockHeader};
+use crate::crypto::Hash;
+use crate::contract::{
+    ContractABI, ContractEvent, ContractMetadata, ContractMethod, ContractParam, ContractVersion,
+};
+use crate::contract::registry::RegistryHistory;
+use crate::contract::state::StateSnapshot;
+use crate::mempool::MempoolSnapshot;
+use crate::network::PeerReputation;
+use crate::receipt::{BlockReceipt, CallReceipt, WasmValue};
+use crate::transaction::{Transaction, TransactionOutput};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Read;
+
+/// Format version records are written in. Bump it, and teach the affected
+/// types to read their previous layout, whenever a persisted layout changes.
+pub const FORMAT_VERSION: u32 = 6;
+
+/// Marks a record as wrapped in a format envelope. Records written before
+/// the envelope existed carry no marker and are read as version 1.
+const FORMAT_MAGIC: [u8; 4] = [0xb1, 0x0c, 0xf0, 0x7a];
+
+const ENVELOPE_HEADER_LEN: usize = FORMAT_MAGIC.len() + 4;
+
+/// A type written to storage, which can upgrade records written in older
+/// format versions
+pub trait Persisted: Serialize + DeserializeOwned {
+    /// Read a record in the layout the type had in format `version`, older
+    /// than `FORMAT_VERSION`. Types whose layout never changed read it as is.
+    fn read_legacy<R: Read>(version: u32, reader: &mut R) -> bincode::Result<Self> {
+        let _ = version;
+        bincode::deserialize_from(reader)
+    }
+}
+
+/// Encode `record` in the current format version
+pub fn encode<T: Serialize + ?Sized>(record: &T) -> bincode::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(ENVELOPE_HEADER_LEN + bincode::serialized_size(record)? as usize);
+    data.extend_from_slice(&FORMAT_MAGIC);
+    data.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bincode::serialize_into(&mut data, record)?;
+    Ok(data)
+}
+
+/// Decode a record written in any supported format version, upgrading it to
+/// the current layout
+pub fn decode<T: Persisted>(data: &[u8]) -> bincode::Result<T> {
+    let Some(version) = format_version(data) else {
+        return T::read_legacy(1, &mut &data[..]);
+    };
+
+    // Enveloped records that fail to decode are errors, never retried as
+    // unversioned ones a garbage value could come out of
+    let mut payload = &data[ENVELOPE_HEADER_LEN..];
+    match version {
+        FORMAT_VERSION => bincode::deserialize(payload),
+        v if v < FORMAT_VERSION => T::read_legacy(v, &mut payload),
+        _ => Err(Box::new(bincode::ErrorKind::Custom(
+            format!("Unsupported format version {}", version)
+        ))),
+    }
+}
+
+/// Format version of an enveloped record, `None` for an unversioned one
+pub fn format_version(data: &[u8]) -> Option<u32> {
+    if data.len() < ENVELOPE_HEADER_LEN || data[..FORMAT_MAGIC.len()] != FORMAT_MAGIC {
+        return None;
+    }
+    let mut version = [0u8; 4];
+    version.copy_from_slice(&data[FORMAT_MAGIC.len()..ENVELOPE_HEADER_LEN]);
+    Some(u32::from_le_bytes(version))
+}
+
+impl Persisted for Block {
+    fn read_legacy<R: Read>(version: u32, reader: &mut R) -> bincode::Result<Self> {
+        match version {
+            ..=1 => bincode::deserialize_from::<_, BlockV1>(reader).map(Into::into),
+            2..=4 => bincode::deserialize_from::<_, BlockV4>(reader).map(Into::into),
+            _ => bincode::deserialize_from(reader),
+        }
+    }
+}
+
+/// A block as written before headers named their proposer
+#[derive(Deserialize)]
+struct BlockV1 {
+    header: BlockHeaderV1,
+    transactions: Vec<Transaction>,
+    hash: Hash,
+}
+
+#[derive(Deserialize)]
+struct BlockHeaderV1 {
+    version: u32,
+    timestamp: u64,
+    prev_hash: Hash,
+    merkle_root: Hash,
+    difficulty: u32,
+    nonce: u64,
+}
+
+/// A block as written before headers carried the proposer's signature
+#[derive(Deserialize)]
+struct BlockV4 {
+    header: BlockHeaderV4,
+    transactions: Vec<Transaction>,
+    hash: Hash,
+}
+
+#[derive(Deserialize)]
+struct BlockHeaderV4 {
+    version: u32,
+    timestamp: u64,
+    prev_hash: Hash,
+    merkle_root: Hash,
+    difficulty: u32,
+    nonce: u64,
+    proposer: [u8; 32],
+}
+
+impl From<BlockV1> for Block {
+    fn from(record: BlockV1) -> Self {
+        let header = record.header;
+        Block {
+            header: BlockHeader {
+                version: header.version,
+                timestamp: header.timestamp,
+                prev_hash: header.prev_hash,
+                merkle_root: header.merkle_root,
+                difficulty: header.difficulty,
+                nonce: header.nonce,
+                proposer: [0u8; 32],
+                proposer_signature: None,
+            },
+            transactions: record.transactions,
+            hash: record.hash,
+        }
+    }
+}
+
+impl From<BlockV4> for Block {
+    fn from(record: BlockV4) -> Self {
+        let header = record.header;
+        Block {
+            header: BlockHeader {
+                version: header.version,
+                timestamp: header.timestamp,
+                prev_hash: header.prev_hash,
+                merkle_root: header.merkle_root,
+                difficulty: header.difficulty,
+                nonce: header.nonce,
+                proposer: header.proposer,
+                proposer_signature: None,
+            },
+            transactions: record.transactions,
+            hash: record.hash,
+        }
+    }
+}
+
+impl Persisted for Transaction {}
+
+impl Persisted for TransactionOutput {}
+
+impl Persisted for PeerReputation {}
+
+/// Contract addresses, as in indexes of contracts
+impl Persisted for [u8; 32] {}
+
+impl Persisted for ContractVersion {
+    fn read_legacy<R: Read>(version: u32, reader: &mut R) -> bincode::Result<Self> {
+        match version {
+            ..=2 => bincode::deserialize_from::<_, ContractVersionV2>(reader).map(Into::into),
+            3 => bincode::deserialize_from::<_, ContractVersionV3>(reader).map(Into::into),
+            _ => bincode::deserialize_from(reader),
+        }
+    }
+}
+
+/// A contract version as written before methods carried a default gas limit
+#[derive(Deserialize)]
+struct ContractVersionV2 {
+    bytecode: Vec<u8>,
+    metadata: ContractMetadataV3,
+    abi: ContractABIV2,
+}
+
+/// A contract version as written before metadata could allow a major upgrade
+#[derive(Deserialize)]
+struct ContractVersionV3 {
+    bytecode: Vec<u8>,
+    metadata: ContractMetadataV3,
+    abi: ContractABI,
+}
+
+#[derive(Deserialize)]
+struct ContractMetadataV3 {
+    version: String,
+    created_at: u64,
+    updated_at: u64,
+    author: [u8; 32],
+    description: String,
+    is_upgradeable: bool,
+}
+
+#[derive(Deserialize)]
+struct ContractABIV2 {
+    methods: Vec<ContractMethodV2>,
+    events: Vec<ContractEvent>,
+    standards: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ContractMethodV2 {
+    name: String,
+    inputs: Vec<ContractParam>,
+    outputs: Vec<ContractParam>,
+    payable: bool,
+}
+
+impl From<ContractMetadataV3> for ContractMetadata {
+    fn from(record: ContractMetadataV3) -> Self {
+        ContractMetadata {
+            version: record.version,
+            created_at: record.created_at,
+            updated_at: record.updated_at,
+            author: record.author,
+            description: record.description,
+            is_upgradeable: record.is_upgradeable,
+            allow_major_upgrade: false,
+        }
+    }
+}
+
+impl From<ContractVersionV3> for ContractVersion {
+    fn from(record: ContractVersionV3) -> Self {
+        ContractVersion {
+            bytecode: record.bytecode,
+            metadata: record.metadata.into(),
+            abi: record.abi,
+        }
+    }
+}
+
+impl From<ContractVersionV2> for ContractVersion {
+    fn from(record: ContractVersionV2) -> Self {
+        ContractVersion {
+            bytecode: record.bytecode,
+            metadata: record.metadata.into(),
+            abi: ContractABI {
+                methods: record.abi.methods.into_iter().map(|method| ContractMethod {
+                    name: method.name,
+                    inputs: method.inputs,
+                    outputs: method.outputs,
+                    payable: method.payable,
+                    default_gas: None,
+                }).collect(),
+                events: record.abi.events,
+                standards: record.abi.standards,
+            },
+        }
+    }
+}
+
+impl Persisted for RegistryHistory {}
+
+impl Persisted for StateSnapshot {
+    fn read_legacy<R: Read>(version: u32, reader: &mut R) -> bincode::Result<Self> {
+        match version {
+            ..=1 => bincode::deserialize_from::<_, StateSnapshotV1>(reader).map(Into::into),
+            _ => bincode::deserialize_from(reader),
+        }
+    }
+}
+
+/// A snapshot as written before snapshots recorded the block they were taken in
+#[derive(Deserialize)]
+struct StateSnapshotV1 {
+    contract_addr: [u8; 32],
+    version: String,
+    timestamp: u64,
+    state: HashMap<Vec<u8>, Vec<u8>>,
+    state_hash: [u8; 32],
+    schema_version: u32,
+}
+
+impl From<StateSnapshotV1> for StateSnapshot {
+    fn from(record: StateSnapshotV1) -> Self {
+        StateSnapshot {
+            contract_addr: record.contract_addr,
+            version: record.version,
+            timestamp: record.timestamp,
+            state: record.state,
+            state_hash: record.state_hash,
+            schema_version: record.schema_version,
+            block_number: 0,
+        }
+    }
+}
+
+/// Contract state, keyed by storage key
+impl Persisted for HashMap<Vec<u8>, Vec<u8>> {}
+
+impl Persisted for BlockReceipt {
+    fn read_legacy<R: Read>(version: u32, reader: &mut R) -> bincode::Result<Self> {
+        match version {
+            ..=5 => bincode::deserialize_from::<_, BlockReceiptV5>(reader).map(Into::into),
+            _ => bincode::deserialize_from(reader),
+        }
+    }
+}
+
+/// A block receipt as written before call receipts carried their index
+/// among the calls of their transaction
+#[derive(Deserialize)]
+struct BlockReceiptV5 {
+    block_hash: Hash,
+    calls: Vec<CallReceiptV5>,
+    gas_used: u64,
+}
+
+#[derive(Deserialize)]
+struct CallReceiptV5 {
+    tx_hash: Hash,
+    contract_addr: [u8; 32],
+    method: String,
+    gas_used: u64,
+    success: bool,
+    result: Option<Vec<WasmValue>>,
+}
+
+impl From<BlockReceiptV5> for BlockReceipt {
+    fn from(record: BlockReceiptV5) -> Self {
+        // Calls were recorded in the order their transaction made them
+        let mut call_counts: HashMap<Hash, u32> = HashMap::new();
+        let calls = record.calls.into_iter().map(|call| {
+            let count = call_counts.entry(call.tx_hash.clone()).or_default();
+            let call_index = *count;
+            *count += 1;
+            CallReceipt {
+                tx_hash: call.tx_hash,
+                call_index,
+                contract_addr: call.contract_addr,
+                method: call.method,
+                gas_used: call.gas_used,
+                success: call.success,
+                result: call.result,
+            }
+        }).collect();
+
+        BlockReceipt {
+            block_hash: record.block_hash,
+            calls,
+            gas_used: record.gas_used,
+        }
+    }
+}
+
+impl Persisted for MempoolSnapshot {
+    fn read_legacy<R: Read>(version: u32, reader: &mut R) -> bincode::Result<Self> {
+        match version {
+            ..=4 => bincode::deserialize_from::<_, MempoolSnapshotV4>(reader).map(Into::into),
+            _ => bincode::deserialize_from(reader),
+        }
+    }
+}
+
+/// A mempool as saved before it recorded the sender of each transaction
+#[derive(Deserialize)]
+struct MempoolSnapshotV4 {
+    max_size: usize,
+    transactions: Vec<Transaction>,
+    seen_txs: Vec<Hash>,
+}
+
+impl From<MempoolSnapshotV4> for MempoolSnapshot {
+    fn from(record: MempoolSnapshotV4) -> Self {
+        MempoolSnapshot {
+            max_size: record.max_size,
+            transactions: record.transactions,
+            seen_txs: record.seen_txs,
+            senders: Vec::new(),
+        }
+    }
+}
+
+impl<T: Persisted> Persisted for Vec<T> {
+    fn read_legacy<R: Read>(version: u32, reader: &mut R) -> bincode::Result<Self> {
+        // Elements may each have their own legacy layout, so read them one
+        // at a time after the length prefix
+        let len: u64 = bincode::deserialize_from(&mut *reader)?;
+        (0..len).map(|_| T::read_legacy(version, reader)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_envelope() {
+        let tx = Transaction::coinbase(vec![1, 2, 3], 50);
+        let data = encode(&tx).unwrap();
+        assert_eq!(format_version(&data), Some(FORMAT_VERSION));
+        assert_eq!(decode::<Transaction>(&data).unwrap().hash, tx.hash);
+
+        // Unversioned records are read as version 1
+        let legacy = bincode::serialize(&tx).unwrap();
+        assert_eq!(format_version(&legacy), None);
+        assert_eq!(decode::<Transaction>(&legacy).unwrap().hash, tx.hash);
+        let legacy_list = bincode::serialize(&vec![tx.clone(), tx.clone()]).unwrap();
+        assert_eq!(decode::<Vec<Transaction>>(&legacy_list).unwrap().len(), 2);
+
+        // Records from a newer format are refused rather than misread
+        let mut future = data.clone();
+        future[FORMAT_MAGIC.len()..ENVELOPE_HEADER_LEN].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert!(decode::<Transaction>(&future).is_err());
+    }
+
+    #[test]
+    fn test_enveloped_errors_not_read_as_legacy() {
+        let block = Block::genesis();
+        let data = encode(&block).unwrap();
+        assert_eq!(decode::<Block>(&data).unwrap().hash, block.hash);
+
+        // A newer format version is reported as such
+        let mut future = data.clone();
+        future[FORMAT_MAGIC.len()..ENVELOPE_HEADER_LEN].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        let err = decode::<Block>(&future).unwrap_err();
+        assert!(err.to_string().contains("Unsupported format version"));
+
+        // So is a current version record cut short
+        let truncated = &data[..data.len() - 1];
+        assert!(decode::<Block>(truncated).is_err());
+    }
+
+    #[test]
+    fn test_contract_version_v2_migrated() {
+        // Fields in order, as the version 2 layout wrote them
+        let metadata = ("1.0.0".to_string(), 1234567890u64, 1234567890u64, [1u8; 32], "Test Contract".to_string(), true);
+        let method = ("add".to_string(), Vec::<ContractParam>::new(), Vec::<ContractParam>::new(), false);
+        let abi = (vec![method], Vec::<ContractEvent>::new(), Vec::<String>::new());
+        let record = (vec![1u8, 2, 3], metadata, abi);
+
+        let mut data = FORMAT_MAGIC.to_vec();
+        data.extend_from_slice(&2u32.to_le_bytes());
+        bincode::serialize_into(&mut data, &vec![record]).unwrap();
+
+        let versions: Vec<ContractVersion> = decode(&data).unwrap();
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].bytecode, vec![1, 2, 3]);
+        assert_eq!(versions[0].metadata.version, "1.0.0");
+        assert!(!versions[0].metadata.allow_major_upgrade);
+        assert_eq!(versions[0].abi.methods[0].name, "add");
+        assert_eq!(versions[0].abi.methods[0].default_gas, None);
+    }
+
+    #[test]
+    fn test_legacy_receipts_migrated() {
+        // Fields in order, as receipts were written before calls carried an index
+        let tx_hash = Hash::new(b"tx");
+        let call = |method: &str, gas_used: u64| {
+            (tx_hash.clone(), [4u8; 32], method.to_string(), gas_used, true, Some(vec![WasmValue::I32(1)]))
+        };
+        let legacy = bincode::serialize(&(Hash::new(b"block"), vec![call("a", 10), call("b", 20)], 30u64)).unwrap();
+
+        let receipt: BlockReceipt = decode(&legacy).unwrap();
+        assert_eq!(receipt.block_hash, Hash::new(b"block"));
+        assert_eq!(receipt.gas_used, 30);
+        let indexes: Vec<_> = receipt.calls.iter().map(|call| (call.method.as_str(), call.call_index)).collect();
+        assert_eq!(indexes, vec![("a", 0), ("b", 1)]);
+        assert_eq!(receipt.calls[1].result, Some(vec![WasmValue::I32(1)]));
+
+        // Current receipts are read as written
+        let data = encode(&receipt).unwrap();
+        assert_eq!(decode::<BlockReceipt>(&data).unwrap(), receipt);
+    }
+
+    #[test]
+    fn test_snapshot_block_number() {
+        let snapshot = StateSnapshot {
+            contract_addr: [1u8; 32],
+            version: "1.0.0".to_string(),
+            timestamp: 1234567890,
+            state: HashMap::from([(b"key".to_vec(), b"value".to_vec())]),
+            state_hash: [3u8; 32],
+            schema_version: 1,
+            block_number: 12,
+        };
+
+        // Unversioned snapshots predate block numbers
+        let legacy = bincode::serialize(&(
+            snapshot.contract_addr, &snapshot.version, snapshot.timestamp, &snapshot.state, snapshot.state_hash, snapshot.schema_version,
+        )).unwrap();
+        let decoded: StateSnapshot = decode(&legacy).unwrap();
+        assert_eq!(decoded.version, "1.0.0");
+        assert_eq!(decoded.schema_version, 1);
+        assert_eq!(decoded.block_number, 0);
+
+        // Versioned ones carry it
+        let decoded: StateSnapshot = decode(&encode(&snapshot).unwrap()).unwrap();
+        assert_eq!(decoded.block_number, 12);
+    }
+
+    #[test]
+    fn test_legacy_blocks_migrated() {
+        let tx = Transaction::coinbase(vec![1, 2, 3], 50);
+        let header = (1u32, 12345u64, Hash::new(b"parent"), Hash::new(b"merkle"), 1u32, 7u64);
+
+        // Unversioned blocks predate the proposer
+        let legacy = bincode::serialize(&(header.clone(), vec![tx.clone()], Hash::new(b"block"))).unwrap();
+        let block: Block = decode(&legacy).unwrap();
+        assert_eq!(block.header.nonce, 7);
+        assert_eq!(block.header.proposer, [0u8; 32]);
+        assert_eq!(block.transactions[0].hash, tx.hash);
+        assert_eq!(block.hash, Hash::new(b"block"));
+
+        // Version 4 blocks name their proposer but carry no signature
+        let (version, timestamp, prev_hash, merkle_root, difficulty, nonce) = header;
+        let header = (version, timestamp, prev_hash, merkle_root, difficulty, nonce, [5u8; 32]);
+        let mut data = FORMAT_MAGIC.to_vec();
+        data.extend_from_slice(&4u32.to_le_bytes());
+        bincode::serialize_into(&mut data, &(header, vec![tx], Hash::new(b"block"))).unwrap();
+        let block: Block = decode(&data).unwrap();
+        assert_eq!(block.header.proposer, [5u8; 32]);
+        assert!(block.header.proposer_signature.is_none());
+    }
+}
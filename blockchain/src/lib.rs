@@ -1,21 +1,31 @@
 pub mod api;
 pub mod block;
+pub mod chain;
 pub mod consensus;
 pub mod contract;
 pub mod crypto;
+pub mod format;
 pub mod mempool;
 pub mod msg;
 pub mod network;
+pub mod params;
+pub mod pbft;
+pub mod receipt;
 pub mod storage;
 pub mod transaction;
 
 pub use api::*;
 pub use block::*;
+pub use chain::*;
 pub use consensus::*;
 pub use contract::*;
 pub use crypto::*;
+pub use format::*;
 pub use mempool::*;
 pub use msg::*;
 pub use network::*;
+pub use params::*;
+pub use pbft::*;
+pub use receipt::*;
 pub use storage::*;
 pub use transaction::*;
@@ -1,28 +1,111 @@
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
-use crate::transaction::Transaction;
-use crate::crypto::Hash;
+use crate::transaction::{Transaction, DEFAULT_VERIFICATION_CONCURRENCY};
+use crate::crypto::{is_zero_address, Hash};
+use crate::format;
+use crate::storage::StorageError;
 
 const DEFAULT_BATCH_SIZE: usize = 1000;
+const DEFAULT_MIN_RBF_BUMP: f64 = 1.0; // Fee per byte a replacement must add
+const DEFAULT_MIN_RBF_FEE_INCREMENT: u64 = 1; // Total fee a replacement must add
+const DEFAULT_RBF_GRACE_SECS: u64 = 10; // Time before replaced inputs can be replaced again
+const DEFAULT_BLOCK_CAPACITY: usize = 1000; // Transactions per block, matches consensus
+const MIN_FEE_RATE: u64 = 1; // Lowest fee per byte ever recommended
+
+/// Outcome of submitting a transaction to the mempool
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddTransactionOutcome {
+    /// The transaction was queued for verification and insertion
+    Added,
+    /// The transaction was already seen and was ignored
+    Duplicate,
+    /// The transaction replaced the pending transaction with this hash
+    ReplacedTransaction(Hash),
+}
+
+/// Tracks which sender submitted each pool transaction and how many
+/// transactions each sender currently has in the pool
+#[derive(Default)]
+struct SenderIndex {
+    by_tx: HashMap<Hash, [u8; 32]>,
+    counts: HashMap<[u8; 32], usize>,
+}
+
+impl SenderIndex {
+    fn insert(&mut self, tx_hash: Hash, sender: [u8; 32]) {
+        if self.by_tx.insert(tx_hash, sender).is_none() {
+            *self.counts.entry(sender).or_insert(0) += 1;
+        }
+    }
+
+    fn remove(&mut self, tx_hash: &Hash) {
+        if let Some(sender) = self.by_tx.remove(tx_hash) {
+            if let Some(count) = self.counts.get_mut(&sender) {
+                *count -= 1;
+                if *count == 0 {
+                    self.counts.remove(&sender);
+                }
+            }
+        }
+    }
+
+    fn count(&self, sender: &[u8; 32]) -> usize {
+        self.counts.get(sender).copied().unwrap_or(0)
+    }
+}
+
+/// On-disk representation of a mempool
+#[derive(Serialize, Deserialize)]
+pub(crate) struct MempoolSnapshot {
+    pub(crate) max_size: usize,
+    pub(crate) transactions: Vec<Transaction>,
+    pub(crate) seen_txs: Vec<Hash>,
+    /// Sender of each pool transaction, see `Mempool::sender_count`
+    pub(crate) senders: Vec<(Hash, [u8; 32])>,
+}
 
 pub struct Mempool {
     transactions: Arc<RwLock<HashMap<Hash, Transaction>>>,
     seen_txs: Arc<RwLock<HashSet<Hash>>>,
     pending_queue: Arc<RwLock<VecDeque<(Transaction, Vec<Vec<u8>>)>>>,
+    // Insertion time (unix seconds) of each transaction in the pool
+    inserted_at: Arc<RwLock<HashMap<Hash, u64>>>,
+    // Pool transaction spending each (tx_hash, output_index) outpoint
+    spent_outpoints: Arc<RwLock<HashMap<(Hash, u32), Hash>>>,
+    // Known values of confirmed outputs, used to compute fees
+    utxo_values: Arc<RwLock<HashMap<(Hash, u32), u64>>>,
+    senders: Arc<RwLock<SenderIndex>>,
+    // Time (unix seconds) each outpoint was last spent by a replacement
+    replaced_at: Arc<RwLock<HashMap<(Hash, u32), u64>>>,
     max_size: usize,
+    // Serialized size of the pool transactions, and the most it may reach
+    // together with the transactions queued or being verified
+    pool_bytes: AtomicUsize,
+    queued_bytes: AtomicUsize,
+    max_bytes: Option<usize>,
     batch_size: usize,
+    ttl_secs: Option<u64>,
+    max_per_sender: Option<usize>,
+    min_rbf_bump: f64,
+    min_rbf_fee_increment: u64,
+    // Lowest fee per byte admitted, and senders admitted below it
+    min_fee_rate: f64,
+    fee_exempt: HashSet<[u8; 32]>,
+    rbf_grace_secs: u64,
+    block_capacity: usize,
+    // Signature verifications run at once when processing the pending queue
+    verification_concurrency: usize,
+    // Logical clock offset, only advanced by tests
+    time_offset: AtomicU64,
 }
 
 impl Mempool {
     pub fn new(max_size: usize) -> Self {
-        Mempool {
-            transactions: Arc::new(RwLock::new(HashMap::new())),
-            seen_txs: Arc::new(RwLock::new(HashSet::new())),
-            pending_queue: Arc::new(RwLock::new(VecDeque::new())),
-            max_size,
-            batch_size: DEFAULT_BATCH_SIZE,
-        }
+        Self::with_batch_size(max_size, DEFAULT_BATCH_SIZE)
     }
 
     pub fn with_batch_size(max_size: usize, batch_size: usize) -> Self {
@@ -30,40 +113,414 @@ impl Mempool {
             transactions: Arc::new(RwLock::new(HashMap::new())),
             seen_txs: Arc::new(RwLock::new(HashSet::new())),
             pending_queue: Arc::new(RwLock::new(VecDeque::new())),
+            inserted_at: Arc::new(RwLock::new(HashMap::new())),
+            spent_outpoints: Arc::new(RwLock::new(HashMap::new())),
+            utxo_values: Arc::new(RwLock::new(HashMap::new())),
+            senders: Arc::new(RwLock::new(SenderIndex::default())),
+            replaced_at: Arc::new(RwLock::new(HashMap::new())),
             max_size,
+            pool_bytes: AtomicUsize::new(0),
+            queued_bytes: AtomicUsize::new(0),
+            max_bytes: None,
             batch_size,
+            ttl_secs: None,
+            max_per_sender: None,
+            min_rbf_bump: DEFAULT_MIN_RBF_BUMP,
+            min_rbf_fee_increment: DEFAULT_MIN_RBF_FEE_INCREMENT,
+            min_fee_rate: 0.0,
+            fee_exempt: HashSet::new(),
+            rbf_grace_secs: DEFAULT_RBF_GRACE_SECS,
+            block_capacity: DEFAULT_BLOCK_CAPACITY,
+            verification_concurrency: DEFAULT_VERIFICATION_CONCURRENCY,
+            time_offset: AtomicU64::new(0),
+        }
+    }
+
+    /// Creates a mempool that drops transactions older than `ttl_secs`
+    /// whenever a new transaction is added.
+    pub fn with_ttl(max_size: usize, ttl_secs: u64) -> Self {
+        let mut mempool = Self::new(max_size);
+        mempool.ttl_secs = Some(ttl_secs);
+        mempool
+    }
+
+    /// Creates a mempool that holds at most `max_per_sender` transactions from
+    /// any one sender. The sender is the signer of a transaction's first input.
+    pub fn with_sender_limit(max_size: usize, max_per_sender: usize) -> Self {
+        let mut mempool = Self::new(max_size);
+        mempool.max_per_sender = Some(max_per_sender);
+        mempool
+    }
+
+    /// Caps the total serialized size of pool transactions at `max_bytes`,
+    /// alongside the cap on their number. No byte cap is enforced by default.
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Sets how much higher (in fee per byte) a replacement's fee-rate must be
+    /// than the transaction it replaces.
+    pub fn with_min_rbf_bump(mut self, min_rbf_bump: f64) -> Self {
+        self.min_rbf_bump = min_rbf_bump;
+        self
+    }
+
+    /// Sets how much higher a replacement's total fee must be than the fee of
+    /// the transaction it replaces.
+    pub fn with_min_rbf_fee_increment(mut self, min_rbf_fee_increment: u64) -> Self {
+        self.min_rbf_fee_increment = min_rbf_fee_increment;
+        self
+    }
+
+    /// Sets the lowest fee-rate (fee per byte) a transaction must pay to be
+    /// admitted. No floor is enforced by default.
+    pub fn with_min_fee_rate(mut self, min_fee_rate: f64) -> Self {
+        self.min_fee_rate = min_fee_rate;
+        self
+    }
+
+    /// Admits transactions from these senders regardless of the fee floor,
+    /// e.g. trusted relays.
+    pub fn with_fee_exemptions(mut self, senders: impl IntoIterator<Item = [u8; 32]>) -> Self {
+        self.fee_exempt.extend(senders);
+        self
+    }
+
+    /// Sets how long, in seconds, inputs spent by a replacement are protected
+    /// from being replaced again.
+    pub fn with_rbf_grace_period(mut self, rbf_grace_secs: u64) -> Self {
+        self.rbf_grace_secs = rbf_grace_secs;
+        self
+    }
+
+    /// Sets the number of transactions assumed to fit in a block when
+    /// estimating fees.
+    pub fn with_block_capacity(mut self, block_capacity: usize) -> Self {
+        self.block_capacity = block_capacity.max(1);
+        self
+    }
+
+    /// Sets how many signature verifications may run at once when pending
+    /// transactions are processed.
+    pub fn with_verification_concurrency(mut self, verification_concurrency: usize) -> Self {
+        self.verification_concurrency = verification_concurrency.max(1);
+        self
+    }
+
+    fn current_time(&self) -> u64 {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        now + self.time_offset.load(Ordering::SeqCst)
+    }
+
+    #[cfg(test)]
+    fn advance_time(&self, secs: u64) {
+        self.time_offset.fetch_add(secs, Ordering::SeqCst);
+    }
+
+    fn sender_of(public_keys: &[Vec<u8>]) -> Option<[u8; 32]> {
+        public_keys.first().and_then(|key| key.as_slice().try_into().ok())
+    }
+
+    /// Number of pool transactions submitted by `sender`
+    pub async fn sender_count(&self, sender: &[u8; 32]) -> usize {
+        self.senders.read().await.count(sender)
+    }
+
+    fn index_spends(spent: &mut HashMap<(Hash, u32), Hash>, tx: &Transaction) {
+        for input in &tx.inputs {
+            spent.insert((input.tx_hash.clone(), input.output_index), tx.hash.clone());
+        }
+    }
+
+    fn unindex_spends(spent: &mut HashMap<(Hash, u32), Hash>, tx: &Transaction) {
+        for input in &tx.inputs {
+            let outpoint = (input.tx_hash.clone(), input.output_index);
+            if spent.get(&outpoint) == Some(&tx.hash) {
+                spent.remove(&outpoint);
+            }
+        }
+    }
+
+    /// Records the value of a confirmed output so fees of transactions
+    /// spending it can be computed.
+    pub async fn add_utxo(&self, tx_hash: Hash, output_index: u32, amount: u64) {
+        self.utxo_values.write().await.insert((tx_hash, output_index), amount);
+    }
+
+    /// Fee paid by a transaction: the value of its inputs minus its outputs.
+    /// Inputs are valued from known UTXOs or outputs of pool transactions;
+    /// unknown inputs count as zero. None if either sum overflows.
+    pub async fn fee(&self, tx: &Transaction) -> Option<u64> {
+        let txs = self.transactions.read().await;
+        let utxos = self.utxo_values.read().await;
+
+        let input_value = tx.inputs
+            .iter()
+            .map(|input| {
+                let outpoint = (input.tx_hash.clone(), input.output_index);
+                utxos.get(&outpoint).copied().unwrap_or_else(|| {
+                    txs.get(&input.tx_hash)
+                        .and_then(|parent| parent.outputs.get(input.output_index as usize))
+                        .map_or(0, |output| output.amount)
+                })
+            })
+            .try_fold(0u64, u64::checked_add)?;
+        let output_value = tx.outputs
+            .iter()
+            .map(|output| output.amount)
+            .try_fold(0u64, u64::checked_add)?;
+
+        Some(input_value.saturating_sub(output_value))
+    }
+
+    /// Fee per byte of the transaction's serialized size. None if its value
+    /// sums overflow.
+    pub async fn fee_rate(&self, tx: &Transaction) -> Option<f64> {
+        let fee = self.fee(tx).await?;
+        Some(Self::fee_per_byte(fee, tx))
+    }
+
+    fn fee_per_byte(fee: u64, tx: &Transaction) -> f64 {
+        fee as f64 / Self::tx_bytes(tx).max(1) as f64
+    }
+
+    fn tx_bytes(tx: &Transaction) -> usize {
+        bincode::serialized_size(tx).unwrap_or(0) as usize
+    }
+
+    fn track_insert(&self, tx: &Transaction) {
+        self.pool_bytes.fetch_add(Self::tx_bytes(tx), Ordering::SeqCst);
+    }
+
+    fn track_remove(&self, tx: &Transaction) {
+        self.pool_bytes.fetch_sub(Self::tx_bytes(tx), Ordering::SeqCst);
+    }
+
+    /// Recommends a fee-rate (fee per byte) for inclusion within `target_blocks`
+    /// blocks. Pending transactions are ranked by fee-rate and the recommendation
+    /// outbids the last one that would still fit in that many blocks.
+    pub async fn estimate_fee_rate(&self, target_blocks: u64) -> u64 {
+        let pending = self.get_all_transactions().await;
+        let mut rates = Vec::with_capacity(pending.len());
+        for tx in &pending {
+            rates.extend(self.fee_rate(tx).await);
+        }
+        rates.sort_by(|a, b| b.total_cmp(a));
+
+        let slots = (target_blocks.max(1) as usize).saturating_mul(self.block_capacity);
+        if rates.len() < slots {
+            return MIN_FEE_RATE;
+        }
+
+        (rates[slots - 1].floor() as u64 + 1).max(MIN_FEE_RATE)
+    }
+
+    /// Hashes of pool and queued transactions spending any of the same
+    /// inputs as `tx`. A queued spender is accepted to replace the pool
+    /// spender of the same input, so it is the one that conflicts.
+    pub async fn conflicts(&self, tx: &Transaction) -> Vec<Hash> {
+        let spent = self.spent_outpoints.read().await;
+        let queue = self.pending_queue.read().await;
+        let mut conflicts: Vec<Hash> = Vec::new();
+        for input in &tx.inputs {
+            let queued = queue.iter().rev().find(|(queued, _)| {
+                queued.inputs
+                    .iter()
+                    .any(|spend| spend.tx_hash == input.tx_hash && spend.output_index == input.output_index)
+            });
+            let spender = queued
+                .map(|(queued, _)| &queued.hash)
+                .or_else(|| spent.get(&(input.tx_hash.clone(), input.output_index)));
+            if let Some(spender) = spender {
+                if *spender != tx.hash && !conflicts.contains(spender) {
+                    conflicts.push(spender.clone());
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// A transaction in the pool or still waiting in the pending queue
+    async fn pool_or_queued(&self, hash: &Hash) -> Option<Transaction> {
+        if let Some(tx) = self.get_transaction(hash).await {
+            return Some(tx);
+        }
+        self.pending_queue.read().await
+            .iter()
+            .find(|(queued, _)| queued.hash == *hash)
+            .map(|(queued, _)| queued.clone())
+    }
+
+    /// Whether any input of `tx` was spent by a replacement within the grace
+    /// period. Replacements older than the grace period are forgotten.
+    async fn replaced_recently(&self, tx: &Transaction) -> bool {
+        let now = self.current_time();
+        let mut replaced_at = self.replaced_at.write().await;
+        replaced_at.retain(|_, &mut time| now.saturating_sub(time) < self.rbf_grace_secs);
+        tx.inputs
+            .iter()
+            .any(|input| replaced_at.contains_key(&(input.tx_hash.clone(), input.output_index)))
+    }
+
+    /// Removes transactions that have been in the pool for longer than
+    /// `max_age_secs` and returns how many were removed. Expired transactions
+    /// are also forgotten by the duplicate filter so they can be re-submitted.
+    pub async fn expire_old(&self, max_age_secs: u64) -> usize {
+        let now = self.current_time();
+
+        let mut txs = self.transactions.write().await;
+        let mut seen = self.seen_txs.write().await;
+        let mut inserted_at = self.inserted_at.write().await;
+        let mut spent = self.spent_outpoints.write().await;
+        let mut senders = self.senders.write().await;
+
+        let expired: Vec<Hash> = inserted_at
+            .iter()
+            .filter(|(_, &time)| now.saturating_sub(time) > max_age_secs)
+            .map(|(hash, _)| hash.clone())
+            .collect();
+
+        for hash in &expired {
+            if let Some(tx) = txs.remove(hash) {
+                Self::unindex_spends(&mut spent, &tx);
+                self.track_remove(&tx);
+            }
+            seen.remove(hash);
+            inserted_at.remove(hash);
+            senders.remove(hash);
         }
+
+        expired.len()
     }
 
-    pub async fn add_transaction(&self, tx: Transaction, public_keys: Vec<Vec<u8>>) -> Result<bool, &'static str> {
+    pub async fn add_transaction(&self, tx: Transaction, public_keys: Vec<Vec<u8>>) -> Result<AddTransactionOutcome, &'static str> {
         let tx_hash = tx.hash.clone();
+
+        // Drop stale transactions before checking duplicates and capacity
+        if let Some(ttl) = self.ttl_secs {
+            self.expire_old(ttl).await;
+        }
         
         // Check if transaction was already seen
         {
             let seen = self.seen_txs.read().await;
             if seen.contains(&tx_hash) {
-                return Ok(false);
+                return Ok(AddTransactionOutcome::Duplicate);
             }
         }
 
-        // Check mempool capacity
-        {
+        // Coinbases are created by block producers and never relayed
+        if tx.is_coinbase() {
+            return Err("Transaction has no inputs");
+        }
+        if tx.has_zero_recipient() {
+            return Err("Zero address recipient");
+        }
+        if Self::sender_of(&public_keys).is_some_and(|sender| is_zero_address(&sender)) {
+            return Err("Zero address sender");
+        }
+
+        // Value sums must fit in a u64 for the fee to mean anything
+        let fee = self.fee(&tx).await.ok_or("Transaction value overflow")?;
+        let fee_rate = Self::fee_per_byte(fee, &tx);
+
+        let exempt = Self::sender_of(&public_keys).is_some_and(|sender| self.fee_exempt.contains(&sender));
+        if !exempt && self.min_fee_rate > 0.0 && fee_rate < self.min_fee_rate {
+            return Err("Fee rate below minimum");
+        }
+
+        // A transaction spending inputs already claimed by the pool may only
+        // replace a single pending transaction, and only with a higher fee-rate
+        let conflicts = self.conflicts(&tx).await;
+        let replaced = match conflicts.as_slice() {
+            [] => None,
+            [conflict] => {
+                let existing = self.pool_or_queued(conflict).await.ok_or("Conflicting input")?;
+                let existing_fee = self.fee(&existing).await.ok_or("Conflicting input")?;
+                if fee_rate <= Self::fee_per_byte(existing_fee, &existing) + self.min_rbf_bump {
+                    return Err("Conflicting input");
+                }
+                if fee < existing_fee.saturating_add(self.min_rbf_fee_increment) {
+                    return Err("Replacement fee increment too small");
+                }
+                // Limit how often the same inputs can churn through the pool
+                if self.replaced_recently(&tx).await {
+                    return Err("Replacement within grace period");
+                }
+                Some(conflict.clone())
+            }
+            _ => return Err("Conflicting input"),
+        };
+
+        // Check mempool capacity; replacements don't grow the pool
+        if replaced.is_none() {
             let txs = self.transactions.read().await;
             if txs.len() >= self.max_size {
                 return Err("Mempool is full");
             }
         }
 
-        // Add to pending queue
-        {
-            let mut queue = self.pending_queue.write().await;
-            queue.push_back((tx, public_keys));
+        // Check the byte cap, counting queued transactions that have yet to
+        // enter the pool and the room a replacement frees
+        if let Some(max_bytes) = self.max_bytes {
+            let freed = match &replaced {
+                Some(old_hash) => self.pool_or_queued(old_hash).await.map_or(0, |old| Self::tx_bytes(&old)),
+                None => 0,
+            };
+            let pending_bytes = (self.pool_bytes.load(Ordering::SeqCst) + self.queued_bytes.load(Ordering::SeqCst))
+                .saturating_sub(freed);
+            if pending_bytes + Self::tx_bytes(&tx) > max_bytes {
+                return Err("Mempool byte limit reached");
+            }
         }
 
+        // Check the per-sender cap; replacing one's own transaction is allowed
+        if let (Some(max_per_sender), Some(sender)) = (self.max_per_sender, Self::sender_of(&public_keys)) {
+            let senders = self.senders.read().await;
+            let replaces_own = replaced
+                .as_ref()
+                .is_some_and(|old_hash| senders.by_tx.get(old_hash) == Some(&sender));
+            if !replaces_own && senders.count(&sender) >= max_per_sender {
+                return Err("Sender transaction limit reached");
+            }
+        }
+
+        let outpoints: Vec<(Hash, u32)> = tx.inputs
+            .iter()
+            .map(|input| (input.tx_hash.clone(), input.output_index))
+            .collect();
+
+        // Add to pending queue
+        self.enqueue(tx, public_keys).await;
+
         // Process pending queue if it reaches batch size
         self.process_pending_queue().await?;
 
-        Ok(true)
+        // A replacement still queued behind a batch in progress evicts the
+        // transaction it replaces once verified, like one inserted already
+        let accepted = self.contains(&tx_hash).await
+            || self.pending_queue.read().await.iter().any(|(queued, _)| queued.hash == tx_hash);
+        match replaced {
+            Some(old_hash) if accepted => {
+                let now = self.current_time();
+                let mut replaced_at = self.replaced_at.write().await;
+                for outpoint in outpoints {
+                    replaced_at.insert(outpoint, now);
+                }
+                Ok(AddTransactionOutcome::ReplacedTransaction(old_hash))
+            }
+            _ => Ok(AddTransactionOutcome::Added),
+        }
+    }
+
+    async fn enqueue(&self, tx: Transaction, public_keys: Vec<Vec<u8>>) {
+        let mut queue = self.pending_queue.write().await;
+        self.queued_bytes.fetch_add(Self::tx_bytes(&tx), Ordering::SeqCst);
+        queue.push_back((tx, public_keys));
     }
 
     async fn process_pending_queue(&self) -> Result<(), &'static str> {
@@ -83,18 +540,46 @@ impl Mempool {
             return Ok(());
         }
 
+        // The batch stays counted as queued until it is in the pool
+        let batch_bytes: usize = batch.iter().map(|(tx, _)| Self::tx_bytes(tx)).sum();
+
         // Verify batch of transactions in parallel
-        let verification_results = Transaction::verify_batch(&batch).await;
+        let verification_results = Transaction::verify_batch_with_limit(&batch, self.verification_concurrency).await;
 
         // Process verification results
+        let now = self.current_time();
         let mut txs = self.transactions.write().await;
         let mut seen = self.seen_txs.write().await;
+        let mut inserted_at = self.inserted_at.write().await;
+        let mut spent = self.spent_outpoints.write().await;
+        let mut senders = self.senders.write().await;
 
-        for ((tx, _), result) in batch.into_iter().zip(verification_results) {
+        for ((tx, public_keys), result) in batch.into_iter().zip(verification_results) {
             match result {
                 Ok(true) => {
                     let tx_hash = tx.hash.clone();
+
+                    // Evict transactions this one was accepted to replace
+                    for input in &tx.inputs {
+                        let outpoint = (input.tx_hash.clone(), input.output_index);
+                        if let Some(replaced) = spent.get(&outpoint).cloned() {
+                            if let Some(old_tx) = txs.remove(&replaced) {
+                                Self::unindex_spends(&mut spent, &old_tx);
+                                self.track_remove(&old_tx);
+                            }
+                            inserted_at.remove(&replaced);
+                            seen.remove(&replaced);
+                            senders.remove(&replaced);
+                        }
+                    }
+
+                    if let Some(sender) = Self::sender_of(&public_keys) {
+                        senders.insert(tx_hash.clone(), sender);
+                    }
+                    Self::index_spends(&mut spent, &tx);
+                    self.track_insert(&tx);
                     txs.insert(tx_hash.clone(), tx);
+                    inserted_at.insert(tx_hash.clone(), now);
                     seen.insert(tx_hash);
                 }
                 Ok(false) => {
@@ -107,13 +592,79 @@ impl Mempool {
                 }
             }
         }
+        self.queued_bytes.fetch_sub(batch_bytes, Ordering::SeqCst);
 
         Ok(())
     }
 
+    /// Pool transactions whose outputs `tx` spends
+    fn in_pool_parents(txs: &HashMap<Hash, Transaction>, tx: &Transaction) -> Vec<Hash> {
+        let mut parents: Vec<Hash> = Vec::new();
+        for input in &tx.inputs {
+            if txs.contains_key(&input.tx_hash) && !parents.contains(&input.tx_hash) {
+                parents.push(input.tx_hash.clone());
+            }
+        }
+        parents
+    }
+
+    /// Returns up to `limit` transactions ordered so that every transaction
+    /// comes after the pool transactions it spends from. A transaction is
+    /// only returned together with all of its in-pool ancestors.
     pub async fn get_pending_transactions(&self, limit: usize) -> Result<Vec<Transaction>, &'static str> {
         let txs = self.transactions.read().await;
-        Ok(txs.values().take(limit).cloned().collect())
+
+        let mut unmet: HashMap<&Hash, usize> = HashMap::new();
+        let mut children: HashMap<Hash, Vec<&Hash>> = HashMap::new();
+        let mut ready: Vec<&Transaction> = Vec::new();
+        for (hash, tx) in txs.iter() {
+            let parents = Self::in_pool_parents(&txs, tx);
+            if parents.is_empty() {
+                ready.push(tx);
+            }
+            unmet.insert(hash, parents.len());
+            for parent in parents {
+                children.entry(parent).or_default().push(hash);
+            }
+        }
+
+        // Oldest first among transactions whose ancestors are already included
+        ready.sort_by_key(|tx| (tx.timestamp, tx.nonce));
+        let mut ready: VecDeque<&Transaction> = ready.into();
+
+        let mut ordered = Vec::with_capacity(limit.min(txs.len()));
+        while ordered.len() < limit {
+            let Some(tx) = ready.pop_front() else { break };
+            for child in children.get(&tx.hash).into_iter().flatten() {
+                let remaining = unmet.get_mut(child).unwrap();
+                *remaining -= 1;
+                if *remaining == 0 {
+                    ready.push_back(&txs[*child]);
+                }
+            }
+            ordered.push(tx.clone());
+        }
+
+        Ok(ordered)
+    }
+
+    /// Hashes of all pool transactions `hash` depends on, ancestors first
+    pub async fn ancestors(&self, hash: &Hash) -> Vec<Hash> {
+        let txs = self.transactions.read().await;
+        let mut ancestors = Vec::new();
+        if let Some(tx) = txs.get(hash) {
+            Self::collect_ancestors(&txs, tx, &mut ancestors);
+        }
+        ancestors
+    }
+
+    fn collect_ancestors(txs: &HashMap<Hash, Transaction>, tx: &Transaction, ancestors: &mut Vec<Hash>) {
+        for parent in Self::in_pool_parents(txs, tx) {
+            if !ancestors.contains(&parent) {
+                Self::collect_ancestors(txs, &txs[&parent], ancestors);
+                ancestors.push(parent);
+            }
+        }
     }
 
     pub async fn process_all_pending(&self) -> Result<(), &'static str> {
@@ -124,21 +675,90 @@ impl Mempool {
     }
 
     pub async fn remove_transaction(&self, hash: &Hash) -> Option<Transaction> {
-        self.transactions.write().await.remove(hash)
+        let removed = self.transactions.write().await.remove(hash);
+        self.inserted_at.write().await.remove(hash);
+        self.senders.write().await.remove(hash);
+        if let Some(tx) = &removed {
+            Self::unindex_spends(&mut *self.spent_outpoints.write().await, tx);
+            self.track_remove(tx);
+        }
+        removed
     }
 
     pub async fn get_transaction(&self, hash: &Hash) -> Option<Transaction> {
         self.transactions.read().await.get(hash).cloned()
     }
 
+    /// Write pending transactions and the duplicate filter to `path`
+    pub async fn save(&self, path: &Path) -> Result<(), StorageError> {
+        let snapshot = MempoolSnapshot {
+            max_size: self.max_size,
+            transactions: self.get_all_transactions().await,
+            seen_txs: self.seen_txs.read().await.iter().cloned().collect(),
+            senders: self.senders.read().await.by_tx.iter().map(|(hash, sender)| (hash.clone(), *sender)).collect(),
+        };
+        let data = format::encode(&snapshot)
+            .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+
+        // Write to a temporary file first so a crash never leaves a partial file
+        let tmp_path = path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, data).await
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        tokio::fs::rename(&tmp_path, path).await
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))
+    }
+
+    /// Load a mempool previously written by `save`. Transactions are treated
+    /// as freshly inserted; other settings use their defaults.
+    pub async fn load(path: &Path) -> Result<Mempool, StorageError> {
+        let data = tokio::fs::read(path).await
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        let snapshot: MempoolSnapshot = format::decode(&data)
+            .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+
+        let mempool = Mempool::new(snapshot.max_size);
+        let now = mempool.current_time();
+        {
+            let mut txs = mempool.transactions.write().await;
+            let mut seen = mempool.seen_txs.write().await;
+            let mut inserted_at = mempool.inserted_at.write().await;
+            let mut spent = mempool.spent_outpoints.write().await;
+            let mut senders = mempool.senders.write().await;
+
+            for tx in snapshot.transactions {
+                Self::index_spends(&mut spent, &tx);
+                mempool.track_insert(&tx);
+                inserted_at.insert(tx.hash.clone(), now);
+                seen.insert(tx.hash.clone());
+                txs.insert(tx.hash.clone(), tx);
+            }
+            seen.extend(snapshot.seen_txs);
+            for (hash, sender) in snapshot.senders {
+                if txs.contains_key(&hash) {
+                    senders.insert(hash, sender);
+                }
+            }
+        }
+
+        Ok(mempool)
+    }
+
     pub async fn get_all_transactions(&self) -> Vec<Transaction> {
         self.transactions.read().await.values().cloned().collect()
     }
 
     pub async fn clear_transactions(&self, hashes: &[Hash]) {
         let mut txs = self.transactions.write().await;
+        let mut inserted_at = self.inserted_at.write().await;
+        let mut spent = self.spent_outpoints.write().await;
+        let mut senders = self.senders.write().await;
         for hash in hashes {
-            txs.remove(hash);
+            if let Some(tx) = txs.remove(hash) {
+                Self::unindex_spends(&mut spent, &tx);
+                self.track_remove(&tx);
+            }
+            inserted_at.remove(hash);
+            senders.remove(hash);
         }
     }
 
@@ -150,6 +770,11 @@ impl Mempool {
         self.transactions.read().await.len()
     }
 
+    /// Total serialized size of the pool transactions
+    pub fn size_bytes(&self) -> usize {
+        self.pool_bytes.load(Ordering::SeqCst)
+    }
+
     pub async fn pending_size(&self) -> usize {
         self.pending_queue.read().await.len()
     }
@@ -159,7 +784,7 @@ impl Mempool {
 mod tests {
     use super::*;
     use crate::transaction::{TransactionInput, TransactionOutput};
-    use crate::crypto::KeyPair;
+    use crate::crypto::{KeyPair, ZERO_ADDRESS};
 
     #[tokio::test]
     async fn test_mempool_parallel_processing() {
@@ -195,8 +820,8 @@ mod tests {
 
         // Add transactions
         let public_keys = vec![keypair.public_key().as_bytes().to_vec()];
-        assert!(mempool.add_transaction(tx1.clone(), public_keys.clone()).await.unwrap());
-        assert!(mempool.add_transaction(tx2.clone(), public_keys.clone()).await.unwrap());
+        assert_eq!(mempool.add_transaction(tx1.clone(), public_keys.clone()).await.unwrap(), AddTransactionOutcome::Added);
+        assert_eq!(mempool.add_transaction(tx2.clone(), public_keys.clone()).await.unwrap(), AddTransactionOutcome::Added);
         
         // Process all pending transactions
         mempool.process_all_pending().await.unwrap();
@@ -207,6 +832,59 @@ mod tests {
         assert!(mempool.contains(&tx2.hash).await);
     }
 
+    #[tokio::test]
+    async fn test_mempool_max_bytes() {
+        let keypair = KeyPair::generate();
+        let public_keys = vec![keypair.public_key().as_bytes().to_vec()];
+        let large_tx = |i: usize| {
+            let mut tx = Transaction::new(
+                vec![TransactionInput {
+                    tx_hash: Hash::new(format!("large_tx_{}", i).as_bytes()),
+                    output_index: 0,
+                    signature: None,
+                }],
+                vec![TransactionOutput {
+                    amount: 100,
+                    recipient: vec![7; 4096],
+                }],
+            );
+            tx.sign(&keypair, 0).unwrap();
+            tx
+        };
+
+        // Room for three and a half large transactions, far below the count cap
+        let tx_bytes = bincode::serialized_size(&large_tx(0)).unwrap() as usize;
+        let mempool = Mempool::new(100).with_max_bytes(tx_bytes * 7 / 2);
+
+        for i in 0..3 {
+            assert_eq!(mempool.add_transaction(large_tx(i), public_keys.clone()).await.unwrap(), AddTransactionOutcome::Added);
+        }
+        mempool.process_all_pending().await.unwrap();
+        assert_eq!(mempool.size().await, 3);
+        assert_eq!(mempool.size_bytes(), 3 * tx_bytes);
+
+        assert_eq!(mempool.add_transaction(large_tx(3), public_keys.clone()).await, Err("Mempool byte limit reached"));
+        assert_eq!(mempool.size().await, 3);
+
+        // Removing a transaction frees its bytes
+        let removed = mempool.get_all_transactions().await[0].hash.clone();
+        mempool.remove_transaction(&removed).await.unwrap();
+        assert_eq!(mempool.size_bytes(), 2 * tx_bytes);
+        assert_eq!(mempool.add_transaction(large_tx(3), public_keys.clone()).await.unwrap(), AddTransactionOutcome::Added);
+        mempool.process_all_pending().await.unwrap();
+        assert_eq!(mempool.size_bytes(), 3 * tx_bytes);
+
+        // Transactions still queued count towards the cap
+        let removed = mempool.get_all_transactions().await[0].hash.clone();
+        mempool.remove_transaction(&removed).await.unwrap();
+        let queued = large_tx(4);
+        mempool.enqueue(queued.clone(), public_keys.clone()).await;
+        assert_eq!(mempool.add_transaction(large_tx(5), public_keys.clone()).await, Err("Mempool byte limit reached"));
+        mempool.process_all_pending().await.unwrap();
+        assert!(mempool.contains(&queued.hash).await);
+        assert_eq!(mempool.size_bytes(), 3 * tx_bytes);
+    }
+
     #[tokio::test]
     async fn test_mempool_duplicate_prevention() {
         let mempool = Mempool::new(100);
@@ -228,11 +906,11 @@ mod tests {
         let public_keys = vec![keypair.public_key().as_bytes().to_vec()];
         
         // Add transaction first time
-        assert!(mempool.add_transaction(tx.clone(), public_keys.clone()).await.unwrap());
+        assert_eq!(mempool.add_transaction(tx.clone(), public_keys.clone()).await.unwrap(), AddTransactionOutcome::Added);
         mempool.process_all_pending().await.unwrap();
         
         // Try to add same transaction again
-        assert!(!mempool.add_transaction(tx.clone(), public_keys.clone()).await.unwrap());
+        assert_eq!(mempool.add_transaction(tx.clone(), public_keys.clone()).await.unwrap(), AddTransactionOutcome::Duplicate);
         
         assert_eq!(mempool.size().await, 1);
     }
@@ -258,7 +936,7 @@ mod tests {
                 }],
             );
             tx.sign(&keypair, 0).unwrap();
-            assert!(mempool.add_transaction(tx, public_keys.clone()).await.unwrap());
+            assert_eq!(mempool.add_transaction(tx, public_keys.clone()).await.unwrap(), AddTransactionOutcome::Added);
         }
 
         mempool.process_all_pending().await.unwrap();
@@ -311,4 +989,650 @@ mod tests {
         let all_txs = mempool.get_pending_transactions(10).await.unwrap();
         assert_eq!(all_txs.len(), 5);
     }
+
+    #[tokio::test]
+    async fn test_expire_old_transactions() {
+        let mempool = Mempool::new(100);
+        let keypair = KeyPair::generate();
+        let public_keys = vec![keypair.public_key().as_bytes().to_vec()];
+
+        let mut old_tx = Transaction::new(
+            vec![TransactionInput {
+                tx_hash: Hash::new(b"old_tx"),
+                output_index: 0,
+                signature: None,
+            }],
+            vec![TransactionOutput {
+                amount: 100,
+                recipient: vec![1, 2, 3, 4],
+            }],
+        );
+        old_tx.sign(&keypair, 0).unwrap();
+        assert_eq!(mempool.add_transaction(old_tx.clone(), public_keys.clone()).await.unwrap(), AddTransactionOutcome::Added);
+
+        // Advance logical time past the threshold before adding a fresh tx
+        mempool.advance_time(120);
+
+        let mut fresh_tx = Transaction::new(
+            vec![TransactionInput {
+                tx_hash: Hash::new(b"fresh_tx"),
+                output_index: 0,
+                signature: None,
+            }],
+            vec![TransactionOutput {
+                amount: 100,
+                recipient: vec![1, 2, 3, 4],
+            }],
+        );
+        fresh_tx.sign(&keypair, 0).unwrap();
+        assert_eq!(mempool.add_transaction(fresh_tx.clone(), public_keys.clone()).await.unwrap(), AddTransactionOutcome::Added);
+
+        assert_eq!(mempool.expire_old(60).await, 1);
+        assert!(!mempool.contains(&old_tx.hash).await);
+        assert!(mempool.contains(&fresh_tx.hash).await);
+
+        // Expired transaction can be re-submitted
+        assert_eq!(mempool.add_transaction(old_tx.clone(), public_keys.clone()).await.unwrap(), AddTransactionOutcome::Added);
+        assert!(mempool.contains(&old_tx.hash).await);
+    }
+
+    #[tokio::test]
+    async fn test_ttl_expires_on_add() {
+        let mempool = Mempool::with_ttl(100, 60);
+        let keypair = KeyPair::generate();
+        let public_keys = vec![keypair.public_key().as_bytes().to_vec()];
+
+        for i in 0..2 {
+            let mut tx = Transaction::new(
+                vec![TransactionInput {
+                    tx_hash: Hash::new(format!("tx_{}", i).as_bytes()),
+                    output_index: 0,
+                    signature: None,
+                }],
+                vec![TransactionOutput {
+                    amount: 100,
+                    recipient: vec![1, 2, 3, 4],
+                }],
+            );
+            tx.sign(&keypair, 0).unwrap();
+            mempool.add_transaction(tx, public_keys.clone()).await.unwrap();
+            mempool.advance_time(61);
+        }
+
+        // The first transaction expired when the second was added
+        assert_eq!(mempool.size().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_conflicting_inputs() {
+        let mempool = Mempool::new(100);
+        let keypair = KeyPair::generate();
+        let public_keys = vec![keypair.public_key().as_bytes().to_vec()];
+        let prev_hash = Hash::new(b"funding_tx");
+        mempool.add_utxo(prev_hash.clone(), 0, 1000).await;
+
+        let spend = |amount: u64| {
+            let mut tx = Transaction::new(
+                vec![TransactionInput {
+                    tx_hash: prev_hash.clone(),
+                    output_index: 0,
+                    signature: None,
+                }],
+                vec![TransactionOutput {
+                    amount,
+                    recipient: vec![1, 2, 3, 4],
+                }],
+            );
+            tx.sign(&keypair, 0).unwrap();
+            tx
+        };
+
+        let first = spend(900);
+        assert_eq!(mempool.add_transaction(first.clone(), public_keys.clone()).await.unwrap(), AddTransactionOutcome::Added);
+        assert_eq!(mempool.fee(&first).await, Some(100));
+
+        // Same outpoint at the same fee is rejected
+        let same_fee = spend(900);
+        assert_eq!(mempool.conflicts(&same_fee).await, vec![first.hash.clone()]);
+        assert_eq!(
+            mempool.add_transaction(same_fee.clone(), public_keys.clone()).await,
+            Err("Conflicting input")
+        );
+        assert!(!mempool.contains(&same_fee.hash).await);
+
+        // A higher fee replaces the pending spend
+        let higher_fee = spend(500);
+        assert_eq!(
+            mempool.add_transaction(higher_fee.clone(), public_keys.clone()).await.unwrap(),
+            AddTransactionOutcome::ReplacedTransaction(first.hash.clone())
+        );
+        assert!(mempool.contains(&higher_fee.hash).await);
+        assert!(!mempool.contains(&first.hash).await);
+        assert_eq!(mempool.size().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_replace_by_fee() {
+        let mempool = Mempool::new(100).with_min_rbf_bump(0.5);
+        let keypair = KeyPair::generate();
+        let public_keys = vec![keypair.public_key().as_bytes().to_vec()];
+        let prev_hash = Hash::new(b"funding_tx");
+        mempool.add_utxo(prev_hash.clone(), 0, 1000).await;
+
+        let spend = |amount: u64| {
+            let mut tx = Transaction::new(
+                vec![TransactionInput {
+                    tx_hash: prev_hash.clone(),
+                    output_index: 0,
+                    signature: None,
+                }],
+                vec![TransactionOutput {
+                    amount,
+                    recipient: vec![1, 2, 3, 4],
+                }],
+            );
+            tx.sign(&keypair, 0).unwrap();
+            tx
+        };
+
+        let original = spend(900);
+        mempool.add_transaction(original.clone(), public_keys.clone()).await.unwrap();
+
+        // Equal fee-rate is not enough to replace
+        let equal_fee = spend(900);
+        assert_eq!(mempool.fee_rate(&equal_fee).await, mempool.fee_rate(&original).await);
+        assert!(mempool.add_transaction(equal_fee, public_keys.clone()).await.is_err());
+        assert!(mempool.contains(&original.hash).await);
+
+        // A fee-rate bumped by more than min_rbf_bump replaces the original
+        let replacement = spend(600);
+        assert!(mempool.fee_rate(&replacement).await.unwrap() > mempool.fee_rate(&original).await.unwrap() + 0.5);
+        assert_eq!(
+            mempool.add_transaction(replacement.clone(), public_keys.clone()).await.unwrap(),
+            AddTransactionOutcome::ReplacedTransaction(original.hash.clone())
+        );
+        assert!(mempool.contains(&replacement.hash).await);
+        assert!(!mempool.contains(&original.hash).await);
+
+        // The replaced transaction is forgotten by the duplicate filter
+        assert_eq!(
+            mempool.add_transaction(original.clone(), public_keys.clone()).await,
+            Err("Conflicting input")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_min_fee_rate() {
+        let keypair = KeyPair::generate();
+        let relay = KeyPair::generate();
+        let spend = |keypair: &KeyPair, prev_hash: &Hash, amount: u64| {
+            let mut tx = Transaction::new(
+                vec![TransactionInput {
+                    tx_hash: prev_hash.clone(),
+                    output_index: 0,
+                    signature: None,
+                }],
+                vec![TransactionOutput {
+                    amount,
+                    recipient: vec![1, 2, 3, 4],
+                }],
+            );
+            tx.sign(keypair, 0).unwrap();
+            tx
+        };
+
+        // The floor is a fee of 100 on a transaction of this size
+        let funding = Hash::new(b"funding_tx");
+        let at_floor = spend(&keypair, &funding, 900);
+        let size = bincode::serialized_size(&at_floor).unwrap();
+        let relay_key = *relay.public_key().as_bytes();
+        let mempool = Mempool::new(100)
+            .with_min_fee_rate(100.0 / size as f64)
+            .with_fee_exemptions([relay_key]);
+        mempool.add_utxo(funding.clone(), 0, 1000).await;
+
+        let public_keys = vec![keypair.public_key().as_bytes().to_vec()];
+        assert_eq!(
+            mempool.add_transaction(spend(&keypair, &funding, 950), public_keys.clone()).await,
+            Err("Fee rate below minimum")
+        );
+        assert_eq!(
+            mempool.add_transaction(at_floor, public_keys).await.unwrap(),
+            AddTransactionOutcome::Added
+        );
+
+        // Exempt relays are admitted below the floor
+        let relay_funding = Hash::new(b"relay_funding_tx");
+        mempool.add_utxo(relay_funding.clone(), 0, 1000).await;
+        assert_eq!(
+            mempool.add_transaction(spend(&relay, &relay_funding, 1000), vec![relay_key.to_vec()]).await.unwrap(),
+            AddTransactionOutcome::Added
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replacement_grace_period() {
+        let mempool = Mempool::new(100)
+            .with_min_rbf_bump(0.0)
+            .with_min_rbf_fee_increment(50)
+            .with_rbf_grace_period(30);
+        let keypair = KeyPair::generate();
+        let public_keys = vec![keypair.public_key().as_bytes().to_vec()];
+        let prev_hash = Hash::new(b"funding_tx");
+        mempool.add_utxo(prev_hash.clone(), 0, 1000).await;
+
+        let spend = |amount: u64| {
+            let mut tx = Transaction::new(
+                vec![TransactionInput {
+                    tx_hash: prev_hash.clone(),
+                    output_index: 0,
+                    signature: None,
+                }],
+                vec![TransactionOutput {
+                    amount,
+                    recipient: vec![1, 2, 3, 4],
+                }],
+            );
+            tx.sign(&keypair, 0).unwrap();
+            tx
+        };
+
+        let original = spend(900);
+        mempool.add_transaction(original.clone(), public_keys.clone()).await.unwrap();
+
+        // The total fee must grow by the minimum increment
+        assert_eq!(
+            mempool.add_transaction(spend(890), public_keys.clone()).await,
+            Err("Replacement fee increment too small")
+        );
+
+        let first = spend(800);
+        assert_eq!(
+            mempool.add_transaction(first.clone(), public_keys.clone()).await.unwrap(),
+            AddTransactionOutcome::ReplacedTransaction(original.hash.clone())
+        );
+
+        // A second replacement within the grace period is rejected
+        mempool.advance_time(10);
+        let second = spend(700);
+        assert_eq!(
+            mempool.add_transaction(second.clone(), public_keys.clone()).await,
+            Err("Replacement within grace period")
+        );
+        assert!(mempool.contains(&first.hash).await);
+        assert!(!mempool.contains(&second.hash).await);
+
+        // Once it has passed the inputs can be replaced again
+        mempool.advance_time(30);
+        let third = spend(600);
+        assert_eq!(
+            mempool.add_transaction(third.clone(), public_keys.clone()).await.unwrap(),
+            AddTransactionOutcome::ReplacedTransaction(first.hash.clone())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replaces_queued_transaction() {
+        let mempool = Mempool::new(100).with_min_rbf_bump(0.0);
+        let keypair = KeyPair::generate();
+        let public_keys = vec![keypair.public_key().as_bytes().to_vec()];
+        let prev_hash = Hash::new(b"funding_tx");
+        mempool.add_utxo(prev_hash.clone(), 0, 1000).await;
+
+        let spend = |amount: u64| {
+            let mut tx = Transaction::new(
+                vec![TransactionInput {
+                    tx_hash: prev_hash.clone(),
+                    output_index: 0,
+                    signature: None,
+                }],
+                vec![TransactionOutput {
+                    amount,
+                    recipient: vec![1, 2, 3, 4],
+                }],
+            );
+            tx.sign(&keypair, 0).unwrap();
+            tx
+        };
+
+        // The original is still waiting behind a batch in progress
+        let original = spend(900);
+        mempool.enqueue(original.clone(), public_keys.clone()).await;
+
+        // A queued spend is held to the same replacement rules
+        assert_eq!(
+            mempool.add_transaction(spend(900), public_keys.clone()).await,
+            Err("Conflicting input")
+        );
+        let replacement = spend(800);
+        assert_eq!(
+            mempool.add_transaction(replacement.clone(), public_keys.clone()).await.unwrap(),
+            AddTransactionOutcome::ReplacedTransaction(original.hash.clone())
+        );
+        mempool.process_all_pending().await.unwrap();
+        assert!(mempool.contains(&replacement.hash).await);
+        assert!(!mempool.contains(&original.hash).await);
+
+        // The replacement started the grace period
+        assert_eq!(
+            mempool.add_transaction(spend(700), public_keys.clone()).await,
+            Err("Replacement within grace period")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_estimate_fee_rate() {
+        let mempool = Mempool::new(100).with_block_capacity(2);
+        let keypair = KeyPair::generate();
+        let public_keys = vec![keypair.public_key().as_bytes().to_vec()];
+
+        // Six transactions paying increasingly higher fees
+        for i in 0..6u64 {
+            let prev_hash = Hash::new(format!("funding_{}", i).as_bytes());
+            mempool.add_utxo(prev_hash.clone(), 0, 10_000).await;
+            let mut tx = Transaction::new(
+                vec![TransactionInput {
+                    tx_hash: prev_hash,
+                    output_index: 0,
+                    signature: None,
+                }],
+                vec![TransactionOutput {
+                    amount: 10_000 - (i + 1) * 1000,
+                    recipient: vec![1, 2, 3, 4],
+                }],
+            );
+            tx.sign(&keypair, 0).unwrap();
+            mempool.add_transaction(tx, public_keys.clone()).await.unwrap();
+        }
+
+        let next_block = mempool.estimate_fee_rate(1).await;
+        let within_two = mempool.estimate_fee_rate(2).await;
+        let within_three = mempool.estimate_fee_rate(3).await;
+
+        assert!(next_block > within_two);
+        assert!(within_two > within_three);
+        // Everything fits within four blocks, so only the minimum is needed
+        assert_eq!(mempool.estimate_fee_rate(4).await, MIN_FEE_RATE);
+    }
+
+    #[tokio::test]
+    async fn test_sender_limit() {
+        let mempool = Mempool::with_sender_limit(100, 2);
+        let spammer = KeyPair::generate();
+        let other = KeyPair::generate();
+
+        let signed_tx = |keypair: &KeyPair, seed: &str| {
+            let mut tx = Transaction::new(
+                vec![TransactionInput {
+                    tx_hash: Hash::new(seed.as_bytes()),
+                    output_index: 0,
+                    signature: None,
+                }],
+                vec![TransactionOutput {
+                    amount: 100,
+                    recipient: vec![1, 2, 3, 4],
+                }],
+            );
+            tx.sign(keypair, 0).unwrap();
+            tx
+        };
+        let spammer_keys = vec![spammer.public_key().as_bytes().to_vec()];
+        let other_keys = vec![other.public_key().as_bytes().to_vec()];
+
+        let first = signed_tx(&spammer, "spam_0");
+        mempool.add_transaction(first.clone(), spammer_keys.clone()).await.unwrap();
+        mempool.add_transaction(signed_tx(&spammer, "spam_1"), spammer_keys.clone()).await.unwrap();
+
+        // The third transaction from the same sender is rejected
+        assert_eq!(
+            mempool.add_transaction(signed_tx(&spammer, "spam_2"), spammer_keys.clone()).await,
+            Err("Sender transaction limit reached")
+        );
+
+        // Other senders are unaffected
+        assert_eq!(
+            mempool.add_transaction(signed_tx(&other, "other_0"), other_keys.clone()).await.unwrap(),
+            AddTransactionOutcome::Added
+        );
+        assert_eq!(mempool.sender_count(&spammer.public_key().to_bytes()).await, 2);
+        assert_eq!(mempool.sender_count(&other.public_key().to_bytes()).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_sender_limit_freed_on_removal() {
+        let mempool = Mempool::with_sender_limit(100, 1);
+        let keypair = KeyPair::generate();
+        let public_keys = vec![keypair.public_key().as_bytes().to_vec()];
+
+        let mut txs = Vec::new();
+        for i in 0..2 {
+            let mut tx = Transaction::new(
+                vec![TransactionInput {
+                    tx_hash: Hash::new(format!("tx_{}", i).as_bytes()),
+                    output_index: 0,
+                    signature: None,
+                }],
+                vec![TransactionOutput {
+                    amount: 100,
+                    recipient: vec![1, 2, 3, 4],
+                }],
+            );
+            tx.sign(&keypair, 0).unwrap();
+            txs.push(tx);
+        }
+
+        mempool.add_transaction(txs[0].clone(), public_keys.clone()).await.unwrap();
+        assert!(mempool.add_transaction(txs[1].clone(), public_keys.clone()).await.is_err());
+
+        // Removing the pending transaction frees the sender's slot
+        mempool.remove_transaction(&txs[0].hash).await;
+        assert_eq!(
+            mempool.add_transaction(txs[1].clone(), public_keys.clone()).await.unwrap(),
+            AddTransactionOutcome::Added
+        );
+    }
+
+    #[tokio::test]
+    async fn test_save_and_load() {
+        let mempool = Mempool::new(100);
+        let keypair = KeyPair::generate();
+        let public_keys = vec![keypair.public_key().as_bytes().to_vec()];
+
+        let mut hashes = Vec::new();
+        for i in 0..3 {
+            let mut tx = Transaction::new(
+                vec![TransactionInput {
+                    tx_hash: Hash::new(format!("tx_{}", i).as_bytes()),
+                    output_index: 0,
+                    signature: None,
+                }],
+                vec![TransactionOutput {
+                    amount: 100,
+                    recipient: vec![1, 2, 3, 4],
+                }],
+            );
+            tx.sign(&keypair, 0).unwrap();
+            hashes.push(tx.hash.clone());
+            mempool.add_transaction(tx, public_keys.clone()).await.unwrap();
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mempool.bin");
+        mempool.save(&path).await.unwrap();
+
+        let loaded = Mempool::load(&path).await.unwrap();
+        assert_eq!(loaded.size().await, mempool.size().await);
+        for hash in &hashes {
+            assert!(loaded.contains(hash).await);
+        }
+
+        // Transactions already in the loaded pool are rejected as duplicates
+        let existing = loaded.get_transaction(&hashes[0]).await.unwrap();
+        assert_eq!(
+            loaded.add_transaction(existing, public_keys.clone()).await.unwrap(),
+            AddTransactionOutcome::Duplicate
+        );
+        assert_eq!(loaded.size().await, 3);
+
+        // Senders are restored along with their transactions
+        let sender = keypair.public_key().as_bytes();
+        assert_eq!(loaded.sender_count(sender).await, 3);
+        loaded.remove_transaction(&hashes[0]).await.unwrap();
+        assert_eq!(loaded.sender_count(sender).await, 2);
+
+        // Mempools saved before senders were recorded still load
+        let legacy = (100usize, mempool.get_all_transactions().await, hashes.clone());
+        std::fs::write(&path, bincode::serialize(&legacy).unwrap()).unwrap();
+        let loaded = Mempool::load(&path).await.unwrap();
+        assert_eq!(loaded.size().await, 3);
+        assert_eq!(loaded.sender_count(sender).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_value_overflow() {
+        let mempool = Mempool::new(100);
+        let keypair = KeyPair::generate();
+        let public_keys = vec![keypair.public_key().as_bytes().to_vec()];
+        let funding = Hash::new(b"funding_tx");
+        mempool.add_utxo(funding.clone(), 0, u64::MAX).await;
+        mempool.add_utxo(funding.clone(), 1, 1).await;
+
+        // Inputs worth more than u64::MAX in total
+        let inputs = (0..2)
+            .map(|output_index| TransactionInput {
+                tx_hash: funding.clone(),
+                output_index,
+                signature: None,
+            })
+            .collect();
+        let mut tx = Transaction::new(inputs, vec![TransactionOutput {
+            amount: 100,
+            recipient: vec![1, 2, 3, 4],
+        }]);
+        tx.sign(&keypair, 0).unwrap();
+        assert_eq!(mempool.fee(&tx).await, None);
+        assert_eq!(mempool.add_transaction(tx, public_keys.clone()).await, Err("Transaction value overflow"));
+
+        // Outputs worth more than u64::MAX in total
+        let outputs = vec![
+            TransactionOutput { amount: u64::MAX, recipient: vec![1, 2, 3, 4] },
+            TransactionOutput { amount: 1, recipient: vec![1, 2, 3, 4] },
+        ];
+        let mut tx = Transaction::new(vec![TransactionInput {
+            tx_hash: funding.clone(),
+            output_index: 0,
+            signature: None,
+        }], outputs);
+        tx.sign(&keypair, 0).unwrap();
+        assert_eq!(mempool.add_transaction(tx, public_keys).await, Err("Transaction value overflow"));
+        assert_eq!(mempool.size().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_chained_transaction_ordering() {
+        let mempool = Mempool::new(100);
+        let keypair = KeyPair::generate();
+        let public_keys = vec![keypair.public_key().as_bytes().to_vec()];
+
+        // Build a chain a -> b -> c where each spends the previous one's output
+        let mut chain: Vec<Transaction> = Vec::new();
+        let mut parent_hash = Hash::new(b"funding_tx");
+        for _ in 0..3 {
+            let mut tx = Transaction::new(
+                vec![TransactionInput {
+                    tx_hash: parent_hash.clone(),
+                    output_index: 0,
+                    signature: None,
+                }],
+                vec![TransactionOutput {
+                    amount: 100,
+                    recipient: vec![1, 2, 3, 4],
+                }],
+            );
+            tx.sign(&keypair, 0).unwrap();
+            parent_hash = tx.hash.clone();
+            chain.push(tx);
+        }
+
+        // Submit descendants before their ancestors
+        for tx in chain.iter().rev() {
+            mempool.add_transaction(tx.clone(), public_keys.clone()).await.unwrap();
+        }
+
+        let hashes: Vec<Hash> = chain.iter().map(|tx| tx.hash.clone()).collect();
+        assert_eq!(mempool.ancestors(&hashes[2]).await, vec![hashes[0].clone(), hashes[1].clone()]);
+        assert!(mempool.ancestors(&hashes[0]).await.is_empty());
+
+        let batch: Vec<Hash> = mempool.get_pending_transactions(10).await.unwrap()
+            .into_iter()
+            .map(|tx| tx.hash)
+            .collect();
+        assert_eq!(batch, hashes);
+
+        // A truncated batch never includes a descendant without its ancestors
+        let batch: Vec<Hash> = mempool.get_pending_transactions(2).await.unwrap()
+            .into_iter()
+            .map(|tx| tx.hash)
+            .collect();
+        assert_eq!(batch, hashes[..2].to_vec());
+
+        // Once the root is gone, the next transaction becomes the chain root
+        mempool.remove_transaction(&hashes[0]).await;
+        assert_eq!(mempool.ancestors(&hashes[2]).await, vec![hashes[1].clone()]);
+    }
+
+    #[tokio::test]
+    async fn test_zero_address_rejected() {
+        let mempool = Mempool::new(100);
+        let keypair = KeyPair::generate();
+        let public_keys = vec![keypair.public_key().as_bytes().to_vec()];
+
+        let mut tx = Transaction::new(
+            vec![TransactionInput {
+                tx_hash: Hash::new(b"funding_tx"),
+                output_index: 0,
+                signature: None,
+            }],
+            vec![TransactionOutput {
+                amount: 100,
+                recipient: ZERO_ADDRESS.to_vec(),
+            }],
+        );
+        tx.sign(&keypair, 0).unwrap();
+        assert_eq!(
+            mempool.add_transaction(tx, public_keys).await,
+            Err("Zero address recipient")
+        );
+
+        let mut tx = Transaction::new(
+            vec![TransactionInput {
+                tx_hash: Hash::new(b"funding_tx"),
+                output_index: 1,
+                signature: None,
+            }],
+            vec![TransactionOutput {
+                amount: 100,
+                recipient: vec![1, 2, 3, 4],
+            }],
+        );
+        tx.sign(&keypair, 0).unwrap();
+        assert_eq!(
+            mempool.add_transaction(tx, vec![ZERO_ADDRESS.to_vec()]).await,
+            Err("Zero address sender")
+        );
+        assert_eq!(mempool.size().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_coinbase() {
+        let mempool = Mempool::new(100);
+        let keypair = KeyPair::generate();
+        let coinbase = Transaction::coinbase(vec![1, 2, 3, 4], 50);
+
+        assert_eq!(
+            mempool.add_transaction(coinbase, vec![keypair.public_key().as_bytes().to_vec()]).await,
+            Err("Transaction has no inputs")
+        );
+        assert_eq!(mempool.size().await, 0);
+    }
 }
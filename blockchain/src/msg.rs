@@ -1,41 +1,44 @@
-use std::sync::RwLock;
-use once_cell::sync::Lazy;
+#[cfg(any(test, feature = "test-utils"))]
+use std::cell::Cell;
 
-// Global sender for testing
-static TEST_SENDER: Lazy<RwLock<Option<[u8; 32]>>> = Lazy::new(|| RwLock::new(None));
+#[cfg(any(test, feature = "test-utils"))]
+thread_local! {
+    // Sender override for testing, per thread so parallel tests don't race
+    static TEST_SENDER: Cell<Option<[u8; 32]>> = const { Cell::new(None) };
+}
 
 /// Get the sender address
 pub fn sender() -> Result<[u8; 32], String> {
-    if cfg!(test) {
-        // In test mode, return the test sender or a default
-        Ok(TEST_SENDER
-            .read()
-            .map_err(|e| format!("Failed to read test sender: {}", e))?
-            .unwrap_or([1u8; 32]))
-    } else {
-        // TODO: Implement proper sender tracking for production
+    // Unit tests default to a non-zero sender; without an override,
+    // production falls back to the zero address
+    // TODO: Implement proper sender tracking for production
+    #[cfg(any(test, feature = "test-utils"))]
+    {
+        let default = if cfg!(test) { [1u8; 32] } else { [0u8; 32] };
+        Ok(TEST_SENDER.with(|sender| sender.get()).unwrap_or(default))
+    }
+    #[cfg(not(any(test, feature = "test-utils")))]
+    {
         Ok([0u8; 32])
     }
 }
 
-// Testing utilities available for both unit and integration tests
+// Testing utilities available to unit tests, and to integration tests and
+// benchmarks through the `test-utils` feature
+#[cfg(any(test, feature = "test-utils"))]
 pub mod test_utils {
     use super::*;
 
-    /// Set the test sender
+    /// Set the test sender for the current thread
     pub fn set_sender(addr: [u8; 32]) -> Result<(), String> {
-        TEST_SENDER
-            .write()
-            .map_err(|e| format!("Failed to write test sender: {}", e))
-            .map(|mut guard| *guard = Some(addr))
+        TEST_SENDER.with(|sender| sender.set(Some(addr)));
+        Ok(())
     }
 
-    /// Clear the test sender
+    /// Clear the test sender for the current thread
     pub fn clear_sender() -> Result<(), String> {
-        TEST_SENDER
-            .write()
-            .map_err(|e| format!("Failed to write test sender: {}", e))
-            .map(|mut guard| *guard = None)
+        TEST_SENDER.with(|sender| sender.set(None));
+        Ok(())
     }
 }
 
@@ -3,18 +3,64 @@ use libp2p::{
     gossipsub::{
         self, IdentTopic as Topic, MessageAuthenticity, ValidationMode,
     },
-    identity, noise, yamux,
-    swarm::{NetworkBehaviour, SwarmEvent},
+    identity, mdns, noise, yamux,
+    swarm::{behaviour::toggle::Toggle, NetworkBehaviour, SwarmEvent},
     tcp, Multiaddr, PeerId, Transport, Swarm,
 };
 use std::error::Error;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc;
 use futures::StreamExt;
-use std::collections::{HashMap, HashSet};
-use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use serde::{de::DeserializeOwned, Serialize, Deserialize};
 use std::fmt;
 use crate::block::Block;
+use crate::crypto::Hash;
+use crate::storage::StorageError;
+use crate::transaction::Transaction;
+
+// Maximum number of blocks requested or served in one sync message
+const MAX_BLOCKS_PER_BATCH: u64 = 50;
+
+// Number of recently seen block hashes remembered for deduplication
+const KNOWN_BLOCKS_CAPACITY: usize = 50_000;
+
+// Number of recently seen transaction hashes remembered for deduplication
+const KNOWN_TRANSACTIONS_CAPACITY: usize = 100_000;
+
+// Largest gossip message accepted by default, enough for a full block batch
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+// Score a newly connected peer starts with
+const INITIAL_PEER_SCORE: f64 = 1.0;
+// Highest score a peer can build up through good behaviour
+const MAX_PEER_SCORE: f64 = 10.0;
+// Score gained for each valid block or transaction relayed
+const VALID_MESSAGE_REWARD: f64 = 0.1;
+// Score lost for each invalid block or malformed message
+const INVALID_MESSAGE_PENALTY: f64 = 1.0;
+// Peers whose score drops below this are banned
+const BAN_THRESHOLD: f64 = -3.0;
+// How long a banned peer is disconnected and ignored
+const BAN_DURATION: Duration = Duration::from_secs(600);
+
+/// Peer scores and bans kept across restarts. Peers are keyed by their
+/// base58 id and bans by their expiry in seconds since the Unix epoch.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PeerReputation {
+    pub scores: HashMap<String, f64>,
+    pub bans: HashMap<String, u64>,
+}
+
+/// Durable backing store for peer reputation
+pub trait PeerStore: Send + Sync {
+    fn load_peer_reputation(&self) -> Result<PeerReputation, StorageError>;
+    fn save_peer_reputation(&self, reputation: &PeerReputation) -> Result<(), StorageError>;
+}
+
+/// Looks up the local blocks at heights `start..=end` to answer sync requests
+pub type BlockProvider = Arc<dyn Fn(u64, u64) -> Vec<Block> + Send + Sync>;
 
 // Custom error type for network operations
 #[derive(Debug)]
@@ -36,6 +82,44 @@ impl fmt::Display for NetworkError {
     }
 }
 
+/// Encoding of messages sent over the network
+pub trait WireCodec {
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, String>;
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, String>;
+}
+
+/// Compact binary encoding, used on the wire by default
+pub struct BincodeCodec;
+
+impl WireCodec for BincodeCodec {
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, String> {
+        bincode::serialize(value).map_err(|e| e.to_string())
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, String> {
+        bincode::deserialize(data).map_err(|e| e.to_string())
+    }
+}
+
+/// Human-readable encoding, useful when inspecting traffic
+pub struct JsonCodec;
+
+impl WireCodec for JsonCodec {
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(value).map_err(|e| e.to_string())
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, String> {
+        serde_json::from_slice(data).map_err(|e| e.to_string())
+    }
+}
+
+/// Codec used for all network messages
+#[cfg(not(feature = "json-wire"))]
+pub type DefaultCodec = BincodeCodec;
+#[cfg(feature = "json-wire")]
+pub type DefaultCodec = JsonCodec;
+
 // Block synchronization message types
 #[derive(Debug, Serialize, Deserialize)]
 pub enum SyncMessage {
@@ -48,7 +132,10 @@ pub enum SyncMessage {
 #[derive(Debug)]
 pub enum NetworkEvent {
     GossipMessage(gossipsub::Event),
+    Mdns(mdns::Event),
+    PeerDiscovered(PeerId, Multiaddr),
     BlockReceived(Block),
+    TransactionReceived(Transaction),
     SyncStarted,
     SyncCompleted,
     PartitionDetected,
@@ -61,19 +148,89 @@ impl From<gossipsub::Event> for NetworkEvent {
     }
 }
 
+impl From<mdns::Event> for NetworkEvent {
+    fn from(event: mdns::Event) -> Self {
+        NetworkEvent::Mdns(event)
+    }
+}
+
 // Network behavior implementation
 #[derive(NetworkBehaviour)]
 #[behaviour(out_event = "NetworkEvent")]
 pub struct BlockchainBehaviour {
     gossipsub: gossipsub::Behaviour,
+    // Local network peer discovery, only enabled on request
+    mdns: Toggle<mdns::tokio::Behaviour>,
 }
 
 pub struct Network {
     swarm: Swarm<BlockchainBehaviour>,
     _events_sender: mpsc::UnboundedSender<NetworkEvent>,
     peers: HashMap<PeerId, PeerInfo>,
-    known_blocks: HashSet<String>, // Block hashes we've seen
+    banned_peers: HashMap<PeerId, std::time::Instant>, // Ban expiry of misbehaving peers
+    known_scores: HashMap<PeerId, f64>, // Scores of disconnected peers, restored when they reconnect
+    reputation_store: Option<Arc<dyn PeerStore>>,
+    known_blocks: KnownHashes, // Block hashes we've seen recently
+    known_transactions: KnownHashes, // Transaction hashes we've seen recently
     sync_state: SyncState,
+    block_provider: Option<BlockProvider>,
+    auto_dial: bool, // Dial peers as soon as mDNS discovers them
+    max_message_size: usize, // Gossip messages larger than this are dropped undecoded
+}
+
+/// Hashes seen recently, evicting the least recently seen once full
+#[derive(Debug)]
+struct KnownHashes {
+    capacity: usize,
+    // Last time each hash was seen, as a sequence number
+    last_seen: HashMap<String, u64>,
+    // Hashes in the order they were seen; entries whose sequence number no
+    // longer matches `last_seen` are stale and skipped on eviction
+    order: VecDeque<(String, u64)>,
+    next_seq: u64,
+}
+
+impl KnownHashes {
+    fn new(capacity: usize) -> Self {
+        KnownHashes {
+            capacity: capacity.max(1),
+            last_seen: HashMap::new(),
+            order: VecDeque::new(),
+            next_seq: 0,
+        }
+    }
+
+    /// Record a hash as seen, refreshing it if already known
+    fn insert(&mut self, hash: String) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.last_seen.insert(hash.clone(), seq);
+        self.order.push_back((hash, seq));
+
+        while self.last_seen.len() > self.capacity {
+            self.evict_oldest();
+        }
+
+        // Refreshing known hashes leaves stale entries behind, drop them
+        // before the queue grows well past the capacity
+        if self.order.len() > 2 * self.capacity {
+            let last_seen = &self.last_seen;
+            self.order.retain(|(hash, seq)| last_seen.get(hash) == Some(seq));
+        }
+    }
+
+    fn evict_oldest(&mut self) {
+        while let Some((hash, seq)) = self.order.pop_front() {
+            if self.last_seen.get(&hash) == Some(&seq) {
+                self.last_seen.remove(&hash);
+                return;
+            }
+        }
+    }
+
+    fn contains(&self, hash: &str) -> bool {
+        self.last_seen.contains_key(hash)
+    }
 }
 
 #[derive(Debug)]
@@ -88,12 +245,23 @@ struct SyncState {
     is_syncing: bool,
     target_height: u64,
     current_height: u64,
+    tip_hash: Option<Hash>, // Hash of the block at current_height, once known
     pending_requests: HashSet<u64>,
 }
 
 impl Network {
     pub async fn new(
         events_sender: mpsc::UnboundedSender<NetworkEvent>,
+    ) -> Result<Self, Box<dyn Error>> {
+        Self::with_discovery(events_sender, false).await
+    }
+
+    /// Create a network node, optionally discovering peers on the local
+    /// network through mDNS. Discovered peers are reported as
+    /// `NetworkEvent::PeerDiscovered` and dialed automatically.
+    pub async fn with_discovery(
+        events_sender: mpsc::UnboundedSender<NetworkEvent>,
+        enable_mdns: bool,
     ) -> Result<Self, Box<dyn Error>> {
         // Create a random PeerId
         let id_keys = identity::Keypair::generate_ed25519();
@@ -120,9 +288,16 @@ impl Network {
             gossipsub_config,
         ).expect("Correct configuration");
 
+        let mdns = if enable_mdns {
+            Some(mdns::tokio::Behaviour::new(mdns::Config::default(), peer_id)?)
+        } else {
+            None
+        };
+
         // Create a Swarm to manage peers and events
         let behaviour = BlockchainBehaviour {
             gossipsub,
+            mdns: Toggle::from(mdns),
         };
 
         let config = libp2p::swarm::Config::with_tokio_executor();
@@ -132,16 +307,99 @@ impl Network {
             swarm,
             _events_sender: events_sender,
             peers: HashMap::new(),
-            known_blocks: HashSet::new(),
+            banned_peers: HashMap::new(),
+            known_scores: HashMap::new(),
+            reputation_store: None,
+            known_blocks: KnownHashes::new(KNOWN_BLOCKS_CAPACITY),
+            known_transactions: KnownHashes::new(KNOWN_TRANSACTIONS_CAPACITY),
             sync_state: SyncState {
                 is_syncing: false,
                 target_height: 0,
                 current_height: 0,
+                tip_hash: None,
                 pending_requests: HashSet::new(),
             },
+            block_provider: None,
+            auto_dial: true,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
         })
     }
 
+    /// Whether peers discovered through mDNS are dialed automatically
+    pub fn set_auto_dial(&mut self, auto_dial: bool) {
+        self.auto_dial = auto_dial;
+    }
+
+    /// Persist peer reputation to `store`, starting from the scores and bans
+    /// already stored there. Stored bans take effect immediately.
+    pub fn set_reputation_store(&mut self, store: Arc<dyn PeerStore>) -> Result<(), StorageError> {
+        let reputation = store.load_peer_reputation()?;
+
+        let now = std::time::SystemTime::now();
+        let instant_now = std::time::Instant::now();
+        for (peer, expiry) in &reputation.bans {
+            let Ok(peer_id) = peer.parse::<PeerId>() else { continue };
+            let expiry = std::time::UNIX_EPOCH + Duration::from_secs(*expiry);
+            if let Ok(remaining) = expiry.duration_since(now) {
+                self.banned_peers.insert(peer_id, instant_now + remaining);
+                self.peers.remove(&peer_id);
+                let _ = self.swarm.disconnect_peer_id(peer_id);
+            }
+        }
+        for (peer, score) in &reputation.scores {
+            let Ok(peer_id) = peer.parse::<PeerId>() else { continue };
+            match self.peers.get_mut(&peer_id) {
+                Some(info) => info.sync_score = *score,
+                None => {
+                    self.known_scores.insert(peer_id, *score);
+                }
+            }
+        }
+
+        self.reputation_store = Some(store);
+        Ok(())
+    }
+
+    /// Current scores and unexpired bans, in their stored form
+    pub fn peer_reputation(&self) -> PeerReputation {
+        let scores = self.known_scores.iter()
+            .map(|(peer_id, score)| (peer_id.to_base58(), *score))
+            .chain(self.peers.iter().map(|(peer_id, info)| (peer_id.to_base58(), info.sync_score)))
+            .collect();
+
+        let now = std::time::SystemTime::now();
+        let instant_now = std::time::Instant::now();
+        let bans = self.banned_peers.iter()
+            .filter(|(_, until)| **until > instant_now)
+            .filter_map(|(peer_id, until)| {
+                let expiry = (now + (*until - instant_now)).duration_since(std::time::UNIX_EPOCH).ok()?;
+                // Round up so a reload never shortens the ban
+                Some((peer_id.to_base58(), expiry.as_secs() + 1))
+            })
+            .collect();
+
+        PeerReputation { scores, bans }
+    }
+
+    fn persist_reputation(&self) {
+        if let Some(store) = &self.reputation_store {
+            if let Err(e) = store.save_peer_reputation(&self.peer_reputation()) {
+                println!("Failed to persist peer reputation: {:?}", e);
+            }
+        }
+    }
+
+    /// Largest gossip message, in bytes, that is decoded. Larger messages are
+    /// dropped and count against the peer that sent them.
+    pub fn set_max_message_size(&mut self, max_message_size: usize) {
+        self.max_message_size = max_message_size;
+    }
+
+    /// Set where blocks are read from when peers request them
+    pub fn set_block_provider(&mut self, provider: BlockProvider) {
+        self.block_provider = Some(provider);
+    }
+
     pub async fn broadcast_block(&mut self, block: Block) -> Result<(), NetworkError> {
         // Validate block before broadcasting
         if !self.validate_block(&block) {
@@ -149,8 +407,8 @@ impl Network {
         }
 
         // Serialize block
-        let block_data = serde_json::to_vec(&block)
-            .map_err(|e| NetworkError::PropagationError(e.to_string()))?;
+        let block_data = DefaultCodec::encode(&block)
+            .map_err(NetworkError::PropagationError)?;
 
         // Broadcast to all peers
         let topic = Topic::new("blocks");
@@ -166,6 +424,23 @@ impl Network {
         Ok(())
     }
 
+    /// Gossip a pending transaction so peers can add it to their mempools
+    pub async fn broadcast_transaction(&mut self, tx: Transaction) -> Result<(), NetworkError> {
+        let tx_data = DefaultCodec::encode(&tx)
+            .map_err(NetworkError::PropagationError)?;
+
+        let topic = Topic::new("transactions");
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(topic, tx_data)
+            .map_err(|e| NetworkError::PropagationError(e.to_string()))?;
+
+        self.known_transactions.insert(tx.hash.to_string());
+
+        Ok(())
+    }
+
     pub async fn sync_blocks(&mut self) -> Result<(), NetworkError> {
         if self.sync_state.is_syncing {
             return Ok(());
@@ -183,11 +458,10 @@ impl Network {
             .map_err(|e| NetworkError::SyncError(e.to_string()))?;
 
         // Request blocks in batches
-        let batch_size = 50;
         let mut start = self.sync_state.current_height + 1;
 
         while start <= target_height {
-            let end = std::cmp::min(start + batch_size - 1, target_height);
+            let end = std::cmp::min(start + MAX_BLOCKS_PER_BATCH - 1, target_height);
             self.request_blocks(start, end).await?;
             start = end + 1;
         }
@@ -223,9 +497,29 @@ impl Network {
     }
 
     async fn request_blocks(&mut self, start: u64, end: u64) -> Result<(), NetworkError> {
+        self.publish_block_request(start, end)?;
+
+        // Mark blocks as pending
+        for block_num in start..=end {
+            self.sync_state.pending_requests.insert(block_num);
+        }
+
+        Ok(())
+    }
+
+    /// Ask again for blocks that didn't arrive as a contiguous sequence
+    fn rerequest_blocks(&mut self, start: u64, end: u64) {
+        // Keep the range pending even if no peer can be reached right now
+        self.sync_state.pending_requests.extend(start..=end);
+        if let Err(e) = self.publish_block_request(start, end) {
+            println!("Failed to re-request blocks {} to {}: {}", start, end, e);
+        }
+    }
+
+    fn publish_block_request(&mut self, start: u64, end: u64) -> Result<(), NetworkError> {
         let msg = SyncMessage::BlockRequest { start, end };
-        let data = serde_json::to_vec(&msg)
-            .map_err(|e| NetworkError::SyncError(e.to_string()))?;
+        let data = DefaultCodec::encode(&msg)
+            .map_err(NetworkError::SyncError)?;
 
         // Send request to best peer
         let topic = Topic::new("sync");
@@ -235,14 +529,306 @@ impl Network {
             .publish(topic, data)
             .map_err(|e| NetworkError::SyncError(e.to_string()))?;
 
-        // Mark blocks as pending
-        for block_num in start..=end {
-            self.sync_state.pending_requests.insert(block_num);
+        Ok(())
+    }
+
+    /// Blocks to answer a request with, capped at one batch. Returns None if
+    /// there is nothing to serve.
+    fn blocks_for_request(&self, start: u64, end: u64) -> Option<Vec<Block>> {
+        let provider = self.block_provider.as_ref()?;
+        if start > end {
+            return None;
+        }
+
+        let end = std::cmp::min(end, start.saturating_add(MAX_BLOCKS_PER_BATCH - 1));
+        let mut blocks = provider(start, end);
+        blocks.truncate(MAX_BLOCKS_PER_BATCH as usize);
+        if blocks.is_empty() {
+            None
+        } else {
+            Some(blocks)
         }
+    }
+
+    fn respond_to_block_request(&mut self, start: u64, end: u64) -> Result<(), NetworkError> {
+        let Some(blocks) = self.blocks_for_request(start, end) else {
+            return Ok(());
+        };
+
+        let msg = SyncMessage::BlockResponse { blocks };
+        let data = DefaultCodec::encode(&msg)
+            .map_err(NetworkError::SyncError)?;
+
+        let topic = Topic::new("sync");
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(topic, data)
+            .map_err(|e| NetworkError::SyncError(e.to_string()))?;
 
         Ok(())
     }
 
+    fn handle_sync_message(&mut self, sync_msg: SyncMessage, source: Option<PeerId>) {
+        match sync_msg {
+            SyncMessage::BlockRequest { start, end } => {
+                if let Err(e) = self.respond_to_block_request(start, end) {
+                    println!("Failed to answer block request {} to {}: {}", start, end, e);
+                }
+            }
+            SyncMessage::BlockResponse { blocks } if self.sync_state.is_syncing => {
+                self.handle_synced_blocks(blocks, source);
+            }
+            SyncMessage::BlockResponse { blocks } => {
+                // Process received blocks
+                for block in blocks {
+                    if !block.verify_linkage() {
+                        self.penalize_peer(source);
+                    } else if self.validate_block(&block) {
+                        self.reward_peer(source);
+                        self._events_sender.send(NetworkEvent::BlockReceived(block))
+                            .expect("Event channel should be open");
+                    }
+                }
+            }
+            SyncMessage::ChainHeight { height } => {
+                // Update peer's chain height
+                if let Some(peer_id) = source {
+                    if let Some(peer_info) = self.peers.get_mut(&peer_id) {
+                        peer_info.chain_height = height;
+                        peer_info.last_seen = std::time::Instant::now();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Apply blocks received while syncing, which must continue the chain at
+    /// `current_height` one after the other. Blocks after a gap or a broken
+    /// link are dropped and the missing range is requested again.
+    fn handle_synced_blocks(&mut self, blocks: Vec<Block>, source: Option<PeerId>) {
+        if self.sync_state.tip_hash.is_none() {
+            let height = self.sync_state.current_height;
+            self.sync_state.tip_hash = self.block_provider.as_ref()
+                .and_then(|provider| provider(height, height).into_iter().next())
+                .map(|block| block.hash);
+        }
+
+        let mut gap = false;
+        for block in blocks {
+            // Copies of blocks we already applied, e.g. from another peer
+            if self.known_blocks.contains(&block.hash.to_string()) {
+                continue;
+            }
+            if !block.verify_linkage() {
+                self.penalize_peer(source);
+                gap = true;
+                break;
+            }
+            if self.sync_state.tip_hash.as_ref().is_some_and(|tip| *tip != block.header.prev_hash) {
+                gap = true;
+                break;
+            }
+
+            self.sync_state.current_height += 1;
+            self.sync_state.pending_requests.remove(&self.sync_state.current_height);
+            self.sync_state.tip_hash = Some(block.hash.clone());
+            self.known_blocks.insert(block.hash.to_string());
+            self.reward_peer(source);
+            self._events_sender.send(NetworkEvent::BlockReceived(block))
+                .expect("Event channel should be open");
+        }
+
+        let current = self.sync_state.current_height;
+        let target = self.sync_state.target_height;
+        if current >= target {
+            self.sync_state.is_syncing = false;
+            self.sync_state.pending_requests.clear();
+            let _ = self._events_sender.send(NetworkEvent::SyncCompleted);
+        } else if gap {
+            println!("Gap in synced blocks after height {}, requesting again", current);
+            let end = std::cmp::min(current + MAX_BLOCKS_PER_BATCH, target);
+            self.rerequest_blocks(current + 1, end);
+        }
+    }
+
+    /// Handle a block gossiped by `broadcast_block`
+    fn handle_block_message(&mut self, data: &[u8], source: Option<PeerId>) {
+        let Ok(block) = DefaultCodec::decode::<Block>(data) else {
+            self.penalize_peer(source);
+            return;
+        };
+
+        if !block.verify_linkage() {
+            self.penalize_peer(source);
+            return;
+        }
+        // Drop blocks we've already seen
+        if !self.validate_block(&block) {
+            return;
+        }
+        self.known_blocks.insert(block.hash.to_string());
+        self.reward_peer(source);
+
+        self._events_sender.send(NetworkEvent::BlockReceived(block))
+            .expect("Event channel should be open");
+    }
+
+    fn handle_transaction_message(&mut self, data: &[u8], source: Option<PeerId>) {
+        let Ok(tx) = DefaultCodec::decode::<Transaction>(data) else {
+            self.penalize_peer(source);
+            return;
+        };
+
+        // A hash that doesn't match the contents is the sender's fault
+        if tx.calculate_hash() != tx.hash {
+            self.penalize_peer(source);
+            return;
+        }
+        // Drop transactions we've already seen
+        let hash = tx.hash.to_string();
+        if self.known_transactions.contains(&hash) {
+            return;
+        }
+        self.known_transactions.insert(hash);
+        self.reward_peer(source);
+
+        self._events_sender.send(NetworkEvent::TransactionReceived(tx))
+            .expect("Event channel should be open");
+    }
+
+    fn handle_swarm_event(&mut self, event: SwarmEvent<NetworkEvent>) {
+        match event {
+            SwarmEvent::Behaviour(NetworkEvent::GossipMessage(gossipsub::Event::Message {
+                message: gossipsub::Message { data, source, topic, .. },
+                ..
+            })) => {
+                self.handle_gossip_message(&topic, &data, source);
+            }
+            SwarmEvent::Behaviour(NetworkEvent::Mdns(mdns::Event::Discovered(discovered))) => {
+                for (peer_id, addr) in discovered {
+                    self.on_peer_discovered(peer_id, addr);
+                }
+            }
+            SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+                self.on_peer_connected(peer_id);
+            }
+            // Only forget the peer once its last connection is gone
+            SwarmEvent::ConnectionClosed { peer_id, num_established: 0, .. } => {
+                self.on_peer_disconnected(&peer_id);
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_gossip_message(&mut self, topic: &gossipsub::TopicHash, data: &[u8], source: Option<PeerId>) {
+        if source.is_some_and(|peer_id| self.is_banned(&peer_id)) {
+            return;
+        }
+
+        // Check the size before decoding, so oversized payloads cost nothing
+        if data.len() > self.max_message_size {
+            println!("Dropping {} byte gossip message over the {} byte limit", data.len(), self.max_message_size);
+            self.penalize_peer(source);
+            return;
+        }
+
+        // Each topic carries its own message type; only a payload that
+        // doesn't decode as the type of its topic counts against the sender
+        if *topic == Topic::new("transactions").hash() {
+            self.handle_transaction_message(data, source);
+        } else if *topic == Topic::new("blocks").hash() {
+            self.handle_block_message(data, source);
+        } else if *topic == Topic::new("sync").hash() {
+            match DefaultCodec::decode::<SyncMessage>(data) {
+                Ok(sync_msg) => self.handle_sync_message(sync_msg, source),
+                Err(_) => self.penalize_peer(source),
+            }
+        }
+    }
+
+    fn on_peer_discovered(&mut self, peer_id: PeerId, addr: Multiaddr) {
+        // The receiver may have been dropped; discovery still goes on
+        let _ = self._events_sender.send(NetworkEvent::PeerDiscovered(peer_id, addr.clone()));
+
+        // mDNS reports each address separately, one connection is enough
+        if self.auto_dial && !self.swarm.is_connected(&peer_id) {
+            if let Err(e) = self.swarm.dial(addr) {
+                println!("Failed to dial discovered peer {}: {}", peer_id, e);
+            }
+        }
+    }
+
+    fn on_peer_connected(&mut self, peer_id: PeerId) {
+        if self.is_banned(&peer_id) {
+            let _ = self.swarm.disconnect_peer_id(peer_id);
+            return;
+        }
+
+        let now = std::time::Instant::now();
+        let known_score = self.known_scores.remove(&peer_id);
+        self.peers
+            .entry(peer_id)
+            .and_modify(|info| info.last_seen = now)
+            .or_insert(PeerInfo {
+                chain_height: 0, // Unknown until the peer announces it
+                last_seen: now,
+                sync_score: known_score.unwrap_or(INITIAL_PEER_SCORE),
+            });
+    }
+
+    fn on_peer_disconnected(&mut self, peer_id: &PeerId) {
+        if let Some(info) = self.peers.remove(peer_id) {
+            // Remember peers that misbehaved so reconnecting doesn't reset them
+            if info.sync_score < INITIAL_PEER_SCORE {
+                self.known_scores.insert(*peer_id, info.sync_score);
+            }
+        }
+    }
+
+    pub fn peer_count(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Current score of a connected peer
+    pub fn peer_score(&self, peer_id: &PeerId) -> Option<f64> {
+        self.peers.get(peer_id).map(|info| info.sync_score)
+    }
+
+    /// Whether messages and connections from `peer_id` are being ignored
+    pub fn is_banned(&self, peer_id: &PeerId) -> bool {
+        self.banned_peers
+            .get(peer_id)
+            .is_some_and(|until| *until > std::time::Instant::now())
+    }
+
+    fn reward_peer(&mut self, peer_id: Option<PeerId>) {
+        self.adjust_peer_score(peer_id, VALID_MESSAGE_REWARD);
+    }
+
+    fn penalize_peer(&mut self, peer_id: Option<PeerId>) {
+        self.adjust_peer_score(peer_id, -INVALID_MESSAGE_PENALTY);
+    }
+
+    fn adjust_peer_score(&mut self, peer_id: Option<PeerId>, delta: f64) {
+        let Some(peer_id) = peer_id else { return };
+        let Some(info) = self.peers.get_mut(&peer_id) else { return };
+
+        info.sync_score = (info.sync_score + delta).min(MAX_PEER_SCORE);
+        if info.sync_score < BAN_THRESHOLD {
+            self.ban_peer(peer_id);
+        }
+    }
+
+    /// Disconnect `peer_id` and ignore it for `BAN_DURATION`
+    fn ban_peer(&mut self, peer_id: PeerId) {
+        println!("Banning misbehaving peer {}", peer_id);
+        self.banned_peers.insert(peer_id, std::time::Instant::now() + BAN_DURATION);
+        self.peers.remove(&peer_id);
+        let _ = self.swarm.disconnect_peer_id(peer_id);
+        self.persist_reputation();
+    }
+
     fn detect_partition(&self) -> bool {
         let now = std::time::Instant::now();
         let active_peers = self.peers.values()
@@ -305,51 +891,14 @@ impl Network {
         loop {
             tokio::select! {
                 Some(event) = self.swarm.next() => {
-                    if let SwarmEvent::Behaviour(event) = event {
-                        match event {
-                            NetworkEvent::GossipMessage(gossip_event) => {
-                                match gossip_event {
-                                    gossipsub::Event::Message { 
-                                        message: gossipsub::Message { data, source, .. },
-                                        ..
-                                    } => {
-                                        // Handle different message types
-                                        if let Ok(sync_msg) = serde_json::from_slice::<SyncMessage>(&data) {
-                                            match sync_msg {
-                                                SyncMessage::BlockRequest { start, end } => {
-                                                    // Handle block request
-                                                    println!("Received block request: {} to {}", start, end);
-                                                }
-                                                SyncMessage::BlockResponse { blocks } => {
-                                                    // Process received blocks
-                                                    for block in blocks {
-                                                        if self.validate_block(&block) {
-                                                            self._events_sender.send(NetworkEvent::BlockReceived(block))
-                                                                .expect("Event channel should be open");
-                                                        }
-                                                    }
-                                                }
-                                                SyncMessage::ChainHeight { height } => {
-                                                    // Update peer's chain height
-                                                    if let Some(peer_id) = source {
-                                                        if let Some(peer_info) = self.peers.get_mut(&peer_id) {
-                                                            peer_info.chain_height = height;
-                                                            peer_info.last_seen = std::time::Instant::now();
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                    _ => {} // Handle other gossipsub events if needed
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
+                    self.handle_swarm_event(event);
                 }
                 _ = interval.tick() => {
                     // Periodic tasks
+                    let now = std::time::Instant::now();
+                    self.banned_peers.retain(|_, until| *until > now);
+                    self.persist_reputation();
+
                     if let Err(e) = self.handle_network_partition().await {
                         println!("Error handling network partition: {:?}", e);
                     }
@@ -391,6 +940,434 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    fn test_chain(length: u64) -> Vec<Block> {
+        (0..length)
+            .map(|height| Block::new(1, crate::crypto::Hash::new(&height.to_le_bytes()), vec![], 1))
+            .collect()
+    }
+
+    fn assert_block_response_round_trip<C: WireCodec>() {
+        let blocks: Vec<Block> = test_chain(5)
+            .into_iter()
+            .map(|mut block| {
+                block.transactions.push(Transaction::coinbase(vec![1, 2, 3, 4], 50));
+                block
+            })
+            .collect();
+        let msg = SyncMessage::BlockResponse { blocks: blocks.clone() };
+
+        let data = C::encode(&msg).unwrap();
+        let decoded: SyncMessage = C::decode(&data).unwrap();
+        let SyncMessage::BlockResponse { blocks: decoded_blocks } = &decoded else {
+            panic!("Decoded the wrong message type: {:?}", decoded);
+        };
+        assert_eq!(decoded_blocks.len(), blocks.len());
+        for (decoded, original) in decoded_blocks.iter().zip(&blocks) {
+            assert_eq!(decoded.hash, original.hash);
+            assert_eq!(decoded.header.prev_hash, original.header.prev_hash);
+            assert_eq!(decoded.transactions[0].hash, original.transactions[0].hash);
+        }
+        assert_eq!(C::encode(&decoded).unwrap(), data);
+    }
+
+    #[test]
+    fn test_wire_codec_round_trip() {
+        assert_block_response_round_trip::<DefaultCodec>();
+        assert_block_response_round_trip::<BincodeCodec>();
+        assert_block_response_round_trip::<JsonCodec>();
+
+        // Binary encoding is the more compact one
+        let msg = SyncMessage::BlockResponse { blocks: test_chain(5) };
+        assert!(BincodeCodec::encode(&msg).unwrap().len() < JsonCodec::encode(&msg).unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn test_block_request_capped_to_batch() {
+        let (sender, _receiver) = unbounded_channel();
+        let mut network = Network::new(sender).await.unwrap();
+        assert!(network.blocks_for_request(1, 10).is_none());
+
+        let chain = test_chain(200);
+        network.set_block_provider(Arc::new(move |start, end| {
+            chain[start as usize..=end as usize].to_vec()
+        }));
+
+        assert_eq!(network.blocks_for_request(1, 10).unwrap().len(), 10);
+        assert_eq!(network.blocks_for_request(1, 150).unwrap().len(), MAX_BLOCKS_PER_BATCH as usize);
+        assert!(network.blocks_for_request(10, 1).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_block_request_answered() {
+        // The serving node has a chain and answers sync requests
+        let (server_sender, _server_receiver) = unbounded_channel();
+        let mut server = Network::new(server_sender).await.unwrap();
+        let chain = test_chain(100);
+        let served = chain.clone();
+        server.set_block_provider(Arc::new(move |start, end| {
+            served[start as usize..=end as usize].to_vec()
+        }));
+        server.subscribe("sync").await.unwrap();
+        server.start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap()).await.unwrap();
+        let addr = loop {
+            if let Some(SwarmEvent::NewListenAddr { address, .. }) = server.swarm.next().await {
+                break address;
+            }
+        };
+        let server_task = tokio::spawn(async move { server.run().await });
+
+        // The requesting node keeps asking until the mesh forms and an answer arrives
+        let (client_sender, mut client_receiver) = unbounded_channel();
+        let mut client = Network::new(client_sender).await.unwrap();
+        client.subscribe("sync").await.unwrap();
+        client.dial_peer(addr).await.unwrap();
+
+        let mut retry = tokio::time::interval(Duration::from_millis(500));
+        let received = tokio::time::timeout(Duration::from_secs(20), async {
+            loop {
+                tokio::select! {
+                    Some(event) = client.swarm.next() => client.handle_swarm_event(event),
+                    _ = retry.tick() => {
+                        // Fails until the server is known to be subscribed
+                        let _ = client.request_blocks(1, 80).await;
+                    }
+                }
+
+                let mut blocks = Vec::new();
+                while let Ok(NetworkEvent::BlockReceived(block)) = client_receiver.try_recv() {
+                    blocks.push(block);
+                }
+                if !blocks.is_empty() {
+                    break blocks;
+                }
+            }
+        })
+        .await
+        .expect("No block response received");
+
+        // The response is capped to one batch starting at the requested height
+        assert_eq!(received.len(), MAX_BLOCKS_PER_BATCH as usize);
+        assert_eq!(received[0].hash, chain[1].hash);
+        assert_eq!(received[49].hash, chain[50].hash);
+
+        server_task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_transaction_gossip() {
+        let (receiver_sender, mut receiver_events) = unbounded_channel();
+        let mut receiver = Network::new(receiver_sender).await.unwrap();
+        receiver.subscribe("transactions").await.unwrap();
+        receiver.start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap()).await.unwrap();
+        let addr = loop {
+            if let Some(SwarmEvent::NewListenAddr { address, .. }) = receiver.swarm.next().await {
+                break address;
+            }
+        };
+
+        let (sender_sender, _sender_events) = unbounded_channel();
+        let mut sender = Network::new(sender_sender).await.unwrap();
+        sender.subscribe("transactions").await.unwrap();
+        sender.dial_peer(addr).await.unwrap();
+
+        // Keep broadcasting until the mesh forms and the transaction arrives
+        let tx = Transaction::coinbase(vec![1, 2, 3, 4], 50);
+        let mut retry = tokio::time::interval(Duration::from_millis(500));
+        let received = tokio::time::timeout(Duration::from_secs(20), async {
+            loop {
+                tokio::select! {
+                    Some(event) = sender.swarm.next() => sender.handle_swarm_event(event),
+                    Some(event) = receiver.swarm.next() => receiver.handle_swarm_event(event),
+                    _ = retry.tick() => {
+                        // Fails until the receiver is known to be subscribed
+                        let _ = sender.broadcast_transaction(tx.clone()).await;
+                    }
+                }
+
+                if let Ok(NetworkEvent::TransactionReceived(received)) = receiver_events.try_recv() {
+                    break received;
+                }
+            }
+        })
+        .await
+        .expect("No transaction received");
+        assert_eq!(received.hash, tx.hash);
+
+        // Copies of a known transaction don't produce more events
+        let data = DefaultCodec::encode(&tx).unwrap();
+        receiver.handle_transaction_message(&data, None);
+        assert!(receiver_events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_peer_tracking() {
+        let (sender, _receiver) = unbounded_channel();
+        let mut network = Network::new(sender).await.unwrap();
+        let peer = PeerId::random();
+        let other = PeerId::random();
+
+        network.on_peer_connected(peer);
+        network.on_peer_connected(other);
+        assert_eq!(network.peer_count(), 2);
+        assert_eq!(network.get_network_height(), 0);
+
+        // Announced heights are recorded for connected peers
+        network.handle_sync_message(SyncMessage::ChainHeight { height: 42 }, Some(peer));
+        network.handle_sync_message(SyncMessage::ChainHeight { height: 17 }, Some(other));
+        assert_eq!(network.get_network_height(), 42);
+
+        // Reconnecting keeps the known height
+        network.on_peer_connected(peer);
+        assert_eq!(network.get_network_height(), 42);
+
+        network.on_peer_disconnected(&peer);
+        assert_eq!(network.peer_count(), 1);
+        assert_eq!(network.get_network_height(), 17);
+    }
+
+    #[tokio::test]
+    async fn test_peer_scoring_and_ban() {
+        let (sender, mut receiver) = unbounded_channel();
+        let mut network = Network::new(sender).await.unwrap();
+        let peer = PeerId::random();
+        assert_eq!(network.peer_score(&peer), None);
+
+        network.on_peer_connected(peer);
+        assert_eq!(network.peer_score(&peer), Some(INITIAL_PEER_SCORE));
+
+        // Valid blocks raise the score
+        let valid = test_chain(2);
+        network.handle_sync_message(SyncMessage::BlockResponse { blocks: valid }, Some(peer));
+        let score = network.peer_score(&peer).unwrap();
+        assert!(score > INITIAL_PEER_SCORE);
+        assert!(matches!(receiver.try_recv(), Ok(NetworkEvent::BlockReceived(_))));
+
+        // Each invalid block lowers it until the peer is banned
+        let mut invalid = Block::new(1, crate::crypto::Hash::new(b"bad"), vec![], 1);
+        invalid.header.nonce += 1;
+        network.handle_sync_message(SyncMessage::BlockResponse { blocks: vec![invalid.clone()] }, Some(peer));
+        assert!(network.peer_score(&peer).unwrap() < score);
+        while network.peer_score(&peer).is_some() {
+            network.handle_sync_message(SyncMessage::BlockResponse { blocks: vec![invalid.clone()] }, Some(peer));
+        }
+        assert!(network.is_banned(&peer));
+
+        // A banned peer can't reconnect until the ban expires
+        network.on_peer_connected(peer);
+        assert_eq!(network.peer_count(), 0);
+        network.banned_peers.insert(peer, std::time::Instant::now());
+        network.on_peer_connected(peer);
+        assert_eq!(network.peer_score(&peer), Some(INITIAL_PEER_SCORE));
+
+        // Malformed transactions count against the peer as well
+        network.handle_transaction_message(b"garbage", Some(peer));
+        assert_eq!(network.peer_score(&peer), Some(INITIAL_PEER_SCORE - INVALID_MESSAGE_PENALTY));
+    }
+
+    #[tokio::test]
+    async fn test_sync_gap_rerequested() {
+        let (sender, mut receiver) = unbounded_channel();
+        let mut network = Network::new(sender).await.unwrap();
+
+        let mut chain = vec![Block::genesis()];
+        for _ in 1..=4 {
+            let prev_hash = chain.last().unwrap().hash.clone();
+            chain.push(Block::new(1, prev_hash, vec![], 1));
+        }
+        let local = chain[..1].to_vec();
+        network.set_block_provider(Arc::new(move |start, end| {
+            local.get(start as usize..=end as usize).map(<[Block]>::to_vec).unwrap_or_default()
+        }));
+        network.sync_state.is_syncing = true;
+        network.sync_state.target_height = 4;
+
+        // Block 2 is missing, so blocks 3 and 4 can't be applied yet
+        let with_gap = vec![chain[1].clone(), chain[3].clone(), chain[4].clone()];
+        network.handle_sync_message(SyncMessage::BlockResponse { blocks: with_gap }, None);
+        assert!(matches!(receiver.try_recv(), Ok(NetworkEvent::BlockReceived(block)) if block.hash == chain[1].hash));
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(network.sync_state.current_height, 1);
+        assert!(network.sync_state.is_syncing);
+        assert!((2..=4).all(|height| network.sync_state.pending_requests.contains(&height)));
+
+        // The re-requested range completes the sync
+        let rest = chain[1..].to_vec();
+        network.handle_sync_message(SyncMessage::BlockResponse { blocks: rest }, None);
+        for block in &chain[2..] {
+            assert!(matches!(receiver.try_recv(), Ok(NetworkEvent::BlockReceived(received)) if received.hash == block.hash));
+        }
+        assert!(matches!(receiver.try_recv(), Ok(NetworkEvent::SyncCompleted)));
+        assert_eq!(network.sync_state.current_height, 4);
+        assert!(!network.sync_state.is_syncing);
+        assert!(network.sync_state.pending_requests.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_message_dropped() {
+        let (sender, mut receiver) = unbounded_channel();
+        let mut network = Network::new(sender).await.unwrap();
+        let peer = PeerId::random();
+        network.on_peer_connected(peer);
+
+        let tx = Transaction::coinbase(vec![1, 2, 3, 4], 50);
+        let data = DefaultCodec::encode(&tx).unwrap();
+        let topic = Topic::new("transactions").hash();
+        network.set_max_message_size(data.len() - 1);
+
+        // A well-formed transaction is still dropped, without being decoded
+        network.handle_gossip_message(&topic, &data, Some(peer));
+        assert!(receiver.try_recv().is_err());
+        assert!(!network.known_transactions.contains(&tx.hash.to_string()));
+        assert_eq!(network.peer_score(&peer), Some(INITIAL_PEER_SCORE - INVALID_MESSAGE_PENALTY));
+
+        // Within the limit the same message is accepted
+        network.set_max_message_size(data.len());
+        network.handle_gossip_message(&topic, &data, Some(peer));
+        assert!(matches!(receiver.try_recv(), Ok(NetworkEvent::TransactionReceived(_))));
+    }
+
+    #[tokio::test]
+    async fn test_gossiped_block_not_penalized() {
+        let (sender, mut receiver) = unbounded_channel();
+        let mut network = Network::new(sender).await.unwrap();
+        let peer = PeerId::random();
+        network.on_peer_connected(peer);
+
+        // Encoded as `broadcast_block` publishes it
+        let block = test_chain(2).remove(1);
+        let data = DefaultCodec::encode(&block).unwrap();
+        let topic = Topic::new("blocks").hash();
+        network.handle_gossip_message(&topic, &data, Some(peer));
+        assert!(matches!(receiver.try_recv(), Ok(NetworkEvent::BlockReceived(received)) if received.hash == block.hash));
+        assert!(network.peer_score(&peer).unwrap() > INITIAL_PEER_SCORE);
+
+        // The same block again is neither delivered nor penalized
+        let score = network.peer_score(&peer).unwrap();
+        network.handle_gossip_message(&topic, &data, Some(peer));
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(network.peer_score(&peer), Some(score));
+
+        // Garbage on the blocks topic still counts against the sender
+        network.handle_gossip_message(&topic, b"garbage", Some(peer));
+        assert_eq!(network.peer_score(&peer), Some(score - INVALID_MESSAGE_PENALTY));
+    }
+
+    #[tokio::test]
+    async fn test_peer_reputation_persists_across_restart() {
+        use crate::storage::BlockchainDB;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let banned = PeerId::random();
+        let suspicious = PeerId::random();
+
+        {
+            let (sender, _receiver) = unbounded_channel();
+            let mut network = Network::new(sender).await.unwrap();
+            network.set_reputation_store(Arc::new(BlockchainDB::new(temp_dir.path()).unwrap())).unwrap();
+
+            network.on_peer_connected(banned);
+            network.on_peer_connected(suspicious);
+            network.handle_transaction_message(b"garbage", Some(suspicious));
+            while network.peer_score(&banned).is_some() {
+                network.handle_transaction_message(b"garbage", Some(banned));
+            }
+            assert!(network.is_banned(&banned));
+        }
+
+        // After a restart the ban applies straight away and the score is kept
+        let (sender, _receiver) = unbounded_channel();
+        let mut network = Network::new(sender).await.unwrap();
+        network.set_reputation_store(Arc::new(BlockchainDB::new(temp_dir.path()).unwrap())).unwrap();
+        assert!(network.is_banned(&banned));
+        network.on_peer_connected(banned);
+        assert_eq!(network.peer_score(&banned), None);
+
+        network.on_peer_connected(suspicious);
+        assert_eq!(network.peer_score(&suspicious), Some(INITIAL_PEER_SCORE - INVALID_MESSAGE_PENALTY));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs multicast on the local network"]
+    async fn test_mdns_discovery() {
+        let (first_sender, mut first_receiver) = unbounded_channel();
+        let (second_sender, _second_receiver) = unbounded_channel();
+        let mut first = Network::with_discovery(first_sender, true).await.unwrap();
+        let mut second = Network::with_discovery(second_sender, true).await.unwrap();
+        first.start_listening("/ip4/0.0.0.0/tcp/0".parse().unwrap()).await.unwrap();
+        second.start_listening("/ip4/0.0.0.0/tcp/0".parse().unwrap()).await.unwrap();
+        let second_id = *second.swarm.local_peer_id();
+
+        // Neither node is dialed explicitly, discovery connects them
+        let mut discovered = false;
+        tokio::time::timeout(Duration::from_secs(30), async {
+            while !(discovered && first.peer_count() > 0 && second.peer_count() > 0) {
+                tokio::select! {
+                    Some(event) = first.swarm.next() => first.handle_swarm_event(event),
+                    Some(event) = second.swarm.next() => second.handle_swarm_event(event),
+                }
+                while let Ok(event) = first_receiver.try_recv() {
+                    if let NetworkEvent::PeerDiscovered(peer_id, _) = event {
+                        discovered |= peer_id == second_id;
+                    }
+                }
+            }
+        })
+        .await
+        .expect("Nodes did not discover each other");
+
+        assert!(first.peers.contains_key(&second_id));
+    }
+
+    #[test]
+    fn test_known_blocks_eviction() {
+        let mut known = KnownHashes::new(3);
+        for hash in ["a", "b", "c"] {
+            known.insert(hash.to_string());
+        }
+
+        // Seeing "a" again makes "b" the least recently seen
+        known.insert("a".to_string());
+        known.insert("d".to_string());
+        assert_eq!(known.last_seen.len(), 3);
+        assert!(!known.contains("b"));
+        assert!(known.contains("a"));
+        assert!(known.contains("c"));
+        assert!(known.contains("d"));
+
+        // Going over capacity keeps evicting the oldest
+        known.insert("e".to_string());
+        assert!(!known.contains("c"));
+        assert!(known.contains("e"));
+
+        // Repeated refreshes don't let the queue grow without bound
+        for _ in 0..100 {
+            known.insert("e".to_string());
+        }
+        assert_eq!(known.last_seen.len(), 3);
+        assert!(known.order.len() <= 6);
+        assert!(known.contains("a") && known.contains("d") && known.contains("e"));
+    }
+
+    #[tokio::test]
+    async fn test_known_blocks_bounded() {
+        let (sender, _receiver) = unbounded_channel();
+        let mut network = Network::new(sender).await.unwrap();
+        network.known_blocks = KnownHashes::new(2);
+
+        let blocks: Vec<Block> = (0..3u64)
+            .map(|i| Block::new(1, crate::crypto::Hash::new(&i.to_le_bytes()), vec![], 1))
+            .collect();
+        for block in &blocks {
+            assert!(network.validate_block(block));
+            network.known_blocks.insert(block.hash.to_string());
+            assert!(!network.validate_block(block));
+        }
+
+        // The oldest block was evicted and is accepted again
+        assert!(network.validate_block(&blocks[0]));
+        assert!(!network.validate_block(&blocks[2]));
+    }
+
     #[tokio::test]
     async fn test_partition_detection() {
         let (sender, _receiver) = unbounded_channel();
@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+
+/// Block reward paid before the first halving
+pub const INITIAL_BLOCK_REWARD: u64 = 50;
+/// Number of blocks between reward halvings
+pub const HALVING_INTERVAL: u64 = 210_000;
+/// How far ahead of local time a block timestamp may be (2 hours)
+pub const MAX_FUTURE_BLOCK_TIME: u64 = 2 * 60 * 60;
+
+/// Network-wide consensus parameters.
+///
+/// Nodes on the same network must use the same parameters. Construct one at
+/// startup and hand it to the consensus engine and genesis block creation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainParams {
+    /// Difficulty of the genesis block and starting proof-of-work difficulty
+    pub genesis_difficulty: u32,
+    /// Timestamp of the genesis block
+    pub genesis_timestamp: u64,
+    /// Intended time between blocks, in seconds
+    pub target_block_time: u64,
+    /// Maximum number of transactions taken into a block
+    pub max_block_size: usize,
+    /// Block reward paid before the first halving
+    pub initial_block_reward: u64,
+    /// Number of blocks between reward halvings
+    pub halving_interval: u64,
+    /// How far ahead of local time a block timestamp may be, in seconds
+    pub max_future_block_time: u64,
+    /// Least time a block must be dated after its parent, in seconds. A
+    /// block is always dated at least a second after its parent.
+    pub min_block_interval: u64,
+}
+
+impl ChainParams {
+    pub fn mainnet() -> Self {
+        ChainParams {
+            genesis_difficulty: 1,
+            genesis_timestamp: 1640995200, // 2022-01-01 00:00:00 UTC
+            target_block_time: 600,
+            max_block_size: 1000,
+            initial_block_reward: INITIAL_BLOCK_REWARD,
+            halving_interval: HALVING_INTERVAL,
+            max_future_block_time: MAX_FUTURE_BLOCK_TIME,
+            min_block_interval: 1,
+        }
+    }
+
+    /// Parameters for local test networks: trivial difficulty, fast blocks
+    /// and frequent halvings
+    pub fn testnet() -> Self {
+        ChainParams {
+            genesis_difficulty: 1,
+            genesis_timestamp: 1640995200,
+            target_block_time: 10,
+            max_block_size: 100,
+            initial_block_reward: 1000,
+            halving_interval: 100,
+            max_future_block_time: 60,
+            min_block_interval: 1,
+        }
+    }
+
+    /// Reward for the block at `height`, halved every `halving_interval` blocks
+    pub fn block_reward(&self, height: u64) -> u64 {
+        let halvings = height / self.halving_interval.max(1);
+        if halvings >= u64::BITS as u64 {
+            return 0;
+        }
+        self.initial_block_reward >> halvings
+    }
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        Self::mainnet()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::consensus::{ConsensusEngine, ProofOfWork};
+    use crate::crypto::{Hash, KeyPair};
+    use crate::mempool::Mempool;
+    use crate::transaction::{Transaction, TransactionInput, TransactionOutput};
+
+    fn custom_params() -> ChainParams {
+        ChainParams {
+            genesis_difficulty: 4,
+            genesis_timestamp: 1700000000,
+            max_block_size: 2,
+            ..ChainParams::testnet()
+        }
+    }
+
+    async fn mempool_with_transactions(count: u64) -> Mempool {
+        let mempool = Mempool::new(100);
+        let keypair = KeyPair::generate();
+        for i in 0..count {
+            let prev_hash = Hash::new(format!("funding_{}", i).as_bytes());
+            mempool.add_utxo(prev_hash.clone(), 0, 100).await;
+            let mut tx = Transaction::new(
+                vec![TransactionInput {
+                    tx_hash: prev_hash,
+                    output_index: 0,
+                    signature: None,
+                }],
+                vec![TransactionOutput {
+                    amount: 90,
+                    recipient: vec![1, 2, 3, 4],
+                }],
+            );
+            tx.sign(&keypair, 0).unwrap();
+            mempool.add_transaction(tx, vec![keypair.public_key().as_bytes().to_vec()]).await.unwrap();
+        }
+        mempool
+    }
+
+    #[test]
+    fn test_block_reward_schedule() {
+        let mainnet = ChainParams::mainnet();
+        let testnet = ChainParams::testnet();
+
+        assert_eq!(mainnet.block_reward(100), INITIAL_BLOCK_REWARD);
+        assert_eq!(testnet.block_reward(99), 1000);
+        assert_eq!(testnet.block_reward(100), 500);
+        assert_eq!(testnet.block_reward(100 * 64), 0);
+    }
+
+    #[tokio::test]
+    async fn test_networks_differ() {
+        let mainnet = ChainParams::mainnet();
+        let custom = custom_params();
+
+        // Genesis blocks follow the parameters
+        let mainnet_genesis = Block::genesis_with_params(&mainnet);
+        let custom_genesis = Block::genesis_with_params(&custom);
+        assert_eq!(mainnet_genesis.hash, Block::genesis().hash);
+        assert_eq!(custom_genesis.header.difficulty, 4);
+        assert_eq!(custom_genesis.header.timestamp, 1700000000);
+        assert_ne!(mainnet_genesis.hash, custom_genesis.hash);
+
+        // Engines start at the genesis difficulty and fill blocks up to the
+        // configured size, paying the configured reward
+        let mut mainnet_pow = ProofOfWork::with_params(mainnet.clone());
+        let mut custom_pow = ProofOfWork::with_params(custom.clone());
+        assert_eq!(mainnet_pow.get_difficulty(), 1);
+        assert_eq!(custom_pow.get_difficulty(), 4);
+        mainnet_pow.set_miner_address(vec![7, 7, 7]);
+        custom_pow.set_miner_address(vec![7, 7, 7]);
+
+        let mempool = mempool_with_transactions(5).await;
+        let mainnet_block = mainnet_pow.create_block(&mempool).await.unwrap();
+        let custom_block = custom_pow.create_block(&mempool).await.unwrap();
+        assert_eq!(mainnet_block.transactions.len(), 6);
+        assert_eq!(custom_block.transactions.len(), 3);
+        assert_eq!(mainnet_block.transactions[0].outputs[0].amount, mainnet.block_reward(0) + 50);
+        assert_eq!(custom_block.transactions[0].outputs[0].amount, custom.block_reward(0) + 20);
+
+        // The allowed clock drift differs as well
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let parent = Block::genesis();
+        let mut block = Block::new(1, parent.hash.clone(), vec![], 1);
+        block.header.timestamp = now + 600;
+        assert!(block.mine());
+        let mainnet_result = ProofOfWork::with_params(mainnet).validate_block_with_parent(&block, &parent).await;
+        let custom_result = ProofOfWork::with_params(ChainParams { genesis_difficulty: 1, ..custom })
+            .validate_block_with_parent(&block, &parent).await;
+        assert!(mainnet_result.unwrap());
+        assert!(custom_result.is_err());
+    }
+}
@@ -0,0 +1,440 @@
+use crate::block::Block;
+use crate::consensus::{
+    create_coinbase, validate_coinbase_placement, Address, ConsensusEngine, ConsensusError, ValidationLevel,
+};
+use crate::crypto::{Hash, KeyPair, Signature};
+use crate::mempool::Mempool;
+use crate::params::ChainParams;
+use ed25519_dalek::{Verifier, VerifyingKey};
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use tokio::sync::{mpsc, RwLock};
+
+/// Voting round a PBFT vote belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PbftPhase {
+    Prepare,
+    Commit,
+}
+
+/// A validator's signed vote for a block in one round
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PbftVote {
+    pub phase: PbftPhase,
+    pub block_hash: Hash,
+    pub validator: Address,
+    pub signature: Signature,
+}
+
+impl PbftVote {
+    fn signing_data(phase: PbftPhase, block_hash: &Hash) -> Vec<u8> {
+        let mut data = vec![phase as u8];
+        data.extend_from_slice(block_hash.to_bytes());
+        data
+    }
+
+    /// Create a vote signed by `keypair`
+    pub fn sign(phase: PbftPhase, block_hash: Hash, keypair: &KeyPair) -> Self {
+        let signature = keypair.sign(&Self::signing_data(phase, &block_hash));
+        PbftVote {
+            phase,
+            block_hash,
+            validator: keypair.public_key().to_bytes(),
+            signature,
+        }
+    }
+
+    pub fn verify(&self) -> bool {
+        let Ok(key) = VerifyingKey::from_bytes(&self.validator) else {
+            return false;
+        };
+        let Ok(signature) = self.signature.to_ed_signature() else {
+            return false;
+        };
+        key.verify(&Self::signing_data(self.phase, &self.block_hash), &signature).is_ok()
+    }
+}
+
+#[derive(Default)]
+struct PbftState {
+    votes: HashMap<(PbftPhase, Hash), HashSet<Address>>,
+    // Blocks this node saw a prepare quorum for; only their commits count
+    prepared: HashSet<Hash>,
+    committed: HashSet<Hash>,
+}
+
+/// Practical Byzantine fault tolerant consensus over a fixed validator set.
+///
+/// With 3f+1 validators a block is prepared once 2f+1 of them sent a prepare
+/// vote, and committed once this node has it prepared and 2f+1 sent a commit
+/// vote. Votes are exchanged as in-memory messages: votes this node casts
+/// are sent to `subscribe`rs and votes from other validators are passed in
+/// with `submit_vote`.
+pub struct Pbft {
+    validators: HashSet<Address>,
+    keypair: Option<KeyPair>,
+    params: ChainParams,
+    validation_level: ValidationLevel,
+    state: RwLock<PbftState>,
+    outbox: Option<mpsc::UnboundedSender<PbftVote>>,
+    // Block the next block builds on
+    parent_hash: Option<Hash>,
+    // Public key block rewards and fees are paid to; blocks can't be
+    // created until it is set
+    miner_address: Option<Vec<u8>>,
+    next_height: u64,
+}
+
+impl Pbft {
+    pub fn new(validators: Vec<Address>) -> Self {
+        Pbft {
+            validators: validators.into_iter().collect(),
+            keypair: None,
+            params: ChainParams::default(),
+            validation_level: ValidationLevel::Full,
+            state: RwLock::new(PbftState::default()),
+            outbox: None,
+            parent_hash: None,
+            miner_address: None,
+            next_height: 0,
+        }
+    }
+
+    /// Create an engine for the network described by `params`
+    pub fn with_params(validators: Vec<Address>, params: ChainParams) -> Self {
+        let mut pbft = Self::new(validators);
+        pbft.params = params;
+        pbft
+    }
+
+    pub fn set_validation_level(&mut self, level: ValidationLevel) {
+        self.validation_level = level;
+    }
+
+    /// Set the key this node votes with. It must belong to a validator.
+    pub fn set_local_keypair(&mut self, keypair: KeyPair) -> Result<(), ConsensusError> {
+        if !self.validators.contains(&keypair.public_key().to_bytes()) {
+            return Err(ConsensusError::ValidationError("Local key is not a validator".into()));
+        }
+        self.keypair = Some(keypair);
+        Ok(())
+    }
+
+    /// Set the hash of the block the next block builds on
+    pub fn set_parent_hash(&mut self, hash: Hash) {
+        self.parent_hash = Some(hash);
+    }
+
+    /// Set the public key that block rewards and fees are paid to
+    pub fn set_miner_address(&mut self, address: Vec<u8>) {
+        self.miner_address = Some(address);
+    }
+
+    /// Set the height of the next block, which determines its reward
+    pub fn set_next_height(&mut self, height: u64) {
+        self.next_height = height;
+    }
+
+    /// Returns a channel that receives every vote this node casts
+    pub fn subscribe(&mut self) -> mpsc::UnboundedReceiver<PbftVote> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.outbox = Some(tx);
+        rx
+    }
+
+    /// Maximum number of faulty validators tolerated
+    pub fn max_faulty(&self) -> usize {
+        self.validators.len().saturating_sub(1) / 3
+    }
+
+    /// Votes needed to prepare or commit a block
+    pub fn quorum(&self) -> usize {
+        2 * self.max_faulty() + 1
+    }
+
+    pub async fn is_committed(&self, block_hash: &Hash) -> bool {
+        self.state.read().await.committed.contains(block_hash)
+    }
+
+    pub async fn vote_count(&self, phase: PbftPhase, block_hash: &Hash) -> usize {
+        self.state.read().await.votes
+            .get(&(phase, block_hash.clone()))
+            .map_or(0, |voters| voters.len())
+    }
+
+    /// Record a vote from a validator and advance the block through the
+    /// prepare and commit rounds once enough votes are in.
+    pub async fn submit_vote(&self, vote: PbftVote) -> Result<(), ConsensusError> {
+        if !self.validators.contains(&vote.validator) {
+            return Err(ConsensusError::ValidationError("Vote from unknown validator".into()));
+        }
+        if !vote.verify() {
+            return Err(ConsensusError::ValidationError("Invalid vote signature".into()));
+        }
+
+        let quorum = self.quorum();
+        let mut pending = vec![vote];
+        while let Some(vote) = pending.pop() {
+            let mut state = self.state.write().await;
+            let voters = state.votes.entry((vote.phase, vote.block_hash.clone())).or_default();
+            if !voters.insert(vote.validator) || voters.len() < quorum {
+                continue;
+            }
+
+            match vote.phase {
+                PbftPhase::Prepare => {
+                    // Prepared: move on to the commit round once
+                    if state.prepared.insert(vote.block_hash.clone()) {
+                        if let Some(commit) = self.cast_vote(PbftPhase::Commit, &vote.block_hash) {
+                            pending.push(commit);
+                        }
+                        // Commits that arrived before this node was prepared count now
+                        let commits = state.votes.get(&(PbftPhase::Commit, vote.block_hash.clone())).map_or(0, HashSet::len);
+                        if commits >= quorum {
+                            state.committed.insert(vote.block_hash);
+                        }
+                    }
+                }
+                PbftPhase::Commit => {
+                    if state.prepared.contains(&vote.block_hash) {
+                        state.committed.insert(vote.block_hash);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sign and send a vote as the local validator
+    fn cast_vote(&self, phase: PbftPhase, block_hash: &Hash) -> Option<PbftVote> {
+        let vote = PbftVote::sign(phase, block_hash.clone(), self.keypair.as_ref()?);
+        if let Some(outbox) = &self.outbox {
+            // A dropped receiver only stops broadcasting, not local voting
+            let _ = outbox.send(vote.clone());
+        }
+        Some(vote)
+    }
+
+    async fn validate_with_level(&self, block: &Block, height: Option<u64>) -> Result<bool, ConsensusError> {
+        // Verify block hash and merkle root match the block contents
+        if !block.verify_linkage() {
+            return Err(ConsensusError::ValidationError("Block hash or merkle root doesn't match contents".into()));
+        }
+
+        if !self.validators.contains(&block.header.proposer) {
+            return Err(ConsensusError::ValidationError("Block proposer is not a validator".into()));
+        }
+        // Only the holder of the proposer's key can have proposed the block
+        if !block.verify_proposer_signature() {
+            return Err(ConsensusError::ValidationError("Missing or invalid proposer signature".into()));
+        }
+        validate_coinbase_placement(block)?;
+
+        if self.validation_level.verifies_signatures(height) {
+            let results = join_all(block.transactions.iter().map(|tx| tx.verify())).await;
+            for result in results {
+                result.map_err(|e| ConsensusError::TransactionError(e.to_string()))?;
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+#[async_trait::async_trait]
+impl ConsensusEngine for Pbft {
+    async fn validate_block(&self, block: &Block) -> Result<bool, ConsensusError> {
+        self.validate_with_level(block, None).await
+    }
+
+    async fn validate_block_at_height(&self, block: &Block, height: u64) -> Result<bool, ConsensusError> {
+        self.validate_with_level(block, Some(height)).await
+    }
+
+    async fn create_block(&self, mempool: &Mempool) -> Result<Block, ConsensusError> {
+        let prev_hash = self.parent_hash.clone()
+            .ok_or_else(|| ConsensusError::BlockCreationError("No parent block configured".into()))?;
+        let keypair = self.keypair.as_ref()
+            .ok_or_else(|| ConsensusError::BlockCreationError("No local validator configured".into()))?;
+
+        let transactions = mempool.get_pending_transactions(self.params.max_block_size).await
+            .map_err(|e| ConsensusError::BlockCreationError(e.to_string()))?;
+
+        // Pay the block reward and collected fees to the proposer
+        let miner_address = self.miner_address.as_ref()
+            .ok_or_else(|| ConsensusError::BlockCreationError("No miner address configured".into()))?;
+        let coinbase = create_coinbase(mempool, &transactions, &self.params, self.next_height, miner_address).await?;
+        let transactions: Vec<_> = std::iter::once(coinbase).chain(transactions).collect();
+
+        let mut block = Block::new(
+            1, // version
+            prev_hash,
+            transactions,
+            1, // difficulty (unused by PBFT)
+        );
+        block.sign_as_proposer(keypair);
+
+        Ok(block)
+    }
+
+    async fn process_new_block(&self, block: Block) -> Result<(), ConsensusError> {
+        self.validate_block(&block).await?;
+
+        // Start the prepare round for this block
+        if let Some(prepare) = self.cast_vote(PbftPhase::Prepare, &block.hash) {
+            self.submit_vote(prepare).await?;
+        }
+
+        Ok(())
+    }
+
+    fn get_difficulty(&self) -> u64 {
+        // PBFT has no difficulty, report the agreement threshold instead
+        self.quorum() as u64
+    }
+
+    fn validation_level(&self) -> ValidationLevel {
+        self.validation_level
+    }
+
+    fn chain_params(&self) -> &ChainParams {
+        &self.params
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Transaction;
+
+    fn validator_keys(count: usize) -> Vec<KeyPair> {
+        (0..count).map(|_| KeyPair::generate()).collect()
+    }
+
+    fn addresses(keys: &[KeyPair]) -> Vec<Address> {
+        keys.iter().map(|key| key.public_key().to_bytes()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_four_validators_commit() {
+        let keys = validator_keys(4);
+        let validators = addresses(&keys);
+
+        let parent = Block::genesis();
+        let mut nodes = Vec::new();
+        let mut inboxes = Vec::new();
+        for key in keys {
+            let mut node = Pbft::new(validators.clone());
+            node.set_parent_hash(parent.hash.clone());
+            node.set_miner_address(key.public_key().to_bytes().to_vec());
+            node.set_local_keypair(key).unwrap();
+            inboxes.push(node.subscribe());
+            nodes.push(node);
+        }
+        assert_eq!(nodes[0].max_faulty(), 1);
+        assert_eq!(nodes[0].quorum(), 3);
+
+        let block = nodes[0].create_block(&Mempool::new(10)).await.unwrap();
+        assert_eq!(block.header.prev_hash, parent.hash);
+        assert!(block.transactions[0].is_coinbase());
+        assert!(block.verify_proposer_signature());
+        for node in &nodes {
+            node.process_new_block(block.clone()).await.unwrap();
+        }
+
+        // Deliver broadcast votes to every other node until no messages remain
+        loop {
+            let mut delivered = false;
+            for (sender, inbox) in inboxes.iter_mut().enumerate() {
+                while let Ok(vote) = inbox.try_recv() {
+                    delivered = true;
+                    for (receiver, node) in nodes.iter().enumerate() {
+                        if receiver != sender {
+                            node.submit_vote(vote.clone()).await.unwrap();
+                        }
+                    }
+                }
+            }
+            if !delivered {
+                break;
+            }
+        }
+
+        for node in &nodes {
+            assert!(node.is_committed(&block.hash).await);
+            assert_eq!(node.vote_count(PbftPhase::Commit, &block.hash).await, 4);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_two_votes_do_not_commit() {
+        let keys = validator_keys(4);
+        let observer = Pbft::new(addresses(&keys));
+        let block_hash = Hash::new(b"block");
+
+        for key in &keys[..2] {
+            observer.submit_vote(PbftVote::sign(PbftPhase::Prepare, block_hash.clone(), key)).await.unwrap();
+            observer.submit_vote(PbftVote::sign(PbftPhase::Commit, block_hash.clone(), key)).await.unwrap();
+        }
+        assert_eq!(observer.vote_count(PbftPhase::Commit, &block_hash).await, 2);
+        assert!(!observer.is_committed(&block_hash).await);
+
+        // Repeated votes from the same validator don't count twice
+        observer.submit_vote(PbftVote::sign(PbftPhase::Commit, block_hash.clone(), &keys[0])).await.unwrap();
+        assert!(!observer.is_committed(&block_hash).await);
+
+        // 2f+1 commits don't commit a block this node hasn't seen prepared
+        observer.submit_vote(PbftVote::sign(PbftPhase::Commit, block_hash.clone(), &keys[2])).await.unwrap();
+        assert_eq!(observer.vote_count(PbftPhase::Commit, &block_hash).await, 3);
+        assert!(!observer.is_committed(&block_hash).await);
+
+        // The third prepare makes them count
+        observer.submit_vote(PbftVote::sign(PbftPhase::Prepare, block_hash.clone(), &keys[2])).await.unwrap();
+        assert!(observer.is_committed(&block_hash).await);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_forged_proposals() {
+        let mut keys = validator_keys(3);
+        keys.insert(0, KeyPair::from_seed(&[1u8; 32]).unwrap());
+        let mut node = Pbft::new(addresses(&keys));
+        node.set_parent_hash(Block::genesis().hash);
+        node.set_miner_address(vec![1, 2, 3]);
+        node.set_local_keypair(KeyPair::from_seed(&[1u8; 32]).unwrap()).unwrap();
+        let block = node.create_block(&Mempool::new(10)).await.unwrap();
+        assert!(node.validate_block(&block).await.unwrap());
+
+        // Another validator can't be named proposer without its key
+        let mut forged = block.clone();
+        forged.set_proposer(keys[1].public_key().to_bytes());
+        assert!(node.validate_block(&forged).await.is_err());
+        let mut unsigned = block.clone();
+        unsigned.header.proposer_signature = None;
+        assert!(node.validate_block(&unsigned).await.is_err());
+
+        // A second coinbase is rejected even when signed by the proposer
+        let mut transactions = block.transactions.clone();
+        transactions.push(Transaction::coinbase(vec![4, 5, 6], 50));
+        let mut two_coinbases = Block::new(1, block.header.prev_hash.clone(), transactions, 1);
+        two_coinbases.sign_as_proposer(&keys[0]);
+        assert!(node.validate_block(&two_coinbases).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_outside_votes() {
+        let keys = validator_keys(4);
+        let node = Pbft::new(addresses(&keys));
+        let block_hash = Hash::new(b"block");
+
+        let outsider = KeyPair::generate();
+        let vote = PbftVote::sign(PbftPhase::Prepare, block_hash.clone(), &outsider);
+        assert!(node.submit_vote(vote).await.is_err());
+
+        // A vote whose signature doesn't cover the claimed block is rejected
+        let mut forged = PbftVote::sign(PbftPhase::Prepare, block_hash, &keys[0]);
+        forged.block_hash = Hash::new(b"other block");
+        assert!(node.submit_vote(forged).await.is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use crate::crypto::Hash;
+use serde::{Deserialize, Serialize};
+
+/// Wrapper type for wasmer::Value that implements Serialize/Deserialize
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum WasmValue {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    String(String),
+    Bytes(Vec<u8>),
+}
+
+impl From<wasmer::Value> for WasmValue {
+    fn from(value: wasmer::Value) -> Self {
+        match value {
+            wasmer::Value::I32(v) => WasmValue::I32(v),
+            wasmer::Value::I64(v) => WasmValue::I64(v),
+            wasmer::Value::F32(v) => WasmValue::F32(v),
+            wasmer::Value::F64(v) => WasmValue::F64(v),
+            _ => WasmValue::String("Unsupported type".to_string()),
+        }
+    }
+}
+
+impl Into<wasmer::Value> for WasmValue {
+    fn into(self) -> wasmer::Value {
+        match self {
+            WasmValue::I32(v) => wasmer::Value::I32(v),
+            WasmValue::I64(v) => wasmer::Value::I64(v),
+            WasmValue::F32(v) => wasmer::Value::F32(v),
+            WasmValue::F64(v) => wasmer::Value::F64(v),
+            _ => wasmer::Value::I32(0), // Default for unsupported types
+        }
+    }
+}
+
+/// Outcome of one contract call made by a transaction
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CallReceipt {
+    /// Transaction that made the call
+    pub tx_hash: Hash,
+    /// Position of the call among those the transaction made
+    pub call_index: u32,
+    pub contract_addr: [u8; 32],
+    pub method: String,
+    pub gas_used: u64,
+    pub success: bool,
+    /// Values returned by the call, if it succeeded
+    pub result: Option<Vec<WasmValue>>,
+}
+
+/// Contract calls made by the transactions of a block, with their total gas
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockReceipt {
+    pub block_hash: Hash,
+    pub calls: Vec<CallReceipt>,
+    /// Sum of the gas used by every call in the block
+    pub gas_used: u64,
+}
+
+impl BlockReceipt {
+    pub fn new(block_hash: Hash, calls: Vec<CallReceipt>) -> Self {
+        let gas_used = calls.iter().map(|call| call.gas_used).sum();
+        BlockReceipt {
+            block_hash,
+            calls,
+            gas_used,
+        }
+    }
+}
+
+/// Event emitted by a contract through the `env.emit` host function
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EmittedEvent {
+    pub contract_addr: [u8; 32],
+    /// Block the emitting execution ran in
+    pub block_number: u64,
+    /// Indexed words identifying the event, such as the hash of its signature
+    pub topics: Vec<[u8; 32]>,
+    pub data: Vec<u8>,
+}
+
+/// Subscription message selecting the emitted events a subscriber receives.
+/// Unset fields match every event.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EventFilter {
+    #[serde(default)]
+    pub contract_addr: Option<[u8; 32]>,
+    /// Name of the event, matched against its first topic
+    #[serde(default)]
+    pub event: Option<String>,
+    /// Values of the indexed parameters, which follow the name topic, by
+    /// position. `None` matches any value.
+    #[serde(default)]
+    pub indexed: Vec<Option<[u8; 32]>>,
+}
+
+impl EventFilter {
+    /// The first topic of events named `name`
+    pub fn event_topic(name: &str) -> [u8; 32] {
+        let mut topic = [0u8; 32];
+        topic.copy_from_slice(Hash::new(name.as_bytes()).to_bytes());
+        topic
+    }
+
+    pub fn matches(&self, event: &EmittedEvent) -> bool {
+        if self.contract_addr.is_some_and(|addr| addr != event.contract_addr) {
+            return false;
+        }
+        if let Some(name) = &self.event {
+            if event.topics.first() != Some(&Self::event_topic(name)) {
+                return false;
+            }
+        }
+
+        let indexed = event.topics.get(1..).unwrap_or_default();
+        self.indexed.iter().enumerate().all(|(i, wanted)| match wanted {
+            Some(value) => indexed.get(i) == Some(value),
+            None => true,
+        })
+    }
+}
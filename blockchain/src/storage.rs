@@ -1,12 +1,24 @@
-use rocksdb::{DB, Options, BlockBasedOptions, WriteOptions, ReadOptions, CompactOptions, SliceTransform};
+use rocksdb::{DB, Options, BlockBasedOptions, WriteBatch, WriteOptions, ReadOptions, CompactOptions, SliceTransform, IteratorMode, Direction};
 use std::path::Path;
 use std::sync::Arc;
 use crate::block::{Block, BlockHeader};
-use crate::transaction::Transaction;
+use crate::chain::ForkChoice;
+use crate::transaction::{Transaction, TransactionOutput};
 use crate::crypto::Hash;
+use crate::receipt::BlockReceipt;
+use crate::contract::ContractVersion;
+use crate::contract::state::{StateDiff, StateSnapshot, StateStore};
+use crate::network::{PeerReputation, PeerStore};
+use crate::format;
 use bincode;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::warn;
 
 // Column family names
 const BLOCKS_CF: &str = "blocks";
@@ -15,6 +27,161 @@ const UTXOS_CF: &str = "utxos";
 const STATE_CF: &str = "state";
 const METADATA_CF: &str = "metadata";
 const CONTRACT_CF: &str = "contracts";
+const CONTRACT_VERSIONS_CF: &str = "contract_versions";
+const RECEIPTS_CF: &str = "receipts";
+const KV_CF: &str = "kv";
+
+// Metadata key recording the hash of the most recently stored block
+const LATEST_BLOCK_KEY: &[u8] = b"latest_block";
+
+// Prefix of the contract state entries and snapshots KvStateStore writes
+const KV_CONTRACT_STATE_PREFIX: &[u8] = b"contract_state:";
+
+// Keys KvStateStore wrote before storing state per entry: the whole state
+// and snapshot history of each contract, and the list of contracts stored
+const KV_LEGACY_STATE_PREFIX: &[u8] = b"state:";
+const KV_LEGACY_SNAPSHOTS_PREFIX: &[u8] = b"snapshots:";
+const KV_LEGACY_CONTRACTS_KEY: &[u8] = b"contracts";
+
+// Tags following the contract address in the key of a state entry, which
+// is followed by its storage key, and of a snapshot, followed by its
+// big-endian sequence number so snapshots sort oldest first
+const STATE_ENTRY_TAG: u8 = b'e';
+const SNAPSHOT_TAG: u8 = b's';
+
+// Metadata key recording peer scores and bans
+const PEER_REPUTATION_KEY: &[u8] = b"peer_reputation";
+
+// Metadata key recording the genesis block the database was initialized with
+const GENESIS_HASH_KEY: &[u8] = b"genesis_hash";
+
+// Metadata key prefixes of the height -> hash index and its hash -> height reverse
+const HEIGHT_KEY_PREFIX: &[u8] = b"height:";
+const BLOCK_HEIGHT_KEY_PREFIX: &[u8] = b"block_height:";
+
+// Column families holding chain state, as opposed to blocks and history.
+// The contracts CF only holds what older nodes wrote before it is migrated.
+const CHAIN_STATE_CFS: [&str; 4] = [UTXOS_CF, STATE_CF, CONTRACT_CF, CONTRACT_VERSIONS_CF];
+
+/// Blocks stored through `store_block_buffered` before they are synced to disk
+pub const DEFAULT_WRITE_BUFFER_BLOCKS: usize = 64;
+
+// Chain events kept for subscribers that fall behind
+const CHAIN_EVENT_CAPACITY: usize = 64;
+
+// Suffix after the contract address of the whole snapshot history of a
+// contract, which was kept in the contracts CF before snapshots were stored
+// one per key in the state CF
+const SNAPSHOTS_KEY_SUFFIX: &[u8] = b"snapshots";
+
+/// Whether a key of the contracts CF holds the whole snapshot history of a
+/// contract rather than a contract version
+fn is_legacy_snapshots_key(key: &[u8]) -> bool {
+    key.len() == 32 + SNAPSHOTS_KEY_SUFFIX.len() && key.ends_with(SNAPSHOTS_KEY_SUFFIX)
+}
+
+/// Key of an unspent output: the hash of its transaction followed by its index
+fn utxo_key(outpoint: &(Hash, u32)) -> Vec<u8> {
+    [outpoint.0.to_bytes(), &outpoint.1.to_le_bytes()].concat()
+}
+
+/// Key of a state entry of a contract, after `prefix`
+fn state_entry_key(prefix: &[u8], addr: &[u8; 32], key: &[u8]) -> Vec<u8> {
+    [prefix, addr.as_slice(), &[STATE_ENTRY_TAG], key].concat()
+}
+
+/// Key of a snapshot of a contract, after `prefix`
+fn snapshot_key(prefix: &[u8], addr: &[u8; 32], seq: u64) -> Vec<u8> {
+    [prefix, addr.as_slice(), &[SNAPSHOT_TAG], &seq.to_be_bytes()].concat()
+}
+
+/// Contract address, tag and remainder of a key written under `prefix` by
+/// `state_entry_key` or `snapshot_key`
+fn split_contract_key<'a>(prefix: &[u8], key: &'a [u8]) -> Option<([u8; 32], u8, &'a [u8])> {
+    let key = key.strip_prefix(prefix)?;
+    if key.len() < 33 {
+        return None;
+    }
+    let mut addr = [0u8; 32];
+    addr.copy_from_slice(&key[..32]);
+    Some((addr, key[32], &key[33..]))
+}
+
+/// Current state of every contract among `entries`, written under `prefix`
+fn decode_states(
+    prefix: &[u8],
+    entries: &[(Vec<u8>, Vec<u8>)],
+) -> HashMap<[u8; 32], HashMap<Vec<u8>, Vec<u8>>> {
+    let mut states: HashMap<[u8; 32], HashMap<Vec<u8>, Vec<u8>>> = HashMap::new();
+    for (key, value) in entries {
+        if let Some((addr, STATE_ENTRY_TAG, entry_key)) = split_contract_key(prefix, key) {
+            states.entry(addr).or_default().insert(entry_key.to_vec(), value.clone());
+        }
+    }
+    states
+}
+
+/// Snapshot history of every contract among `entries`, written under
+/// `prefix` in key order
+fn decode_snapshots(
+    prefix: &[u8],
+    entries: &[(Vec<u8>, Vec<u8>)],
+) -> Result<HashMap<[u8; 32], Vec<(u64, StateSnapshot)>>, StorageError> {
+    let mut snapshots: HashMap<[u8; 32], Vec<(u64, StateSnapshot)>> = HashMap::new();
+    for (key, value) in entries {
+        let Some((addr, SNAPSHOT_TAG, seq)) = split_contract_key(prefix, key) else {
+            continue;
+        };
+        let seq = seq.try_into().map(u64::from_be_bytes).map_err(|_| StorageError::InvalidData)?;
+        let snapshot = format::decode(value)
+            .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+        snapshots.entry(addr).or_default().push((seq, snapshot));
+    }
+    Ok(snapshots)
+}
+
+/// Writes storing the changes of `diff` to the state of a contract
+fn state_change_ops(prefix: &[u8], addr: &[u8; 32], diff: &StateDiff) -> Vec<BatchOp> {
+    let set = diff.added.iter()
+        .chain(diff.modified.iter().map(|(key, (_, new))| (key, new)))
+        .map(|(key, value)| BatchOp::Set(state_entry_key(prefix, addr, key), value.clone()));
+    let deleted = diff.deleted.keys()
+        .map(|key| BatchOp::Delete(state_entry_key(prefix, addr, key)));
+    set.chain(deleted).collect()
+}
+
+/// Writes moving a whole state and snapshot history stored the old way to
+/// one key per entry and per snapshot
+fn legacy_migration_ops(
+    prefix: &[u8],
+    addr: &[u8; 32],
+    state: Option<HashMap<Vec<u8>, Vec<u8>>>,
+    snapshots: Option<Vec<StateSnapshot>>,
+) -> Result<Vec<BatchOp>, StorageError> {
+    let mut ops = Vec::new();
+    for (key, value) in state.unwrap_or_default() {
+        ops.push(BatchOp::Set(state_entry_key(prefix, addr, &key), value));
+    }
+    for (seq, snapshot) in snapshots.unwrap_or_default().iter().enumerate() {
+        let value = format::encode(snapshot)
+            .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+        ops.push(BatchOp::Set(snapshot_key(prefix, addr, seq as u64), value));
+    }
+    Ok(ops)
+}
+
+/// Key of a stored contract version: the contract address followed by the version string
+fn contract_version_key(addr: &[u8; 32], version: &str) -> Vec<u8> {
+    [addr.as_slice(), version.as_bytes()].concat()
+}
+
+fn height_key(height: u64) -> Vec<u8> {
+    [HEIGHT_KEY_PREFIX, &height.to_be_bytes()].concat()
+}
+
+fn block_height_key(hash: &Hash) -> Vec<u8> {
+    [BLOCK_HEIGHT_KEY_PREFIX, hash.to_bytes()].concat()
+}
 
 #[derive(Debug)]
 pub enum StorageError {
@@ -23,6 +190,26 @@ pub enum StorageError {
     NotFound,
     InvalidData,
     CacheError(String),
+    AlreadyInitialized(String),
+}
+
+/// One write in a `KeyValueStore::batch`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchOp {
+    Set(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+}
+
+/// Byte key-value storage, implemented both in memory and on RocksDB so
+/// the same code can run against either
+pub trait KeyValueStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError>;
+    fn set(&mut self, key: &[u8], value: &[u8]) -> Result<(), StorageError>;
+    fn delete(&mut self, key: &[u8]) -> Result<(), StorageError>;
+    /// Apply all the writes or, on error, none of them
+    fn batch(&mut self, ops: Vec<BatchOp>) -> Result<(), StorageError>;
+    /// Every entry whose key starts with `prefix`, in key order
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, StorageError>;
 }
 
 // Simple in-memory storage for testing
@@ -52,6 +239,40 @@ impl Storage {
     }
 }
 
+impl KeyValueStore for Storage {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError> {
+        Storage::get(self, key)
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) -> Result<(), StorageError> {
+        Storage::set(self, key, value)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), StorageError> {
+        Storage::delete(self, key)
+    }
+
+    fn batch(&mut self, ops: Vec<BatchOp>) -> Result<(), StorageError> {
+        // In-memory writes can't fail, so applying them in turn is atomic
+        for op in ops {
+            match op {
+                BatchOp::Set(key, value) => self.data.insert(key, value),
+                BatchOp::Delete(key) => self.data.remove(&key),
+            };
+        }
+        Ok(())
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, StorageError> {
+        let mut entries: Vec<_> = self.data.iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        entries.sort();
+        Ok(entries)
+    }
+}
+
 impl From<rocksdb::Error> for StorageError {
     fn from(err: rocksdb::Error) -> Self {
         StorageError::DatabaseError(err.to_string())
@@ -64,10 +285,61 @@ impl From<Box<dyn Error>> for StorageError {
     }
 }
 
+/// Dump of the chain state written by `BlockchainDB::export_chain_state`
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct ChainStateExport {
+    /// Tip the state was exported at
+    latest_block: Option<Hash>,
+    /// Raw entries of each chain state column family, in key order
+    column_families: Vec<(String, Vec<(Vec<u8>, Vec<u8>)>)>,
+    /// Unspent value held by each recipient, so the dump can be analyzed
+    /// without decoding the UTXO set. Derived, so not imported.
+    balances: Vec<(Vec<u8>, u64)>,
+}
+
+/// Changes to the best chain, for wallets and indexers following it
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChainEvent {
+    /// The tip moved to a block not built on the previous tip. `removed`
+    /// left the best chain and `added` joined it, each in height order.
+    Reorg { removed: Vec<Hash>, added: Vec<Hash> },
+}
+
+// A reorg found while storing a block, with the height of the common ancestor
+struct Reorg {
+    ancestor_height: u64,
+    removed: Vec<Hash>,
+    added: Vec<Hash>,
+}
+
+// How storing a block moves the tip of the best chain
+enum TipChange {
+    // The block becomes the tip without any block leaving the best chain
+    Extend,
+    Reorg(Reorg),
+    // The block is on a branch with less work; the tip stays where it is
+    Side,
+}
+
+impl TipChange {
+    fn into_reorg(self) -> Option<Reorg> {
+        match self {
+            TipChange::Reorg(reorg) => Some(reorg),
+            TipChange::Extend | TipChange::Side => None,
+        }
+    }
+}
+
 pub struct BlockchainDB {
     db: DB,
     write_options: WriteOptions,
+    // Writes that skip the fsync, for the buffered block path
+    buffered_write_options: WriteOptions,
     read_options: ReadOptions,
+    events: broadcast::Sender<ChainEvent>,
+    // Blocks written without sync since the last flush
+    unsynced_blocks: AtomicUsize,
+    write_buffer_blocks: usize,
 }
 
 impl BlockchainDB {
@@ -97,21 +369,41 @@ impl BlockchainDB {
         // Configure prefix extractor for efficient queries
         opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(32)); // Hash size
 
-        let column_families = vec![BLOCKS_CF, TRANSACTIONS_CF, UTXOS_CF, STATE_CF, METADATA_CF, CONTRACT_CF];
+        let column_families = vec![BLOCKS_CF, TRANSACTIONS_CF, UTXOS_CF, STATE_CF, METADATA_CF, CONTRACT_CF, CONTRACT_VERSIONS_CF, RECEIPTS_CF, KV_CF];
         let db = DB::open_cf(&opts, path, &column_families)?;
 
         let mut write_options = WriteOptions::default();
         write_options.set_sync(true);
+
+        let mut buffered_write_options = WriteOptions::default();
+        buffered_write_options.set_sync(false);
         
         let mut read_options = ReadOptions::default();
         read_options.set_verify_checksums(true);
         read_options.set_readahead_size(1024 * 1024); // 1MB readahead
 
-        Ok(BlockchainDB { 
+        let db = BlockchainDB { 
             db,
             write_options,
+            buffered_write_options,
             read_options,
-        })
+            events: broadcast::channel(CHAIN_EVENT_CAPACITY).0,
+            unsynced_blocks: AtomicUsize::new(0),
+            write_buffer_blocks: DEFAULT_WRITE_BUFFER_BLOCKS,
+        };
+        db.migrate_contract_versions()?;
+        Ok(db)
+    }
+
+    /// Sync buffered block writes to disk once `blocks` have accumulated
+    pub fn with_write_buffer(mut self, blocks: usize) -> Self {
+        self.write_buffer_blocks = blocks.max(1);
+        self
+    }
+
+    /// Returns a channel that receives every chain event from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<ChainEvent> {
+        self.events.subscribe()
     }
 
     pub async fn store_block(&self, block: &Block) -> Result<(), StorageError> {
@@ -119,23 +411,162 @@ impl BlockchainDB {
             .ok_or(StorageError::DatabaseError("Block CF not found".to_string()))?;
         
         let key = block.hash.to_bytes();
-        let value = bincode::serialize(block)
+        let value = format::encode(block)
             .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+
+        let change = self.tip_change(block).await?;
         
         self.db.put_cf_opt(cf, key, value, &self.write_options)?;
-        
-        // Update metadata
+
+        // Side blocks only get their height recorded, for their children
+        let side = matches!(change, TipChange::Side);
+        self.index_block_height(block, !side)?;
+        if side {
+            return Ok(());
+        }
         self.update_metadata(&block.hash)?;
+
+        if let Some(reorg) = change.into_reorg() {
+            let mut batch = WriteBatch::default();
+            self.reindex_reorg(&mut batch, &reorg)?;
+            self.db.write_opt(batch, &self.write_options)?;
+            self.notify_reorg(reorg);
+        }
         
         Ok(())
     }
 
+    /// How storing `block` moves the tip. A block not built on the current
+    /// tip only takes over when `ForkChoice` picks its branch over the
+    /// current one, both counted from their common ancestor. Blocks without
+    /// a stored common ancestor with the current tip still become the tip.
+    async fn tip_change(&self, block: &Block) -> Result<TipChange, StorageError> {
+        let Some(old_tip) = self.get_latest_hash() else {
+            return Ok(TipChange::Extend);
+        };
+        if old_tip == block.header.prev_hash || old_tip == block.hash {
+            return Ok(TipChange::Extend);
+        }
+        let (Some(mut old_height), Some(new_height)) = (self.stored_height(&old_tip)?, self.derive_block_height(block)?) else {
+            return Ok(TipChange::Extend);
+        };
+
+        let mut removed = Vec::new();
+        let mut added = vec![(block.hash.clone(), block.header.clone())];
+        let (mut old_cursor, mut new_cursor) = (old_tip, block.header.prev_hash.clone());
+        let Some(mut new_cursor_height) = new_height.checked_sub(1) else {
+            return Ok(TipChange::Extend);
+        };
+
+        // Step back along the higher branch until both meet
+        while old_cursor != new_cursor {
+            let (cursor, height, blocks) = if old_height >= new_cursor_height {
+                (&mut old_cursor, &mut old_height, &mut removed)
+            } else {
+                (&mut new_cursor, &mut new_cursor_height, &mut added)
+            };
+            if *height == 0 {
+                return Ok(TipChange::Extend);
+            }
+            let header = match self.get_block(cursor).await {
+                Ok(block) => block.header,
+                Err(StorageError::NotFound) => return Ok(TipChange::Extend),
+                Err(e) => return Err(e),
+            };
+            let hash = std::mem::replace(cursor, header.prev_hash.clone());
+            blocks.push((hash, header));
+            *height -= 1;
+        }
+
+        let branches: Vec<_> = removed.iter().chain(&added).cloned().collect();
+        if ForkChoice::best_tip(&branches).as_ref() != Some(&block.hash) {
+            return Ok(TipChange::Side);
+        }
+
+        let hashes = |blocks: Vec<(Hash, BlockHeader)>| blocks.into_iter().rev().map(|(hash, _)| hash).collect();
+        Ok(TipChange::Reorg(Reorg { ancestor_height: old_height, removed: hashes(removed), added: hashes(added) }))
+    }
+
+    /// Point the height index at the new branch of a reorg and drop the
+    /// heights above it left by the old one
+    fn reindex_reorg(&self, batch: &mut WriteBatch, reorg: &Reorg) -> Result<(), StorageError> {
+        let cf = self.db.cf_handle(METADATA_CF)
+            .ok_or(StorageError::DatabaseError("Metadata CF not found".to_string()))?;
+
+        for (height, hash) in (reorg.ancestor_height + 1..).zip(&reorg.added) {
+            let value = bincode::serialize(hash)
+                .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+            batch.put_cf(cf, height_key(height), value);
+        }
+        for height in reorg.ancestor_height + 1 + reorg.added.len() as u64..=reorg.ancestor_height + reorg.removed.len() as u64 {
+            batch.delete_cf(cf, height_key(height));
+        }
+        Ok(())
+    }
+
+    fn notify_reorg(&self, reorg: Reorg) {
+        // Nobody may be subscribed, which is fine
+        let _ = self.events.send(ChainEvent::Reorg {
+            removed: reorg.removed,
+            added: reorg.added,
+        });
+    }
+
+    /// Height of a stored block, if it is indexed
+    fn stored_height(&self, hash: &Hash) -> Result<Option<u64>, StorageError> {
+        let cf = self.db.cf_handle(METADATA_CF)
+            .ok_or(StorageError::DatabaseError("Metadata CF not found".to_string()))?;
+
+        match self.db.get_cf_opt(cf, block_height_key(hash), &self.read_options)? {
+            Some(bytes) => {
+                let bytes: [u8; 8] = bytes.as_slice().try_into().map_err(|_| StorageError::InvalidData)?;
+                Ok(Some(u64::from_be_bytes(bytes)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Whether a genesis block has been stored in this database
+    pub fn is_initialized(&self) -> Result<bool, StorageError> {
+        Ok(self.stored_genesis_hash()?.is_some())
+    }
+
+    fn stored_genesis_hash(&self) -> Result<Option<Vec<u8>>, StorageError> {
+        let cf = self.db.cf_handle(METADATA_CF)
+            .ok_or(StorageError::DatabaseError("Metadata CF not found".to_string()))?;
+
+        Ok(self.db.get_cf_opt(cf, GENESIS_HASH_KEY, &self.read_options)?)
+    }
+
+    /// Store the genesis block of a new chain. Initializing again with the
+    /// same genesis does nothing; a different genesis is rejected.
+    pub async fn initialize_genesis(&self, genesis: &Block) -> Result<(), StorageError> {
+        if let Some(stored) = self.stored_genesis_hash()? {
+            if stored.as_slice() == genesis.hash.to_bytes() {
+                return Ok(());
+            }
+            return Err(StorageError::AlreadyInitialized(format!(
+                "Chain already initialized with genesis {}, refusing genesis {}",
+                hex::encode(stored),
+                genesis.hash
+            )));
+        }
+
+        self.store_block(genesis).await?;
+
+        let cf = self.db.cf_handle(METADATA_CF)
+            .ok_or(StorageError::DatabaseError("Metadata CF not found".to_string()))?;
+        self.db.put_cf_opt(cf, GENESIS_HASH_KEY, genesis.hash.to_bytes(), &self.write_options)?;
+
+        Ok(())
+    }
+
     pub async fn get_block(&self, hash: &Hash) -> Result<Block, StorageError> {
         let cf = self.db.cf_handle(BLOCKS_CF)
             .ok_or(StorageError::DatabaseError("Block CF not found".to_string()))?;
         
         if let Some(data) = self.db.get_cf_opt(cf, hash.to_bytes(), &self.read_options)? {
-            let block: Block = bincode::deserialize(&data)
+            let block: Block = format::decode(&data)
                 .map_err(|e| StorageError::SerializationError(e.to_string()))?;
             Ok(block)
         } else {
@@ -143,12 +574,225 @@ impl BlockchainDB {
         }
     }
 
+    /// Hash of the most recently stored block, if any
+    pub fn get_latest_hash(&self) -> Option<Hash> {
+        let cf = self.db.cf_handle(METADATA_CF)?;
+        let data = self.db.get_cf_opt(cf, LATEST_BLOCK_KEY, &self.read_options).ok()??;
+        bincode::deserialize(&data).ok()
+    }
+
+    /// The most recently stored block
+    pub async fn get_latest_block(&self) -> Result<Block, StorageError> {
+        let hash = self.get_latest_hash().ok_or(StorageError::NotFound)?;
+        self.get_block(&hash).await
+    }
+
+    pub async fn get_block_by_height(&self, height: u64) -> Result<Block, StorageError> {
+        let cf = self.db.cf_handle(METADATA_CF)
+            .ok_or(StorageError::DatabaseError("Metadata CF not found".to_string()))?;
+
+        let hash = self.db.get_cf_opt(cf, height_key(height), &self.read_options)?
+            .ok_or(StorageError::NotFound)?;
+        let hash = bincode::deserialize(&hash)
+            .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+        self.get_block(&hash).await
+    }
+
+    /// Record the height of a block and, if it is on the best chain, index
+    /// the block by that height
+    fn index_block_height(&self, block: &Block, canonical: bool) -> Result<(), StorageError> {
+        let cf = self.db.cf_handle(METADATA_CF)
+            .ok_or(StorageError::DatabaseError("Metadata CF not found".to_string()))?;
+
+        if let Some(height) = self.derive_block_height(block)? {
+            if canonical {
+                let hash = bincode::serialize(&block.hash)
+                    .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+                self.db.put_cf_opt(cf, height_key(height), hash, &self.write_options)?;
+            }
+            self.db.put_cf_opt(cf, block_height_key(&block.hash), height.to_be_bytes(), &self.write_options)?;
+        }
+        Ok(())
+    }
+
+    /// Height of a block: one above its parent's, or 0 for a block on the
+    /// genesis parent. `None` when the parent is unknown.
+    fn derive_block_height(&self, block: &Block) -> Result<Option<u64>, StorageError> {
+        match self.stored_height(&block.header.prev_hash)? {
+            Some(height) => Ok(Some(height + 1)),
+            None if block.header.prev_hash == Hash::new(&[0u8; 32]) => Ok(Some(0)),
+            None => Ok(None),
+        }
+    }
+
+    /// Delete the blocks below `height` and their height index entries,
+    /// except the genesis block, returning how many were removed. Metadata such as the latest block is kept.
+    pub async fn prune_blocks_below(&self, height: u64) -> Result<usize, StorageError> {
+        self.prune_below(height, false)
+    }
+
+    /// Like `prune_blocks_below`, also deleting the transactions of pruned blocks
+    pub async fn prune_blocks_and_transactions_below(&self, height: u64) -> Result<usize, StorageError> {
+        self.prune_below(height, true)
+    }
+
+    fn prune_below(&self, height: u64, prune_transactions: bool) -> Result<usize, StorageError> {
+        let blocks_cf = self.db.cf_handle(BLOCKS_CF)
+            .ok_or(StorageError::DatabaseError("Block CF not found".to_string()))?;
+        let transactions_cf = self.db.cf_handle(TRANSACTIONS_CF)
+            .ok_or(StorageError::DatabaseError("Transaction CF not found".to_string()))?;
+        let metadata_cf = self.db.cf_handle(METADATA_CF)
+            .ok_or(StorageError::DatabaseError("Metadata CF not found".to_string()))?;
+
+        let mut batch = WriteBatch::default();
+        let mut pruned = 0;
+        // Height 0 is the genesis block, which is always kept
+        for h in 1..height {
+            let Some(hash) = self.db.get_cf_opt(metadata_cf, height_key(h), &self.read_options)? else {
+                continue;
+            };
+            let hash: Hash = bincode::deserialize(&hash)
+                .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+            let Some(data) = self.db.get_cf_opt(blocks_cf, hash.to_bytes(), &self.read_options)? else {
+                continue;
+            };
+
+            if prune_transactions {
+                let block: Block = format::decode(&data)
+                    .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+                for tx in &block.transactions {
+                    batch.delete_cf(transactions_cf, tx.hash.to_bytes());
+                }
+            }
+            batch.delete_cf(blocks_cf, hash.to_bytes());
+            batch.delete_cf(metadata_cf, height_key(h));
+            batch.delete_cf(metadata_cf, block_height_key(&hash));
+            pruned += 1;
+        }
+
+        self.db.write_opt(batch, &self.write_options)?;
+        Ok(pruned)
+    }
+
+    /// Store a block with its transactions, height index entry and metadata
+    /// in a single atomic write, so a crash can't leave only part of it stored
+    pub async fn store_block_atomic(&self, block: &Block) -> Result<(), StorageError> {
+        let (batch, reorg) = self.block_batch(block).await?;
+        self.db.write_opt(batch, &self.write_options)?;
+        if let Some(reorg) = reorg {
+            self.notify_reorg(reorg);
+        }
+        Ok(())
+    }
+
+    /// Like `store_block_atomic`, but without waiting for the write to reach
+    /// the disk. The block is readable right away and survives a process
+    /// crash, but a power loss can drop the blocks written since the last
+    /// sync. Writes are synced every `with_write_buffer` blocks, by `flush`,
+    /// or by a task from `spawn_flusher`.
+    pub async fn store_block_buffered(&self, block: &Block) -> Result<(), StorageError> {
+        let (batch, reorg) = self.block_batch(block).await?;
+        self.db.write_opt(batch, &self.buffered_write_options)?;
+        if let Some(reorg) = reorg {
+            self.notify_reorg(reorg);
+        }
+
+        if self.unsynced_blocks.fetch_add(1, Ordering::SeqCst) + 1 >= self.write_buffer_blocks {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Sync every buffered write to disk. Blocks stored before this returns
+    /// are durable.
+    pub fn flush(&self) -> Result<(), StorageError> {
+        let pending = self.unsynced_blocks.swap(0, Ordering::SeqCst);
+        if pending == 0 {
+            return Ok(());
+        }
+        if let Err(e) = self.db.flush_wal(true) {
+            // Still unsynced, so the next flush tries again
+            self.unsynced_blocks.fetch_add(pending, Ordering::SeqCst);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Number of blocks written since the last sync
+    pub fn unsynced_blocks(&self) -> usize {
+        self.unsynced_blocks.load(Ordering::SeqCst)
+    }
+
+    /// Spawn a background task flushing buffered writes every `interval`,
+    /// bounding how long a block stays unsynced
+    pub fn spawn_flusher(db: Arc<BlockchainDB>, interval: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                if let Err(e) = db.flush() {
+                    warn!("Failed to sync buffered block writes: {:?}", e);
+                }
+            }
+        })
+    }
+
+    /// The atomic write storing `block`, and the reorg storing it causes.
+    /// Side blocks are written without moving the tip.
+    async fn block_batch(&self, block: &Block) -> Result<(WriteBatch, Option<Reorg>), StorageError> {
+        let blocks_cf = self.db.cf_handle(BLOCKS_CF)
+            .ok_or(StorageError::DatabaseError("Block CF not found".to_string()))?;
+        let transactions_cf = self.db.cf_handle(TRANSACTIONS_CF)
+            .ok_or(StorageError::DatabaseError("Transaction CF not found".to_string()))?;
+        let metadata_cf = self.db.cf_handle(METADATA_CF)
+            .ok_or(StorageError::DatabaseError("Metadata CF not found".to_string()))?;
+
+        let mut batch = WriteBatch::default();
+        let change = self.tip_change(block).await?;
+        let side = matches!(change, TipChange::Side);
+
+        let value = format::encode(block)
+            .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+        batch.put_cf(blocks_cf, block.hash.to_bytes(), value);
+
+        for tx in &block.transactions {
+            let value = format::encode(tx)
+                .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+            batch.put_cf(transactions_cf, tx.hash.to_bytes(), value);
+        }
+
+        if let Some(height) = self.derive_block_height(block)? {
+            if !side {
+                let hash = bincode::serialize(&block.hash)
+                    .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+                batch.put_cf(metadata_cf, height_key(height), hash);
+            }
+            batch.put_cf(metadata_cf, block_height_key(&block.hash), height.to_be_bytes());
+        }
+        if side {
+            return Ok((batch, None));
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        batch.put_cf(metadata_cf, LATEST_BLOCK_KEY, block.hash.to_bytes());
+        batch.put_cf(metadata_cf, b"last_update", timestamp.to_string().as_bytes());
+        let reorg = change.into_reorg();
+        if let Some(reorg) = &reorg {
+            self.reindex_reorg(&mut batch, reorg)?;
+        }
+
+        Ok((batch, reorg))
+    }
+
     pub async fn store_transaction(&self, tx: &Transaction) -> Result<(), StorageError> {
         let cf = self.db.cf_handle(TRANSACTIONS_CF)
             .ok_or(StorageError::DatabaseError("Transaction CF not found".to_string()))?;
         
         let key = tx.hash.to_bytes();
-        let value = bincode::serialize(tx)
+        let value = format::encode(tx)
             .map_err(|e| StorageError::SerializationError(e.to_string()))?;
         
         self.db.put_cf_opt(cf, key, value, &self.write_options)?;
@@ -160,7 +804,7 @@ impl BlockchainDB {
             .ok_or(StorageError::DatabaseError("Transaction CF not found".to_string()))?;
         
         if let Some(data) = self.db.get_cf_opt(cf, hash.to_bytes(), &self.read_options)? {
-            let tx: Transaction = bincode::deserialize(&data)
+            let tx: Transaction = format::decode(&data)
                 .map_err(|e| StorageError::SerializationError(e.to_string()))?;
             Ok(tx)
         } else {
@@ -168,9 +812,93 @@ impl BlockchainDB {
         }
     }
 
+    pub async fn add_utxo(&self, outpoint: (Hash, u32), output: &TransactionOutput) -> Result<(), StorageError> {
+        let cf = self.db.cf_handle(UTXOS_CF)
+            .ok_or(StorageError::DatabaseError("UTXO CF not found".to_string()))?;
+
+        let value = format::encode(output)
+            .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+
+        self.db.put_cf_opt(cf, utxo_key(&outpoint), value, &self.write_options)?;
+        Ok(())
+    }
+
+    /// The unspent output at `outpoint`, or `None` if it was spent or never existed
+    pub async fn get_utxo(&self, outpoint: (Hash, u32)) -> Result<Option<TransactionOutput>, StorageError> {
+        let cf = self.db.cf_handle(UTXOS_CF)
+            .ok_or(StorageError::DatabaseError("UTXO CF not found".to_string()))?;
+
+        self.db.get_cf_opt(cf, utxo_key(&outpoint), &self.read_options)?
+            .map(|data| format::decode(&data)
+                .map_err(|e| StorageError::SerializationError(e.to_string())))
+            .transpose()
+    }
+
+    /// Remove the output at `outpoint` from the unspent set
+    pub async fn spend_utxo(&self, outpoint: (Hash, u32)) -> Result<(), StorageError> {
+        let cf = self.db.cf_handle(UTXOS_CF)
+            .ok_or(StorageError::DatabaseError("UTXO CF not found".to_string()))?;
+
+        self.db.delete_cf_opt(cf, utxo_key(&outpoint), &self.write_options)?;
+        Ok(())
+    }
+
+    /// Store a version of a contract, so a restarted node can rehydrate its registry
+    pub async fn store_contract(&self, addr: &[u8; 32], version: &ContractVersion) -> Result<(), StorageError> {
+        let cf = self.db.cf_handle(CONTRACT_VERSIONS_CF)
+            .ok_or(StorageError::DatabaseError("Contract versions CF not found".to_string()))?;
+
+        let value = format::encode(version)
+            .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+
+        self.db.put_cf_opt(cf, contract_version_key(addr, &version.metadata.version), value, &self.write_options)?;
+        Ok(())
+    }
+
+    pub async fn get_contract(&self, addr: &[u8; 32], version: &str) -> Result<ContractVersion, StorageError> {
+        let cf = self.db.cf_handle(CONTRACT_VERSIONS_CF)
+            .ok_or(StorageError::DatabaseError("Contract versions CF not found".to_string()))?;
+
+        if let Some(data) = self.db.get_cf_opt(cf, contract_version_key(addr, version), &self.read_options)? {
+            format::decode(&data)
+                .map_err(|e| StorageError::SerializationError(e.to_string()))
+        } else {
+            Err(StorageError::NotFound)
+        }
+    }
+
+    /// Store the contract call receipts of a block
+    pub async fn store_block_receipt(&self, receipt: &BlockReceipt) -> Result<(), StorageError> {
+        let cf = self.db.cf_handle(RECEIPTS_CF)
+            .ok_or(StorageError::DatabaseError("Receipts CF not found".to_string()))?;
+
+        let value = format::encode(receipt)
+            .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+
+        self.db.put_cf_opt(cf, receipt.block_hash.to_bytes(), value, &self.write_options)?;
+        Ok(())
+    }
+
+    pub async fn get_block_receipt(&self, hash: &Hash) -> Result<BlockReceipt, StorageError> {
+        let cf = self.db.cf_handle(RECEIPTS_CF)
+            .ok_or(StorageError::DatabaseError("Receipts CF not found".to_string()))?;
+
+        if let Some(data) = self.db.get_cf_opt(cf, hash.to_bytes(), &self.read_options)? {
+            format::decode(&data)
+                .map_err(|e| StorageError::SerializationError(e.to_string()))
+        } else {
+            Err(StorageError::NotFound)
+        }
+    }
+
+    /// Total gas used by the contract calls of a block
+    pub async fn block_gas_used(&self, hash: &Hash) -> Result<u64, StorageError> {
+        Ok(self.get_block_receipt(hash).await?.gas_used)
+    }
+
     pub async fn optimize_storage(&mut self) -> Result<(), StorageError> {
         // Trigger compaction for all column families
-        for cf_name in &[BLOCKS_CF, TRANSACTIONS_CF, UTXOS_CF, STATE_CF, METADATA_CF, CONTRACT_CF] {
+        for cf_name in &[BLOCKS_CF, TRANSACTIONS_CF, UTXOS_CF, STATE_CF, METADATA_CF, CONTRACT_CF, CONTRACT_VERSIONS_CF, RECEIPTS_CF, KV_CF] {
             if let Some(cf) = self.db.cf_handle(cf_name) {
                 let mut compact_opts = CompactOptions::default();
                 compact_opts.set_exclusive_manual_compaction(true);
@@ -209,7 +937,7 @@ impl BlockchainDB {
         // Store latest block hash
         self.db.put_cf_opt(
             cf,
-            b"latest_block",
+            LATEST_BLOCK_KEY,
             latest_block_hash.to_bytes(),
             &self.write_options
         )?;
@@ -225,11 +953,115 @@ impl BlockchainDB {
         Ok(())
     }
 
-    pub async fn get_storage_stats(&self) -> Result<String, StorageError> {
-        let mut stats = String::new();
-        
+    /// Write the UTXO set, contract states and balances to `dest`, all read
+    /// from one database snapshot so the dump is consistent even while
+    /// blocks are being stored
+    pub async fn export_chain_state(&self, dest: &Path) -> Result<(), StorageError> {
+        let snapshot = self.db.snapshot();
+
+        let mut column_families = Vec::with_capacity(CHAIN_STATE_CFS.len());
+        for cf_name in CHAIN_STATE_CFS {
+            let cf = self.db.cf_handle(cf_name)
+                .ok_or(StorageError::DatabaseError(format!("{} CF not found", cf_name)))?;
+            let entries = snapshot.iterator_cf(cf, IteratorMode::Start)
+                .map(|item| item.map(|(key, value)| (key.to_vec(), value.to_vec())))
+                .collect::<Result<Vec<_>, _>>()?;
+            column_families.push((cf_name.to_string(), entries));
+        }
+
+        let mut balances: BTreeMap<Vec<u8>, u64> = BTreeMap::new();
+        for (_, value) in &column_families[0].1 {
+            let output: TransactionOutput = format::decode(value)
+                .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+            let balance = balances.entry(output.recipient).or_default();
+            // Outputs summing past u64::MAX can only come from corrupt data
+            *balance = balance.checked_add(output.amount).ok_or(StorageError::InvalidData)?;
+        }
+
+        let metadata_cf = self.db.cf_handle(METADATA_CF)
+            .ok_or(StorageError::DatabaseError("Metadata CF not found".to_string()))?;
+        let latest_block = snapshot.get_cf(metadata_cf, LATEST_BLOCK_KEY)?
+            .and_then(|data| bincode::deserialize(&data).ok());
+
+        let export = ChainStateExport {
+            latest_block,
+            column_families,
+            balances: balances.into_iter().collect(),
+        };
+        let data = bincode::serialize(&export)
+            .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+
+        // Write to a temporary file first so a crash never leaves a partial file
+        let tmp_path = dest.with_extension("tmp");
+        tokio::fs::write(&tmp_path, data).await
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        tokio::fs::rename(&tmp_path, dest).await
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))
+    }
+
+    /// Replace the chain state with a dump written by `export_chain_state`,
+    /// in a single atomic write
+    pub async fn import_chain_state(&self, src: &Path) -> Result<(), StorageError> {
+        let data = tokio::fs::read(src).await
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        let export: ChainStateExport = bincode::deserialize(&data)
+            .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+
+        // Every chain state CF is cleared, including any a dump written
+        // before it existed does not list
+        let mut batch = WriteBatch::default();
+        for cf_name in CHAIN_STATE_CFS {
+            let cf = self.db.cf_handle(cf_name)
+                .ok_or(StorageError::DatabaseError(format!("{} CF not found", cf_name)))?;
+            for item in self.db.iterator_cf(cf, IteratorMode::Start) {
+                let (key, _) = item?;
+                batch.delete_cf(cf, key);
+            }
+        }
+        for (cf_name, entries) in &export.column_families {
+            if !CHAIN_STATE_CFS.contains(&cf_name.as_str()) {
+                return Err(StorageError::InvalidData);
+            }
+            let cf = self.db.cf_handle(cf_name)
+                .ok_or(StorageError::DatabaseError(format!("{} CF not found", cf_name)))?;
+            for (key, value) in entries {
+                batch.put_cf(cf, key, value);
+            }
+        }
+
+        self.db.write_opt(batch, &self.write_options)?;
+        self.migrate_contract_versions()
+    }
+
+    /// Hash committing to every entry of the chain state: the UTXO set,
+    /// contract versions, states and their snapshots
+    pub fn state_root(&self) -> Result<Hash, StorageError> {
+        let snapshot = self.db.snapshot();
+
+        // Entries are hashed as they are read rather than collected first
+        let mut hasher = blake3::Hasher::new();
+        for cf_name in CHAIN_STATE_CFS {
+            let cf = self.db.cf_handle(cf_name)
+                .ok_or(StorageError::DatabaseError(format!("{} CF not found", cf_name)))?;
+            hasher.update(cf_name.as_bytes());
+            for item in snapshot.iterator_cf(cf, IteratorMode::Start) {
+                let (key, value) = item?;
+                // Length prefixes keep entry boundaries unambiguous
+                hasher.update(&(key.len() as u64).to_le_bytes());
+                hasher.update(&key);
+                hasher.update(&(value.len() as u64).to_le_bytes());
+                hasher.update(&value);
+            }
+        }
+
+        Ok(Hash::from_bytes(*hasher.finalize().as_bytes()))
+    }
+
+    pub async fn get_storage_stats(&self) -> Result<String, StorageError> {
+        let mut stats = String::new();
+        
         // Get statistics for each column family
-        for cf_name in &[BLOCKS_CF, TRANSACTIONS_CF, UTXOS_CF, STATE_CF, METADATA_CF, CONTRACT_CF] {
+        for cf_name in &[BLOCKS_CF, TRANSACTIONS_CF, UTXOS_CF, STATE_CF, METADATA_CF, CONTRACT_CF, CONTRACT_VERSIONS_CF, RECEIPTS_CF, KV_CF] {
             if let Some(cf) = self.db.cf_handle(cf_name) {
                 let cf_stats = self.db.property_value_cf(cf, "rocksdb.stats")?
                     .ok_or(StorageError::DatabaseError("Could not get CF stats".to_string()))?;
@@ -241,6 +1073,316 @@ impl BlockchainDB {
     }
 }
 
+impl BlockchainDB {
+    /// Every entry of a column family whose key starts with `prefix`, in key order
+    fn scan_cf_prefix(&self, cf_name: &str, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, StorageError> {
+        let cf = self.db.cf_handle(cf_name)
+            .ok_or(StorageError::DatabaseError(format!("{} CF not found", cf_name)))?;
+
+        let mut entries = Vec::new();
+        for item in self.db.iterator_cf(cf, IteratorMode::From(prefix, Direction::Forward)) {
+            let (key, value) = item?;
+            if !key.starts_with(prefix) {
+                break;
+            }
+            entries.push((key.to_vec(), value.to_vec()));
+        }
+        Ok(entries)
+    }
+
+    /// Apply writes to the state CF in one batch
+    fn write_state_ops(&self, ops: Vec<BatchOp>) -> Result<(), StorageError> {
+        let cf = self.db.cf_handle(STATE_CF)
+            .ok_or(StorageError::DatabaseError("State CF not found".to_string()))?;
+
+        let mut batch = WriteBatch::default();
+        for op in ops {
+            match op {
+                BatchOp::Set(key, value) => batch.put_cf(cf, key, value),
+                BatchOp::Delete(key) => batch.delete_cf(cf, key),
+            }
+        }
+        self.db.write_opt(batch, &self.write_options)?;
+        Ok(())
+    }
+
+    /// Move state stored whole under the contract address and snapshot
+    /// histories stored whole in the contracts CF to one key per entry and
+    /// per snapshot in the state CF
+    fn migrate_legacy_state(&self) -> Result<(), StorageError> {
+        let state_cf = self.db.cf_handle(STATE_CF)
+            .ok_or(StorageError::DatabaseError("State CF not found".to_string()))?;
+        let contract_cf = self.db.cf_handle(CONTRACT_CF)
+            .ok_or(StorageError::DatabaseError("Contract CF not found".to_string()))?;
+
+        let mut legacy: BTreeMap<[u8; 32], (Option<HashMap<Vec<u8>, Vec<u8>>>, Option<Vec<StateSnapshot>>)> = BTreeMap::new();
+        for item in self.db.iterator_cf(state_cf, IteratorMode::Start) {
+            let (key, value) = item?;
+            if let Ok(addr) = <[u8; 32]>::try_from(&key[..]) {
+                let state = format::decode(&value)
+                    .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+                legacy.entry(addr).or_default().0 = Some(state);
+            }
+        }
+        for item in self.db.iterator_cf(contract_cf, IteratorMode::Start) {
+            let (key, value) = item?;
+            if is_legacy_snapshots_key(&key) {
+                let mut addr = [0u8; 32];
+                addr.copy_from_slice(&key[..32]);
+                let snapshots = format::decode(&value)
+                    .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+                legacy.entry(addr).or_default().1 = Some(snapshots);
+            }
+        }
+        if legacy.is_empty() {
+            return Ok(());
+        }
+
+        let mut batch = WriteBatch::default();
+        for (addr, (state, snapshots)) in legacy {
+            if state.is_some() {
+                batch.delete_cf(state_cf, addr);
+            }
+            if snapshots.is_some() {
+                batch.delete_cf(contract_cf, [addr.as_slice(), SNAPSHOTS_KEY_SUFFIX].concat());
+            }
+            for op in legacy_migration_ops(&[], &addr, state, snapshots)? {
+                if let BatchOp::Set(key, value) = op {
+                    batch.put_cf(state_cf, key, value);
+                }
+            }
+        }
+        self.db.write_opt(batch, &self.write_options)?;
+        Ok(())
+    }
+
+    /// Move contract versions older nodes kept in the contracts CF, next to
+    /// snapshot histories, to their own CF. The histories are left for
+    /// `migrate_legacy_state`.
+    fn migrate_contract_versions(&self) -> Result<(), StorageError> {
+        let contract_cf = self.db.cf_handle(CONTRACT_CF)
+            .ok_or(StorageError::DatabaseError("Contract CF not found".to_string()))?;
+        let versions_cf = self.db.cf_handle(CONTRACT_VERSIONS_CF)
+            .ok_or(StorageError::DatabaseError("Contract versions CF not found".to_string()))?;
+
+        let mut batch = WriteBatch::default();
+        for item in self.db.iterator_cf(contract_cf, IteratorMode::Start) {
+            let (key, value) = item?;
+            if !is_legacy_snapshots_key(&key) {
+                batch.put_cf(versions_cf, &key, value);
+                batch.delete_cf(contract_cf, key);
+            }
+        }
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.db.write_opt(batch, &self.write_options)?;
+        Ok(())
+    }
+}
+
+impl KeyValueStore for BlockchainDB {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError> {
+        let cf = self.db.cf_handle(KV_CF)
+            .ok_or(StorageError::DatabaseError("KV CF not found".to_string()))?;
+
+        Ok(self.db.get_cf_opt(cf, key, &self.read_options)?)
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) -> Result<(), StorageError> {
+        let cf = self.db.cf_handle(KV_CF)
+            .ok_or(StorageError::DatabaseError("KV CF not found".to_string()))?;
+
+        self.db.put_cf_opt(cf, key, value, &self.write_options)?;
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), StorageError> {
+        let cf = self.db.cf_handle(KV_CF)
+            .ok_or(StorageError::DatabaseError("KV CF not found".to_string()))?;
+
+        self.db.delete_cf_opt(cf, key, &self.write_options)?;
+        Ok(())
+    }
+
+    fn batch(&mut self, ops: Vec<BatchOp>) -> Result<(), StorageError> {
+        let cf = self.db.cf_handle(KV_CF)
+            .ok_or(StorageError::DatabaseError("KV CF not found".to_string()))?;
+
+        let mut batch = WriteBatch::default();
+        for op in ops {
+            match op {
+                BatchOp::Set(key, value) => batch.put_cf(cf, key, value),
+                BatchOp::Delete(key) => batch.delete_cf(cf, key),
+            }
+        }
+        self.db.write_opt(batch, &self.write_options)?;
+        Ok(())
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, StorageError> {
+        self.scan_cf_prefix(KV_CF, prefix)
+    }
+}
+
+/// Persists contract state and snapshots into any `KeyValueStore`
+pub struct KvStateStore<S> {
+    store: Mutex<S>,
+}
+
+impl<S: KeyValueStore> KvStateStore<S> {
+    pub fn new(store: S) -> Self {
+        KvStateStore { store: Mutex::new(store) }
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, S>, StorageError> {
+        self.store.lock().map_err(|e| StorageError::DatabaseError(e.to_string()))
+    }
+
+    /// Entries stored under the contract state prefix, after moving any
+    /// contracts stored whole the old way to one key per entry and snapshot
+    fn entries(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>, StorageError> {
+        let mut store = self.lock()?;
+        if let Some(index) = store.get(KV_LEGACY_CONTRACTS_KEY)? {
+            let contracts: Vec<[u8; 32]> = format::decode(&index)
+                .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+
+            let mut ops = vec![BatchOp::Delete(KV_LEGACY_CONTRACTS_KEY.to_vec())];
+            for addr in contracts {
+                let state_key = [KV_LEGACY_STATE_PREFIX, addr.as_slice()].concat();
+                let snapshots_key = [KV_LEGACY_SNAPSHOTS_PREFIX, addr.as_slice()].concat();
+                let state = store.get(&state_key)?.map(|data| format::decode(&data)).transpose()
+                    .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+                let snapshots = store.get(&snapshots_key)?.map(|data| format::decode(&data)).transpose()
+                    .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+                ops.extend(legacy_migration_ops(KV_CONTRACT_STATE_PREFIX, &addr, state, snapshots)?);
+                ops.push(BatchOp::Delete(state_key));
+                ops.push(BatchOp::Delete(snapshots_key));
+            }
+            store.batch(ops)?;
+        }
+        store.scan_prefix(KV_CONTRACT_STATE_PREFIX)
+    }
+
+    fn contract_prefix(contract_addr: &[u8; 32]) -> Vec<u8> {
+        [KV_CONTRACT_STATE_PREFIX, contract_addr.as_slice()].concat()
+    }
+}
+
+impl<S: KeyValueStore + Send> StateStore for KvStateStore<S> {
+    fn load_states(&self) -> Result<HashMap<[u8; 32], HashMap<Vec<u8>, Vec<u8>>>, StorageError> {
+        Ok(decode_states(KV_CONTRACT_STATE_PREFIX, &self.entries()?))
+    }
+
+    fn load_snapshots(&self) -> Result<HashMap<[u8; 32], Vec<(u64, StateSnapshot)>>, StorageError> {
+        decode_snapshots(KV_CONTRACT_STATE_PREFIX, &self.entries()?)
+    }
+
+    fn save_state_changes(&self, contract_addr: &[u8; 32], diff: &StateDiff) -> Result<(), StorageError> {
+        self.lock()?.batch(state_change_ops(KV_CONTRACT_STATE_PREFIX, contract_addr, diff))
+    }
+
+    fn save_snapshot(&self, contract_addr: &[u8; 32], seq: u64, snapshot: &StateSnapshot) -> Result<(), StorageError> {
+        let value = format::encode(snapshot)
+            .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+        self.lock()?.set(&snapshot_key(KV_CONTRACT_STATE_PREFIX, contract_addr, seq), &value)
+    }
+
+    fn remove_snapshots(&self, contract_addr: &[u8; 32], seqs: &[u64]) -> Result<(), StorageError> {
+        let ops = seqs.iter()
+            .map(|seq| BatchOp::Delete(snapshot_key(KV_CONTRACT_STATE_PREFIX, contract_addr, *seq)))
+            .collect();
+        self.lock()?.batch(ops)
+    }
+
+    fn remove_state(&self, contract_addr: &[u8; 32]) -> Result<(), StorageError> {
+        let mut store = self.lock()?;
+        let prefix = [Self::contract_prefix(contract_addr).as_slice(), &[STATE_ENTRY_TAG]].concat();
+        let ops = store.scan_prefix(&prefix)?.into_iter()
+            .map(|(key, _)| BatchOp::Delete(key))
+            .collect();
+        store.batch(ops)
+    }
+
+    fn remove_contract(&self, contract_addr: &[u8; 32]) -> Result<(), StorageError> {
+        let mut store = self.lock()?;
+        let ops = store.scan_prefix(&Self::contract_prefix(contract_addr))?.into_iter()
+            .map(|(key, _)| BatchOp::Delete(key))
+            .collect();
+        store.batch(ops)
+    }
+}
+
+impl StateStore for BlockchainDB {
+    fn load_states(&self) -> Result<HashMap<[u8; 32], HashMap<Vec<u8>, Vec<u8>>>, StorageError> {
+        self.migrate_legacy_state()?;
+        Ok(decode_states(&[], &self.scan_cf_prefix(STATE_CF, &[])?))
+    }
+
+    fn load_snapshots(&self) -> Result<HashMap<[u8; 32], Vec<(u64, StateSnapshot)>>, StorageError> {
+        self.migrate_legacy_state()?;
+        decode_snapshots(&[], &self.scan_cf_prefix(STATE_CF, &[])?)
+    }
+
+    fn save_state_changes(&self, contract_addr: &[u8; 32], diff: &StateDiff) -> Result<(), StorageError> {
+        self.write_state_ops(state_change_ops(&[], contract_addr, diff))
+    }
+
+    fn save_snapshot(&self, contract_addr: &[u8; 32], seq: u64, snapshot: &StateSnapshot) -> Result<(), StorageError> {
+        let cf = self.db.cf_handle(STATE_CF)
+            .ok_or(StorageError::DatabaseError("State CF not found".to_string()))?;
+
+        let value = format::encode(snapshot)
+            .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+
+        self.db.put_cf_opt(cf, snapshot_key(&[], contract_addr, seq), value, &self.write_options)?;
+        Ok(())
+    }
+
+    fn remove_snapshots(&self, contract_addr: &[u8; 32], seqs: &[u64]) -> Result<(), StorageError> {
+        self.write_state_ops(seqs.iter()
+            .map(|seq| BatchOp::Delete(snapshot_key(&[], contract_addr, *seq)))
+            .collect())
+    }
+
+    fn remove_state(&self, contract_addr: &[u8; 32]) -> Result<(), StorageError> {
+        let prefix = [contract_addr.as_slice(), &[STATE_ENTRY_TAG]].concat();
+        self.write_state_ops(self.scan_cf_prefix(STATE_CF, &prefix)?.into_iter()
+            .map(|(key, _)| BatchOp::Delete(key))
+            .collect())
+    }
+
+    fn remove_contract(&self, contract_addr: &[u8; 32]) -> Result<(), StorageError> {
+        self.write_state_ops(self.scan_cf_prefix(STATE_CF, contract_addr)?.into_iter()
+            .map(|(key, _)| BatchOp::Delete(key))
+            .collect())
+    }
+}
+
+impl PeerStore for BlockchainDB {
+    fn load_peer_reputation(&self) -> Result<PeerReputation, StorageError> {
+        let cf = self.db.cf_handle(METADATA_CF)
+            .ok_or(StorageError::DatabaseError("Metadata CF not found".to_string()))?;
+
+        match self.db.get_cf_opt(cf, PEER_REPUTATION_KEY, &self.read_options)? {
+            Some(data) => format::decode(&data)
+                .map_err(|e| StorageError::SerializationError(e.to_string())),
+            None => Ok(PeerReputation::default()),
+        }
+    }
+
+    fn save_peer_reputation(&self, reputation: &PeerReputation) -> Result<(), StorageError> {
+        let cf = self.db.cf_handle(METADATA_CF)
+            .ok_or(StorageError::DatabaseError("Metadata CF not found".to_string()))?;
+
+        let value = format::encode(reputation)
+            .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+
+        self.db.put_cf_opt(cf, PEER_REPUTATION_KEY, value, &self.write_options)?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -261,6 +1403,8 @@ mod tests {
                 merkle_root: Hash::new(b"merkle root"),
                 difficulty: 1,
                 nonce: 0,
+                proposer: [0u8; 32],
+                proposer_signature: None,
             },
             transactions: vec![],
             hash: Hash::new(b"block hash"),
@@ -282,6 +1426,538 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_genesis_initialized_once() -> Result<(), StorageError> {
+        let temp_dir = tempdir().map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        let genesis = Block::genesis();
+
+        {
+            let db = BlockchainDB::new(temp_dir.path())?;
+            assert!(!db.is_initialized()?);
+            db.initialize_genesis(&genesis).await?;
+            assert!(db.is_initialized()?);
+        }
+
+        // The genesis is remembered across restarts
+        let db = BlockchainDB::new(temp_dir.path())?;
+        assert!(db.is_initialized()?);
+        assert_eq!(db.get_block(&genesis.hash).await?.hash, genesis.hash);
+
+        // Initializing with the same genesis is a no-op, a different one is refused
+        db.initialize_genesis(&genesis).await?;
+        let conflicting = Block::genesis_with_params(&crate::params::ChainParams {
+            genesis_timestamp: 1700000000,
+            ..crate::params::ChainParams::mainnet()
+        });
+        assert!(matches!(
+            db.initialize_genesis(&conflicting).await,
+            Err(StorageError::AlreadyInitialized(_))
+        ));
+        assert!(db.get_block(&conflicting.hash).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_block_by_height() -> Result<(), StorageError> {
+        let temp_dir = tempdir().map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        let db = BlockchainDB::new(temp_dir.path())?;
+
+        let genesis = Block::genesis();
+        let first = Block::new(1, genesis.hash.clone(), vec![], 1);
+        let second = Block::new(1, first.hash.clone(), vec![], 1);
+        for block in [&genesis, &first, &second] {
+            db.store_block(block).await?;
+        }
+
+        for (height, block) in [&genesis, &first, &second].into_iter().enumerate() {
+            assert_eq!(db.get_block_by_height(height as u64).await?.hash, block.hash);
+        }
+        assert!(matches!(db.get_block_by_height(3).await, Err(StorageError::NotFound)));
+
+        // A block whose parent is unknown is stored but not indexed
+        let orphan = Block::new(1, Hash::new(b"unknown parent"), vec![], 1);
+        db.store_block(&orphan).await?;
+        assert!(matches!(db.get_block_by_height(3).await, Err(StorageError::NotFound)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reorg_event() -> Result<(), StorageError> {
+        let temp_dir = tempdir().map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        let db = BlockchainDB::new(temp_dir.path())?;
+
+        let genesis = Block::genesis();
+        let a1 = Block::new(1, genesis.hash.clone(), vec![], 1);
+        let a2 = Block::new(1, a1.hash.clone(), vec![], 1);
+        let a3 = Block::new(1, a2.hash.clone(), vec![], 1);
+        for block in [&genesis, &a1, &a2, &a3] {
+            db.store_block(block).await?;
+        }
+
+        // Extending the tip is not a reorg
+        let mut events = db.subscribe();
+        let a4 = Block::new(1, a3.hash.clone(), vec![], 1);
+        db.store_block(&a4).await?;
+        assert!(events.try_recv().is_err());
+
+        // A block on a1 with less work than a2..a4 is kept on the side
+        let b2 = Block::new(2, a1.hash.clone(), vec![], 1);
+        db.store_block_atomic(&b2).await?;
+        assert!(events.try_recv().is_err());
+        assert_eq!(db.get_latest_hash(), Some(a4.hash.clone()));
+        assert_eq!(db.get_block_by_height(2).await?.hash, a2.hash);
+        assert_eq!(db.get_block_by_height(4).await?.hash, a4.hash);
+        assert_eq!(db.get_block(&b2.hash).await?.hash, b2.hash);
+
+        // Once the side branch has more work it displaces a2..a4
+        let b3 = Block::new(2, b2.hash.clone(), vec![], 3);
+        db.store_block(&b3).await?;
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ChainEvent::Reorg {
+                removed: vec![a2.hash.clone(), a3.hash.clone(), a4.hash.clone()],
+                added: vec![b2.hash.clone(), b3.hash.clone()],
+            }
+        );
+        assert_eq!(db.get_latest_hash(), Some(b3.hash.clone()));
+        assert_eq!(db.get_block_by_height(2).await?.hash, b2.hash);
+        assert_eq!(db.get_block_by_height(3).await?.hash, b3.hash);
+        assert!(matches!(db.get_block_by_height(4).await, Err(StorageError::NotFound)));
+
+        // Switching back to a4 adds the whole old branch again
+        let a5 = Block::new(1, a4.hash.clone(), vec![], 2);
+        db.store_block(&a5).await?;
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ChainEvent::Reorg {
+                removed: vec![b2.hash.clone(), b3.hash.clone()],
+                added: vec![a2.hash.clone(), a3.hash.clone(), a4.hash.clone(), a5.hash.clone()],
+            }
+        );
+        assert_eq!(db.get_block_by_height(2).await?.hash, a2.hash);
+        assert_eq!(db.get_block_by_height(5).await?.hash, a5.hash);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_latest_block() -> Result<(), StorageError> {
+        let temp_dir = tempdir().map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        let db = BlockchainDB::new(temp_dir.path())?;
+
+        assert!(db.get_latest_hash().is_none());
+        assert!(matches!(db.get_latest_block().await, Err(StorageError::NotFound)));
+
+        let genesis = Block::genesis();
+        let next = Block::new(1, genesis.hash.clone(), vec![], 1);
+        db.store_block(&genesis).await?;
+        db.store_block(&next).await?;
+
+        assert_eq!(db.get_latest_hash(), Some(next.hash.clone()));
+        assert_eq!(db.get_latest_block().await?.hash, next.hash);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_utxo_set() -> Result<(), StorageError> {
+        let temp_dir = tempdir().map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        let db = BlockchainDB::new(temp_dir.path())?;
+
+        let tx_hash = Hash::new(b"funding transaction");
+        let kept = (tx_hash.clone(), 0);
+        let spent = (tx_hash.clone(), 1);
+        db.add_utxo(kept.clone(), &TransactionOutput { amount: 50, recipient: vec![2u8; 32] }).await?;
+        db.add_utxo(spent.clone(), &TransactionOutput { amount: 25, recipient: vec![3u8; 32] }).await?;
+
+        db.spend_utxo(spent.clone()).await?;
+
+        assert!(db.get_utxo(spent).await?.is_none());
+        let output = db.get_utxo(kept).await?.expect("unspent output should remain");
+        assert_eq!(output.amount, 50);
+        assert_eq!(output.recipient, vec![2u8; 32]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_and_import_chain_state() -> Result<(), StorageError> {
+        let source_dir = tempdir().map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        let target_dir = tempdir().map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        let export_dir = tempdir().map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        let source = BlockchainDB::new(source_dir.path())?;
+        let target = BlockchainDB::new(target_dir.path())?;
+
+        let genesis = Block::genesis();
+        source.store_block(&genesis).await?;
+        let tx_hash = Hash::new(b"funding transaction");
+        for (index, recipient) in [(0, 2u8), (1, 3u8), (2, 2u8)] {
+            let output = TransactionOutput { amount: 10 * (index as u64 + 1), recipient: vec![recipient; 32] };
+            source.add_utxo((tx_hash.clone(), index), &output).await?;
+        }
+        let contract = [7u8; 32];
+        let state: HashMap<Vec<u8>, Vec<u8>> = [(b"balance".to_vec(), vec![42])].into_iter().collect();
+        source.save_state_changes(&contract, &StateDiff {
+            added: state.clone(),
+            modified: HashMap::new(),
+            deleted: HashMap::new(),
+            block_number: 0,
+        })?;
+
+        // Something already in the target is replaced by the import
+        target.add_utxo((Hash::new(b"stale"), 0), &TransactionOutput { amount: 1, recipient: vec![9u8; 32] }).await?;
+        assert_ne!(target.state_root()?, source.state_root()?);
+
+        let path = export_dir.path().join("chain_state.bin");
+        source.export_chain_state(&path).await?;
+        target.import_chain_state(&path).await?;
+
+        assert_eq!(target.state_root()?, source.state_root()?);
+        assert!(target.get_utxo((Hash::new(b"stale"), 0)).await?.is_none());
+        assert_eq!(target.get_utxo((tx_hash.clone(), 2)).await?.map(|output| output.amount), Some(30));
+        assert_eq!(target.load_states()?.get(&contract), Some(&state));
+
+        // The dump carries balances summed over the UTXO set
+        let export: ChainStateExport = bincode::deserialize(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(export.latest_block, Some(genesis.hash.clone()));
+        assert_eq!(export.balances, vec![(vec![2u8; 32], 40), (vec![3u8; 32], 20)]);
+
+        // Balances overflowing u64 fail the export instead of wrapping
+        source.add_utxo((tx_hash.clone(), 3), &TransactionOutput { amount: u64::MAX, recipient: vec![2u8; 32] }).await?;
+        let overflow_path = export_dir.path().join("overflow.bin");
+        assert!(matches!(source.export_chain_state(&overflow_path).await, Err(StorageError::InvalidData)));
+        assert!(!overflow_path.exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_v1_records_migrated_on_read() -> Result<(), StorageError> {
+        let temp_dir = tempdir().map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        let db = BlockchainDB::new(temp_dir.path())?;
+        let contract_addr = [7u8; 32];
+
+        // Write unversioned records, as older nodes did, with the whole
+        // state and snapshot history of a contract under one key each.
+        // Their snapshots predate block numbers; fields in order.
+        let state = HashMap::from([(b"key".to_vec(), b"value".to_vec())]);
+        let state_cf = db.db.cf_handle(STATE_CF).unwrap();
+        db.db.put_cf(state_cf, contract_addr, bincode::serialize(&state).unwrap())?;
+        let snapshots = vec![(contract_addr, "1.0.0".to_string(), 1234567890u64, state.clone(), [3u8; 32], 2u32)];
+        let contracts_cf = db.db.cf_handle(CONTRACT_CF).unwrap();
+        db.db.put_cf(contracts_cf, [contract_addr.as_slice(), SNAPSHOTS_KEY_SUFFIX].concat(), bincode::serialize(&snapshots).unwrap())?;
+
+        let block = Block::genesis();
+        let blocks_cf = db.db.cf_handle(BLOCKS_CF).unwrap();
+        db.db.put_cf(blocks_cf, block.hash.to_bytes(), bincode::serialize(&block).unwrap())?;
+
+        // All are read in the current layout
+        let loaded = db.load_snapshots()?.remove(&contract_addr).unwrap();
+        assert_eq!(loaded.len(), 1);
+        let (seq, snapshot) = &loaded[0];
+        assert_eq!(*seq, 0);
+        assert_eq!(snapshot.version, "1.0.0");
+        assert_eq!(snapshot.state.get(b"key".as_slice()), Some(&b"value".to_vec()));
+        assert_eq!(snapshot.state_hash, [3u8; 32]);
+        assert_eq!(snapshot.schema_version, 2);
+        assert_eq!(snapshot.block_number, 0);
+        assert_eq!(db.load_states()?.get(&contract_addr), Some(&state));
+        assert_eq!(db.get_block(&block.hash).await?.hash, block.hash);
+
+        // Loading moved the state and snapshots to one key each, in the current format
+        assert!(db.db.get_cf(contracts_cf, [contract_addr.as_slice(), SNAPSHOTS_KEY_SUFFIX].concat())?.is_none());
+        assert!(db.db.get_cf(state_cf, contract_addr)?.is_none());
+        let data = db.db.get_cf(state_cf, snapshot_key(&[], &contract_addr, 0))?.unwrap();
+        assert_eq!(format::format_version(&data), Some(format::FORMAT_VERSION));
+        assert_eq!(db.db.get_cf(state_cf, state_entry_key(&[], &contract_addr, b"key"))?, Some(b"value".to_vec()));
+        assert_eq!(db.load_snapshots()?[&contract_addr][0].1.state_hash, [3u8; 32]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_buffered_block_writes() -> Result<(), StorageError> {
+        let temp_dir = tempdir().map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        let mut hashes = Vec::new();
+
+        {
+            let db = BlockchainDB::new(temp_dir.path())?.with_write_buffer(16);
+            let mut block = Block::genesis();
+            db.store_block_buffered(&block).await?;
+            hashes.push(block.hash.clone());
+            for _ in 0..39 {
+                block = Block::new(1, block.hash.clone(), vec![], 1);
+                db.store_block_buffered(&block).await?;
+                hashes.push(block.hash.clone());
+            }
+
+            // Two full buffers were synced; the rest waits for a flush but
+            // can already be read
+            assert_eq!(db.unsynced_blocks(), 8);
+            assert_eq!(db.get_block_by_height(39).await?.hash, hashes[39]);
+
+            db.flush()?;
+            assert_eq!(db.unsynced_blocks(), 0);
+        }
+
+        let db = BlockchainDB::new(temp_dir.path())?;
+        for (height, hash) in hashes.iter().enumerate() {
+            assert_eq!(&db.get_block_by_height(height as u64).await?.hash, hash);
+        }
+        assert_eq!(db.get_latest_hash(), hashes.last().cloned());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_store_block_atomic() -> Result<(), StorageError> {
+        let temp_dir = tempdir().map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        let db = BlockchainDB::new(temp_dir.path())?;
+
+        let genesis = Block::genesis();
+        db.store_block_atomic(&genesis).await?;
+
+        let transactions: Vec<_> = (0..3u8)
+            .map(|i| Transaction {
+                hash: Hash::new(&[i]),
+                ..Transaction::default()
+            })
+            .collect();
+        let block = Block::new(1, genesis.hash.clone(), transactions.clone(), 1);
+        db.store_block_atomic(&block).await?;
+
+        assert_eq!(db.get_block(&block.hash).await?.hash, block.hash);
+        for tx in &transactions {
+            assert_eq!(db.get_transaction(&tx.hash).await?.hash, tx.hash);
+        }
+        assert_eq!(db.get_block_by_height(1).await?.hash, block.hash);
+        assert_eq!(db.get_latest_hash(), Some(block.hash));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_prune_blocks_below() -> Result<(), StorageError> {
+        let temp_dir = tempdir().map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        let db = BlockchainDB::new(temp_dir.path())?;
+
+        let genesis = Block::genesis();
+        db.initialize_genesis(&genesis).await?;
+        let mut blocks = vec![genesis];
+        for i in 1..10u8 {
+            let tx = Transaction {
+                hash: Hash::new(&[i]),
+                ..Transaction::default()
+            };
+            let block = Block::new(1, blocks.last().unwrap().hash.clone(), vec![tx], 1);
+            db.store_block(&block).await?;
+            db.store_transaction(&block.transactions[0]).await?;
+            blocks.push(block);
+        }
+
+        assert_eq!(db.prune_blocks_and_transactions_below(5).await?, 4);
+
+        for block in &blocks[1..5] {
+            assert!(matches!(db.get_block(&block.hash).await, Err(StorageError::NotFound)));
+            assert!(matches!(db.get_transaction(&block.transactions[0].hash).await, Err(StorageError::NotFound)));
+        }
+        for block in &blocks[5..] {
+            assert_eq!(db.get_block(&block.hash).await?.hash, block.hash);
+            assert!(db.get_transaction(&block.transactions[0].hash).await.is_ok());
+        }
+        assert!(matches!(db.get_block_by_height(3).await, Err(StorageError::NotFound)));
+        assert_eq!(db.get_block_by_height(5).await?.hash, blocks[5].hash);
+        // The reverse height lookup goes with the pruned blocks
+        assert_eq!(db.stored_height(&blocks[3].hash)?, None);
+        assert_eq!(db.stored_height(&blocks[5].hash)?, Some(5));
+
+        // The genesis block and metadata are kept
+        assert_eq!(db.get_block_by_height(0).await?.hash, blocks[0].hash);
+        assert!(db.is_initialized()?);
+        assert_eq!(db.get_latest_hash(), Some(blocks[9].hash.clone()));
+
+        // Pruning again finds nothing left to remove
+        assert_eq!(db.prune_blocks_below(5).await?, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_contract_versions() -> Result<(), StorageError> {
+        use crate::contract::{ContractABI, ContractMetadata};
+
+        let temp_dir = tempdir().map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        let db = BlockchainDB::new(temp_dir.path())?;
+        let addr = [5u8; 32];
+
+        let contract_version = |version: &str, bytecode: Vec<u8>| ContractVersion {
+            bytecode,
+            metadata: ContractMetadata {
+                version: version.into(),
+                created_at: 1234567890,
+                updated_at: 1234567890,
+                author: [1u8; 32],
+                description: "Test Contract".into(),
+                is_upgradeable: true,
+                allow_major_upgrade: false,
+            },
+            abi: ContractABI {
+                methods: vec![],
+                events: vec![],
+                standards: vec![],
+            },
+        };
+        db.store_contract(&addr, &contract_version("1.0.0", vec![1, 2, 3])).await?;
+        db.store_contract(&addr, &contract_version("2.0.0", vec![4, 5, 6])).await?;
+
+        let v1 = db.get_contract(&addr, "1.0.0").await?;
+        assert_eq!(v1.metadata.version, "1.0.0");
+        assert_eq!(v1.bytecode, vec![1, 2, 3]);
+        let v2 = db.get_contract(&addr, "2.0.0").await?;
+        assert_eq!(v2.metadata.version, "2.0.0");
+        assert_eq!(v2.bytecode, vec![4, 5, 6]);
+
+        assert!(matches!(db.get_contract(&addr, "3.0.0").await, Err(StorageError::NotFound)));
+        assert!(matches!(db.get_contract(&[6u8; 32], "1.0.0").await, Err(StorageError::NotFound)));
+
+        // Versions have a CF of their own, so any version string is a version
+        db.store_contract(&addr, &contract_version("snapshots", vec![7])).await?;
+        assert_eq!(db.get_contract(&addr, "snapshots").await?.bytecode, vec![7]);
+
+        // Versions older nodes kept next to snapshot histories move on open;
+        // the histories stay until the state is loaded
+        let contracts_cf = db.db.cf_handle(CONTRACT_CF).unwrap();
+        let legacy_key = [addr.as_slice(), b"0.9.0"].concat();
+        db.db.put_cf(contracts_cf, &legacy_key, format::encode(&contract_version("0.9.0", vec![8])).unwrap())?;
+        let history_key = [addr.as_slice(), SNAPSHOTS_KEY_SUFFIX].concat();
+        db.db.put_cf(contracts_cf, &history_key, format::encode(&Vec::<StateSnapshot>::new()).unwrap())?;
+        drop(db);
+
+        let db = BlockchainDB::new(temp_dir.path())?;
+        assert_eq!(db.get_contract(&addr, "0.9.0").await?.bytecode, vec![8]);
+        let contracts_cf = db.db.cf_handle(CONTRACT_CF).unwrap();
+        assert!(db.db.get_cf(contracts_cf, &legacy_key)?.is_none());
+        assert!(db.db.get_cf(contracts_cf, &history_key)?.is_some());
+        db.load_snapshots()?;
+        assert!(db.db.get_cf(contracts_cf, &history_key)?.is_none());
+
+        Ok(())
+    }
+
+    // Runs the same operations against any backend and reports what was read back
+    fn exercise_store<S: KeyValueStore>(store: &mut S) -> Result<Vec<Option<Vec<u8>>>, StorageError> {
+        store.set(b"a", b"1")?;
+        store.set(b"b", b"2")?;
+        store.delete(b"a")?;
+        store.batch(vec![
+            BatchOp::Set(b"c".to_vec(), b"3".to_vec()),
+            BatchOp::Set(b"b".to_vec(), b"4".to_vec()),
+            BatchOp::Delete(b"c".to_vec()),
+        ])?;
+
+        [b"a", b"b", b"c"].iter()
+            .map(|key| store.get(*key))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_key_value_backends_agree() -> Result<(), StorageError> {
+        use crate::contract::StateManager;
+
+        let temp_dir = tempdir().map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        let mut in_memory = Storage::new_in_memory()?;
+        let mut rocksdb = BlockchainDB::new(temp_dir.path())?;
+
+        let expected = vec![None, Some(b"4".to_vec()), None];
+        assert_eq!(exercise_store(&mut in_memory)?, expected);
+        assert_eq!(exercise_store(&mut rocksdb)?, expected);
+
+        // State persisted through either backend reloads the same way
+        let states: Vec<_> = [
+            Arc::new(KvStateStore::new(in_memory)) as Arc<dyn StateStore>,
+            Arc::new(KvStateStore::new(rocksdb)) as Arc<dyn StateStore>,
+        ]
+        .into_iter()
+        .map(|store| {
+            let mut manager = StateManager::with_store(store.clone()).unwrap();
+            manager.update_state([7u8; 32], b"key".to_vec(), b"value".to_vec()).unwrap();
+            manager.create_snapshot([7u8; 32], "1.0.0".to_string()).unwrap();
+
+            let reloaded = StateManager::with_store(store).unwrap();
+            assert_eq!(reloaded.get_snapshots(&[7u8; 32]).unwrap().len(), 1);
+            reloaded.get_state(&[7u8; 32]).cloned()
+        })
+        .collect();
+        assert_eq!(states[0], states[1]);
+        assert_eq!(states[0].as_ref().unwrap().get(&b"key".to_vec()), Some(&b"value".to_vec()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_state_stored_per_entry() -> Result<(), StorageError> {
+        use crate::contract::StateManager;
+
+        let temp_dir = tempdir().map_err(|e| StorageError::DatabaseError(e.to_string()))?;
+        let db = Arc::new(BlockchainDB::new(temp_dir.path())?);
+        let contract_addr = [7u8; 32];
+        let stored_keys = || db.scan_cf_prefix(STATE_CF, &contract_addr).map(|entries| {
+            entries.into_iter().map(|(key, _)| key).collect::<Vec<_>>()
+        });
+
+        let mut manager = StateManager::with_store(db.clone()).unwrap();
+        manager.set_max_snapshots(2);
+        manager.update_state(contract_addr, b"a".to_vec(), b"1".to_vec()).unwrap();
+        manager.update_state(contract_addr, b"b".to_vec(), b"2".to_vec()).unwrap();
+        for _ in 0..3 {
+            manager.create_snapshot(contract_addr, "1.0.0".to_string()).unwrap();
+        }
+        manager.delete_state(contract_addr, b"a").unwrap();
+
+        // One key for the remaining entry and each snapshot within the cap
+        assert_eq!(stored_keys()?, vec![
+            state_entry_key(&[], &contract_addr, b"b"),
+            snapshot_key(&[], &contract_addr, 1),
+            snapshot_key(&[], &contract_addr, 2),
+        ]);
+
+        // New snapshots continue the sequence after a reload
+        let mut reloaded = StateManager::with_store(db.clone()).unwrap();
+        assert_eq!(reloaded.get_state(&contract_addr), manager.get_state(&contract_addr));
+        assert_eq!(reloaded.get_snapshots(&contract_addr).unwrap().len(), 2);
+        reloaded.create_snapshot(contract_addr, "1.0.0".to_string()).unwrap();
+        assert_eq!(stored_keys()?.last(), Some(&snapshot_key(&[], &contract_addr, 3)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_legacy_kv_state_migrated() -> Result<(), StorageError> {
+        let contract_addr = [7u8; 32];
+        let state = HashMap::from([(b"key".to_vec(), b"value".to_vec())]);
+
+        // The whole state under one key, listed in the contracts index
+        let mut storage = Storage::new_in_memory()?;
+        storage.set(KV_LEGACY_CONTRACTS_KEY, &bincode::serialize(&vec![contract_addr]).unwrap())?;
+        storage.set(&[KV_LEGACY_STATE_PREFIX, contract_addr.as_slice()].concat(), &format::encode(&state).unwrap())?;
+
+        let store = KvStateStore::new(storage);
+        assert_eq!(store.load_states()?.get(&contract_addr), Some(&state));
+        assert!(store.load_snapshots()?.is_empty());
+
+        let storage = store.lock()?;
+        assert_eq!(storage.get(KV_LEGACY_CONTRACTS_KEY)?, None);
+        assert_eq!(storage.get(&[KV_LEGACY_STATE_PREFIX, contract_addr.as_slice()].concat())?, None);
+        assert_eq!(
+            storage.get(&state_entry_key(KV_CONTRACT_STATE_PREFIX, &contract_addr, b"key"))?,
+            Some(b"value".to_vec())
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_in_memory_storage() -> Result<(), StorageError> {
         let mut storage = Storage::new_in_memory()?;
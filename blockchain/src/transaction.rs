@@ -1,11 +1,20 @@
-use crate::crypto::{Hash, KeyPair, Signature};
+use crate::crypto::{is_zero_address, Hash, KeyPair, Signature};
 use ed25519_dalek::{VerifyingKey, Verifier};
 use serde::{Deserialize, Serialize};
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
 use tokio::task::JoinSet;
 
 static NONCE_COUNTER: AtomicU64 = AtomicU64::new(0);
 
+/// Signature verifications run at once by `verify_all_signatures` and
+/// `verify_batch` unless a limit is given
+pub const DEFAULT_VERIFICATION_CONCURRENCY: usize = 64;
+
+// Times a verification task that died before producing a result is run again
+const MAX_TASK_RETRIES: usize = 2;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TransactionInput {
     pub tx_hash: Hash,
@@ -49,7 +58,35 @@ impl Transaction {
         tx
     }
 
+    /// Create the coinbase transaction paying `amount` to `recipient`. It has
+    /// no inputs, the value comes from the block reward and collected fees.
+    pub fn coinbase(recipient: Vec<u8>, amount: u64) -> Self {
+        Self::new(vec![], vec![TransactionOutput { amount, recipient }])
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    /// Whether any output pays the zero address
+    pub fn has_zero_recipient(&self) -> bool {
+        self.outputs.iter().any(|output| is_zero_address(&output.recipient))
+    }
+
+    /// The hash identifying this transaction, which is its `txid`
     pub fn calculate_hash(&self) -> Hash {
+        self.txid()
+    }
+
+    /// Hash of the transaction without its signatures, known before it is
+    /// signed and unchanged by signing, so it cannot be altered by
+    /// re-encoding a signature. Mempool and block identity use this.
+    pub fn txid(&self) -> Hash {
+        Hash::new(&self.get_signing_data())
+    }
+
+    /// Hash of the transaction including its signatures
+    pub fn wtxid(&self) -> Hash {
         let mut data = Vec::new();
         
         // Add timestamp and nonce first to ensure uniqueness
@@ -100,9 +137,6 @@ impl Transaction {
         let data = self.get_signing_data();
         let signature = keypair.sign(&data);
         self.inputs[input_index].signature = Some(signature);
-
-        // Recalculate final hash including the signature
-        self.hash = self.calculate_hash();
         Ok(())
     }
 
@@ -134,6 +168,10 @@ impl Transaction {
             }
         }
 
+        if self.has_zero_recipient() {
+            return Err("Zero address recipient");
+        }
+
         // Verify the transaction hash is correct
         if self.hash != self.calculate_hash() {
             return Err("Invalid transaction hash");
@@ -144,68 +182,118 @@ impl Transaction {
 
     // Verify all signatures in a transaction
     pub async fn verify_all_signatures(&self, public_keys: &[Vec<u8>]) -> Result<bool, &'static str> {
+        self.verify_all_signatures_with_limit(public_keys, DEFAULT_VERIFICATION_CONCURRENCY).await
+    }
+
+    /// Verify all signatures in a transaction, running at most
+    /// `max_concurrent` verifications at once
+    pub async fn verify_all_signatures_with_limit(
+        &self,
+        public_keys: &[Vec<u8>],
+        max_concurrent: usize,
+    ) -> Result<bool, &'static str> {
         if self.inputs.len() != public_keys.len() {
             return Err("Number of public keys does not match number of inputs");
         }
 
-        let mut tasks = JoinSet::new();
-
-        for (index, public_key) in public_keys.iter().enumerate() {
-            let tx_clone = self.clone();
-            let pk_clone = public_key.clone();
-            
-            tasks.spawn(async move {
-                tx_clone.verify_signature(index, &pk_clone)
-            });
-        }
+        let tx = Arc::new(self.clone());
+        let items = public_keys.iter().cloned().enumerate().collect();
+        let results = run_limited(items, max_concurrent, move |(index, public_key): (usize, Vec<u8>)| {
+            tx.verify_signature(index, &public_key)
+        }).await;
 
-        while let Some(result) = tasks.join_next().await {
-            match result {
-                Ok(verification_result) => {
-                    match verification_result {
-                        Ok(is_valid) => {
-                            if !is_valid {
-                                return Ok(false);
-                            }
-                        }
-                        Err(e) => return Err(e),
-                    }
-                }
-                Err(_) => return Err("Task execution failed"),
+        for result in results {
+            if !result? {
+                return Ok(false);
             }
         }
-
         Ok(true)
     }
 
     // Batch verification of multiple transactions
     pub async fn verify_batch(transactions: &[(Transaction, Vec<Vec<u8>>)]) -> Vec<Result<bool, &'static str>> {
-        let mut tasks = JoinSet::new();
+        Self::verify_batch_with_limit(transactions, DEFAULT_VERIFICATION_CONCURRENCY).await
+    }
 
-        for (tx, public_keys) in transactions {
-            let tx_clone = tx.clone();
-            let pk_clone = public_keys.clone();
-            
+    /// Verify the signatures of each transaction in a batch, running at most
+    /// `max_concurrent` transactions at once. Results are in batch order.
+    pub async fn verify_batch_with_limit(
+        transactions: &[(Transaction, Vec<Vec<u8>>)],
+        max_concurrent: usize,
+    ) -> Vec<Result<bool, &'static str>> {
+        run_limited(transactions.to_vec(), max_concurrent, |(tx, public_keys): (Transaction, Vec<Vec<u8>>)| {
+            tx.check_signatures(&public_keys)
+        }).await
+    }
+
+    // Verify every input signature in turn, stopping at the first that fails
+    fn check_signatures(&self, public_keys: &[Vec<u8>]) -> Result<bool, &'static str> {
+        if self.inputs.len() != public_keys.len() {
+            return Err("Number of public keys does not match number of inputs");
+        }
+
+        for (index, public_key) in public_keys.iter().enumerate() {
+            if !self.verify_signature(index, public_key)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Run `verify` on every item in its own task, with at most `max_concurrent`
+/// tasks in existence at once. Results are in the order of `items`.
+///
+/// An item whose task dies before producing a result, as opposed to one
+/// that fails verification, is retried up to `MAX_TASK_RETRIES` times.
+async fn run_limited<T, F>(items: Vec<T>, max_concurrent: usize, verify: F) -> Vec<Result<bool, &'static str>>
+where
+    T: Clone + Send + 'static,
+    F: Fn(T) -> Result<bool, &'static str> + Send + Sync + 'static,
+{
+    let semaphore = Arc::new(Semaphore::new(max_concurrent.max(1)));
+    let verify = Arc::new(verify);
+    let mut results = vec![Err("Task execution failed"); items.len()];
+    let mut completed = vec![false; items.len()];
+    let mut pending: Vec<usize> = (0..items.len()).collect();
+
+    for _ in 0..=MAX_TASK_RETRIES {
+        if pending.is_empty() {
+            break;
+        }
+
+        let mut tasks = JoinSet::new();
+        for &index in &pending {
+            // Wait for a running verification to finish before spawning another
+            let permit = semaphore.clone().acquire_owned().await
+                .expect("verification semaphore is never closed");
+            let verify = verify.clone();
+            let item = items[index].clone();
             tasks.spawn(async move {
-                tx_clone.verify_all_signatures(&pk_clone).await
+                let result = verify(item);
+                drop(permit);
+                (index, result)
             });
         }
 
-        let mut results = Vec::with_capacity(transactions.len());
-        while let Some(result) = tasks.join_next().await {
-            match result {
-                Ok(verification_result) => results.push(verification_result),
-                Err(_) => results.push(Err("Task execution failed")),
+        // A task that panicked keeps the default error until it is retried
+        while let Some(joined) = tasks.join_next().await {
+            if let Ok((index, result)) = joined {
+                results[index] = result;
+                completed[index] = true;
             }
         }
-
-        results
+        pending.retain(|&index| !completed[index]);
     }
+
+    results
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crypto::ZERO_ADDRESS;
+    use std::sync::atomic::AtomicBool;
 
     fn create_test_transaction() -> Transaction {
         let input = TransactionInput {
@@ -245,6 +333,25 @@ mod tests {
         assert_ne!(tx3.hash, original_hash);
     }
 
+    #[test]
+    fn test_txid_independent_of_signatures() {
+        let mut tx = create_test_transaction();
+        let txid = tx.txid();
+        let wtxid = tx.wtxid();
+        assert_eq!(tx.hash, txid);
+
+        tx.sign(&KeyPair::generate(), 0).unwrap();
+        assert_eq!(tx.txid(), txid);
+        assert_eq!(tx.hash, txid);
+        assert_ne!(tx.wtxid(), wtxid);
+
+        // A different signature over the same transaction keeps the txid too
+        let signed_wtxid = tx.wtxid();
+        tx.sign(&KeyPair::generate(), 0).unwrap();
+        assert_eq!(tx.txid(), txid);
+        assert_ne!(tx.wtxid(), signed_wtxid);
+    }
+
     #[test]
     fn test_transaction_signing() {
         let mut tx = create_test_transaction();
@@ -300,6 +407,71 @@ mod tests {
         assert!(results[1].as_ref().unwrap());
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_batch_verification_concurrency_limit() {
+        let keypair = KeyPair::generate();
+        let public_key = keypair.public_key().as_bytes().to_vec();
+
+        // Every third transaction is signed by a different key
+        let batch: Vec<_> = (0..200)
+            .map(|i| {
+                let mut tx = create_test_transaction();
+                if i % 3 == 0 {
+                    tx.sign(&KeyPair::generate(), 0).unwrap();
+                } else {
+                    tx.sign(&keypair, 0).unwrap();
+                }
+                (tx, vec![public_key.clone()])
+            })
+            .collect();
+
+        let results = Transaction::verify_batch_with_limit(&batch, 3).await;
+        assert_eq!(results.len(), batch.len());
+        for (i, result) in results.iter().enumerate() {
+            assert_eq!(*result, Ok(i % 3 != 0), "transaction {}", i);
+        }
+
+        // No more than the limit ever run at once
+        let active = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let peak = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let (counter, max_seen) = (active.clone(), peak.clone());
+        let results = run_limited((0..50).collect(), 3, move |_: usize| {
+            let running = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            max_seen.fetch_max(running, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            counter.fetch_sub(1, Ordering::SeqCst);
+            Ok(true)
+        }).await;
+        assert!(results.iter().all(|result| *result == Ok(true)));
+        assert!(peak.load(Ordering::SeqCst) <= 3, "peak of {}", peak.load(Ordering::SeqCst));
+        assert!(peak.load(Ordering::SeqCst) > 1);
+    }
+
+    #[tokio::test]
+    async fn test_coinbase() {
+        let tx = Transaction::coinbase(vec![9, 9, 9], 50);
+        assert!(tx.is_coinbase());
+        assert_eq!(tx.outputs.len(), 1);
+        assert_eq!(tx.outputs[0].amount, 50);
+        assert_eq!(tx.outputs[0].recipient, vec![9, 9, 9]);
+        assert!(tx.verify().await.unwrap());
+        assert!(!create_test_transaction().is_coinbase());
+    }
+
+    #[tokio::test]
+    async fn test_zero_address_recipient() {
+        let keypair = KeyPair::generate();
+        let mut tx = create_test_transaction();
+        tx.outputs.push(TransactionOutput {
+            amount: 1,
+            recipient: ZERO_ADDRESS.to_vec(),
+        });
+        tx.sign(&keypair, 0).unwrap();
+
+        assert!(tx.has_zero_recipient());
+        assert_eq!(tx.verify().await, Err("Zero address recipient"));
+    }
+
     #[tokio::test]
     async fn test_verify() {
         let mut tx = create_test_transaction();
@@ -316,4 +488,29 @@ mod tests {
         tx.hash = Hash::new(&[0u8; 32]);
         assert!(tx.verify().await.is_err());
     }
+
+    #[tokio::test]
+    async fn test_failed_verification_task_is_retried() {
+        let failed = Arc::new(AtomicBool::new(false));
+        let fail_once = failed.clone();
+        let results = run_limited(vec![1, 2, 3], 2, move |item: i32| {
+            // The first task verifying the second item dies
+            if item == 2 && !fail_once.swap(true, Ordering::SeqCst) {
+                panic!("transient task failure");
+            }
+            Ok(item != 3)
+        }).await;
+        assert!(failed.load(Ordering::SeqCst));
+        assert_eq!(results, vec![Ok(true), Ok(true), Ok(false)]);
+
+        // A task that never completes gives up after its retries
+        let attempts = Arc::new(AtomicU64::new(0));
+        let counted = attempts.clone();
+        let results = run_limited(vec![1], 1, move |_: i32| -> Result<bool, &'static str> {
+            counted.fetch_add(1, Ordering::SeqCst);
+            panic!("persistent task failure");
+        }).await;
+        assert_eq!(results, vec![Err("Task execution failed")]);
+        assert_eq!(attempts.load(Ordering::SeqCst), MAX_TASK_RETRIES as u64 + 1);
+    }
 }
@@ -1,23 +1,40 @@
 use blockchain::contract::{
     ContractRuntime, ContractEnvironment, ResourceLimits, ContractABI,
-    ContractMethod, ContractParam, ContractMetadata, DEPLOYER_ROLE, EXECUTOR_ROLE, DEFAULT_ADMIN_ROLE,
+    ContractMethod, ContractParam, ContractMetadata, DEPLOYER_ROLE, EXECUTOR_ROLE, UPGRADER_ROLE, DEFAULT_ADMIN_ROLE, PAUSER_ROLE,
+    ContractError, CallPriority, ContractCall, ExecutionPool, ExecutionPoolConfig,
 };
 use blockchain::msg;
+use blockchain::{BlockReceipt, BlockchainDB, EventFilter, Hash, WasmValue};
 use wasmer::Value;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
 // Test WASM module that implements basic arithmetic operations
 const TEST_WASM: &[u8] = include_bytes!("fixtures/test_contract.wasm");
 
+// Contract with `add` and a `store` method writing its arguments, little
+// endian, through the host storage interface
+const STORAGE_WAT: &str = r#"
+(module
+  (import "env" "storage_write" (func $storage_write (param i32 i32 i32 i32)))
+  (memory (export "memory") 1)
+  (func (export "add") (param i32 i32) (result i32)
+    (i32.add (local.get 0) (local.get 1)))
+  (func (export "store") (param $key i32) (param $value i32)
+    (i32.store (i32.const 0) (local.get $key))
+    (i32.store (i32.const 4) (local.get $value))
+    (call $storage_write (i32.const 0) (i32.const 4) (i32.const 4) (i32.const 4))))
+"#;
+
 // Test account for all operations
-const TEST_ACCOUNT: [u8; 32] = [0u8; 32];  // Match DEFAULT_ADMIN_ROLE
-const ADMIN_ACCOUNT: [u8; 32] = [0u8; 32];  // Must match DEFAULT_ADMIN_ROLE
+const TEST_ACCOUNT: [u8; 32] = [9u8; 32];
+const ADMIN_ACCOUNT: [u8; 32] = [9u8; 32];
 
 async fn setup_runtime() -> ContractRuntime {
     let mut runtime = ContractRuntime::new();
     
-    // Set sender as admin account (which is all zeros)
+    // Set sender as admin account
     msg::test_utils::set_sender(ADMIN_ACCOUNT).unwrap();
     
     // Grant admin role to admin account (this should work as it's the first admin role grant)
@@ -68,6 +85,7 @@ async fn test_contract_existence() {
                     },
                 ],
                 payable: false,
+                default_gas: None,
             },
         ],
         events: vec![],
@@ -75,7 +93,7 @@ async fn test_contract_existence() {
     };
 
     let limits = ResourceLimits {
-        max_memory: 1024 * 1024,
+        max_memory: 2 * 1024 * 1024,
         max_gas: 1_000_000,
         max_storage: 1024 * 1024,
         max_call_depth: 5,
@@ -88,6 +106,7 @@ async fn test_contract_existence() {
         author: TEST_ACCOUNT,
         description: "Test Contract".into(),
         is_upgradeable: true,
+        allow_major_upgrade: false,
     };
 
     // Deploy the contract
@@ -100,10 +119,11 @@ async fn test_contract_existence() {
 
     // Try to execute non-existent contract
     let env = ContractEnvironment {
-        gas_limit: 1_000_000,
+        gas_limit: Some(1_000_000),
         block_number: 1,
         timestamp: 1234567890,
         caller: TEST_ACCOUNT,
+        value: 0,
         resource_limits: limits,
         gas_used: Arc::new(RwLock::new(0)),
     };
@@ -155,6 +175,7 @@ async fn test_contract_deployment() {
                     },
                 ],
                 payable: false,
+                default_gas: None,
             },
         ],
         events: vec![],
@@ -162,7 +183,7 @@ async fn test_contract_deployment() {
     };
 
     let limits = ResourceLimits {
-        max_memory: 1024 * 1024,
+        max_memory: 2 * 1024 * 1024,
         max_gas: 1_000_000,
         max_storage: 1024 * 1024,
         max_call_depth: 5,
@@ -175,6 +196,7 @@ async fn test_contract_deployment() {
         author: TEST_ACCOUNT,
         description: "Test Contract".into(),
         is_upgradeable: true,
+        allow_major_upgrade: false,
     };
 
     let result = runtime.deploy_contract(TEST_WASM, &contract_addr, &abi, metadata, &limits).await;
@@ -214,6 +236,7 @@ async fn test_contract_execution() {
                     },
                 ],
                 payable: false,
+                default_gas: None,
             },
         ],
         events: vec![],
@@ -221,7 +244,7 @@ async fn test_contract_execution() {
     };
 
     let limits = ResourceLimits {
-        max_memory: 1024 * 1024,
+        max_memory: 2 * 1024 * 1024,
         max_gas: 1_000_000,
         max_storage: 1024 * 1024,
         max_call_depth: 5,
@@ -234,16 +257,18 @@ async fn test_contract_execution() {
         author: TEST_ACCOUNT,
         description: "Test Contract".into(),
         is_upgradeable: true,
+        allow_major_upgrade: false,
     };
 
     runtime.deploy_contract(TEST_WASM, &contract_addr, &abi, metadata, &limits).await.unwrap();
 
     // Create execution environment
     let env = ContractEnvironment {
-        gas_limit: 1_000_000,
+        gas_limit: Some(1_000_000),
         block_number: 1,
         timestamp: 1234567890,
         caller: TEST_ACCOUNT,
+        value: 0,
         resource_limits: limits,
         gas_used: Arc::new(RwLock::new(0)),
     };
@@ -280,6 +305,7 @@ async fn test_gas_metering() {
                 ],
                 outputs: vec![],
                 payable: false,
+                default_gas: None,
             },
         ],
         events: vec![],
@@ -287,7 +313,7 @@ async fn test_gas_metering() {
     };
 
     let limits = ResourceLimits {
-        max_memory: 1024 * 1024,
+        max_memory: 2 * 1024 * 1024,
         max_gas: 1_000,  // Very low gas limit
         max_storage: 1024 * 1024,
         max_call_depth: 5,
@@ -300,16 +326,18 @@ async fn test_gas_metering() {
         author: TEST_ACCOUNT,
         description: "Test Contract".into(),
         is_upgradeable: true,
+        allow_major_upgrade: false,
     };
 
     runtime.deploy_contract(TEST_WASM, &contract_addr, &abi, metadata, &limits).await.unwrap();
 
     // Create execution environment with low gas limit
     let env = ContractEnvironment {
-        gas_limit: 1_000,
+        gas_limit: Some(1_000),
         block_number: 1,
         timestamp: 1234567890,
         caller: TEST_ACCOUNT,
+        value: 0,
         resource_limits: limits,
         gas_used: Arc::new(RwLock::new(0)),
     };
@@ -357,6 +385,7 @@ async fn test_error_handling() {
                     },
                 ],
                 payable: false,
+                default_gas: None,
             },
         ],
         events: vec![],
@@ -364,7 +393,7 @@ async fn test_error_handling() {
     };
 
     let limits = ResourceLimits {
-        max_memory: 1024 * 1024,
+        max_memory: 2 * 1024 * 1024,
         max_gas: 1_000_000,
         max_storage: 1024 * 1024,
         max_call_depth: 5,
@@ -377,6 +406,7 @@ async fn test_error_handling() {
         author: TEST_ACCOUNT,
         description: "Test Contract".into(),
         is_upgradeable: true,
+        allow_major_upgrade: false,
     };
 
     runtime.deploy_contract(TEST_WASM, &contract_addr, &abi, metadata, &limits).await.unwrap();
@@ -392,12 +422,13 @@ async fn test_error_handling() {
     msg::test_utils::set_sender(unprivileged_account).unwrap();
     
     let env = ContractEnvironment {
-        gas_limit: 1_000_000,
+        gas_limit: Some(1_000_000),
         block_number: 1,
         timestamp: 1234567890,
         caller: unprivileged_account,
+        value: 0,
         resource_limits: ResourceLimits {
-            max_memory: 1024 * 1024,
+            max_memory: 2 * 1024 * 1024,
             max_gas: 1_000_000,
             max_storage: 1024 * 1024,
             max_call_depth: 5,
@@ -436,6 +467,7 @@ async fn test_error_handling() {
         author: TEST_ACCOUNT,
         description: "Invalid Contract".into(),
         is_upgradeable: true,
+        allow_major_upgrade: false,
     };
 
     let result = runtime.deploy_contract(invalid_wasm, &contract_addr, &abi, metadata, &limits).await;
@@ -445,3 +477,2139 @@ async fn test_error_handling() {
     // Clean up
     msg::test_utils::clear_sender().unwrap();
 }
+
+#[tokio::test]
+async fn test_per_contract_execution_timeout() {
+    let mut runtime = setup_runtime().await;
+    let slow_addr = [5u8; 32];
+    let default_addr = [6u8; 32];
+
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "loop_test".into(),
+                inputs: vec![
+                    ContractParam {
+                        name: "iterations".into(),
+                        param_type: "i32".into(),
+                        indexed: false,
+                    },
+                ],
+                outputs: vec![],
+                payable: false,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 10_000_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    for addr in [slow_addr, default_addr] {
+        let metadata = ContractMetadata {
+            version: "1.0.0".into(),
+            created_at: 1234567890,
+            updated_at: 1234567890,
+            author: TEST_ACCOUNT,
+            description: "Test Contract".into(),
+            is_upgradeable: true,
+            allow_major_upgrade: false,
+        };
+        runtime.deploy_contract(TEST_WASM, &addr, &abi, metadata, &limits).await.unwrap();
+    }
+
+    // Only the slow contract gets a short execution timeout
+    runtime.set_execution_timeout(&slow_addr, Duration::from_millis(1)).unwrap();
+    assert_eq!(runtime.get_execution_timeout(&slow_addr), Duration::from_millis(1));
+    assert_eq!(runtime.get_execution_timeout(&default_addr), Duration::from_secs(30));
+
+    let env = ContractEnvironment {
+        gas_limit: Some(10_000_000_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value: 0,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+
+    let args = vec![Value::I32(20_000_000)];
+    let result = runtime.execute_contract(slow_addr, "loop_test", args.clone(), &env, None).await;
+    assert!(matches!(result, Err(ContractError::OperationTimeout(_))), "Unexpected result: {:?}", result);
+
+    // The aborted execution is no longer tracked, so the contract can run
+    // again once given more time
+    assert_eq!(runtime.get_active_operations(), 0);
+    runtime.set_execution_timeout(&slow_addr, Duration::from_secs(30)).unwrap();
+    let result = runtime.execute_contract(slow_addr, "loop_test", vec![Value::I32(1)], &env, None).await;
+    assert!(result.is_ok(), "Unexpected error: {:?}", result.err());
+
+    // The same work completes on a contract using the default timeout
+    let result = runtime.execute_contract(default_addr, "loop_test", args, &env, None).await;
+    assert!(result.is_ok(), "Unexpected error: {:?}", result.err());
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_deploy_to_occupied_address() {
+    let mut runtime = setup_runtime().await;
+    let contract_addr = [7u8; 32];
+
+    let abi = ContractABI {
+        methods: vec![],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    let metadata = |version: &str| ContractMetadata {
+        version: version.into(),
+        created_at: 1234567890,
+        updated_at: 1234567890,
+        author: TEST_ACCOUNT,
+        description: "Test Contract".into(),
+        is_upgradeable: true,
+        allow_major_upgrade: false,
+    };
+
+    runtime.deploy_contract(TEST_WASM, &contract_addr, &abi, metadata("1.0.0"), &limits).await.unwrap();
+
+    // A fresh deployment to the same address collides, even with a newer version
+    let result = runtime.deploy_contract(TEST_WASM, &contract_addr, &abi, metadata("2.0.0"), &limits).await;
+    match result {
+        Err(ContractError::VersionConflict(msg)) => assert!(msg.contains("already deployed"), "Unexpected message: {}", msg),
+        other => panic!("Expected collision error, got {:?}", other),
+    }
+
+    // The original deployment is untouched
+    let versions = runtime.get_contract_versions(&contract_addr).unwrap();
+    assert_eq!(versions.len(), 1);
+    assert_eq!(versions[0].metadata.version, "1.0.0");
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_execution_pool_concurrency() {
+    let mut runtime = setup_runtime().await;
+    let contracts: Vec<[u8; 32]> = (10u8..18).map(|i| [i; 32]).collect();
+
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "loop_test".into(),
+                inputs: vec![
+                    ContractParam {
+                        name: "iterations".into(),
+                        param_type: "i32".into(),
+                        indexed: false,
+                    },
+                ],
+                outputs: vec![],
+                payable: false,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 10_000_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    for addr in &contracts {
+        let metadata = ContractMetadata {
+            version: "1.0.0".into(),
+            created_at: 1234567890,
+            updated_at: 1234567890,
+            author: TEST_ACCOUNT,
+            description: "Test Contract".into(),
+            is_upgradeable: true,
+            allow_major_upgrade: false,
+        };
+        runtime.deploy_contract(TEST_WASM, addr, &abi, metadata, &limits).await.unwrap();
+    }
+
+    let runtime = Arc::new(RwLock::new(runtime));
+    let env = ContractEnvironment {
+        gas_limit: Some(10_000_000_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value: 0,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+    let call = |contract_addr: [u8; 32]| ContractCall {
+        contract_addr,
+        method: "loop_test".into(),
+        args: vec![Value::I32(100_000)],
+        env: env.clone(),
+        version: None,
+        priority: CallPriority::Normal,
+    };
+
+    // Calls across distinct contracts run concurrently, up to the worker count
+    let pool = ExecutionPool::new(runtime.clone(), ExecutionPoolConfig {
+        workers: 4,
+        queue_capacity: 4,
+    });
+    let mut pending = Vec::new();
+    for addr in contracts.iter().chain(contracts.iter()) {
+        pending.push(pool.submit(call(*addr)).await.unwrap());
+    }
+    for result in pending {
+        assert!(result.await.unwrap().is_ok());
+    }
+    assert_eq!(pool.peak_concurrency(), 4);
+    assert_eq!(pool.active_calls(), 0);
+    pool.shutdown().await;
+
+    // Calls to a single contract are serialized
+    let pool = ExecutionPool::new(runtime.clone(), ExecutionPoolConfig {
+        workers: 4,
+        queue_capacity: 8,
+    });
+    let mut pending = Vec::new();
+    for _ in 0..8 {
+        pending.push(pool.submit(call(contracts[0])).await.unwrap());
+    }
+    for result in pending {
+        assert!(result.await.unwrap().is_ok());
+    }
+    assert_eq!(pool.peak_concurrency(), 1);
+    pool.shutdown().await;
+
+    // A full queue pushes back on submitters that don't wait
+    let pool = ExecutionPool::new(runtime, ExecutionPoolConfig {
+        workers: 1,
+        queue_capacity: 1,
+    });
+    let mut accepted = Vec::new();
+    let mut rejected = 0;
+    for addr in &contracts {
+        match pool.try_submit(call(*addr)) {
+            Ok(result) => accepted.push(result),
+            Err(ContractError::ConcurrencyLimitExceeded(_)) => rejected += 1,
+            Err(e) => panic!("Unexpected error: {:?}", e),
+        }
+    }
+    assert!(rejected > 0);
+    for result in accepted {
+        assert!(result.await.unwrap().is_ok());
+    }
+    pool.shutdown().await;
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_reentrancy_allowlist() {
+    let mut runtime = setup_runtime().await;
+    let contract_addr = [20u8; 32];
+    let callback = [21u8; 32];
+    let stranger = [22u8; 32];
+
+    let i32_param = |name: &str| ContractParam {
+        name: name.into(),
+        param_type: "i32".into(),
+        indexed: false,
+    };
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "add".into(),
+                inputs: vec![i32_param("a"), i32_param("b")],
+                outputs: vec![i32_param("result")],
+                payable: false,
+                default_gas: None,
+            },
+            ContractMethod {
+                name: "loop_test".into(),
+                inputs: vec![i32_param("iterations")],
+                outputs: vec![],
+                payable: false,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    let metadata = ContractMetadata {
+        version: "1.0.0".into(),
+        created_at: 1234567890,
+        updated_at: 1234567890,
+        author: TEST_ACCOUNT,
+        description: "Test Contract".into(),
+        is_upgradeable: true,
+        allow_major_upgrade: false,
+    };
+    runtime.deploy_contract(TEST_WASM, &contract_addr, &abi, metadata, &limits).await.unwrap();
+    runtime.set_execution_timeout(&contract_addr, Duration::from_secs(1)).unwrap();
+
+    let env_for = |caller: [u8; 32]| ContractEnvironment {
+        gas_limit: Some(1_000_000_000_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+
+    // Keep the contract busy with a long-running call
+    let runtime = Arc::new(RwLock::new(runtime));
+    let pool = ExecutionPool::new(runtime.clone(), ExecutionPoolConfig::default());
+    let long_call = pool.submit(ContractCall {
+        contract_addr,
+        method: "loop_test".into(),
+        args: vec![Value::I32(i32::MAX)],
+        env: env_for(TEST_ACCOUNT),
+        version: None,
+        priority: CallPriority::Normal,
+    }).await.unwrap();
+    while pool.active_calls() == 0 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    let args = vec![Value::I32(1), Value::I32(2)];
+
+    // Re-entering the busy contract is rejected by default
+    let result = runtime.write().await
+        .execute_contract(contract_addr, "add", args.clone(), &env_for(callback), None).await;
+    assert!(matches!(result, Err(ContractError::ReentrancyError(_))), "Unexpected result: {:?}", result);
+
+    // An allowlisted caller may re-enter, others still may not
+    runtime.write().await.allow_reentrant_caller(&contract_addr, callback).unwrap();
+    assert!(runtime.read().await.is_reentrant_caller_allowed(&contract_addr, &callback));
+    let result = runtime.write().await
+        .execute_contract(contract_addr, "add", args.clone(), &env_for(callback), None).await;
+    assert_eq!(result.unwrap(), vec![Value::I32(3)]);
+    let result = runtime.write().await
+        .execute_contract(contract_addr, "add", args.clone(), &env_for(stranger), None).await;
+    assert!(matches!(result, Err(ContractError::ReentrancyError(_))), "Unexpected result: {:?}", result);
+
+    // Once the long call ends the contract accepts any caller again
+    assert!(matches!(long_call.await.unwrap(), Err(ContractError::OperationTimeout(_))));
+    let result = runtime.write().await
+        .execute_contract(contract_addr, "add", args, &env_for(stranger), None).await;
+    assert_eq!(result.unwrap(), vec![Value::I32(3)]);
+
+    pool.shutdown().await;
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_transaction_atomicity() {
+    let mut runtime = setup_runtime().await;
+    let first_addr = [30u8; 32];
+    let second_addr = [31u8; 32];
+
+    let i32_param = |name: &str| ContractParam {
+        name: name.into(),
+        param_type: "i32".into(),
+        indexed: false,
+    };
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "add".into(),
+                inputs: vec![i32_param("a"), i32_param("b")],
+                outputs: vec![i32_param("result")],
+                payable: false,
+                default_gas: None,
+            },
+            ContractMethod {
+                name: "store".into(),
+                inputs: vec![i32_param("key"), i32_param("value")],
+                outputs: vec![],
+                payable: false,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    for addr in [&first_addr, &second_addr] {
+        let metadata = ContractMetadata {
+            version: "1.0.0".into(),
+            created_at: 1234567890,
+            updated_at: 1234567890,
+            author: TEST_ACCOUNT,
+            description: "Test Contract".into(),
+            is_upgradeable: true,
+            allow_major_upgrade: false,
+        };
+        runtime.deploy_contract(STORAGE_WAT.as_bytes(), addr, &abi, metadata, &limits).await.unwrap();
+    }
+
+    let env = ContractEnvironment {
+        gas_limit: Some(1_000_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value: 0,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+    let call = |contract_addr: [u8; 32], method: &str, args: Vec<Value>| ContractCall {
+        contract_addr,
+        method: method.into(),
+        args,
+        env: env.clone(),
+        version: None,
+        priority: CallPriority::Normal,
+    };
+    let stored = |runtime: &ContractRuntime, addr: &[u8; 32], key: i32| {
+        runtime.get_contract_state(addr).unwrap()
+            .get(&key.to_le_bytes().to_vec())
+            .cloned()
+    };
+
+    // A successful transaction commits the writes of all its calls
+    let results = runtime.execute_transaction(&[
+        call(first_addr, "store", vec![Value::I32(1), Value::I32(42)]),
+        call(first_addr, "add", vec![Value::I32(1), Value::I32(2)]),
+    ]).await.unwrap();
+    assert_eq!(results[1], vec![Value::I32(3)]);
+    assert_eq!(stored(&runtime, &first_addr, 1), Some(42i32.to_le_bytes().to_vec()));
+
+    // A failing call reverts the earlier calls, across every contract involved
+    let result = runtime.execute_transaction(&[
+        call(first_addr, "store", vec![Value::I32(1), Value::I32(100)]),
+        call(second_addr, "store", vec![Value::I32(2), Value::I32(7)]),
+        call(first_addr, "add", vec![Value::I32(1)]),
+    ]).await;
+    assert!(matches!(result, Err(ContractError::InvalidArguments(_))), "Unexpected result: {:?}", result);
+    assert_eq!(stored(&runtime, &first_addr, 1), Some(42i32.to_le_bytes().to_vec()));
+    assert_eq!(stored(&runtime, &second_addr, 2), None);
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_block_gas_accounting() {
+    let mut runtime = setup_runtime().await;
+    let contract_addr = [40u8; 32];
+
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "loop_test".into(),
+                inputs: vec![ContractParam {
+                    name: "iterations".into(),
+                    param_type: "i32".into(),
+                    indexed: false,
+                }],
+                outputs: vec![],
+                payable: false,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    let metadata = ContractMetadata {
+        version: "1.0.0".into(),
+        created_at: 1234567890,
+        updated_at: 1234567890,
+        author: TEST_ACCOUNT,
+        description: "Test Contract".into(),
+        is_upgradeable: true,
+        allow_major_upgrade: false,
+    };
+    runtime.deploy_contract(TEST_WASM, &contract_addr, &abi, metadata, &limits).await.unwrap();
+
+    // Each transaction of the block makes one gas-consuming call
+    let mut receipts = Vec::new();
+    for (i, iterations) in [10, 250, 1000].into_iter().enumerate() {
+        let call = ContractCall {
+            contract_addr,
+            method: "loop_test".into(),
+            args: vec![Value::I32(iterations)],
+            env: ContractEnvironment {
+                gas_limit: Some(1_000_000),
+                block_number: 1,
+                timestamp: 1234567890,
+                caller: TEST_ACCOUNT,
+                value: 0,
+                resource_limits: limits,
+                gas_used: Arc::new(RwLock::new(0)),
+            },
+            version: None,
+            priority: CallPriority::Normal,
+        };
+        let (result, receipt) = runtime.execute_with_receipt(Hash::new(&[i as u8]), 0, &call).await;
+        result.unwrap();
+        assert!(receipt.success);
+        assert!(receipt.gas_used > 0);
+        receipts.push(receipt);
+    }
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db = BlockchainDB::new(temp_dir.path()).unwrap();
+    let block_hash = Hash::new(b"block with calls");
+    db.store_block_receipt(&BlockReceipt::new(block_hash.clone(), receipts.clone())).await.unwrap();
+
+    let expected: u64 = receipts.iter().map(|receipt| receipt.gas_used).sum();
+    assert_eq!(db.block_gas_used(&block_hash).await.unwrap(), expected);
+    assert_eq!(db.get_block_receipt(&block_hash).await.unwrap().calls, receipts);
+    assert!(db.block_gas_used(&Hash::new(b"unknown block")).await.is_err());
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_call_result_stored_in_receipt() {
+    let mut runtime = setup_runtime().await;
+    let contract_addr = [41u8; 32];
+
+    let i32_param = |name: &str| ContractParam {
+        name: name.into(),
+        param_type: "i32".into(),
+        indexed: false,
+    };
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "add".into(),
+                inputs: vec![i32_param("a"), i32_param("b")],
+                outputs: vec![i32_param("result")],
+                payable: false,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    let metadata = ContractMetadata {
+        version: "1.0.0".into(),
+        created_at: 1234567890,
+        updated_at: 1234567890,
+        author: TEST_ACCOUNT,
+        description: "Test Contract".into(),
+        is_upgradeable: true,
+        allow_major_upgrade: false,
+    };
+    runtime.deploy_contract(TEST_WASM, &contract_addr, &abi, metadata, &limits).await.unwrap();
+
+    let call = |args: Vec<Value>| ContractCall {
+        contract_addr,
+        method: "add".into(),
+        args,
+        env: ContractEnvironment {
+            gas_limit: Some(1_000_000),
+            block_number: 1,
+            timestamp: 1234567890,
+            caller: TEST_ACCOUNT,
+            value: 0,
+            resource_limits: limits,
+            gas_used: Arc::new(RwLock::new(0)),
+        },
+        version: None,
+        priority: CallPriority::Normal,
+    };
+
+    let tx_hash = Hash::new(b"add transaction");
+    let (result, receipt) = runtime.execute_with_receipt(tx_hash.clone(), 0, &call(vec![Value::I32(5), Value::I32(3)])).await;
+    assert_eq!(result.unwrap(), vec![Value::I32(8)]);
+    assert_eq!(receipt.result, Some(vec![WasmValue::I32(8)]));
+
+    // The result can be queried after the call has returned
+    assert_eq!(runtime.get_call_result(&tx_hash, 0), Some(vec![WasmValue::I32(8)]));
+
+    // Each call of a transaction keeps its own result
+    let (result, _) = runtime.execute_with_receipt(tx_hash.clone(), 1, &call(vec![Value::I32(2), Value::I32(2)])).await;
+    assert_eq!(result.unwrap(), vec![Value::I32(4)]);
+    assert_eq!(runtime.get_call_result(&tx_hash, 0), Some(vec![WasmValue::I32(8)]));
+    assert_eq!(runtime.get_call_result(&tx_hash, 1), Some(vec![WasmValue::I32(4)]));
+
+    // Failed calls and unknown transactions have no result
+    let failed_hash = Hash::new(b"failed transaction");
+    let (result, _) = runtime.execute_with_receipt(failed_hash.clone(), 0, &call(vec![Value::I32(5)])).await;
+    assert!(result.is_err());
+    assert_eq!(runtime.get_call_result(&failed_hash, 0), None);
+    assert_eq!(runtime.get_call_result(&tx_hash, 2), None);
+    assert_eq!(runtime.get_call_result(&Hash::new(b"unknown transaction"), 0), None);
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_wasm_execution() {
+    let mut runtime = setup_runtime().await;
+    let contract_addr = [42u8; 32];
+
+    let i32_param = |name: &str| ContractParam {
+        name: name.into(),
+        param_type: "i32".into(),
+        indexed: false,
+    };
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "add".into(),
+                inputs: vec![i32_param("a"), i32_param("b")],
+                outputs: vec![i32_param("result")],
+                payable: false,
+                default_gas: None,
+            },
+            // Declared in the ABI but not exported by the module
+            ContractMethod {
+                name: "subtract".into(),
+                inputs: vec![i32_param("a"), i32_param("b")],
+                outputs: vec![i32_param("result")],
+                payable: false,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    let metadata = ContractMetadata {
+        version: "1.0.0".into(),
+        created_at: 1234567890,
+        updated_at: 1234567890,
+        author: TEST_ACCOUNT,
+        description: "Test Contract".into(),
+        is_upgradeable: true,
+        allow_major_upgrade: false,
+    };
+    runtime.deploy_contract(TEST_WASM, &contract_addr, &abi, metadata, &limits).await.unwrap();
+
+    let env = ContractEnvironment {
+        gas_limit: Some(1_000_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value: 0,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+    let args = vec![Value::I32(5), Value::I32(3)];
+
+    // The fixture's add charges 10 gas through env.gas on top of its instructions
+    let result = runtime.execute_contract(contract_addr, "add", args.clone(), &env, None).await.unwrap();
+    assert_eq!(result, vec![Value::I32(8)]);
+    assert!(*env.gas_used.read().await > 10);
+
+    let result = runtime.execute_contract(contract_addr, "subtract", args, &env, None).await;
+    assert!(
+        matches!(&result, Err(ContractError::ExecutionError(message)) if message.contains("does not export")),
+        "Unexpected result: {:?}", result
+    );
+
+    let result = runtime.execute_contract(contract_addr, "add", vec![Value::I64(5), Value::I64(3)], &env, None).await;
+    assert!(matches!(result, Err(ContractError::InvalidArguments(_))), "Unexpected result: {:?}", result);
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_governance_calls_run_first() {
+    let mut runtime = setup_runtime().await;
+    let busy_addr = [43u8; 32];
+    let storage_addr = [44u8; 32];
+
+    let i32_param = |name: &str| ContractParam {
+        name: name.into(),
+        param_type: "i32".into(),
+        indexed: false,
+    };
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "loop_test".into(),
+                inputs: vec![i32_param("iterations")],
+                outputs: vec![],
+                payable: false,
+                default_gas: None,
+            },
+            ContractMethod {
+                name: "store".into(),
+                inputs: vec![i32_param("key"), i32_param("value")],
+                outputs: vec![],
+                payable: false,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 10_000_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    for (addr, bytecode) in [(&busy_addr, TEST_WASM), (&storage_addr, STORAGE_WAT.as_bytes())] {
+        let metadata = ContractMetadata {
+            version: "1.0.0".into(),
+            created_at: 1234567890,
+            updated_at: 1234567890,
+            author: TEST_ACCOUNT,
+            description: "Test Contract".into(),
+            is_upgradeable: true,
+            allow_major_upgrade: false,
+        };
+        runtime.deploy_contract(bytecode, addr, &abi, metadata, &limits).await.unwrap();
+    }
+
+    let runtime = Arc::new(RwLock::new(runtime));
+    let env = ContractEnvironment {
+        gas_limit: Some(10_000_000_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value: 0,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+    let call = |contract_addr: [u8; 32], method: &str, args: Vec<Value>, priority: CallPriority| ContractCall {
+        contract_addr,
+        method: method.into(),
+        args,
+        env: env.clone(),
+        version: None,
+        priority,
+    };
+
+    // Occupy the only worker so the next calls have to wait in the queue
+    let pool = ExecutionPool::new(runtime.clone(), ExecutionPoolConfig {
+        workers: 1,
+        queue_capacity: 4,
+    });
+    let busy = pool.submit(call(busy_addr, "loop_test", vec![Value::I32(1_000_000)], CallPriority::Normal)).await.unwrap();
+    while pool.active_calls() == 0 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    // Both calls write the same key, so the value left behind is from the call that ran last
+    let normal = pool.submit(call(storage_addr, "store", vec![Value::I32(1), Value::I32(10)], CallPriority::Normal)).await.unwrap();
+    let governance = pool.submit(call(storage_addr, "store", vec![Value::I32(1), Value::I32(20)], CallPriority::Governance)).await.unwrap();
+    for result in [busy, normal, governance] {
+        assert!(result.await.unwrap().is_ok());
+    }
+    pool.shutdown().await;
+
+    let stored = runtime.read().await.get_contract_state(&storage_addr).unwrap()
+        .get(&1i32.to_le_bytes().to_vec())
+        .cloned();
+    assert_eq!(stored, Some(10i32.to_le_bytes().to_vec()));
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_instruction_metering() {
+    let mut runtime = setup_runtime().await;
+    let contract_addr = [45u8; 32];
+
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "loop_test".into(),
+                inputs: vec![ContractParam {
+                    name: "iterations".into(),
+                    param_type: "i32".into(),
+                    indexed: false,
+                }],
+                outputs: vec![],
+                payable: false,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    let metadata = ContractMetadata {
+        version: "1.0.0".into(),
+        created_at: 1234567890,
+        updated_at: 1234567890,
+        author: TEST_ACCOUNT,
+        description: "Test Contract".into(),
+        is_upgradeable: true,
+        allow_major_upgrade: false,
+    };
+    runtime.deploy_contract(TEST_WASM, &contract_addr, &abi, metadata, &limits).await.unwrap();
+
+    let env = || ContractEnvironment {
+        gas_limit: Some(1_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value: 0,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+
+    // A short loop fits in the limit and is charged for the instructions it ran
+    let short = env();
+    runtime.execute_contract(contract_addr, "loop_test", vec![Value::I32(15)], &short, None).await.unwrap();
+    let short_gas = *short.gas_used.read().await;
+    assert!(short_gas > 15 && short_gas < 1_000, "Unexpected gas used: {}", short_gas);
+
+    // More iterations cost more gas
+    let longer = env();
+    runtime.execute_contract(contract_addr, "loop_test", vec![Value::I32(30)], &longer, None).await.unwrap();
+    assert!(*longer.gas_used.read().await > short_gas);
+
+    // Later calls reuse the compiled module but are metered against their own limit
+    let again = env();
+    runtime.execute_contract(contract_addr, "loop_test", vec![Value::I32(15)], &again, None).await.unwrap();
+    assert_eq!(*again.gas_used.read().await, short_gas);
+    let tight = ContractEnvironment { gas_limit: Some(short_gas - 1), ..env() };
+    let result = runtime.execute_contract(contract_addr, "loop_test", vec![Value::I32(15)], &tight, None).await;
+    assert!(
+        matches!(&result, Err(ContractError::ExecutionError(message)) if message.contains("Gas limit exceeded")),
+        "Unexpected result: {:?}", result
+    );
+    assert_eq!(*tight.gas_used.read().await, short_gas - 1);
+
+    // A long loop stops once the limit is used up, and is charged the whole limit
+    let long = env();
+    let result = runtime.execute_contract(contract_addr, "loop_test", vec![Value::I32(1_000_000)], &long, None).await;
+    assert!(
+        matches!(&result, Err(ContractError::ExecutionError(message)) if message.contains("Gas limit exceeded")),
+        "Unexpected result: {:?}", result
+    );
+    assert_eq!(*long.gas_used.read().await, 1_000);
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_generated_contract_addresses() {
+    let mut runtime = setup_runtime().await;
+
+    let abi = ContractABI {
+        methods: vec![],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    let metadata = ContractMetadata {
+        version: "1.0.0".into(),
+        created_at: 1234567890,
+        updated_at: 1234567890,
+        author: TEST_ACCOUNT,
+        description: "Test Contract".into(),
+        is_upgradeable: true,
+        allow_major_upgrade: false,
+    };
+
+    let mut addresses = Vec::new();
+    for _ in 0..2 {
+        let nonce = runtime.deployment_nonce(&TEST_ACCOUNT);
+        let address = ContractRuntime::generate_address(&TEST_ACCOUNT, nonce);
+        runtime.deploy_contract(TEST_WASM, &address, &abi, metadata.clone(), &limits).await.unwrap();
+        addresses.push(address);
+    }
+
+    // Each deployment by the same account lands on its own address
+    assert_ne!(addresses[0], addresses[1]);
+    assert_eq!(runtime.deployment_nonce(&TEST_ACCOUNT), 2);
+
+    // Addresses can be recomputed from the deployer and nonce
+    assert_eq!(ContractRuntime::generate_address(&TEST_ACCOUNT, 0), addresses[0]);
+    assert_eq!(ContractRuntime::generate_address(&TEST_ACCOUNT, 1), addresses[1]);
+    assert_ne!(ContractRuntime::generate_address(&[1u8; 32], 0), addresses[0]);
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_abi_default_gas() {
+    let mut runtime = setup_runtime().await;
+    let contract_addr = [59u8; 32];
+
+    let i32_param = |name: &str| ContractParam {
+        name: name.into(),
+        param_type: "i32".into(),
+        indexed: false,
+    };
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "loop_test".into(),
+                inputs: vec![i32_param("iterations")],
+                outputs: vec![],
+                payable: false,
+                default_gas: Some(1_000),
+            },
+            ContractMethod {
+                name: "add".into(),
+                inputs: vec![i32_param("a"), i32_param("b")],
+                outputs: vec![i32_param("result")],
+                payable: false,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    let metadata = ContractMetadata {
+        version: "1.0.0".into(),
+        created_at: 1234567890,
+        updated_at: 1234567890,
+        author: TEST_ACCOUNT,
+        description: "Test Contract".into(),
+        is_upgradeable: true,
+        allow_major_upgrade: false,
+    };
+    runtime.deploy_contract(TEST_WASM, &contract_addr, &abi, metadata, &limits).await.unwrap();
+
+    // The caller leaves the gas limit to the contract
+    let env = ContractEnvironment {
+        gas_limit: None,
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value: 0,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+    assert_eq!(runtime.effective_gas_limit(&contract_addr, &abi.methods[0], &env), 1_000);
+    assert_eq!(runtime.effective_gas_limit(&contract_addr, &abi.methods[1], &env), 1_000_000);
+
+    let result = runtime.execute_contract(contract_addr, "loop_test", vec![Value::I32(1_000_000)], &env, None).await;
+    assert!(
+        matches!(&result, Err(ContractError::ExecutionError(message)) if message.contains("more than 1000 gas")),
+        "Unexpected result: {:?}", result
+    );
+    assert_eq!(*env.gas_used.read().await, 1_000);
+
+    // A limit given by the caller takes precedence over the default
+    let env = ContractEnvironment {
+        gas_limit: Some(5_000),
+        gas_used: Arc::new(RwLock::new(0)),
+        ..env
+    };
+    assert_eq!(runtime.effective_gas_limit(&contract_addr, &abi.methods[0], &env), 5_000);
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_contract_gas_ceiling() {
+    let mut runtime = setup_runtime().await;
+    let contract_addr = [46u8; 32];
+
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "loop_test".into(),
+                inputs: vec![ContractParam {
+                    name: "iterations".into(),
+                    param_type: "i32".into(),
+                    indexed: false,
+                }],
+                outputs: vec![],
+                payable: false,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    let metadata = ContractMetadata {
+        version: "1.0.0".into(),
+        created_at: 1234567890,
+        updated_at: 1234567890,
+        author: TEST_ACCOUNT,
+        description: "Test Contract".into(),
+        is_upgradeable: true,
+        allow_major_upgrade: false,
+    };
+    runtime.deploy_contract(TEST_WASM, &contract_addr, &abi, metadata, &limits).await.unwrap();
+
+    // The caller asks for far more gas than the contract allows
+    let env = ContractEnvironment {
+        gas_limit: Some(1_000_000_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value: 0,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+    assert_eq!(runtime.effective_gas_limit(&contract_addr, &abi.methods[0], &env), 1_000);
+
+    let result = runtime.execute_contract(contract_addr, "loop_test", vec![Value::I32(1_000_000)], &env, None).await;
+    assert!(
+        matches!(&result, Err(ContractError::ExecutionError(message)) if message.contains("more than 1000 gas")),
+        "Unexpected result: {:?}", result
+    );
+    assert_eq!(*env.gas_used.read().await, 1_000);
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_memory_limit_enforced() {
+    let mut runtime = setup_runtime().await;
+    let contract_addr = [46u8; 32];
+    let oversized_addr = [47u8; 32];
+
+    // Grows its memory a page at a time, aborting like an allocator would
+    // once the memory cannot grow
+    let growing_wat = r#"
+    (module
+      (import "env" "memory_limit" (func $memory_limit (result i64)))
+      (memory (export "memory") 1)
+      (func (export "grow") (param $pages i32) (result i32)
+        (block $done
+          (loop $next
+            (br_if $done (i32.eqz (local.get $pages)))
+            (if (i32.eq (memory.grow (i32.const 1)) (i32.const -1))
+              (then unreachable))
+            (local.set $pages (i32.sub (local.get $pages) (i32.const 1)))
+            (br $next)))
+        (memory.size))
+      (func (export "limit") (result i64)
+        (call $memory_limit)))
+    "#;
+    // Declares more initial memory than the limit allows
+    let oversized_wat = r#"
+    (module
+      (memory (export "memory") 8)
+      (func (export "limit") (result i64)
+        (i64.const 0)))
+    "#;
+
+    let i32_param = |name: &str| ContractParam {
+        name: name.into(),
+        param_type: "i32".into(),
+        indexed: false,
+    };
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "grow".into(),
+                inputs: vec![i32_param("pages")],
+                outputs: vec![i32_param("size")],
+                payable: false,
+                default_gas: None,
+            },
+            ContractMethod {
+                name: "limit".into(),
+                inputs: vec![],
+                outputs: vec![],
+                payable: false,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+
+    // Four 64KiB pages
+    let limits = ResourceLimits {
+        max_memory: 4 * 65536,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    let metadata = || ContractMetadata {
+        version: "1.0.0".into(),
+        created_at: 1234567890,
+        updated_at: 1234567890,
+        author: TEST_ACCOUNT,
+        description: "Test Contract".into(),
+        is_upgradeable: true,
+        allow_major_upgrade: false,
+    };
+    runtime.deploy_contract(growing_wat.as_bytes(), &contract_addr, &abi, metadata(), &limits).await.unwrap();
+
+    // A module that starts out larger than the limit is rejected at deployment
+    let result = runtime.deploy_contract(oversized_wat.as_bytes(), &oversized_addr, &abi, metadata(), &limits).await;
+    assert!(
+        matches!(&result, Err(ContractError::BytecodeVerificationError(message)) if message.contains("memory limit")),
+        "Unexpected result: {:?}", result
+    );
+
+    let env = ContractEnvironment {
+        gas_limit: Some(1_000_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value: 0,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+
+    // The contract can see its limit and grow up to it
+    let result = runtime.execute_contract(contract_addr, "limit", vec![], &env, None).await.unwrap();
+    assert_eq!(result, vec![Value::I64(4 * 65536)]);
+    let result = runtime.execute_contract(contract_addr, "grow", vec![Value::I32(3)], &env, None).await.unwrap();
+    assert_eq!(result, vec![Value::I32(4)]);
+
+    // Growing past it fails the execution
+    let result = runtime.execute_contract(contract_addr, "grow", vec![Value::I32(4)], &env, None).await;
+    assert!(
+        matches!(&result, Err(ContractError::ExecutionError(message)) if message.contains("Memory limit")),
+        "Unexpected result: {:?}", result
+    );
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_remove_contract_requires_admin() {
+    let mut runtime = setup_runtime().await;
+    let contract_addr = [58u8; 32];
+    let operator = [10u8; 32];
+
+    let abi = ContractABI {
+        methods: vec![],
+        events: vec![],
+        standards: vec![],
+    };
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+    let metadata = ContractMetadata {
+        version: "1.0.0".into(),
+        created_at: 1234567890,
+        updated_at: 1234567890,
+        author: TEST_ACCOUNT,
+        description: "Test Contract".into(),
+        is_upgradeable: true,
+        allow_major_upgrade: false,
+    };
+    runtime.deploy_contract(STORAGE_WAT.as_bytes(), &contract_addr, &abi, metadata, &limits).await.unwrap();
+
+    // Upgraders may self-destruct a contract but not remove it
+    runtime.grant_role(UPGRADER_ROLE, operator).unwrap();
+    msg::test_utils::set_sender(operator).unwrap();
+    assert!(matches!(runtime.remove_contract(&contract_addr), Err(ContractError::AccessDenied(_))));
+    assert!(runtime.contract_exists(&contract_addr));
+
+    msg::test_utils::set_sender(ADMIN_ACCOUNT).unwrap();
+    runtime.remove_contract(&contract_addr).unwrap();
+    assert!(!runtime.contract_exists(&contract_addr));
+    assert!(runtime.get_resource_limits(&contract_addr).is_none());
+    assert!(matches!(runtime.get_latest_version(&contract_addr), Err(ContractError::NotFound(_))));
+    assert!(matches!(runtime.remove_contract(&contract_addr), Err(ContractError::NotFound(_))));
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_gc_orphaned_state() {
+    let mut runtime = setup_runtime().await;
+    runtime.grant_role(UPGRADER_ROLE, TEST_ACCOUNT).unwrap();
+    let destroyed_addr = [48u8; 32];
+    let live_addr = [49u8; 32];
+
+    let i32_param = |name: &str| ContractParam {
+        name: name.into(),
+        param_type: "i32".into(),
+        indexed: false,
+    };
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "store".into(),
+                inputs: vec![i32_param("key"), i32_param("value")],
+                outputs: vec![],
+                payable: false,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    for addr in [&destroyed_addr, &live_addr] {
+        let metadata = ContractMetadata {
+            version: "1.0.0".into(),
+            created_at: 1234567890,
+            updated_at: 1234567890,
+            author: TEST_ACCOUNT,
+            description: "Test Contract".into(),
+            is_upgradeable: true,
+            allow_major_upgrade: false,
+        };
+        runtime.deploy_contract(STORAGE_WAT.as_bytes(), addr, &abi, metadata, &limits).await.unwrap();
+    }
+
+    let env = ContractEnvironment {
+        gas_limit: Some(1_000_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value: 0,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+    for addr in [destroyed_addr, live_addr] {
+        runtime.execute_contract(addr, "store", vec![Value::I32(1), Value::I32(42)], &env, None).await.unwrap();
+    }
+
+    // Nothing is orphaned while both contracts are registered
+    assert_eq!(runtime.gc_orphaned_state().unwrap(), 0);
+
+    runtime.self_destruct(&destroyed_addr).unwrap();
+    assert!(!runtime.contract_exists(&destroyed_addr));
+    assert!(runtime.execute_contract(destroyed_addr, "store", vec![Value::I32(1), Value::I32(7)], &env, None).await.is_err());
+
+    // The destroyed contract's state and history are reclaimed, the live one's are kept
+    let state_size = runtime.get_contract_state(&destroyed_addr).unwrap().iter()
+        .map(|(key, value)| key.len() + value.len())
+        .sum::<usize>();
+    let reclaimed = runtime.gc_orphaned_state().unwrap();
+    assert!(reclaimed > state_size, "Reclaimed {} bytes", reclaimed);
+    assert!(runtime.get_contract_state(&destroyed_addr).is_none());
+    assert!(runtime.get_state_snapshots(&destroyed_addr).is_none());
+    assert!(runtime.get_state_diffs(&destroyed_addr).is_none());
+    assert!(runtime.get_contract_state(&live_addr).is_some());
+    assert!(runtime.get_state_snapshots(&live_addr).is_some());
+
+    assert_eq!(runtime.gc_orphaned_state().unwrap(), 0);
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_emitted_events_logged() {
+    let mut runtime = setup_runtime().await;
+    let contract_addr = [50u8; 32];
+    let other_addr = [51u8; 32];
+
+    // Emits one event with a single topic and its argument as the data,
+    // optionally trapping afterwards
+    let events_wat = r#"
+    (module
+      (import "env" "emit" (func $emit (param i32 i32 i32 i32)))
+      (memory (export "memory") 1)
+      (data (i32.const 0) "Transfer")
+      (func $emit_value (param $value i32)
+        (i32.store (i32.const 32) (local.get $value))
+        (call $emit (i32.const 0) (i32.const 1) (i32.const 32) (i32.const 4)))
+      (func (export "transfer") (param $value i32)
+        (call $emit_value (local.get $value)))
+      (func (export "transfer_and_fail") (param $value i32)
+        (call $emit_value (local.get $value))
+        unreachable))
+    "#;
+
+    let abi = ContractABI {
+        methods: ["transfer", "transfer_and_fail"].iter()
+            .map(|name| ContractMethod {
+                name: name.to_string(),
+                inputs: vec![ContractParam {
+                    name: "value".into(),
+                    param_type: "i32".into(),
+                    indexed: false,
+                }],
+                outputs: vec![],
+                payable: false,
+                default_gas: None,
+            })
+            .collect(),
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    for addr in [&contract_addr, &other_addr] {
+        let metadata = ContractMetadata {
+            version: "1.0.0".into(),
+            created_at: 1234567890,
+            updated_at: 1234567890,
+            author: TEST_ACCOUNT,
+            description: "Test Contract".into(),
+            is_upgradeable: true,
+            allow_major_upgrade: false,
+        };
+        runtime.deploy_contract(events_wat.as_bytes(), addr, &abi, metadata, &limits).await.unwrap();
+    }
+
+    let env_at = |block_number| ContractEnvironment {
+        gas_limit: Some(1_000_000),
+        block_number,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value: 0,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+
+    runtime.execute_contract(contract_addr, "transfer", vec![Value::I32(7)], &env_at(1), None).await.unwrap();
+    runtime.execute_contract(contract_addr, "transfer", vec![Value::I32(8)], &env_at(5), None).await.unwrap();
+    runtime.execute_contract(other_addr, "transfer", vec![Value::I32(9)], &env_at(5), None).await.unwrap();
+
+    // Events of a failed execution are not logged
+    let result = runtime.execute_contract(contract_addr, "transfer_and_fail", vec![Value::I32(10)], &env_at(5), None).await;
+    assert!(result.is_err());
+
+    let mut topic = [0u8; 32];
+    topic[..8].copy_from_slice(b"Transfer");
+
+    let logs = runtime.get_logs(&contract_addr, 0, 10);
+    assert_eq!(logs.len(), 2);
+    assert!(logs.iter().all(|event| event.contract_addr == contract_addr && event.topics == vec![topic]));
+    assert_eq!(logs[0].block_number, 1);
+    assert_eq!(logs[0].data, 7i32.to_le_bytes());
+    assert_eq!(logs[1].block_number, 5);
+    assert_eq!(logs[1].data, 8i32.to_le_bytes());
+
+    // The block range is inclusive at both ends
+    assert_eq!(runtime.get_logs(&contract_addr, 1, 1), logs[..1]);
+    assert_eq!(runtime.get_logs(&contract_addr, 2, 5), logs[1..]);
+    assert!(runtime.get_logs(&contract_addr, 6, 10).is_empty());
+
+    let other_logs = runtime.get_logs(&other_addr, 0, 10);
+    assert_eq!(other_logs.len(), 1);
+    assert_eq!(other_logs[0].data, 9i32.to_le_bytes());
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_simulate_contract() {
+    let mut runtime = setup_runtime().await;
+    let contract_addr = [56u8; 32];
+
+    // Stores a value and returns what storage then holds for its key
+    let store_value_wat = r#"
+    (module
+      (import "env" "storage_read" (func $storage_read (param i32 i32) (result i64)))
+      (import "env" "storage_write" (func $storage_write (param i32 i32 i32 i32)))
+      (memory (export "memory") 1)
+      (global $heap (mut i32) (i32.const 1024))
+      (func (export "alloc") (param $size i32) (result i32)
+        (global.get $heap)
+        (global.set $heap (i32.add (global.get $heap) (local.get $size))))
+      (func (export "store_value") (param $key i32) (param $value i32) (result i32)
+        (i32.store (i32.const 0) (local.get $key))
+        (i32.store (i32.const 4) (local.get $value))
+        (call $storage_write (i32.const 0) (i32.const 4) (i32.const 4) (i32.const 4))
+        (i32.load (i32.wrap_i64 (i64.shr_u
+          (call $storage_read (i32.const 0) (i32.const 4))
+          (i64.const 32))))))
+    "#;
+
+    let i32_param = |name: &str| ContractParam {
+        name: name.into(),
+        param_type: "i32".into(),
+        indexed: false,
+    };
+    let abi = ContractABI {
+        methods: vec![ContractMethod {
+            name: "store_value".into(),
+            inputs: vec![i32_param("key"), i32_param("value")],
+            outputs: vec![i32_param("stored")],
+            payable: false,
+            default_gas: None,
+        }],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+    let metadata = ContractMetadata {
+        version: "1.0.0".into(),
+        created_at: 1234567890,
+        updated_at: 1234567890,
+        author: TEST_ACCOUNT,
+        description: "Test Contract".into(),
+        is_upgradeable: true,
+        allow_major_upgrade: false,
+    };
+    runtime.deploy_contract(store_value_wat.as_bytes(), &contract_addr, &abi, metadata, &limits).await.unwrap();
+
+    let env = ContractEnvironment {
+        gas_limit: Some(1_000_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value: 0,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+    let state_before = runtime.get_contract_state(&contract_addr).cloned();
+    let snapshots_before = runtime.get_state_snapshots(&contract_addr).unwrap().len();
+
+    // The simulated call sees its own write and reports its gas
+    let (values, gas_used) = runtime.simulate_contract(contract_addr, "store_value", vec![Value::I32(1), Value::I32(42)], &env)
+        .await
+        .unwrap();
+    assert_eq!(values[0].unwrap_i32(), 42);
+    assert!(gas_used > 0);
+
+    // Nothing of it is kept
+    assert_eq!(runtime.get_contract_state(&contract_addr).cloned(), state_before);
+    assert_eq!(runtime.get_state_snapshots(&contract_addr).unwrap().len(), snapshots_before);
+    assert_eq!(runtime.get_active_operations(), 0);
+    assert_eq!(*env.gas_used.read().await, 0);
+
+    // Executing for real charges the same gas and keeps the write
+    let values = runtime.execute_contract(contract_addr, "store_value", vec![Value::I32(1), Value::I32(42)], &env, None)
+        .await
+        .unwrap();
+    assert_eq!(values[0].unwrap_i32(), 42);
+    assert_eq!(*env.gas_used.read().await, gas_used);
+    let stored = runtime.get_contract_state(&contract_addr).unwrap().get(1i32.to_le_bytes().as_slice()).cloned();
+    assert_eq!(stored, Some(42i32.to_le_bytes().to_vec()));
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_execute_contract_metered() {
+    let mut runtime = setup_runtime().await;
+    let contract_addr = [57u8; 32];
+
+    let i32_param = |name: &str| ContractParam {
+        name: name.into(),
+        param_type: "i32".into(),
+        indexed: false,
+    };
+    let abi = ContractABI {
+        methods: vec![ContractMethod {
+            name: "add".into(),
+            inputs: vec![i32_param("a"), i32_param("b")],
+            outputs: vec![i32_param("result")],
+            payable: false,
+            default_gas: None,
+        }],
+        events: vec![],
+        standards: vec![],
+    };
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+    let metadata = ContractMetadata {
+        version: "1.0.0".into(),
+        created_at: 1234567890,
+        updated_at: 1234567890,
+        author: TEST_ACCOUNT,
+        description: "Test Contract".into(),
+        is_upgradeable: true,
+        allow_major_upgrade: false,
+    };
+    runtime.deploy_contract(STORAGE_WAT.as_bytes(), &contract_addr, &abi, metadata, &limits).await.unwrap();
+
+    let env = ContractEnvironment {
+        gas_limit: Some(1_000_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value: 0,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+
+    // The reported gas is what the environment was charged
+    let result = runtime.execute_contract_metered(contract_addr, "add", vec![Value::I32(5), Value::I32(3)], &env, None)
+        .await
+        .unwrap();
+    assert_eq!(result.values[0].unwrap_i32(), 8);
+    assert!(result.gas_used > 0);
+    assert_eq!(*env.gas_used.read().await, result.gas_used);
+
+    // Each execution reports only its own gas, the environment keeps the total
+    let second = runtime.execute_contract_metered(contract_addr, "add", vec![Value::I32(5), Value::I32(3)], &env, None)
+        .await
+        .unwrap();
+    assert_eq!(second.gas_used, result.gas_used);
+    assert_eq!(*env.gas_used.read().await, result.gas_used + second.gas_used);
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_pause_contract() {
+    let mut runtime = setup_runtime().await;
+    let contract_addr = [55u8; 32];
+
+    let i32_param = |name: &str| ContractParam {
+        name: name.into(),
+        param_type: "i32".into(),
+        indexed: false,
+    };
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "add".into(),
+                inputs: vec![i32_param("a"), i32_param("b")],
+                outputs: vec![i32_param("result")],
+                payable: false,
+                default_gas: None,
+            },
+            ContractMethod {
+                name: "store".into(),
+                inputs: vec![i32_param("key"), i32_param("value")],
+                outputs: vec![],
+                payable: false,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+    let metadata = ContractMetadata {
+        version: "1.0.0".into(),
+        created_at: 1234567890,
+        updated_at: 1234567890,
+        author: TEST_ACCOUNT,
+        description: "Test Contract".into(),
+        is_upgradeable: true,
+        allow_major_upgrade: false,
+    };
+    runtime.deploy_contract(STORAGE_WAT.as_bytes(), &contract_addr, &abi, metadata, &limits).await.unwrap();
+
+    let env = ContractEnvironment {
+        gas_limit: Some(1_000_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value: 0,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+    let stored = |runtime: &ContractRuntime| runtime.get_contract_state(&contract_addr).unwrap()
+        .get(1i32.to_le_bytes().as_slice())
+        .cloned();
+
+    // Pausing needs the pauser role
+    assert!(matches!(runtime.pause_contract(&contract_addr), Err(ContractError::AccessDenied(_))));
+    runtime.grant_role(PAUSER_ROLE, TEST_ACCOUNT).unwrap();
+    runtime.pause_contract(&contract_addr).unwrap();
+    assert!(runtime.is_paused(&contract_addr));
+
+    // Writes are blocked while paused, reads still work
+    let result = runtime.execute_contract(contract_addr, "store", vec![Value::I32(1), Value::I32(10)], &env, None).await;
+    assert!(
+        matches!(&result, Err(ContractError::InvalidOperation(message)) if message == "Contract is paused"),
+        "Unexpected result: {:?}", result
+    );
+    assert_eq!(stored(&runtime), None);
+    let result = runtime.execute_contract(contract_addr, "add", vec![Value::I32(1), Value::I32(2)], &env, None).await.unwrap();
+    assert_eq!(result[0].unwrap_i32(), 3);
+
+    // Unpausing resumes normal execution
+    runtime.unpause_contract(&contract_addr).unwrap();
+    assert!(!runtime.is_paused(&contract_addr));
+    runtime.execute_contract(contract_addr, "store", vec![Value::I32(1), Value::I32(10)], &env, None).await.unwrap();
+    assert_eq!(stored(&runtime), Some(10i32.to_le_bytes().to_vec()));
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_value_only_sent_to_payable_methods() {
+    let mut runtime = setup_runtime().await;
+    let contract_addr = [54u8; 32];
+
+    let i32_param = |name: &str| ContractParam {
+        name: name.into(),
+        param_type: "i32".into(),
+        indexed: false,
+    };
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "add".into(),
+                inputs: vec![i32_param("a"), i32_param("b")],
+                outputs: vec![i32_param("result")],
+                payable: false,
+                default_gas: None,
+            },
+            ContractMethod {
+                name: "store".into(),
+                inputs: vec![i32_param("key"), i32_param("value")],
+                outputs: vec![],
+                payable: true,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+    let metadata = ContractMetadata {
+        version: "1.0.0".into(),
+        created_at: 1234567890,
+        updated_at: 1234567890,
+        author: TEST_ACCOUNT,
+        description: "Test Contract".into(),
+        is_upgradeable: true,
+        allow_major_upgrade: false,
+    };
+    runtime.deploy_contract(STORAGE_WAT.as_bytes(), &contract_addr, &abi, metadata, &limits).await.unwrap();
+
+    let env = |value| ContractEnvironment {
+        gas_limit: Some(1_000_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+    let args = vec![Value::I32(1), Value::I32(2)];
+
+    // Value attached to a non-payable method is rejected before it runs
+    let result = runtime.execute_contract(contract_addr, "add", args.clone(), &env(100), None).await;
+    assert!(
+        matches!(&result, Err(ContractError::InvalidArguments(message)) if message.contains("not payable")),
+        "Unexpected result: {:?}", result
+    );
+
+    // Without value it runs as before, and payable methods accept value
+    let result = runtime.execute_contract(contract_addr, "add", args.clone(), &env(0), None).await.unwrap();
+    assert_eq!(result[0].unwrap_i32(), 3);
+    runtime.execute_contract(contract_addr, "store", args, &env(100), None).await.unwrap();
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_read_only_calls_skip_snapshots() {
+    let mut runtime = setup_runtime().await;
+    let contract_addr = [53u8; 32];
+
+    let i32_param = |name: &str| ContractParam {
+        name: name.into(),
+        param_type: "i32".into(),
+        indexed: false,
+    };
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "add".into(),
+                inputs: vec![i32_param("a"), i32_param("b")],
+                outputs: vec![i32_param("result")],
+                payable: false,
+                default_gas: None,
+            },
+            ContractMethod {
+                name: "store".into(),
+                inputs: vec![i32_param("key"), i32_param("value")],
+                outputs: vec![],
+                payable: false,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+    let metadata = ContractMetadata {
+        version: "1.0.0".into(),
+        created_at: 1234567890,
+        updated_at: 1234567890,
+        author: TEST_ACCOUNT,
+        description: "Test Contract".into(),
+        is_upgradeable: true,
+        allow_major_upgrade: false,
+    };
+    runtime.deploy_contract(STORAGE_WAT.as_bytes(), &contract_addr, &abi, metadata, &limits).await.unwrap();
+    let snapshot_count = |runtime: &ContractRuntime| runtime.get_state_snapshots(&contract_addr).unwrap().len();
+    let deployed = snapshot_count(&runtime);
+
+    let env = ContractEnvironment {
+        gas_limit: Some(1_000_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value: 0,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+
+    // Calls that write nothing leave the snapshot history alone
+    for _ in 0..5 {
+        runtime.execute_contract(contract_addr, "add", vec![Value::I32(1), Value::I32(2)], &env, None).await.unwrap();
+    }
+    assert_eq!(snapshot_count(&runtime), deployed);
+
+    // A call that writes snapshots the state it changes
+    runtime.execute_contract(contract_addr, "store", vec![Value::I32(1), Value::I32(10)], &env, None).await.unwrap();
+    assert_eq!(snapshot_count(&runtime), deployed + 1);
+    let snapshot = runtime.get_state_snapshots(&contract_addr).unwrap().last().unwrap();
+    assert!(!snapshot.state.contains_key(1i32.to_le_bytes().as_slice()));
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_storage_persists_between_executions() {
+    let mut runtime = setup_runtime().await;
+    let contract_addr = [52u8; 32];
+
+    // Stores i32 values under i32 keys, reading them back through a bump
+    // allocator the host copies values into
+    let counter_wat = r#"
+    (module
+      (import "env" "storage_read" (func $storage_read (param i32 i32) (result i64)))
+      (import "env" "storage_write" (func $storage_write (param i32 i32 i32 i32)))
+      (memory (export "memory") 1)
+      (global $heap (mut i32) (i32.const 1024))
+      (func (export "alloc") (param $size i32) (result i32)
+        (global.get $heap)
+        (global.set $heap (i32.add (global.get $heap) (local.get $size))))
+      (func (export "set") (param $key i32) (param $value i32)
+        (i32.store (i32.const 0) (local.get $key))
+        (i32.store (i32.const 4) (local.get $value))
+        (call $storage_write (i32.const 0) (i32.const 4) (i32.const 4) (i32.const 4)))
+      (func (export "get") (param $key i32) (result i32)
+        (local $found i64)
+        (i32.store (i32.const 0) (local.get $key))
+        (local.set $found (call $storage_read (i32.const 0) (i32.const 4)))
+        (if (result i32) (i64.eq (local.get $found) (i64.const -1))
+          (then (i32.const -1))
+          (else (i32.load (i32.wrap_i64 (i64.shr_u (local.get $found) (i64.const 32)))))))
+      (func (export "set_oversized")
+        (call $storage_write (i32.const 0) (i32.const 2048) (i32.const 0) (i32.const 4))))
+    "#;
+
+    let i32_param = |name: &str| ContractParam {
+        name: name.into(),
+        param_type: "i32".into(),
+        indexed: false,
+    };
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "set".into(),
+                inputs: vec![i32_param("key"), i32_param("value")],
+                outputs: vec![],
+                payable: false,
+                default_gas: None,
+            },
+            ContractMethod {
+                name: "get".into(),
+                inputs: vec![i32_param("key")],
+                outputs: vec![i32_param("value")],
+                payable: false,
+                default_gas: None,
+            },
+            ContractMethod {
+                name: "set_oversized".into(),
+                inputs: vec![],
+                outputs: vec![],
+                payable: false,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+    let metadata = ContractMetadata {
+        version: "1.0.0".into(),
+        created_at: 1234567890,
+        updated_at: 1234567890,
+        author: TEST_ACCOUNT,
+        description: "Test Contract".into(),
+        is_upgradeable: true,
+        allow_major_upgrade: false,
+    };
+    runtime.deploy_contract(counter_wat.as_bytes(), &contract_addr, &abi, metadata, &limits).await.unwrap();
+
+    let env = ContractEnvironment {
+        gas_limit: Some(1_000_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value: 0,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+
+    let result = runtime.execute_contract(contract_addr, "get", vec![Value::I32(7)], &env, None).await.unwrap();
+    assert_eq!(result, vec![Value::I32(-1)]);
+
+    // A value written by one execution is read back by the next
+    runtime.execute_contract(contract_addr, "set", vec![Value::I32(7), Value::I32(1234)], &env, None).await.unwrap();
+    let result = runtime.execute_contract(contract_addr, "get", vec![Value::I32(7)], &env, None).await.unwrap();
+    assert_eq!(result, vec![Value::I32(1234)]);
+    assert_eq!(
+        runtime.get_contract_state(&contract_addr).unwrap().get(&7i32.to_le_bytes().to_vec()),
+        Some(&1234i32.to_le_bytes().to_vec())
+    );
+
+    // Simulations read the same stored state
+    let (result, _) = runtime.simulate_contract(contract_addr, "get", vec![Value::I32(7)], &env).await.unwrap();
+    assert_eq!(result, vec![Value::I32(1234)]);
+
+    // Keys over the state size limit are rejected
+    let result = runtime.execute_contract(contract_addr, "set_oversized", vec![], &env, None).await;
+    assert!(matches!(result, Err(ContractError::ExecutionError(_))), "Unexpected result: {:?}", result);
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_non_deterministic_bytecode_rejected() {
+    let mut runtime = setup_runtime().await;
+
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "run".into(),
+                inputs: vec![],
+                outputs: vec![],
+                payable: false,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+    let limits = ResourceLimits {
+        max_memory: 4 * 65536,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+    let metadata = || ContractMetadata {
+        version: "1.0.0".into(),
+        created_at: 1234567890,
+        updated_at: 1234567890,
+        author: TEST_ACCOUNT,
+        description: "Test Contract".into(),
+        is_upgradeable: true,
+        allow_major_upgrade: false,
+    };
+
+    let float_wat = r#"
+    (module
+      (func (export "run") (result f64)
+        (f64.div (f64.const 1) (f64.const 3))))
+    "#;
+    let import_wat = r#"
+    (module
+      (import "wasi_snapshot_preview1" "clock_time_get" (func $clock (param i32 i64 i32) (result i32)))
+      (func (export "run")))
+    "#;
+    let growing_wat = r#"
+    (module
+      (memory 1)
+      (func (export "run") (result i32)
+        (memory.grow (i32.const 100))))
+    "#;
+    // Floats reached only through a reinterpreted local are still floats
+    let reinterpret_wat = r#"
+    (module
+      (func (export "run") (result i32)
+        (local f32)
+        (i32.reinterpret_f32 (local.get 0))))
+    "#;
+    // Only the wasm-bindgen imports used to describe bindings are stubbed
+    let bindgen_wat = r#"
+    (module
+      (import "__wbindgen_placeholder__" "__wbindgen_throw" (func $throw (param i32 i32)))
+      (func (export "run")))
+    "#;
+    let rejected = [
+        ([60u8; 32], float_wat, "Floating point"),
+        ([61u8; 32], import_wat, "clock_time_get"),
+        ([62u8; 32], growing_wat, "memory.grow"),
+        ([64u8; 32], reinterpret_wat, "Floating point"),
+        ([65u8; 32], bindgen_wat, "__wbindgen_throw"),
+    ];
+    for (addr, wat, feature) in rejected {
+        let result = runtime.deploy_contract(wat.as_bytes(), &addr, &abi, metadata(), &limits).await;
+        assert!(
+            matches!(&result, Err(ContractError::BytecodeVerificationError(message)) if message.contains(feature)),
+            "Unexpected result: {:?}", result
+        );
+        assert!(!runtime.contract_exists(&addr));
+    }
+
+    // Integer arithmetic over allowed host functions deploys and runs
+    let integer_wat = r#"
+    (module
+      (import "env" "gas" (func $gas (param i32)))
+      (memory 1)
+      (func (export "run") (result i64)
+        (call $gas (i32.const 1))
+        (drop (memory.grow (i32.const 1)))
+        (i64.div_s (i64.const 10) (i64.const 3))))
+    "#;
+    let addr = [63u8; 32];
+    runtime.deploy_contract(integer_wat.as_bytes(), &addr, &abi, metadata(), &limits).await.unwrap();
+
+    let env = ContractEnvironment {
+        gas_limit: Some(1_000_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value: 0,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+    let result = runtime.execute_contract(addr, "run", vec![], &env, None).await.unwrap();
+    assert_eq!(result, vec![Value::I64(3)]);
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_event_subscription_filters_by_topic() {
+    let mut runtime = setup_runtime().await;
+    let contract_addr = [64u8; 32];
+
+    // Emits Transfer or Approval events with the recipient as the indexed
+    // topic following the event name
+    let escape = |topic: [u8; 32]| topic.iter().map(|byte| format!("\\{:02x}", byte)).collect::<String>();
+    let events_wat = format!(r#"
+    (module
+      (import "env" "emit" (func $emit (param i32 i32 i32 i32)))
+      (memory (export "memory") 1)
+      (data (i32.const 0) "{}")
+      (data (i32.const 64) "{}")
+      (func $emit_indexed (param $topics i32) (param $to i32)
+        (i32.store (i32.add (local.get $topics) (i32.const 32)) (local.get $to))
+        (call $emit (local.get $topics) (i32.const 2) (i32.add (local.get $topics) (i32.const 32)) (i32.const 4)))
+      (func (export "transfer") (param $to i32)
+        (call $emit_indexed (i32.const 0) (local.get $to)))
+      (func (export "approve") (param $to i32)
+        (call $emit_indexed (i32.const 64) (local.get $to))))
+    "#, escape(EventFilter::event_topic("Transfer")), escape(EventFilter::event_topic("Approval")));
+
+    let abi = ContractABI {
+        methods: ["transfer", "approve"].iter()
+            .map(|name| ContractMethod {
+                name: name.to_string(),
+                inputs: vec![ContractParam {
+                    name: "to".into(),
+                    param_type: "i32".into(),
+                    indexed: true,
+                }],
+                outputs: vec![],
+                payable: false,
+                default_gas: None,
+            })
+            .collect(),
+        events: vec![],
+        standards: vec![],
+    };
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+    let metadata = ContractMetadata {
+        version: "1.0.0".into(),
+        created_at: 1234567890,
+        updated_at: 1234567890,
+        author: TEST_ACCOUNT,
+        description: "Test Contract".into(),
+        is_upgradeable: true,
+        allow_major_upgrade: false,
+    };
+    runtime.deploy_contract(events_wat.as_bytes(), &contract_addr, &abi, metadata, &limits).await.unwrap();
+
+    // Subscribe to transfers to recipient 2, as a subscription message would
+    let recipient = |to: i32| {
+        let mut topic = [0u8; 32];
+        topic[..4].copy_from_slice(&to.to_le_bytes());
+        topic
+    };
+    let filter: EventFilter = serde_json::from_value(serde_json::json!({
+        "contract_addr": contract_addr,
+        "event": "Transfer",
+        "indexed": [recipient(2)],
+    })).unwrap();
+    let mut transfers_to_2 = runtime.subscribe_events(filter);
+    let mut everything = runtime.subscribe_events(EventFilter::default());
+
+    let env = ContractEnvironment {
+        gas_limit: Some(1_000_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value: 0,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+    for (method, to) in [("transfer", 1), ("transfer", 2), ("approve", 2)] {
+        runtime.execute_contract(contract_addr, method, vec![Value::I32(to)], &env, None).await.unwrap();
+    }
+
+    // Only the matching transfer is delivered to the filtered subscriber
+    let event = transfers_to_2.try_recv().unwrap();
+    assert_eq!(event.topics, vec![EventFilter::event_topic("Transfer"), recipient(2)]);
+    assert_eq!(event.data, 2i32.to_le_bytes());
+    assert!(transfers_to_2.try_recv().is_err());
+
+    let mut received = 0;
+    while everything.try_recv().is_ok() {
+        received += 1;
+    }
+    assert_eq!(received, 3);
+
+    // Clean up
+    msg::test_utils::clear_sender().unwrap();
+}
@@ -31,6 +31,7 @@ fn create_test_metadata(version: &str) -> ContractMetadata {
         author: [0u8; 32],
         description: "Test Contract".to_string(),
         is_upgradeable: true,
+        allow_major_upgrade: false,
     }
 }
 
@@ -50,6 +51,7 @@ fn create_test_abi() -> ContractABI {
                 indexed: false,
             }],
             payable: false,
+            default_gas: None,
         }],
         events: vec![],
         standards: vec!["test".to_string()],
@@ -81,10 +83,10 @@ fn setup_test_account(runtime: &mut ContractRuntime) -> Result<(), Box<dyn std::
     blockchain::msg::test_utils::clear_sender()?;
     
     // Set up test sender with admin role
-    let admin = [0u8; 32]; // Use DEFAULT_ADMIN_ROLE value
+    let admin = [1u8; 32];
     blockchain::msg::test_utils::set_sender(admin)?;
     
-    // First grant admin role (this should work since we're using the default admin account)
+    // First grant admin role (this should work as it is the first admin role grant)
     runtime.grant_role(DEFAULT_ADMIN_ROLE, admin)?;
     
     // Now grant deployer role
@@ -1,7 +1,7 @@
 use blockchain::contract::{
     ContractRuntime, ContractEnvironment, ResourceLimits, ContractABI,
     ContractMethod, ContractParam, ContractMetadata, DEPLOYER_ROLE, EXECUTOR_ROLE, 
-    DEFAULT_ADMIN_ROLE, UPGRADER_ROLE,
+    DEFAULT_ADMIN_ROLE, UPGRADER_ROLE, ContractError, StateMigration,
 };
 use blockchain::msg;
 use wasmer::Value;
@@ -12,9 +12,21 @@ use tokio::sync::RwLock;
 const TEST_WASM_V1: &[u8] = include_bytes!("fixtures/test_contract.wasm");
 const TEST_WASM_V2: &[u8] = include_bytes!("fixtures/test_contract_v2/target/wasm32-unknown-unknown/release/test_contract_v2.wasm");
 
+// Contract with a `store` method writing its arguments, little endian,
+// through the host storage interface
+const STORAGE_WAT: &str = r#"
+(module
+  (import "env" "storage_write" (func $storage_write (param i32 i32 i32 i32)))
+  (memory (export "memory") 1)
+  (func (export "store") (param $key i32) (param $value i32)
+    (i32.store (i32.const 0) (local.get $key))
+    (i32.store (i32.const 4) (local.get $value))
+    (call $storage_write (i32.const 0) (i32.const 4) (i32.const 4) (i32.const 4))))
+"#;
+
 // Test accounts
-const TEST_ACCOUNT: [u8; 32] = [0u8; 32];
-const ADMIN_ACCOUNT: [u8; 32] = [0u8; 32];
+const TEST_ACCOUNT: [u8; 32] = [9u8; 32];
+const ADMIN_ACCOUNT: [u8; 32] = [9u8; 32];
 const UPGRADER_ACCOUNT: [u8; 32] = [1u8; 32];
 
 async fn setup_runtime() -> ContractRuntime {
@@ -58,6 +70,7 @@ async fn test_version_upgrade_authorization() {
                     },
                 ],
                 payable: false,
+                default_gas: None,
             },
         ],
         events: vec![],
@@ -65,7 +78,7 @@ async fn test_version_upgrade_authorization() {
     };
 
     let limits = ResourceLimits {
-        max_memory: 1024 * 1024,
+        max_memory: 2 * 1024 * 1024,
         max_gas: 1_000_000,
         max_storage: 1024 * 1024,
         max_call_depth: 5,
@@ -78,6 +91,7 @@ async fn test_version_upgrade_authorization() {
         author: TEST_ACCOUNT,
         description: "Test Contract V1".into(),
         is_upgradeable: true,
+        allow_major_upgrade: false,
     };
 
     // Deploy v1
@@ -90,6 +104,7 @@ async fn test_version_upgrade_authorization() {
         author: UPGRADER_ACCOUNT,
         description: "Test Contract V2".into(),
         is_upgradeable: true,
+        allow_major_upgrade: true,
     };
 
     // Try to upgrade without proper role (using test account)
@@ -127,6 +142,7 @@ async fn test_version_compatibility() {
                 ],
                 outputs: vec![],
                 payable: false,
+                default_gas: None,
             },
             ContractMethod {
                 name: "get_value".into(),
@@ -139,6 +155,7 @@ async fn test_version_compatibility() {
                     },
                 ],
                 payable: false,
+                default_gas: None,
             },
         ],
         events: vec![],
@@ -146,7 +163,7 @@ async fn test_version_compatibility() {
     };
 
     let limits = ResourceLimits {
-        max_memory: 1024 * 1024,
+        max_memory: 2 * 1024 * 1024,
         max_gas: 1_000_000,
         max_storage: 1024 * 1024,
         max_call_depth: 5,
@@ -159,6 +176,7 @@ async fn test_version_compatibility() {
         author: TEST_ACCOUNT,
         description: "Test Contract V1".into(),
         is_upgradeable: true,
+        allow_major_upgrade: false,
     };
 
     // Deploy v1
@@ -166,10 +184,11 @@ async fn test_version_compatibility() {
 
     // Store initial value
     let env = ContractEnvironment {
-        gas_limit: 1_000_000,
+        gas_limit: Some(1_000_000),
         block_number: 1,
         timestamp: 1234567890,
         caller: TEST_ACCOUNT,
+        value: 0,
         resource_limits: limits.clone(),
         gas_used: Arc::new(RwLock::new(0)),
     };
@@ -199,6 +218,7 @@ async fn test_version_compatibility() {
                     },
                 ],
                 payable: false,
+                default_gas: None,
             },
         ],
         events: vec![],
@@ -212,6 +232,7 @@ async fn test_version_compatibility() {
         author: UPGRADER_ACCOUNT,
         description: "Test Contract V2".into(),
         is_upgradeable: true,
+        allow_major_upgrade: true,
     };
 
     let result = runtime.deploy_contract(TEST_WASM_V2, &contract_addr, &incompatible_abi, metadata_v2, &limits).await;
@@ -241,6 +262,7 @@ async fn test_state_persistence() {
                 ],
                 outputs: vec![],
                 payable: false,
+                default_gas: None,
             },
             ContractMethod {
                 name: "get_value".into(),
@@ -253,6 +275,7 @@ async fn test_state_persistence() {
                     },
                 ],
                 payable: false,
+                default_gas: None,
             },
         ],
         events: vec![],
@@ -260,7 +283,7 @@ async fn test_state_persistence() {
     };
 
     let limits = ResourceLimits {
-        max_memory: 1024 * 1024,
+        max_memory: 2 * 1024 * 1024,
         max_gas: 1_000_000,
         max_storage: 1024 * 1024,
         max_call_depth: 5,
@@ -273,16 +296,18 @@ async fn test_state_persistence() {
         author: TEST_ACCOUNT,
         description: "Test Contract V1".into(),
         is_upgradeable: true,
+        allow_major_upgrade: false,
     };
 
     // Deploy v1
     runtime.deploy_contract(TEST_WASM_V1, &contract_addr, &abi, metadata_v1, &limits).await.unwrap();
 
     let env = ContractEnvironment {
-        gas_limit: 1_000_000,
+        gas_limit: Some(1_000_000),
         block_number: 1,
         timestamp: 1234567890,
         caller: TEST_ACCOUNT,
+        value: 0,
         resource_limits: limits.clone(),
         gas_used: Arc::new(RwLock::new(0)),
     };
@@ -306,6 +331,7 @@ async fn test_state_persistence() {
         author: UPGRADER_ACCOUNT,
         description: "Test Contract V2".into(),
         is_upgradeable: true,
+        allow_major_upgrade: true,
     };
 
     // Upgrade to v2
@@ -348,6 +374,7 @@ async fn test_rollback_functionality() {
                     },
                 ],
                 payable: false,
+                default_gas: None,
             },
         ],
         events: vec![],
@@ -355,7 +382,7 @@ async fn test_rollback_functionality() {
     };
 
     let limits = ResourceLimits {
-        max_memory: 1024 * 1024,
+        max_memory: 2 * 1024 * 1024,
         max_gas: 1_000_000,
         max_storage: 1024 * 1024,
         max_call_depth: 5,
@@ -368,6 +395,7 @@ async fn test_rollback_functionality() {
         author: TEST_ACCOUNT,
         description: "Test Contract V1".into(),
         is_upgradeable: true,
+        allow_major_upgrade: false,
     };
 
     // Deploy v1
@@ -383,16 +411,18 @@ async fn test_rollback_functionality() {
         author: UPGRADER_ACCOUNT,
         description: "Test Contract V2".into(),
         is_upgradeable: true,
+        allow_major_upgrade: true,
     };
 
     // Upgrade to v2
     runtime.deploy_contract(TEST_WASM_V2, &contract_addr, &abi, metadata_v2, &limits).await.unwrap();
 
     let env = ContractEnvironment {
-        gas_limit: 1_000_000,
+        gas_limit: Some(1_000_000),
         block_number: 1,
         timestamp: 1234567890,
         caller: TEST_ACCOUNT,
+        value: 0,
         resource_limits: limits.clone(),
         gas_used: Arc::new(RwLock::new(0)),
     };
@@ -423,3 +453,375 @@ async fn test_rollback_functionality() {
     // Clean up
     msg::test_utils::clear_sender().unwrap();
 }
+
+#[tokio::test]
+async fn test_execute_rolled_back_version() {
+    let mut runtime = setup_runtime().await;
+    runtime.grant_role(UPGRADER_ROLE, TEST_ACCOUNT).unwrap();
+    let contract_addr = [3u8; 32];
+
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "add".into(),
+                inputs: vec![
+                    ContractParam {
+                        name: "a".into(),
+                        param_type: "i32".into(),
+                        indexed: false,
+                    },
+                    ContractParam {
+                        name: "b".into(),
+                        param_type: "i32".into(),
+                        indexed: false,
+                    },
+                ],
+                outputs: vec![
+                    ContractParam {
+                        name: "result".into(),
+                        param_type: "i32".into(),
+                        indexed: false,
+                    },
+                ],
+                payable: false,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    let metadata = |version: &str, updated_at| ContractMetadata {
+        version: version.into(),
+        created_at: 1234567890,
+        updated_at,
+        author: TEST_ACCOUNT,
+        description: format!("Test Contract {}", version),
+        is_upgradeable: true,
+        allow_major_upgrade: true,
+    };
+    runtime.deploy_contract(TEST_WASM_V1, &contract_addr, &abi, metadata("1.0.0", 1234567890), &limits).await.unwrap();
+    runtime.upgrade_contract(&contract_addr, TEST_WASM_V1, &abi, metadata("2.0.0", 1234567891), None).await.unwrap();
+
+    runtime.rollback_contract(&contract_addr).await.unwrap();
+
+    let env = ContractEnvironment {
+        gas_limit: Some(1_000_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value: 0,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+    let args = vec![Value::I32(1), Value::I32(2)];
+
+    // Executing the rolled back version explains why it is unavailable
+    let err = runtime.execute_contract(contract_addr, "add", args.clone(), &env, Some("2.0.0"))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ContractError::VersionRolledBack(_)));
+    assert!(err.to_string().contains("removed by a rollback"));
+
+    // A version that never existed is still reported as not found
+    let err = runtime.execute_contract(contract_addr, "add", args.clone(), &env, Some("3.0.0"))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ContractError::VersionNotFound(_)));
+
+    // Without an explicit version the current latest is used
+    let result = runtime.execute_contract(contract_addr, "add", args, &env, None).await.unwrap();
+    assert_eq!(result[0].unwrap_i32(), 3);
+}
+
+#[tokio::test]
+async fn test_rollback_restores_version_schema() {
+    let mut runtime = setup_runtime().await;
+    runtime.grant_role(UPGRADER_ROLE, TEST_ACCOUNT).unwrap();
+    let contract_addr = [4u8; 32];
+
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "add".into(),
+                inputs: vec![
+                    ContractParam {
+                        name: "a".into(),
+                        param_type: "i32".into(),
+                        indexed: false,
+                    },
+                    ContractParam {
+                        name: "b".into(),
+                        param_type: "i32".into(),
+                        indexed: false,
+                    },
+                ],
+                outputs: vec![
+                    ContractParam {
+                        name: "result".into(),
+                        param_type: "i32".into(),
+                        indexed: false,
+                    },
+                ],
+                payable: false,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    let metadata = |version: &str, updated_at| ContractMetadata {
+        version: version.into(),
+        created_at: 1234567890,
+        updated_at,
+        author: TEST_ACCOUNT,
+        description: format!("Test Contract {}", version),
+        is_upgradeable: true,
+        allow_major_upgrade: true,
+    };
+
+    // v1 keeps the balance as a 32-bit value
+    runtime.deploy_contract(TEST_WASM_V1, &contract_addr, &abi, metadata("1.0.0", 1234567890), &limits).await.unwrap();
+    runtime.update_contract_state(contract_addr, b"balance".to_vec(), 7u32.to_le_bytes().to_vec()).await.unwrap();
+
+    // v2 migrates it to a 64-bit value under a new key
+    runtime.upgrade_contract(&contract_addr, TEST_WASM_V2, &abi, metadata("2.0.0", 1234567891), None).await.unwrap();
+    runtime.update_contract_state(contract_addr, b"balance_v2".to_vec(), 7u64.to_le_bytes().to_vec()).await.unwrap();
+    runtime.update_contract_state(contract_addr, b"balance".to_vec(), vec![]).await.unwrap();
+
+    // Executions under v2 must not disturb the upgrade snapshot
+    let env = ContractEnvironment {
+        gas_limit: Some(1_000_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value: 0,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+    for _ in 0..2 {
+        runtime.execute_contract(contract_addr, "add", vec![Value::I32(1), Value::I32(2)], &env, None).await.unwrap();
+    }
+
+    runtime.rollback_contract(&contract_addr).await.unwrap();
+
+    // Bytecode and state both come back as v1 left them
+    let current = runtime.get_latest_version(&contract_addr).unwrap();
+    assert_eq!(current.metadata.version, "1.0.0");
+    assert_eq!(current.bytecode, TEST_WASM_V1);
+
+    let state = runtime.get_contract_state(&contract_addr).unwrap();
+    assert_eq!(state.get(b"balance".as_slice()), Some(&7u32.to_le_bytes().to_vec()));
+    assert!(!state.contains_key(b"balance_v2".as_slice()));
+
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_upgrade_with_state_migration() {
+    let mut runtime = setup_runtime().await;
+    runtime.grant_role(UPGRADER_ROLE, TEST_ACCOUNT).unwrap();
+    let contract_addr = [5u8; 32];
+
+    let abi = ContractABI {
+        methods: vec![],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    let metadata = |version: &str, updated_at| ContractMetadata {
+        version: version.into(),
+        created_at: 1234567890,
+        updated_at,
+        author: TEST_ACCOUNT,
+        description: format!("Test Contract {}", version),
+        is_upgradeable: true,
+        allow_major_upgrade: true,
+    };
+
+    runtime.deploy_contract(TEST_WASM_V1, &contract_addr, &abi, metadata("1.0.0", 1234567890), &limits).await.unwrap();
+    runtime.update_contract_state(contract_addr, b"owner".to_vec(), TEST_ACCOUNT.to_vec()).await.unwrap();
+    assert_eq!(runtime.get_schema_version(&contract_addr), 1);
+
+    // v2 renames the key
+    let rename_owner: &StateMigration = &|mut state| {
+        if let Some(owner) = state.remove(b"owner".as_slice()) {
+            state.insert(b"admin".to_vec(), owner);
+        }
+        state
+    };
+    runtime.upgrade_contract(&contract_addr, TEST_WASM_V1, &abi, metadata("2.0.0", 1234567891), Some(rename_owner))
+        .await
+        .unwrap();
+
+    let state = runtime.get_contract_state(&contract_addr).unwrap();
+    assert_eq!(state.get(b"admin".as_slice()), Some(&TEST_ACCOUNT.to_vec()));
+    assert!(!state.contains_key(b"owner".as_slice()));
+    assert_eq!(runtime.get_schema_version(&contract_addr), 2);
+
+    // The pre-upgrade snapshot still holds the state in the old schema
+    let snapshots = runtime.get_state_snapshots(&contract_addr).unwrap();
+    let old = snapshots.iter().rev().find(|snapshot| snapshot.version == "1.0.0").unwrap();
+    assert_eq!(old.schema_version, 1);
+    assert_eq!(old.state.get(b"owner".as_slice()), Some(&TEST_ACCOUNT.to_vec()));
+    assert!(!old.state.contains_key(b"admin".as_slice()));
+
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_snapshot_cap_keeps_rollback_target() {
+    let mut runtime = setup_runtime().await;
+    runtime.grant_role(UPGRADER_ROLE, TEST_ACCOUNT).unwrap();
+    runtime.set_max_snapshots(3);
+    let contract_addr = [6u8; 32];
+
+    let abi = ContractABI {
+        methods: vec![
+            ContractMethod {
+                name: "store".into(),
+                inputs: vec![
+                    ContractParam {
+                        name: "key".into(),
+                        param_type: "i32".into(),
+                        indexed: false,
+                    },
+                    ContractParam {
+                        name: "value".into(),
+                        param_type: "i32".into(),
+                        indexed: false,
+                    },
+                ],
+                outputs: vec![],
+                payable: false,
+                default_gas: None,
+            },
+        ],
+        events: vec![],
+        standards: vec![],
+    };
+
+    let limits = ResourceLimits {
+        max_memory: 2 * 1024 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    let metadata = |version: &str, updated_at| ContractMetadata {
+        version: version.into(),
+        created_at: 1234567890,
+        updated_at,
+        author: TEST_ACCOUNT,
+        description: format!("Test Contract {}", version),
+        is_upgradeable: true,
+        allow_major_upgrade: true,
+    };
+
+    runtime.deploy_contract(STORAGE_WAT.as_bytes(), &contract_addr, &abi, metadata("1.0.0", 1234567890), &limits).await.unwrap();
+    runtime.update_contract_state(contract_addr, b"counter".to_vec(), vec![1]).await.unwrap();
+
+    // Upgrading snapshots the v1 state; writes under v2 then go over the cap
+    runtime.upgrade_contract(&contract_addr, STORAGE_WAT.as_bytes(), &abi, metadata("2.0.0", 1234567891), None).await.unwrap();
+    let env = ContractEnvironment {
+        gas_limit: Some(1_000_000),
+        block_number: 1,
+        timestamp: 1234567890,
+        caller: TEST_ACCOUNT,
+        value: 0,
+        resource_limits: limits,
+        gas_used: Arc::new(RwLock::new(0)),
+    };
+    for value in 2..6 {
+        runtime.update_contract_state(contract_addr, b"counter".to_vec(), vec![value]).await.unwrap();
+        runtime.execute_contract(contract_addr, "store", vec![Value::I32(0), Value::I32(value as i32)], &env, None).await.unwrap();
+    }
+
+    // The oldest snapshots are gone, except the one v1 rolls back to
+    let snapshots = runtime.get_state_snapshots(&contract_addr).unwrap();
+    assert_eq!(snapshots.len(), 3);
+    assert_eq!(snapshots[0].version, "1.0.0");
+    assert_eq!(snapshots[0].state.get(b"counter".as_slice()), Some(&vec![1]));
+    let counters: Vec<_> = snapshots[1..].iter().map(|snapshot| snapshot.state[b"counter".as_slice()][0]).collect();
+    assert_eq!(counters, vec![4, 5]);
+
+    runtime.rollback_contract(&contract_addr).await.unwrap();
+    assert_eq!(runtime.get_latest_version(&contract_addr).unwrap().metadata.version, "1.0.0");
+    let state = runtime.get_contract_state(&contract_addr).unwrap();
+    assert_eq!(state.get(b"counter".as_slice()), Some(&vec![1]));
+
+    msg::test_utils::clear_sender().unwrap();
+}
+
+#[tokio::test]
+async fn test_upgrade_respects_memory_limit() {
+    let mut runtime = setup_runtime().await;
+    let contract_addr = [12u8; 32];
+
+    let abi = ContractABI {
+        methods: vec![],
+        events: vec![],
+        standards: vec![],
+    };
+
+    // Room for two pages, the deployed contract uses one
+    let limits = ResourceLimits {
+        max_memory: 2 * 64 * 1024,
+        max_gas: 1_000_000,
+        max_storage: 1024 * 1024,
+        max_call_depth: 5,
+    };
+
+    let metadata = |version: &str, updated_at| ContractMetadata {
+        version: version.into(),
+        created_at: 1234567890,
+        updated_at,
+        author: TEST_ACCOUNT,
+        description: format!("Test Contract {}", version),
+        is_upgradeable: true,
+        allow_major_upgrade: true,
+    };
+
+    runtime.deploy_contract(STORAGE_WAT.as_bytes(), &contract_addr, &abi, metadata("1.0.0", 1234567890), &limits).await.unwrap();
+
+    // New code declaring more memory than the contract was deployed with
+    let oversized_wat = r#"
+    (module
+      (memory (export "memory") 4)
+      (func (export "run")))
+    "#;
+    let result = runtime.upgrade_contract(&contract_addr, oversized_wat.as_bytes(), &abi, metadata("2.0.0", 1234567891), None).await;
+    assert!(
+        matches!(&result, Err(ContractError::BytecodeVerificationError(message)) if message.contains("memory limit")),
+        "Unexpected result: {:?}", result
+    );
+    assert_eq!(runtime.get_latest_version(&contract_addr).unwrap().metadata.version, "1.0.0");
+
+    // Code within the limit still upgrades
+    runtime.upgrade_contract(&contract_addr, STORAGE_WAT.as_bytes(), &abi, metadata("2.0.0", 1234567891), None).await.unwrap();
+    assert_eq!(runtime.get_latest_version(&contract_addr).unwrap().metadata.version, "2.0.0");
+
+    msg::test_utils::clear_sender().unwrap();
+}